@@ -0,0 +1,16 @@
+fn main() {
+	// Keeps include/nessy.h in step with the nessy_* functions in src/ffi.rs
+	#[cfg(feature = "ffi")]
+	{
+		println!("cargo:rerun-if-changed=src/ffi.rs");
+		println!("cargo:rerun-if-changed=cbindgen.toml");
+		let config = cbindgen::Config::from_file("cbindgen.toml").expect("cbindgen.toml is readable");
+		cbindgen::Builder::new()
+			.with_config(config)
+			.with_src("src/ffi.rs")
+			.generate()
+			.expect("src/ffi.rs is parsable")
+			.write_to_file("include/nessy.h");
+	}
+	println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,147 @@
+/* C interface of the nessy NES emulator, generated from src/ffi.rs by cbindgen.
+ * Link against the nessy cdylib built with the ffi feature. */
+
+#ifndef NESSY_H
+#define NESSY_H
+
+/* Do not edit, build with the ffi feature to regenerate */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define NESSY_WIDTH 256
+
+#define NESSY_HEIGHT 240
+
+#define NESSY_OK 0
+
+#define NESSY_ERROR_INVALID_ARGUMENT -1
+
+#define NESSY_ERROR_ROM -2
+
+#define NESSY_ERROR_NO_ROM -3
+
+#define NESSY_ERROR_STATE -4
+
+/*
+ The emulator panicked, on an opcode it does not implement for example. The ROM is unloaded
+ */
+#define NESSY_ERROR_PANIC -5
+
+/*
+ Controller bits for nessy_set_input
+ */
+#define NESSY_BUTTON_A 1
+
+#define NESSY_BUTTON_B 2
+
+#define NESSY_BUTTON_SELECT 4
+
+#define NESSY_BUTTON_START 8
+
+#define NESSY_BUTTON_UP 16
+
+#define NESSY_BUTTON_DOWN 32
+
+#define NESSY_BUTTON_LEFT 64
+
+#define NESSY_BUTTON_RIGHT 128
+
+typedef struct NessyEmulator NessyEmulator;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+const char *nessy_version(void);
+
+NessyEmulator *nessy_create(void);
+
+/*
+ # Safety
+ `emulator` must come from nessy_create and not be used afterwards, null is ignored
+ */
+void nessy_destroy(NessyEmulator *emulator);
+
+/*
+ Message of the last error, empty before the first one
+
+ # Safety
+ `emulator` must come from nessy_create
+ */
+const char *nessy_last_error(const NessyEmulator *emulator);
+
+/*
+ iNES or NES 2.0 file content, copied
+
+ # Safety
+ `emulator` must come from nessy_create and `data` point to `size` readable bytes
+ */
+int nessy_load_rom(NessyEmulator *emulator, const uint8_t *data, size_t size);
+
+/*
+ # Safety
+ `emulator` must come from nessy_create
+ */
+int nessy_reset(NessyEmulator *emulator);
+
+/*
+ # Safety
+ `emulator` must come from nessy_create
+ */
+int nessy_run_frame(NessyEmulator *emulator);
+
+/*
+ NESSY_WIDTH * NESSY_HEIGHT RGB pixels, 3 bytes each, valid until the next call taking the emulator.
+ Null when no ROM is loaded
+
+ # Safety
+ `emulator` must come from nessy_create
+ */
+const uint8_t *nessy_framebuffer(const NessyEmulator *emulator);
+
+/*
+ `port` 0 or 1, `buttons` a mask of NESSY_BUTTON_*
+
+ # Safety
+ `emulator` must come from nessy_create
+ */
+int nessy_set_input(NessyEmulator *emulator, unsigned int port, uint8_t buttons);
+
+/*
+ # Safety
+ `emulator` must come from nessy_create
+ */
+int nessy_set_sample_rate(NessyEmulator *emulator, unsigned int sample_rate);
+
+/*
+ Moves up to `capacity` mono samples into `samples`, returns how many
+
+ # Safety
+ `emulator` must come from nessy_create and `samples` point to `capacity` writable floats
+ */
+size_t nessy_read_audio(NessyEmulator *emulator, float *samples, size_t capacity);
+
+/*
+ Size of the save state, written to `buffer` only when `capacity` is large enough,
+ so a first call with a null buffer gives the size to allocate. 0 when no ROM is loaded
+
+ # Safety
+ `emulator` must come from nessy_create and `buffer` point to `capacity` writable bytes
+ */
+size_t nessy_save_state(NessyEmulator *emulator, uint8_t *buffer, size_t capacity);
+
+/*
+ # Safety
+ `emulator` must come from nessy_create and `data` point to `size` readable bytes
+ */
+int nessy_load_state(NessyEmulator *emulator, const uint8_t *data, size_t size);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NESSY_H */
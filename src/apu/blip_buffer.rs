@@ -0,0 +1,162 @@
+use std::f64::consts::PI;
+
+// Taps of the band-limited step and sub-sample positions it is precomputed for
+const KERNEL_WIDTH: usize = 16;
+const PHASES: usize = 32;
+// Cutoff relative to the output sample rate, under Nyquist to leave room for the window roll-off
+const CUTOFF: f64 = 0.45;
+
+// Band-limited step synthesis: level changes of the APU output are added as
+// windowed-sinc impulses at their exact sub-sample time, then integrated back
+// into samples, so square waves don't alias at the output rate
+#[derive(Clone)]
+pub struct BlipBuffer {
+	clock_rate: f64,
+	sample_rate: f64,
+	kernel: Vec<[f32; KERNEL_WIDTH]>,
+
+	// Position of the next CPU cycle in output samples, relative to `deltas[0]`
+	time: f64,
+	deltas: Vec<f32>,
+	level: f32,
+	integrator: f32,
+
+	samples: Vec<f32>
+}
+
+impl BlipBuffer {
+	pub fn new(clock_rate: f64, sample_rate: u32) -> BlipBuffer {
+		BlipBuffer {
+			clock_rate,
+			sample_rate: f64::from(sample_rate),
+			kernel: BlipBuffer::build_kernel(),
+			time: 0.0,
+			deltas: vec![0.0; KERNEL_WIDTH],
+			level: 0.0,
+			integrator: 0.0,
+			samples: Vec::new()
+		}
+	}
+
+	fn build_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+		(0..PHASES).map(|phase| {
+			let offset = phase as f64 / PHASES as f64;
+			let mut taps = [0.0f64; KERNEL_WIDTH];
+			for (i, tap) in taps.iter_mut().enumerate() {
+				let x = i as f64 - KERNEL_WIDTH as f64 / 2.0 - offset + 1.0;
+				let sinc = if x == 0.0 { 1.0 } else { (2.0 * PI * CUTOFF * x).sin() / (PI * x) / (2.0 * CUTOFF) };
+				// Blackman window over the kernel span
+				let n = (x + KERNEL_WIDTH as f64 / 2.0) / KERNEL_WIDTH as f64;
+				let window = 0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos();
+				*tap = sinc * window.max(0.0);
+			}
+
+			// Each step must add exactly its delta once integrated
+			let sum: f64 = taps.iter().sum();
+			let mut kernel = [0.0f32; KERNEL_WIDTH];
+			for (k, tap) in kernel.iter_mut().zip(taps.iter()) {
+				*k = (tap / sum) as f32;
+			}
+			kernel
+		}).collect()
+	}
+
+	// Input values per second
+	pub fn set_clock_rate(&mut self, clock_rate: f64) {
+		self.clock_rate = clock_rate;
+	}
+
+	pub fn set_sample_rate(&mut self, sample_rate: u32) {
+		self.sample_rate = f64::from(sample_rate);
+	}
+
+	pub fn sample_rate(&self) -> u32 {
+		self.sample_rate as u32
+	}
+
+	// One input value per CPU cycle
+	pub fn push(&mut self, value: f32) {
+		if value != self.level {
+			self.add_delta(value - self.level);
+			self.level = value;
+		}
+
+		self.time += self.sample_rate / self.clock_rate;
+		self.flush();
+	}
+
+	fn add_delta(&mut self, delta: f32) {
+		let start = self.time as usize;
+		let phase = ((self.time.fract() * PHASES as f64) as usize).min(PHASES - 1);
+
+		if self.deltas.len() < start + KERNEL_WIDTH {
+			self.deltas.resize(start + KERNEL_WIDTH, 0.0);
+		}
+		for (i, tap) in self.kernel[phase].iter().enumerate() {
+			self.deltas[start + i] += delta * tap;
+		}
+	}
+
+	// Samples before the current time won't receive any more deltas
+	fn flush(&mut self) {
+		let ready = self.time as usize;
+		if ready == 0 {
+			return;
+		}
+
+		for &delta in &self.deltas[..ready] {
+			self.integrator += delta;
+			self.samples.push(self.integrator);
+		}
+		self.deltas.drain(..ready);
+		self.deltas.resize(self.deltas.len().max(KERNEL_WIDTH), 0.0);
+		self.time -= ready as f64;
+	}
+
+	pub fn take_samples(&mut self) -> Vec<f32> {
+		std::mem::take(&mut self.samples)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sample_count() {
+		let mut blip = BlipBuffer::new(1_789_773.0, 44_100);
+		for _ in 0..1_789_773 {
+			blip.push(0.5);
+		}
+
+		let samples = blip.take_samples();
+		assert!((44_099..=44_100).contains(&samples.len()));
+		// The initial step settles once past the kernel
+		assert!(samples[KERNEL_WIDTH..].iter().all(|&sample| (sample - 0.5).abs() < 1e-4));
+		assert!(blip.take_samples().is_empty());
+	}
+
+	#[test]
+	fn kernel_normalized() {
+		for phase in BlipBuffer::build_kernel() {
+			assert!((phase.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+		}
+	}
+
+	#[test]
+	fn no_aliasing_above_nyquist() {
+		// A 30kHz square wave is above the 22.05kHz Nyquist, its fundamental must not fold back
+		let mut blip = BlipBuffer::new(1_789_773.0, 44_100);
+		let half_period = 1_789_773.0 / 30_000.0 / 2.0;
+		for cycle in 0..178_977 {
+			let high = (cycle as f64 / half_period) as u64 % 2 == 1;
+			blip.push(if high { 1.0 } else { 0.0 });
+		}
+
+		let samples = blip.take_samples();
+		let settled = &samples[KERNEL_WIDTH..];
+		let mean = settled.iter().sum::<f32>() / settled.len() as f32;
+		let peak = settled.iter().map(|sample| (sample - mean).abs()).fold(0.0, f32::max);
+		assert!(peak < 0.1, "peak {}", peak);
+	}
+}
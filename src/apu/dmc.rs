@@ -0,0 +1,224 @@
+use crate::region::Region;
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dmc {
+	irq_enabled: bool,
+	looping: bool,
+	pub irq: bool,
+
+	timer: u16,
+	timer_period: u16,
+	// Picks the period in CPU cycles of each rate index
+	region: Region,
+
+	// Memory reader
+	sample_address: u16,
+	sample_length: u16,
+	current_address: u16,
+	bytes_remaining: u16,
+	sample_buffer: Option<u8>,
+
+	// Output unit
+	shift_register: u8,
+	bits_remaining: u8,
+	silence: bool,
+	level: u8
+}
+
+impl Dmc {
+	pub fn new() -> Dmc {
+		Dmc {
+			irq_enabled: false,
+			looping: false,
+			irq: false,
+			timer: Region::Ntsc.dmc_rates()[0],
+			timer_period: Region::Ntsc.dmc_rates()[0],
+			region: Region::Ntsc,
+			sample_address: 0xC000,
+			sample_length: 1,
+			current_address: 0xC000,
+			bytes_remaining: 0,
+			sample_buffer: None,
+			shift_register: 0,
+			bits_remaining: 8,
+			silence: true,
+			level: 0
+		}
+	}
+
+	// The selected rate keeps its index in the new table
+	pub fn set_region(&mut self, region: Region) {
+		if let Some(index) = self.region.dmc_rates().iter().position(|&period| period == self.timer_period) {
+			self.timer_period = region.dmc_rates()[index];
+		}
+		self.region = region;
+	}
+
+	// Register 0 to 3 of the channel
+	pub fn write(&mut self, register: u16, value: u8) {
+		match register {
+			0 => {
+				self.irq_enabled = value & 0x80 != 0;
+				self.looping = value & 0x40 != 0;
+				self.timer_period = self.region.dmc_rates()[usize::from(value & 0x0F)];
+				if !self.irq_enabled {
+					self.irq = false;
+				}
+			},
+			1 => self.level = value & 0x7F,
+			2 => self.sample_address = 0xC000 + u16::from(value) * 64,
+			3 => self.sample_length = u16::from(value) * 16 + 1,
+			_ => panic!("DMC register {} does not exist", register)
+		}
+	}
+
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.irq = false;
+
+		if !enabled {
+			self.bytes_remaining = 0;
+		} else if self.bytes_remaining == 0 {
+			self.restart();
+		}
+	}
+
+	fn restart(&mut self) {
+		self.current_address = self.sample_address;
+		self.bytes_remaining = self.sample_length;
+	}
+
+	pub fn is_active(&self) -> bool {
+		self.bytes_remaining > 0
+	}
+
+	// Address the memory reader wants to fetch, the bus halts the CPU to serve it
+	pub fn dma_request(&self) -> Option<u16> {
+		if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+			Some(self.current_address)
+		} else {
+			None
+		}
+	}
+
+	pub fn dma_complete(&mut self, value: u8) {
+		self.sample_buffer = Some(value);
+		self.current_address = if self.current_address == 0xFFFF { 0x8000 } else { self.current_address + 1 };
+		self.bytes_remaining -= 1;
+
+		if self.bytes_remaining == 0 {
+			if self.looping {
+				self.restart();
+			} else if self.irq_enabled {
+				self.irq = true;
+			}
+		}
+	}
+
+	// CPU cycle
+	pub fn clock_timer(&mut self) {
+		if self.timer > 1 {
+			self.timer -= 1;
+			return;
+		}
+		self.timer = self.timer_period;
+
+		if !self.silence {
+			if self.shift_register & 0x01 != 0 {
+				if self.level <= 125 {
+					self.level += 2;
+				}
+			} else if self.level >= 2 {
+				self.level -= 2;
+			}
+		}
+		self.shift_register >>= 1;
+
+		self.bits_remaining -= 1;
+		if self.bits_remaining == 0 {
+			self.bits_remaining = 8;
+			match self.sample_buffer.take() {
+				Some(sample) => {
+					self.silence = false;
+					self.shift_register = sample;
+				},
+				None => self.silence = true
+			}
+		}
+	}
+
+	pub fn output(&self) -> u8 {
+		self.level
+	}
+
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_bool(self.irq_enabled);
+		state.write_bool(self.looping);
+		state.write_bool(self.irq);
+		state.write_u16(self.timer);
+		state.write_u16(self.timer_period);
+		state.write_u16(self.sample_address);
+		state.write_u16(self.sample_length);
+		state.write_u16(self.current_address);
+		state.write_u16(self.bytes_remaining);
+		state.write_bool(self.sample_buffer.is_some());
+		state.write_u8(self.sample_buffer.unwrap_or(0));
+		state.write_u8(self.shift_register);
+		state.write_u8(self.bits_remaining);
+		state.write_bool(self.silence);
+		state.write_u8(self.level);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.irq_enabled = state.read_bool()?;
+		self.looping = state.read_bool()?;
+		self.irq = state.read_bool()?;
+		self.timer = state.read_u16()?;
+		self.timer_period = state.read_u16()?;
+		self.sample_address = state.read_u16()?;
+		self.sample_length = state.read_u16()?;
+		self.current_address = state.read_u16()?;
+		self.bytes_remaining = state.read_u16()?;
+		let buffered = state.read_bool()?;
+		let sample = state.read_u8()?;
+		self.sample_buffer = if buffered { Some(sample) } else { None };
+		self.shift_register = state.read_u8()?;
+		self.bits_remaining = state.read_u8_in(1..=8)?;
+		self.silence = state.read_bool()?;
+		self.level = state.read_u8_in(0..=127)?;
+		Ok(())
+	}
+}
+
+impl Default for Dmc {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sample_playback() {
+		let mut dmc = Dmc::new();
+		dmc.write(0, 0x8F); // IRQ, fastest rate
+		dmc.write(2, 0x00);
+		dmc.write(3, 0x00); // 1 byte
+		dmc.set_enabled(true);
+
+		assert_eq!(dmc.dma_request(), Some(0xC000));
+		dmc.dma_complete(0xFF);
+		assert_eq!(dmc.dma_request(), None);
+		assert!(dmc.irq);
+		assert!(!dmc.is_active());
+
+		// Timer still has the power-up period, the empty shift register runs out first
+		for _ in 0..(428 + 54 * 15) {
+			dmc.clock_timer();
+		}
+		assert_eq!(dmc.output(), 16);
+	}
+}
@@ -0,0 +1,87 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Envelope {
+	start: bool,
+	looping: bool,
+	constant: bool,
+	volume: u8, // Also the divider period
+	divider: u8,
+	decay: u8
+}
+
+impl Envelope {
+	pub fn new() -> Envelope {
+		Envelope {
+			start: false,
+			looping: false,
+			constant: false,
+			volume: 0,
+			divider: 0,
+			decay: 0
+		}
+	}
+
+	// --LC VVVV
+	pub fn write(&mut self, value: u8) {
+		self.looping = value & 0x20 != 0;
+		self.constant = value & 0x10 != 0;
+		self.volume = value & 0x0F;
+	}
+
+	pub fn restart(&mut self) {
+		self.start = true;
+	}
+
+	// Quarter frame
+	pub fn clock(&mut self) {
+		if self.start {
+			self.start = false;
+			self.decay = 15;
+			self.divider = self.volume;
+			return;
+		}
+
+		if self.divider > 0 {
+			self.divider -= 1;
+			return;
+		}
+
+		self.divider = self.volume;
+		if self.decay > 0 {
+			self.decay -= 1;
+		} else if self.looping {
+			self.decay = 15;
+		}
+	}
+
+	pub fn output(&self) -> u8 {
+		if self.constant { self.volume } else { self.decay }
+	}
+
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_bool(self.start);
+		state.write_bool(self.looping);
+		state.write_bool(self.constant);
+		state.write_u8(self.volume);
+		state.write_u8(self.divider);
+		state.write_u8(self.decay);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.start = state.read_bool()?;
+		self.looping = state.read_bool()?;
+		self.constant = state.read_bool()?;
+		self.volume = state.read_u8_in(0..=15)?;
+		self.divider = state.read_u8()?;
+		self.decay = state.read_u8_in(0..=15)?;
+		Ok(())
+	}
+}
+
+impl Default for Envelope {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,39 @@
+use crate::state::StateError;
+
+// Extra sound channels of a cartridge (VRC6, VRC7, FDS, MMC5, Namco 163, Sunsoft 5B),
+// mixed with the 2A03 output by the APU
+pub trait ExpansionAudio: ExpansionAudioClone {
+	// Advance by `cycles` CPU cycles
+	fn clock(&mut self, cycles: u32);
+
+	// Level on the scale of `mixer::mix`, a 2A03 pulse at full volume is about 0.11
+	fn output(&self) -> f32;
+
+	// Cartridge register writes, at $4020-$FFFF
+	fn write(&mut self, _adress: u16, _value: u8) {}
+
+	// Channel state for save states, load_state gets back what save_state returned
+	fn save_state(&self) -> Vec<u8> {
+		Vec::new()
+	}
+
+	fn load_state(&mut self, _data: &[u8]) -> Result<(), StateError> {
+		Ok(())
+	}
+}
+
+pub trait ExpansionAudioClone {
+	fn clone_box(&self) -> Box<dyn ExpansionAudio>;
+}
+
+impl<T: ExpansionAudio + Clone + 'static> ExpansionAudioClone for T {
+	fn clone_box(&self) -> Box<dyn ExpansionAudio> {
+		Box::new(self.clone())
+	}
+}
+
+impl Clone for Box<dyn ExpansionAudio> {
+	fn clone(&self) -> Self {
+		self.clone_box()
+	}
+}
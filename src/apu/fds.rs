@@ -0,0 +1,448 @@
+use super::expansion::ExpansionAudio;
+use crate::state::{ensure, StateError, StateReader, StateWriter};
+
+// Level 63 of the wave channel is about 2.4 times a 2A03 pulse at full volume
+const OUTPUT_SCALE: f32 = 0.0042;
+
+// Wave output divider for master volume 2/2, 2/3, 2/4 and 2/5
+const MASTER_VOLUME: [u32; 4] = [36, 24, 17, 14];
+
+// Modulation counter steps of the 3 bit table entries, 4 resets the counter
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MOD_RESET: u8 = 4;
+
+// Volume and modulation gain envelopes, sharing the $4080/$4084 layout
+#[derive(Clone)]
+struct Envelope {
+	speed: u8,
+	increase: bool,
+	// Envelope off, the gain is set directly to the speed
+	direct: bool,
+	gain: u8,
+	timer: u32,
+	// 12 bit frequency at +2/+3
+	frequency: u16
+}
+
+impl Envelope {
+	fn new() -> Envelope {
+		Envelope {
+			speed: 0,
+			increase: false,
+			direct: true,
+			gain: 0,
+			timer: 0,
+			frequency: 0
+		}
+	}
+
+	fn write(&mut self, register: u16, value: u8, master_speed: u8) {
+		match register {
+			0 => {
+				self.speed = value & 0x3F;
+				self.increase = value & 0x40 != 0;
+				self.direct = value & 0x80 != 0;
+				self.reset_timer(master_speed);
+				if self.direct {
+					self.gain = self.speed;
+				}
+			},
+			2 => self.frequency = (self.frequency & 0x0F00) | u16::from(value),
+			3 => self.frequency = (self.frequency & 0x00FF) | (u16::from(value & 0x0F) << 8),
+			_ => {}
+		}
+	}
+
+	fn reset_timer(&mut self, master_speed: u8) {
+		self.timer = 8 * (u32::from(self.speed) + 1) * u32::from(master_speed);
+	}
+
+	// True when the gain changed
+	fn clock(&mut self, master_speed: u8) -> bool {
+		if self.direct || master_speed == 0 {
+			return false;
+		}
+
+		self.timer = self.timer.saturating_sub(1);
+		if self.timer > 0 {
+			return false;
+		}
+
+		self.reset_timer(master_speed);
+		if self.increase && self.gain < 32 {
+			self.gain += 1;
+		} else if !self.increase && self.gain > 0 {
+			self.gain -= 1;
+		}
+		true
+	}
+
+	fn save_state(&self, state: &mut StateWriter) {
+		state.write_u8(self.speed);
+		state.write_bool(self.increase);
+		state.write_bool(self.direct);
+		state.write_u8(self.gain);
+		state.write_u32(self.timer);
+		state.write_u16(self.frequency);
+	}
+
+	fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.speed = state.read_u8()?;
+		self.increase = state.read_bool()?;
+		self.direct = state.read_bool()?;
+		self.gain = state.read_u8()?;
+		self.timer = state.read_u32()?;
+		self.frequency = state.read_u16()?;
+		Ok(())
+	}
+}
+
+// Frequency modulation unit, bends the wave pitch with a 7 bit signed counter
+#[derive(Clone)]
+struct Modulator {
+	envelope: Envelope,
+	table: [u8; 64],
+	position: u8,
+	counter: i8,
+	halt: bool,
+	accumulator: u16,
+	// Pitch offset added to the wave frequency
+	output: i32
+}
+
+impl Modulator {
+	fn new() -> Modulator {
+		Modulator {
+			envelope: Envelope::new(),
+			table: [0; 64],
+			position: 0,
+			counter: 0,
+			halt: true,
+			accumulator: 0,
+			output: 0
+		}
+	}
+
+	fn set_counter(&mut self, value: i32) {
+		// Wraps within -64..=63
+		self.counter = ((value + 64).rem_euclid(128) - 64) as i8;
+	}
+
+	// Each write fills two entries, only while the modulator is halted
+	fn write_table(&mut self, value: u8) {
+		if self.halt {
+			self.table[usize::from(self.position)] = value & 0x07;
+			self.table[usize::from(self.position + 1) & 0x3F] = value & 0x07;
+			self.position = (self.position + 2) & 0x3F;
+		}
+	}
+
+	// True when the counter changed
+	fn clock(&mut self) -> bool {
+		let frequency = self.envelope.frequency;
+		if self.halt || frequency == 0 {
+			return false;
+		}
+
+		let (accumulator, overflow) = self.accumulator.overflowing_add(frequency);
+		self.accumulator = accumulator;
+		if !overflow {
+			return false;
+		}
+
+		let entry = self.table[usize::from(self.position)];
+		if entry == MOD_RESET {
+			self.set_counter(0);
+		} else {
+			self.set_counter(i32::from(self.counter) + i32::from(MOD_STEPS[usize::from(entry)]));
+		}
+		self.position = (self.position + 1) & 0x3F;
+		true
+	}
+
+	// Pitch offset for `pitch`, following the rounding of the 2C33 multiplier
+	fn update_output(&mut self, pitch: u16) {
+		let counter = i32::from(self.counter);
+		let mut temp = counter * i32::from(self.envelope.gain);
+		let remainder = temp & 0x0F;
+		temp >>= 4;
+		if remainder > 0 && temp & 0x80 == 0 {
+			temp += if counter < 0 { -1 } else { 2 };
+		}
+
+		if temp >= 192 {
+			temp -= 256;
+		} else if temp < -64 {
+			temp += 256;
+		}
+
+		temp *= i32::from(pitch);
+		let remainder = temp & 0x3F;
+		temp >>= 6;
+		if remainder >= 32 {
+			temp += 1;
+		}
+		self.output = temp;
+	}
+
+	fn save_state(&self, state: &mut StateWriter) {
+		self.envelope.save_state(state);
+		state.write_bytes(&self.table);
+		state.write_u8(self.position);
+		state.write_u8(self.counter as u8);
+		state.write_bool(self.halt);
+		state.write_u16(self.accumulator);
+		state.write_u32(self.output as u32);
+	}
+
+	fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.envelope.load_state(state)?;
+		state.read_into(&mut self.table)?;
+		ensure(self.table.iter().all(|&entry| entry < 8), "modulation table entry out of range")?;
+		self.position = state.read_u8_in(0..=0x3F)?;
+		self.counter = state.read_u8()? as i8;
+		self.halt = state.read_bool()?;
+		self.accumulator = state.read_u16()?;
+		self.output = state.read_u32()? as i32;
+		Ok(())
+	}
+}
+
+// Famicom Disk System wavetable channel, clocked at the CPU rate
+#[derive(Clone)]
+pub struct FdsAudio {
+	wave_table: [u8; 64],
+	// $4089 bit 7 stops the wave and opens the table for writing
+	wave_write: bool,
+	wave_halt: bool,
+	envelopes_halt: bool,
+	wave_accumulator: u16,
+	wave_position: u8,
+	volume: Envelope,
+	modulator: Modulator,
+	master_volume: u8,
+	master_speed: u8,
+	level: u8
+}
+
+impl FdsAudio {
+	pub fn new() -> FdsAudio {
+		FdsAudio {
+			wave_table: [0; 64],
+			wave_write: false,
+			wave_halt: true,
+			envelopes_halt: false,
+			wave_accumulator: 0,
+			wave_position: 0,
+			volume: Envelope::new(),
+			modulator: Modulator::new(),
+			master_volume: 0,
+			master_speed: 0xE8,
+			level: 0
+		}
+	}
+
+	// Raw DAC level, 0 to 63
+	pub fn level(&self) -> u8 {
+		self.level
+	}
+
+	fn update_level(&mut self) {
+		// The gain saturates at 32 even though the envelope can reach 63
+		let gain = u32::from(self.volume.gain.min(32)) * MASTER_VOLUME[usize::from(self.master_volume)];
+		let sample = u32::from(self.wave_table[usize::from(self.wave_position)]);
+		self.level = (sample * gain / 1152) as u8;
+	}
+
+	fn step(&mut self) {
+		let pitch = self.volume.frequency;
+
+		if !self.wave_halt && !self.envelopes_halt {
+			self.volume.clock(self.master_speed);
+			if self.modulator.envelope.clock(self.master_speed) {
+				self.modulator.update_output(pitch);
+			}
+		}
+
+		if self.modulator.clock() {
+			self.modulator.update_output(pitch);
+		}
+
+		if self.wave_halt {
+			self.wave_position = 0;
+			self.update_level();
+			return;
+		}
+
+		// The DAC holds its level while the table is written
+		if !self.wave_write {
+			self.update_level();
+		}
+
+		let frequency = i32::from(pitch) + self.modulator.output;
+		if frequency > 0 && !self.wave_write {
+			let (accumulator, overflow) = self.wave_accumulator.overflowing_add(frequency as u16);
+			self.wave_accumulator = accumulator;
+			if overflow {
+				self.wave_position = (self.wave_position + 1) & 0x3F;
+			}
+		}
+	}
+}
+
+impl ExpansionAudio for FdsAudio {
+	fn clock(&mut self, cycles: u32) {
+		for _ in 0..cycles {
+			self.step();
+		}
+	}
+
+	fn output(&self) -> f32 {
+		f32::from(self.level) * OUTPUT_SCALE
+	}
+
+	// Wave table at $4040-$407F, registers at $4080-$408A
+	fn write(&mut self, adress: u16, value: u8) {
+		match adress {
+			0x4040..=0x407F if self.wave_write => self.wave_table[usize::from(adress & 0x3F)] = value & 0x3F,
+			0x4080 | 0x4082 => self.volume.write(adress - 0x4080, value, self.master_speed),
+			0x4083 => {
+				self.volume.write(3, value, self.master_speed);
+				self.wave_halt = value & 0x80 != 0;
+				self.envelopes_halt = value & 0x40 != 0;
+				if self.wave_halt {
+					self.wave_position = 0;
+					self.wave_accumulator = 0;
+				}
+				if self.envelopes_halt {
+					self.volume.reset_timer(self.master_speed);
+					self.modulator.envelope.reset_timer(self.master_speed);
+				}
+			},
+			0x4084 => {
+				self.modulator.envelope.write(0, value, self.master_speed);
+				self.modulator.update_output(self.volume.frequency);
+			},
+			0x4085 => {
+				self.modulator.set_counter(i32::from(value & 0x7F));
+				self.modulator.update_output(self.volume.frequency);
+			},
+			0x4086 => self.modulator.envelope.write(2, value, self.master_speed),
+			0x4087 => {
+				self.modulator.envelope.write(3, value, self.master_speed);
+				self.modulator.halt = value & 0x80 != 0;
+				if self.modulator.halt {
+					self.modulator.accumulator = 0;
+				}
+			},
+			0x4088 => self.modulator.write_table(value),
+			0x4089 => {
+				self.master_volume = value & 0x03;
+				self.wave_write = value & 0x80 != 0;
+			},
+			0x408A => self.master_speed = value,
+			_ => {}
+		}
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_bytes(&self.wave_table);
+		state.write_bool(self.wave_write);
+		state.write_bool(self.wave_halt);
+		state.write_bool(self.envelopes_halt);
+		state.write_u16(self.wave_accumulator);
+		state.write_u8(self.wave_position);
+		self.volume.save_state(&mut state);
+		self.modulator.save_state(&mut state);
+		state.write_u8(self.master_volume);
+		state.write_u8(self.master_speed);
+		state.write_u8(self.level);
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		state.read_into(&mut self.wave_table)?;
+		self.wave_write = state.read_bool()?;
+		self.wave_halt = state.read_bool()?;
+		self.envelopes_halt = state.read_bool()?;
+		self.wave_accumulator = state.read_u16()?;
+		self.wave_position = state.read_u8_in(0..=0x3F)?;
+		self.volume.load_state(&mut state)?;
+		self.modulator.load_state(&mut state)?;
+		self.master_volume = state.read_u8_in(0..=3)?;
+		self.master_speed = state.read_u8()?;
+		self.level = state.read_u8()?;
+		Ok(())
+	}
+}
+
+impl Default for FdsAudio {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Square wave, 32 steps at 63 then 32 at 0
+	fn square(fds: &mut FdsAudio) {
+		fds.write(0x4089, 0x80);
+		for step in 0..64 {
+			fds.write(0x4040 + step, if step < 32 { 63 } else { 0 });
+		}
+		fds.write(0x4089, 0x00);
+	}
+
+	#[test]
+	fn wave_table() {
+		let mut fds = FdsAudio::new();
+		square(&mut fds);
+		fds.write(0x4080, 0x80 | 32); // Direct gain 32
+		// One step every 64 cycles
+		fds.write(0x4082, 0x00);
+		fds.write(0x4083, 0x04);
+
+		fds.clock(1);
+		assert_eq!(fds.level(), 63);
+
+		let high = (0..64 * 64).filter(|_| {
+			fds.clock(1);
+			fds.level() > 0
+		}).count();
+		assert_eq!(high, 32 * 64);
+	}
+
+	#[test]
+	fn halted_and_volume() {
+		let mut fds = FdsAudio::new();
+		square(&mut fds);
+		fds.write(0x4080, 0x80 | 32);
+		fds.clock(10);
+		// $4083 bit 7 is set at power on
+		assert_eq!(fds.level(), 63);
+
+		fds.write(0x4089, 0x03); // 2/5
+		fds.clock(1);
+		// 63 * 32 * 14 / 1152
+		assert_eq!(fds.level(), 24);
+	}
+
+	#[test]
+	fn modulation_counter() {
+		let mut fds = FdsAudio::new();
+		for _ in 0..32 {
+			fds.write(0x4088, 0x01); // +1 per step
+		}
+		fds.write(0x4085, 0x3F);
+		fds.write(0x4086, 0x00);
+		fds.write(0x4087, 0x08); // 2048, one step every 32 cycles
+
+		fds.clock(32);
+		// 63 + 1 wraps to -64
+		assert_eq!(fds.modulator.counter, -64);
+	}
+}
@@ -0,0 +1,259 @@
+use crate::region::Region;
+use crate::state::{ensure, StateError, StateReader, StateWriter};
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct FrameClock {
+	pub quarter: bool,
+	pub half: bool
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameCounter {
+	five_step: bool,
+	irq_inhibit: bool,
+	pub irq: bool,
+
+	// CPU cycles of the sequencer steps
+	steps: [u32; 5],
+	cycle: u32,
+	// Value written to $4017 and the CPU cycles before it applies
+	pending_write: Option<(u8, u8)>
+}
+
+impl FrameCounter {
+	pub fn new() -> FrameCounter {
+		FrameCounter {
+			five_step: false,
+			irq_inhibit: false,
+			irq: false,
+			steps: Region::Ntsc.frame_counter_steps(),
+			cycle: 0,
+			pending_write: None
+		}
+	}
+
+	pub fn set_region(&mut self, region: Region) {
+		self.steps = region.frame_counter_steps();
+	}
+
+	// MI-- ----, the sequencer reset is delayed by 3 or 4 CPU cycles
+	pub fn write(&mut self, value: u8, odd_cycle: bool) {
+		self.irq_inhibit = value & 0x40 != 0;
+		if self.irq_inhibit {
+			self.irq = false;
+		}
+
+		let delay = if odd_cycle { 4 } else { 3 };
+		self.pending_write = Some((value, delay));
+	}
+
+	// One CPU cycle
+	pub fn clock(&mut self) -> FrameClock {
+		let mut clock = FrameClock::default();
+
+		if let Some((value, delay)) = self.pending_write {
+			if delay > 1 {
+				self.pending_write = Some((value, delay - 1));
+			} else {
+				self.pending_write = None;
+				self.five_step = value & 0x80 != 0;
+				self.cycle = 0;
+
+				// 5-step mode clocks the units right away
+				if self.five_step {
+					clock.quarter = true;
+					clock.half = true;
+				}
+				return clock;
+			}
+		}
+
+		self.cycle = self.cycle.saturating_add(1);
+
+		let [step_1, step_2, step_3, step_4, step_5] = self.steps;
+		match (self.five_step, self.cycle) {
+			(_, c) if c == step_1 || c == step_3 => clock.quarter = true,
+			(_, c) if c == step_2 => {
+				clock.quarter = true;
+				clock.half = true;
+			},
+			(false, c) if c == step_4 => {
+				clock.quarter = true;
+				clock.half = true;
+				self.set_irq();
+			},
+			(false, c) if c == step_4 - 1 => self.set_irq(),
+			(false, c) if c == step_4 + 1 => {
+				self.set_irq();
+				self.cycle = 0;
+			},
+			(true, c) if c == step_5 => {
+				clock.quarter = true;
+				clock.half = true;
+			},
+			(true, c) if c == step_5 + 1 => self.cycle = 0,
+			_ => {}
+		}
+
+		clock
+	}
+
+	// CPU cycles until a clock that changes something: a step, an IRQ or the $4017 write
+	pub fn cycles_to_next_event(&self) -> u32 {
+		let [step_1, step_2, step_3, step_4, step_5] = self.steps;
+		let candidates = if self.five_step {
+			[step_1, step_2, step_3, step_5, step_5 + 1, step_5 + 1]
+		} else {
+			[step_1, step_2, step_3, step_4 - 1, step_4, step_4 + 1]
+		};
+		// Past every step after a region change, the sequencer never wraps
+		let step = candidates.into_iter().find(|&step| step > self.cycle).map_or(u32::MAX, |step| step - self.cycle);
+		match self.pending_write {
+			Some((_, delay)) => step.min(u32::from(delay)),
+			None => step
+		}
+	}
+
+	// `cycles` CPU cycles at once, at most cycles_to_next_event
+	pub fn advance(&mut self, cycles: u32) -> FrameClock {
+		if cycles == 0 {
+			return FrameClock::default();
+		}
+
+		let skipped = cycles - 1;
+		if let Some((value, delay)) = self.pending_write {
+			self.pending_write = Some((value, delay - skipped as u8));
+		}
+		self.cycle = self.cycle.saturating_add(skipped);
+		self.clock()
+	}
+
+	fn set_irq(&mut self) {
+		if !self.irq_inhibit {
+			self.irq = true;
+		}
+	}
+
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_bool(self.five_step);
+		state.write_bool(self.irq_inhibit);
+		state.write_bool(self.irq);
+		state.write_u32(self.cycle);
+		let (value, delay) = self.pending_write.unwrap_or((0, 0));
+		state.write_bool(self.pending_write.is_some());
+		state.write_u8(value);
+		state.write_u8(delay);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.five_step = state.read_bool()?;
+		self.irq_inhibit = state.read_bool()?;
+		self.irq = state.read_bool()?;
+		self.cycle = state.read_u32()?;
+		let pending = state.read_bool()?;
+		let value = state.read_u8()?;
+		let delay = state.read_u8()?;
+		self.pending_write = if pending { Some((value, delay)) } else { None };
+		ensure(!pending || (1..=4).contains(&delay), "$4017 write delay out of range")?;
+		Ok(())
+	}
+}
+
+impl Default for FrameCounter {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn run(counter: &mut FrameCounter, cycles: u32) -> (u32, u32) {
+		let (mut quarters, mut halves) = (0, 0);
+		for _ in 0..cycles {
+			let clock = counter.clock();
+			quarters += u32::from(clock.quarter);
+			halves += u32::from(clock.half);
+		}
+		(quarters, halves)
+	}
+
+	const STEP_4: u32 = 29829;
+	const STEP_5: u32 = 37281;
+
+	#[test]
+	fn four_step_mode() {
+		let mut counter = FrameCounter::new();
+
+		assert_eq!(run(&mut counter, STEP_4 - 2), (3, 1));
+		assert!(!counter.irq);
+		assert_eq!(run(&mut counter, 1), (0, 0));
+		assert!(counter.irq);
+		assert_eq!(run(&mut counter, 2), (1, 1));
+	}
+
+	#[test]
+	fn pal_steps() {
+		let mut counter = FrameCounter::new();
+		counter.set_region(Region::Pal);
+
+		assert_eq!(run(&mut counter, 8313), (1, 0));
+		assert_eq!(run(&mut counter, 16627 - 8313), (1, 1));
+		// Past the NTSC fourth step
+		assert_eq!(run(&mut counter, STEP_4 - 16627), (1, 0));
+		assert!(!counter.irq);
+		run(&mut counter, 33252 - STEP_4);
+		assert!(counter.irq);
+	}
+
+	#[test]
+	fn five_step_mode() {
+		let mut counter = FrameCounter::new();
+		counter.write(0x80, false);
+
+		// Units are clocked when the write applies
+		assert_eq!(run(&mut counter, 3), (1, 1));
+		assert_eq!(run(&mut counter, STEP_5 + 1), (4, 2));
+		assert!(!counter.irq);
+	}
+
+	#[test]
+	fn irq_inhibit() {
+		let mut counter = FrameCounter::new();
+		run(&mut counter, STEP_4);
+		assert!(counter.irq);
+
+		counter.write(0x40, false);
+		assert!(!counter.irq);
+		run(&mut counter, STEP_4 + 10);
+		assert!(!counter.irq);
+	}
+
+	#[test]
+	fn advance_matches_clock() {
+		let mut stepped = FrameCounter::new();
+		let mut advanced = FrameCounter::new();
+		let write_at = STEP_4 + 100;
+		let mut cycle = 0;
+		while cycle < STEP_5 * 3 {
+			let mut cycles = advanced.cycles_to_next_event();
+			if cycle < write_at {
+				cycles = cycles.min(write_at - cycle);
+			}
+
+			for _ in 1..cycles {
+				assert_eq!(stepped.clock(), FrameClock::default());
+			}
+			assert_eq!(stepped.clock(), advanced.advance(cycles));
+			assert_eq!(stepped.irq, advanced.irq);
+			cycle += cycles;
+
+			if cycle == write_at {
+				stepped.write(0x80, true);
+				advanced.write(0x80, true);
+			}
+		}
+	}
+}
@@ -0,0 +1,76 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+static LENGTH_TABLE: [u8; 32] = [
+	10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+	12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LengthCounter {
+	enabled: bool,
+	halt: bool,
+	counter: u8
+}
+
+impl LengthCounter {
+	pub fn new() -> LengthCounter {
+		LengthCounter {
+			enabled: false,
+			halt: false,
+			counter: 0
+		}
+	}
+
+	// Disabling the channel clears the counter
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.enabled = enabled;
+		if !enabled {
+			self.counter = 0;
+		}
+	}
+
+	pub fn set_halt(&mut self, halt: bool) {
+		self.halt = halt;
+	}
+
+	pub fn load(&mut self, index: u8) {
+		if self.enabled {
+			self.counter = LENGTH_TABLE[usize::from(index & 0x1F)];
+		}
+	}
+
+	// Half frame
+	pub fn clock(&mut self) {
+		if !self.halt && self.counter > 0 {
+			self.counter -= 1;
+		}
+	}
+
+	pub fn is_active(&self) -> bool {
+		self.counter > 0
+	}
+
+	pub fn value(&self) -> u8 {
+		self.counter
+	}
+
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_bool(self.enabled);
+		state.write_bool(self.halt);
+		state.write_u8(self.counter);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.enabled = state.read_bool()?;
+		self.halt = state.read_bool()?;
+		self.counter = state.read_u8()?;
+		Ok(())
+	}
+}
+
+impl Default for LengthCounter {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,141 @@
+use std::f32::consts::PI;
+
+// Non-linear DAC of the 2A03, levels are the raw channel outputs
+pub fn mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+	let pulse = f32::from(pulse1) + f32::from(pulse2);
+	let pulse_out = if pulse == 0.0 { 0.0 } else { 95.88 / (8128.0 / pulse + 100.0) };
+
+	let tnd = f32::from(triangle) / 8227.0 + f32::from(noise) / 12241.0 + f32::from(dmc) / 22638.0;
+	let tnd_out = if tnd == 0.0 { 0.0 } else { 159.79 / (1.0 / tnd + 100.0) };
+
+	pulse_out + tnd_out
+}
+
+// Cutoff frequencies in Hz, `None` disables the stage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilterConfig {
+	pub high_pass_1: Option<f32>,
+	pub high_pass_2: Option<f32>,
+	pub low_pass: Option<f32>
+}
+
+impl FilterConfig {
+	// Filters of the NES output path
+	pub fn nes() -> FilterConfig {
+		FilterConfig {
+			high_pass_1: Some(90.0),
+			high_pass_2: Some(440.0),
+			low_pass: Some(14_000.0)
+		}
+	}
+
+	pub fn disabled() -> FilterConfig {
+		FilterConfig {
+			high_pass_1: None,
+			high_pass_2: None,
+			low_pass: None
+		}
+	}
+}
+
+impl Default for FilterConfig {
+	fn default() -> Self {
+		Self::nes()
+	}
+}
+
+// First order RC filter
+#[derive(Clone)]
+struct Filter {
+	high_pass: bool,
+	alpha: f32,
+	previous_input: f32,
+	previous_output: f32
+}
+
+impl Filter {
+	fn new(high_pass: bool, cutoff: f32, sample_rate: u32) -> Filter {
+		let rc = 1.0 / (2.0 * PI * cutoff);
+		let dt = 1.0 / sample_rate as f32;
+		let alpha = if high_pass { rc / (rc + dt) } else { dt / (rc + dt) };
+
+		Filter {
+			high_pass,
+			alpha,
+			previous_input: 0.0,
+			previous_output: 0.0
+		}
+	}
+
+	fn process(&mut self, input: f32) -> f32 {
+		let output = if self.high_pass {
+			self.alpha * (self.previous_output + input - self.previous_input)
+		} else {
+			self.previous_output + self.alpha * (input - self.previous_output)
+		};
+
+		self.previous_input = input;
+		self.previous_output = output;
+		output
+	}
+}
+
+#[derive(Clone)]
+pub struct FilterChain {
+	config: FilterConfig,
+	filters: Vec<Filter>
+}
+
+impl FilterChain {
+	pub fn new(config: FilterConfig, sample_rate: u32) -> FilterChain {
+		let stages = [
+			(true, config.high_pass_1),
+			(true, config.high_pass_2),
+			(false, config.low_pass)
+		];
+
+		FilterChain {
+			config,
+			filters: stages.iter()
+				.filter_map(|&(high_pass, cutoff)| cutoff.map(|cutoff| Filter::new(high_pass, cutoff, sample_rate)))
+				.collect()
+		}
+	}
+
+	pub fn config(&self) -> FilterConfig {
+		self.config
+	}
+
+	pub fn process(&mut self, sample: f32) -> f32 {
+		self.filters.iter_mut().fold(sample, |sample, filter| filter.process(sample))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn non_linear_mix() {
+		assert_eq!(mix(0, 0, 0, 0, 0), 0.0);
+
+		// Two pulses at full volume are quieter than twice one
+		let one = mix(15, 0, 0, 0, 0);
+		let two = mix(15, 15, 0, 0, 0);
+		assert!(two < 2.0 * one);
+		assert!((two - 0.2585).abs() < 1e-3);
+	}
+
+	#[test]
+	fn high_pass_removes_dc() {
+		let mut chain = FilterChain::new(FilterConfig::nes(), 44_100);
+		let mut last = 1.0;
+		for _ in 0..44_100 {
+			last = chain.process(1.0);
+		}
+		assert!(last.abs() < 1e-3);
+
+		let mut disabled = FilterChain::new(FilterConfig::disabled(), 44_100);
+		assert_eq!(disabled.process(1.0), 1.0);
+	}
+}
@@ -0,0 +1,529 @@
+pub mod envelope;
+pub mod length_counter;
+pub mod pulse;
+pub mod dmc;
+pub mod frame_counter;
+pub mod blip_buffer;
+pub mod mixer;
+pub mod vrc6;
+pub mod fds;
+pub mod expansion;
+
+use pulse::Pulse;
+use dmc::Dmc;
+use frame_counter::FrameCounter;
+use blip_buffer::BlipBuffer;
+use mixer::{FilterChain, FilterConfig};
+use expansion::ExpansionAudio;
+use crate::region::Region;
+use crate::state::{ensure, StateError, StateReader, StateWriter};
+
+// Of the NTSC console, Region::cpu_frequency has the others
+pub const CPU_FREQUENCY: f64 = 1_789_773.0;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+	Pulse1,
+	Pulse2,
+	Triangle,
+	Noise,
+	Dmc
+}
+
+impl Channel {
+	pub const ALL: [Channel; 5] = [Channel::Pulse1, Channel::Pulse2, Channel::Triangle, Channel::Noise, Channel::Dmc];
+
+	fn mask(self) -> u8 {
+		1 << self as u8
+	}
+
+	// Highest raw level of the channel DAC
+	fn max_level(self) -> f32 {
+		match self {
+			Channel::Dmc => 127.0,
+			_ => 15.0
+		}
+	}
+}
+
+// Serde leaves out the output settings and the expansion audio of the cartridge,
+// a deserialized APU has those of Apu::new
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Apu {
+	pulse1: Pulse,
+	pulse2: Pulse,
+	dmc: Dmc,
+	frame_counter: FrameCounter,
+	region: Region,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	expansion: Option<Box<dyn ExpansionAudio>>,
+	#[cfg_attr(feature = "serde", serde(skip, default = "Apu::default_blip_buffer"))]
+	blip_buffer: BlipBuffer,
+	#[cfg_attr(feature = "serde", serde(skip, default = "Apu::default_filters"))]
+	filters: FilterChain,
+	// Frontend mute toggles, independent from $4015
+	#[cfg_attr(feature = "serde", serde(skip, default = "Apu::all_channels"))]
+	enabled_channels: u8,
+	// Raw level of each channel at the output rate, for visualizers
+	#[cfg_attr(feature = "serde", serde(skip))]
+	channel_capture: bool,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	channel_countdown: f64,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	channel_samples: [Vec<f32>; 5],
+
+	cycle: u64,
+	// The frame counter runs from event to event, the bus schedules `frame_counter_due`
+	frame_counter_synced: u64,
+	frame_counter_due: u64
+}
+
+impl Apu {
+	pub fn new() -> Apu {
+		let mut apu = Apu {
+			pulse1: Pulse::new(true),
+			pulse2: Pulse::new(false),
+			dmc: Dmc::new(),
+			frame_counter: FrameCounter::new(),
+			region: Region::Ntsc,
+			expansion: None,
+			blip_buffer: Apu::default_blip_buffer(),
+			filters: Apu::default_filters(),
+			enabled_channels: Apu::all_channels(),
+			channel_capture: false,
+			channel_countdown: 0.0,
+			channel_samples: Default::default(),
+			cycle: 0,
+			frame_counter_synced: 0,
+			frame_counter_due: 0
+		};
+		apu.sync_frame_counter();
+		apu
+	}
+
+	fn default_blip_buffer() -> BlipBuffer {
+		BlipBuffer::new(CPU_FREQUENCY, DEFAULT_SAMPLE_RATE)
+	}
+
+	fn default_filters() -> FilterChain {
+		FilterChain::new(FilterConfig::nes(), DEFAULT_SAMPLE_RATE)
+	}
+
+	fn all_channels() -> u8 {
+		0x1F
+	}
+
+	// Register at $4000-$4013, $4015 and $4017
+	pub fn write_register(&mut self, adress: u16, value: u8) {
+		match adress {
+			0x4000..=0x4003 => self.pulse1.write(adress - 0x4000, value),
+			0x4004..=0x4007 => self.pulse2.write(adress - 0x4004, value),
+			0x4008..=0x400F => {}, // Channel not emulated yet
+			0x4010..=0x4013 => self.dmc.write(adress - 0x4010, value),
+			0x4015 => {
+				self.pulse1.length_counter.set_enabled(value & 0x01 != 0);
+				self.pulse2.length_counter.set_enabled(value & 0x02 != 0);
+				self.dmc.set_enabled(value & 0x10 != 0);
+			},
+			0x4017 => {
+				self.sync_frame_counter();
+				self.frame_counter.write(value, self.cycle % 2 == 1);
+				self.sync_frame_counter();
+			},
+			_ => panic!("{:#06x} is not an APU register", adress)
+		}
+	}
+
+	// $4015: IF-D NT21, reading acknowledges the frame IRQ
+	pub fn read_status(&mut self) -> u8 {
+		let status = self.peek_status();
+		self.frame_counter.irq = false;
+		status
+	}
+
+	// $4015 without acknowledging the frame IRQ
+	pub fn peek_status(&self) -> u8 {
+		let mut status = 0;
+		if self.pulse1.length_counter.is_active() {
+			status |= 0x01;
+		}
+		if self.pulse2.length_counter.is_active() {
+			status |= 0x02;
+		}
+		if self.dmc.is_active() {
+			status |= 0x10;
+		}
+		if self.frame_counter.irq {
+			status |= 0x40;
+		}
+		if self.dmc.irq {
+			status |= 0x80;
+		}
+		status
+	}
+
+	// Channel registers and sequencers for save states, the output filters are left alone
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		self.pulse1.save_state(state);
+		self.pulse2.save_state(state);
+		self.dmc.save_state(state);
+		self.frame_counter.save_state(state);
+		state.write_bytes(&self.expansion_state());
+		state.write_u64(self.cycle);
+		state.write_u64(self.frame_counter_synced);
+		state.write_u64(self.frame_counter_due);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.pulse1.load_state(state)?;
+		self.pulse2.load_state(state)?;
+		self.dmc.load_state(state)?;
+		self.frame_counter.load_state(state)?;
+		let expansion = state.read_bytes()?;
+		if let Some(audio) = &mut self.expansion {
+			audio.load_state(expansion)?;
+		}
+		self.cycle = state.read_u64()?;
+		self.frame_counter_synced = state.read_u64()?;
+		self.frame_counter_due = state.read_u64()?;
+		// The frame counter is synced before it reaches its next event
+		let elapsed = self.cycle.checked_sub(self.frame_counter_synced);
+		ensure(elapsed.is_some_and(|elapsed| elapsed <= u64::from(self.frame_counter.cycles_to_next_event())), "frame counter sync out of range")?;
+		Ok(())
+	}
+
+	// Cartridge sound channels, empty without expansion audio
+	pub(crate) fn expansion_state(&self) -> Vec<u8> {
+		self.expansion.as_ref().map_or_else(Vec::new, |expansion| expansion.save_state())
+	}
+
+	// Copy of the expansion audio with `data` loaded, for restore
+	#[cfg(feature = "serde")]
+	pub(crate) fn loaded_expansion(&self, data: &[u8]) -> Result<Option<Box<dyn ExpansionAudio>>, StateError> {
+		let mut expansion = self.expansion.clone();
+		if let Some(audio) = &mut expansion {
+			audio.load_state(data)?;
+		}
+		Ok(expansion)
+	}
+
+	// Take over a deserialized APU and the expansion audio of loaded_expansion, keeping the output settings
+	#[cfg(feature = "serde")]
+	pub(crate) fn restore(&mut self, mut state: Apu, expansion: Option<Box<dyn ExpansionAudio>>) {
+		state.expansion = expansion;
+		state.blip_buffer = self.blip_buffer.clone();
+		state.filters = self.filters.clone();
+		state.enabled_channels = self.enabled_channels;
+		state.channel_capture = self.channel_capture;
+		state.channel_countdown = self.channel_countdown;
+		state.channel_samples = std::mem::take(&mut self.channel_samples);
+		*self = state;
+		self.blip_buffer.set_clock_rate(self.region.cpu_frequency());
+	}
+
+	// TV system the APU tables and clock currently follow
+	pub fn region(&self) -> Region {
+		self.region
+	}
+
+	// Frame counter steps, DMC rates and the clock the output is resampled from
+	pub fn set_region(&mut self, region: Region) {
+		self.region = region;
+		self.sync_frame_counter();
+		self.frame_counter.set_region(region);
+		self.sync_frame_counter();
+		self.dmc.set_region(region);
+		self.blip_buffer.set_clock_rate(region.cpu_frequency());
+	}
+
+	// Register the cartridge sound channels in the mix
+	pub fn set_expansion_audio(&mut self, expansion: Option<Box<dyn ExpansionAudio>>) {
+		self.expansion = expansion;
+	}
+
+	pub fn has_expansion_audio(&self) -> bool {
+		self.expansion.is_some()
+	}
+
+	// Cartridge register writes, for expansion audio
+	pub fn write_expansion(&mut self, adress: u16, value: u8) {
+		if let Some(expansion) = &mut self.expansion {
+			expansion.write(adress, value);
+		}
+	}
+
+	// Advance the APU by `cycles` CPU cycles, running the frame counter itself
+	pub fn tick(&mut self, cycles: u16) {
+		for _ in 0..cycles {
+			self.step();
+			if self.cycle >= self.frame_counter_due {
+				self.run_frame_counter();
+			}
+		}
+	}
+
+	// One CPU cycle, without the frame counter that the bus runs as a scheduled event
+	pub fn step(&mut self) {
+		self.dmc.clock_timer();
+
+		// Pulse timers run at half the CPU clock
+		if self.cycle % 2 == 1 {
+			self.pulse1.clock_timer();
+			self.pulse2.clock_timer();
+		}
+
+		if let Some(expansion) = &mut self.expansion {
+			expansion.clock(1);
+		}
+
+		self.blip_buffer.push(self.output());
+		if self.channel_capture {
+			self.capture_channels();
+		}
+		self.cycle += 1;
+	}
+
+	// Frame counter event, due after `frame_counter_countdown` more cycles
+	pub fn run_frame_counter(&mut self) {
+		self.sync_frame_counter();
+	}
+
+	pub fn frame_counter_countdown(&self) -> u64 {
+		self.frame_counter_due.saturating_sub(self.cycle)
+	}
+
+	// Catch the frame counter up with the cycles stepped since the last sync
+	fn sync_frame_counter(&mut self) {
+		let elapsed = (self.cycle - self.frame_counter_synced) as u32;
+		let clock = self.frame_counter.advance(elapsed);
+		self.frame_counter_synced = self.cycle;
+		if clock.quarter {
+			self.clock_quarter_frame();
+		}
+		if clock.half {
+			self.clock_half_frame();
+		}
+		self.frame_counter_due = self.cycle + u64::from(self.frame_counter.cycles_to_next_event());
+	}
+
+	fn clock_quarter_frame(&mut self) {
+		self.pulse1.clock_quarter_frame();
+		self.pulse2.clock_quarter_frame();
+	}
+
+	fn clock_half_frame(&mut self) {
+		self.pulse1.clock_half_frame();
+		self.pulse2.clock_half_frame();
+	}
+
+	// Sample address the DMC needs, the bus must stall the CPU and answer with `dmc_dma_complete`
+	pub fn dmc_dma_request(&self) -> Option<u16> {
+		self.dmc.dma_request()
+	}
+
+	pub fn dmc_dma_complete(&mut self, value: u8) {
+		self.dmc.dma_complete(value);
+	}
+
+	pub fn irq(&self) -> bool {
+		self.dmc.irq || self.frame_counter.irq
+	}
+
+	pub fn set_sample_rate(&mut self, sample_rate: u32) {
+		self.blip_buffer.set_sample_rate(sample_rate);
+		self.filters = FilterChain::new(self.filters.config(), sample_rate);
+	}
+
+	pub fn set_filter_config(&mut self, config: FilterConfig) {
+		self.filters = FilterChain::new(config, self.sample_rate());
+	}
+
+	pub fn filter_config(&self) -> FilterConfig {
+		self.filters.config()
+	}
+
+	pub fn sample_rate(&self) -> u32 {
+		self.blip_buffer.sample_rate()
+	}
+
+	// Samples produced at the output rate since the last call
+	pub fn take_samples(&mut self) -> Vec<f32> {
+		let mut samples = self.blip_buffer.take_samples();
+		for sample in samples.iter_mut() {
+			*sample = self.filters.process(*sample);
+		}
+		samples
+	}
+
+	pub fn set_channel_enabled(&mut self, channel: Channel, enabled: bool) {
+		if enabled {
+			self.enabled_channels |= channel.mask();
+		} else {
+			self.enabled_channels &= !channel.mask();
+		}
+	}
+
+	pub fn is_channel_enabled(&self, channel: Channel) -> bool {
+		self.enabled_channels & channel.mask() != 0
+	}
+
+	// Mute every channel but `channel`
+	pub fn solo_channel(&mut self, channel: Channel) {
+		self.enabled_channels = channel.mask();
+	}
+
+	fn channel_output(&self, channel: Channel) -> u8 {
+		if !self.is_channel_enabled(channel) {
+			return 0;
+		}
+
+		match channel {
+			Channel::Pulse1 => self.pulse1.output(),
+			Channel::Pulse2 => self.pulse2.output(),
+			Channel::Triangle | Channel::Noise => 0, // Channel not emulated yet
+			Channel::Dmc => self.dmc.output()
+		}
+	}
+
+	// Record per-channel streams, retrieved with `take_channel_samples`
+	pub fn set_channel_capture(&mut self, enabled: bool) {
+		self.channel_capture = enabled;
+		if !enabled {
+			self.channel_samples = Default::default();
+		}
+	}
+
+	fn capture_channels(&mut self) {
+		self.channel_countdown -= 1.0;
+		if self.channel_countdown > 0.0 {
+			return;
+		}
+		self.channel_countdown += self.region.cpu_frequency() / f64::from(self.sample_rate());
+
+		for channel in Channel::ALL {
+			let level = f32::from(self.channel_output(channel)) / channel.max_level();
+			self.channel_samples[channel as usize].push(level);
+		}
+	}
+
+	// Channel level between 0.0 and 1.0 at the output rate since the last call
+	pub fn take_channel_samples(&mut self, channel: Channel) -> Vec<f32> {
+		std::mem::take(&mut self.channel_samples[channel as usize])
+	}
+
+	// Mixed output between 0.0 and 1.0, before filtering
+	pub fn output(&self) -> f32 {
+		let internal = mixer::mix(
+			self.channel_output(Channel::Pulse1),
+			self.channel_output(Channel::Pulse2),
+			self.channel_output(Channel::Triangle),
+			self.channel_output(Channel::Noise),
+			self.channel_output(Channel::Dmc)
+		);
+
+		let expansion = self.expansion.as_ref().map_or(0.0, |expansion| expansion.output());
+		internal + expansion
+	}
+}
+
+impl Default for Apu {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn length_counter_half_frames() {
+		let mut apu = Apu::new();
+		apu.write_register(0x4015, 0x01);
+		apu.write_register(0x4000, 0b1001_1111);
+		apu.write_register(0x4003, 0x18); // Length index 3: 2
+
+		assert_eq!(apu.pulse1.length_counter.value(), 2);
+		apu.tick(14913);
+		assert_eq!(apu.pulse1.length_counter.value(), 1);
+		apu.tick(29829 - 14913);
+		assert_eq!(apu.pulse1.length_counter.value(), 0);
+		assert_eq!(apu.output(), 0.0);
+	}
+
+	#[test]
+	fn status_register() {
+		let mut apu = Apu::new();
+		apu.write_register(0x4015, 0x03);
+		apu.write_register(0x4003, 0x08);
+		apu.write_register(0x4007, 0x08);
+		assert_eq!(apu.read_status(), 0x03);
+
+		// Disabling a channel clears its length counter
+		apu.write_register(0x4015, 0x01);
+		assert_eq!(apu.read_status(), 0x01);
+
+		apu.tick(29829);
+		assert_eq!(apu.read_status() & 0x40, 0x40);
+		assert_eq!(apu.read_status() & 0x40, 0x00); // Cleared by read
+	}
+
+	#[test]
+	fn channel_mute() {
+		let mut apu = Apu::new();
+		apu.write_register(0x4015, 0x01);
+		apu.write_register(0x4000, 0b1011_1111); // Constant volume 15
+		apu.write_register(0x4002, 0x08);
+		apu.write_register(0x4003, 0x08);
+		apu.tick(32);
+		assert!(apu.output() > 0.0);
+
+		apu.set_channel_enabled(Channel::Pulse1, false);
+		assert_eq!(apu.output(), 0.0);
+		assert_eq!(apu.read_status() & 0x01, 0x01); // Game still sees the channel playing
+
+		apu.solo_channel(Channel::Dmc);
+		assert!(!apu.is_channel_enabled(Channel::Pulse2));
+		assert!(apu.is_channel_enabled(Channel::Dmc));
+	}
+
+	#[test]
+	fn channel_streams() {
+		let mut apu = Apu::new();
+		apu.write_register(0x4015, 0x01);
+		apu.write_register(0x4000, 0b1011_1111);
+		apu.write_register(0x4002, 0x08);
+		apu.write_register(0x4003, 0x08);
+
+		apu.tick(1000);
+		assert!(apu.take_channel_samples(Channel::Pulse1).is_empty());
+
+		apu.set_channel_capture(true);
+		apu.tick((CPU_FREQUENCY / 100.0) as u16);
+		let pulse1 = apu.take_channel_samples(Channel::Pulse1);
+		assert!((440..=442).contains(&pulse1.len()));
+		assert!(pulse1.contains(&1.0));
+		assert!(apu.take_channel_samples(Channel::Pulse2).iter().all(|&level| level == 0.0));
+	}
+
+	#[test]
+	fn expansion_audio() {
+		#[derive(Clone)]
+		struct Constant(u32);
+		impl ExpansionAudio for Constant {
+			fn clock(&mut self, cycles: u32) {
+				self.0 += cycles;
+			}
+			fn output(&self) -> f32 {
+				0.25
+			}
+		}
+
+		let mut apu = Apu::new();
+		apu.set_expansion_audio(Some(Box::new(Constant(0))));
+		apu.tick(10);
+		assert_eq!(apu.output(), 0.25);
+	}
+}
@@ -0,0 +1,228 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::state::{StateError, StateReader, StateWriter};
+
+static DUTY_TABLE: [[u8; 8]; 4] = [
+	[0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+	[0, 1, 1, 0, 0, 0, 0, 0], // 25%
+	[0, 1, 1, 1, 1, 0, 0, 0], // 50%
+	[1, 0, 0, 1, 1, 1, 1, 1]  // 25% negated
+];
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Sweep {
+	enabled: bool,
+	period: u8,
+	negate: bool,
+	shift: u8,
+	divider: u8,
+	reload: bool
+}
+
+impl Sweep {
+	fn new() -> Sweep {
+		Sweep {
+			enabled: false,
+			period: 0,
+			negate: false,
+			shift: 0,
+			divider: 0,
+			reload: false
+		}
+	}
+
+	// EPPP NSSS
+	fn write(&mut self, value: u8) {
+		self.enabled = value & 0x80 != 0;
+		self.period = (value >> 4) & 0x07;
+		self.negate = value & 0x08 != 0;
+		self.shift = value & 0x07;
+		self.reload = true;
+	}
+
+	fn save_state(&self, state: &mut StateWriter) {
+		state.write_bool(self.enabled);
+		state.write_u8(self.period);
+		state.write_bool(self.negate);
+		state.write_u8(self.shift);
+		state.write_u8(self.divider);
+		state.write_bool(self.reload);
+	}
+
+	fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.enabled = state.read_bool()?;
+		self.period = state.read_u8_in(0..=7)?;
+		self.negate = state.read_bool()?;
+		self.shift = state.read_u8_in(0..=7)?;
+		self.divider = state.read_u8()?;
+		self.reload = state.read_bool()?;
+		Ok(())
+	}
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pulse {
+	// Pulse 1 negates with one's complement, pulse 2 with two's complement
+	ones_complement: bool,
+
+	duty: u8,
+	sequence: u8,
+	timer: u16,
+	timer_period: u16,
+
+	envelope: Envelope,
+	sweep: Sweep,
+	pub length_counter: LengthCounter
+}
+
+impl Pulse {
+	pub fn new(ones_complement: bool) -> Pulse {
+		Pulse {
+			ones_complement,
+			duty: 0,
+			sequence: 0,
+			timer: 0,
+			timer_period: 0,
+			envelope: Envelope::new(),
+			sweep: Sweep::new(),
+			length_counter: LengthCounter::new()
+		}
+	}
+
+	// Register 0 to 3 of the channel
+	pub fn write(&mut self, register: u16, value: u8) {
+		match register {
+			0 => {
+				self.duty = value >> 6;
+				self.length_counter.set_halt(value & 0x20 != 0);
+				self.envelope.write(value);
+			},
+			1 => self.sweep.write(value),
+			2 => self.timer_period = (self.timer_period & 0x0700) | u16::from(value),
+			3 => {
+				self.timer_period = (self.timer_period & 0x00FF) | (u16::from(value & 0x07) << 8);
+				self.length_counter.load(value >> 3);
+				self.sequence = 0;
+				self.envelope.restart();
+			},
+			_ => panic!("Pulse register {} does not exist", register)
+		}
+	}
+
+	// APU cycle (every 2 CPU cycles)
+	pub fn clock_timer(&mut self) {
+		if self.timer == 0 {
+			self.timer = self.timer_period;
+			self.sequence = (self.sequence + 1) % 8;
+		} else {
+			self.timer -= 1;
+		}
+	}
+
+	pub fn clock_quarter_frame(&mut self) {
+		self.envelope.clock();
+	}
+
+	pub fn clock_half_frame(&mut self) {
+		self.length_counter.clock();
+
+		if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.is_sweep_muting() {
+			self.timer_period = self.sweep_target();
+		}
+
+		if self.sweep.divider == 0 || self.sweep.reload {
+			self.sweep.divider = self.sweep.period;
+			self.sweep.reload = false;
+		} else {
+			self.sweep.divider -= 1;
+		}
+	}
+
+	fn sweep_target(&self) -> u16 {
+		let change = self.timer_period >> self.sweep.shift;
+		if !self.sweep.negate {
+			return self.timer_period + change;
+		}
+
+		let change = if self.ones_complement { change + 1 } else { change };
+		self.timer_period.saturating_sub(change)
+	}
+
+	fn is_sweep_muting(&self) -> bool {
+		self.timer_period < 8 || self.sweep_target() > 0x7FF
+	}
+
+	pub fn output(&self) -> u8 {
+		if DUTY_TABLE[usize::from(self.duty)][usize::from(self.sequence)] == 0
+			|| !self.length_counter.is_active() || self.is_sweep_muting() {
+			return 0;
+		}
+
+		self.envelope.output()
+	}
+
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_u8(self.duty);
+		state.write_u8(self.sequence);
+		state.write_u16(self.timer);
+		state.write_u16(self.timer_period);
+		self.envelope.save_state(state);
+		self.sweep.save_state(state);
+		self.length_counter.save_state(state);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.duty = state.read_u8_in(0..=3)?;
+		self.sequence = state.read_u8_in(0..=7)?;
+		self.timer = state.read_u16()?;
+		self.timer_period = state.read_u16_in(0..=0x7FF)?;
+		self.envelope.load_state(state)?;
+		self.sweep.load_state(state)?;
+		self.length_counter.load_state(state)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn sweep_negate() {
+		let mut pulse1 = Pulse::new(true);
+		let mut pulse2 = Pulse::new(false);
+
+		for pulse in [&mut pulse1, &mut pulse2] {
+			pulse.write(2, 0x00);
+			pulse.write(3, 0x01); // Period $100
+			pulse.write(1, 0b1000_1001); // Enabled, period 0, negate, shift 1
+		}
+
+		assert_eq!(pulse1.sweep_target(), 0x7F);
+		assert_eq!(pulse2.sweep_target(), 0x80);
+
+		pulse2.clock_half_frame();
+		assert_eq!(pulse2.timer_period, 0x80);
+	}
+
+	#[test]
+	fn output() {
+		let mut pulse = Pulse::new(false);
+		pulse.length_counter.set_enabled(true);
+		pulse.write(0, 0b1001_1111); // 50%, constant volume 15
+		pulse.write(2, 0x10);
+		pulse.write(3, 0x08); // Length index 1
+
+		// 50% duty starts low, then high for 4 steps
+		assert_eq!(pulse.output(), 0);
+		for _ in 0..=0x10 {
+			pulse.clock_timer();
+		}
+		assert_eq!(pulse.output(), 15);
+
+		pulse.length_counter.set_enabled(false);
+		assert_eq!(pulse.output(), 0);
+	}
+}
@@ -0,0 +1,297 @@
+use super::expansion::ExpansionAudio;
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Level scale close to the 2A03 pulses, VRC6 channels mix linearly
+const OUTPUT_SCALE: f32 = 0.00752;
+
+// Konami VRC6 extra channels, clocked at the CPU rate
+#[derive(Clone)]
+struct Vrc6Pulse {
+	enabled: bool,
+	// Ignore the duty and output the volume constantly
+	digitized: bool,
+	duty: u8,
+	volume: u8,
+	step: u8,
+	timer: u16,
+	timer_period: u16
+}
+
+impl Vrc6Pulse {
+	fn new() -> Vrc6Pulse {
+		Vrc6Pulse {
+			enabled: false,
+			digitized: false,
+			duty: 0,
+			volume: 0,
+			step: 15,
+			timer: 0,
+			timer_period: 0
+		}
+	}
+
+	fn write(&mut self, register: u16, value: u8) {
+		match register {
+			0 => {
+				self.digitized = value & 0x80 != 0;
+				self.duty = (value >> 4) & 0x07;
+				self.volume = value & 0x0F;
+			},
+			1 => self.timer_period = (self.timer_period & 0x0F00) | u16::from(value),
+			2 => {
+				self.timer_period = (self.timer_period & 0x00FF) | (u16::from(value & 0x0F) << 8);
+				self.enabled = value & 0x80 != 0;
+				if !self.enabled {
+					self.step = 15;
+				}
+			},
+			_ => panic!("VRC6 pulse register {} does not exist", register)
+		}
+	}
+
+	fn clock_timer(&mut self, shift: u8) {
+		if !self.enabled {
+			return;
+		}
+
+		if self.timer == 0 {
+			self.timer = self.timer_period >> shift;
+			self.step = if self.step == 0 { 15 } else { self.step - 1 };
+		} else {
+			self.timer -= 1;
+		}
+	}
+
+	fn output(&self) -> u8 {
+		if self.enabled && (self.digitized || self.step <= self.duty) {
+			self.volume
+		} else {
+			0
+		}
+	}
+
+	fn save_state(&self, state: &mut StateWriter) {
+		state.write_bool(self.enabled);
+		state.write_bool(self.digitized);
+		state.write_u8(self.duty);
+		state.write_u8(self.volume);
+		state.write_u8(self.step);
+		state.write_u16(self.timer);
+		state.write_u16(self.timer_period);
+	}
+
+	fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.enabled = state.read_bool()?;
+		self.digitized = state.read_bool()?;
+		self.duty = state.read_u8_in(0..=7)?;
+		self.volume = state.read_u8_in(0..=15)?;
+		self.step = state.read_u8_in(0..=15)?;
+		self.timer = state.read_u16()?;
+		self.timer_period = state.read_u16()?;
+		Ok(())
+	}
+}
+
+#[derive(Clone)]
+struct Sawtooth {
+	enabled: bool,
+	rate: u8,
+	accumulator: u8,
+	step: u8,
+	timer: u16,
+	timer_period: u16
+}
+
+impl Sawtooth {
+	fn new() -> Sawtooth {
+		Sawtooth {
+			enabled: false,
+			rate: 0,
+			accumulator: 0,
+			step: 0,
+			timer: 0,
+			timer_period: 0
+		}
+	}
+
+	fn write(&mut self, register: u16, value: u8) {
+		match register {
+			0 => self.rate = value & 0x3F,
+			1 => self.timer_period = (self.timer_period & 0x0F00) | u16::from(value),
+			2 => {
+				self.timer_period = (self.timer_period & 0x00FF) | (u16::from(value & 0x0F) << 8);
+				self.enabled = value & 0x80 != 0;
+				if !self.enabled {
+					self.accumulator = 0;
+					self.step = 0;
+				}
+			},
+			_ => panic!("VRC6 sawtooth register {} does not exist", register)
+		}
+	}
+
+	fn clock_timer(&mut self, shift: u8) {
+		if !self.enabled {
+			return;
+		}
+
+		if self.timer > 0 {
+			self.timer -= 1;
+			return;
+		}
+		self.timer = self.timer_period >> shift;
+
+		// The accumulator adds the rate every other clock and resets after 7 additions
+		self.step += 1;
+		if self.step == 14 {
+			self.step = 0;
+			self.accumulator = 0;
+		} else if self.step & 1 == 0 {
+			self.accumulator = self.accumulator.wrapping_add(self.rate);
+		}
+	}
+
+	fn output(&self) -> u8 {
+		if self.enabled { self.accumulator >> 3 } else { 0 }
+	}
+
+	fn save_state(&self, state: &mut StateWriter) {
+		state.write_bool(self.enabled);
+		state.write_u8(self.rate);
+		state.write_u8(self.accumulator);
+		state.write_u8(self.step);
+		state.write_u16(self.timer);
+		state.write_u16(self.timer_period);
+	}
+
+	fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.enabled = state.read_bool()?;
+		self.rate = state.read_u8_in(0..=0x3F)?;
+		self.accumulator = state.read_u8()?;
+		self.step = state.read_u8_in(0..=13)?;
+		self.timer = state.read_u16()?;
+		self.timer_period = state.read_u16()?;
+		Ok(())
+	}
+}
+
+#[derive(Clone)]
+pub struct Vrc6Audio {
+	pulse1: Vrc6Pulse,
+	pulse2: Vrc6Pulse,
+	sawtooth: Sawtooth,
+
+	halt: bool,
+	shift: u8
+}
+
+impl Vrc6Audio {
+	pub fn new() -> Vrc6Audio {
+		Vrc6Audio {
+			pulse1: Vrc6Pulse::new(),
+			pulse2: Vrc6Pulse::new(),
+			sawtooth: Sawtooth::new(),
+			halt: false,
+			shift: 0
+		}
+	}
+
+	// Raw DAC level, 0 to 61
+	pub fn level(&self) -> u8 {
+		self.pulse1.output() + self.pulse2.output() + self.sawtooth.output()
+	}
+
+	fn step(&mut self) {
+		if self.halt {
+			return;
+		}
+
+		self.pulse1.clock_timer(self.shift);
+		self.pulse2.clock_timer(self.shift);
+		self.sawtooth.clock_timer(self.shift);
+	}
+
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		self.pulse1.save_state(state);
+		self.pulse2.save_state(state);
+		self.sawtooth.save_state(state);
+		state.write_bool(self.halt);
+		state.write_u8(self.shift);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.pulse1.load_state(state)?;
+		self.pulse2.load_state(state)?;
+		self.sawtooth.load_state(state)?;
+		self.halt = state.read_bool()?;
+		self.shift = state.read_u8_in(0..=8)?;
+		Ok(())
+	}
+}
+
+impl ExpansionAudio for Vrc6Audio {
+	fn clock(&mut self, cycles: u32) {
+		for _ in 0..cycles {
+			self.step();
+		}
+	}
+
+	fn output(&self) -> f32 {
+		f32::from(self.level()) * OUTPUT_SCALE
+	}
+
+	// Register at $9000-$9003, $A000-$A002 and $B000-$B002, with mapper 26 lines already swapped by the board
+	fn write(&mut self, adress: u16, value: u8) {
+		match adress {
+			0x9000..=0x9002 => self.pulse1.write(adress - 0x9000, value),
+			0x9003 => {
+				self.halt = value & 0x01 != 0;
+				self.shift = if value & 0x04 != 0 { 8 } else if value & 0x02 != 0 { 4 } else { 0 };
+			},
+			0xA000..=0xA002 => self.pulse2.write(adress - 0xA000, value),
+			0xB000..=0xB002 => self.sawtooth.write(adress - 0xB000, value),
+			_ => {}
+		}
+	}
+}
+
+impl Default for Vrc6Audio {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn pulse_duty() {
+		let mut vrc6 = Vrc6Audio::new();
+		vrc6.write(0x9000, 0x7F); // Duty 8/16, volume 15
+		vrc6.write(0x9001, 0x00);
+		vrc6.write(0x9002, 0x80);
+
+		let high = (0..16).filter(|_| {
+			vrc6.clock(1);
+			vrc6.level() == 15
+		}).count();
+		assert_eq!(high, 8);
+
+		vrc6.write(0x9002, 0x00);
+		assert_eq!(vrc6.level(), 0);
+	}
+
+	#[test]
+	fn sawtooth_ramp() {
+		let mut vrc6 = Vrc6Audio::new();
+		vrc6.write(0xB000, 0x08);
+		vrc6.write(0xB002, 0x80);
+
+		let levels: Vec<u8> = (0..14).map(|_| {
+			vrc6.clock(1);
+			vrc6.level()
+		}).collect();
+		assert_eq!(levels, [0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0]);
+	}
+}
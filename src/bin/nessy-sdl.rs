@@ -0,0 +1,196 @@
+// SDL2 frontend: nessy-sdl <rom> [config.toml]
+//
+// Options are read from nessy.toml unless another config is given. The default keys are
+// arrows, Z (B), X (A), Right Shift (Select) and Enter (Start), or a game controller.
+// F1 resets, F5/F7 save and load the state slot picked with 0-9, P pauses,
+// N advances one frame while paused, Tab fast-forwards while held
+use std::env;
+use std::process;
+
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::{self, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+
+use nessy::config::Config;
+use nessy::frame_timer::{FrameTimer, Speed};
+use nessy::joypad::Button;
+use nessy::nes::Nes;
+use nessy::ppu::frame::{HEIGHT, WIDTH};
+use nessy::state_manager::StateManager;
+
+const CONFIG: &str = "nessy.toml";
+const SCALE: u32 = 3;
+const FAST_FORWARD: f64 = 4.0;
+
+// Bound key of either player, by SDL key name
+fn keyboard_button(config: &Config, key: Keycode) -> Option<(u8, Button)> {
+	let name = key.name();
+	config.input.player1.button(&name).map(|button| (1, button))
+		.or_else(|| config.input.player2.button(&name).map(|button| (2, button)))
+}
+
+fn set_button(nes: &mut Nes, player: u8, button: Button, pressed: bool) {
+	if player == 1 {
+		nes.set_button(button, pressed);
+	} else {
+		nes.set_button2(button, pressed);
+	}
+}
+
+// Face buttons in the NES layout: B on the left, A on the right
+fn controller_button(button: controller::Button) -> Option<Button> {
+	match button {
+		controller::Button::B => Some(Button::A),
+		controller::Button::A => Some(Button::B),
+		controller::Button::Back => Some(Button::Select),
+		controller::Button::Start => Some(Button::Start),
+		controller::Button::DPadUp => Some(Button::Up),
+		controller::Button::DPadDown => Some(Button::Down),
+		controller::Button::DPadLeft => Some(Button::Left),
+		controller::Button::DPadRight => Some(Button::Right),
+		_ => None
+	}
+}
+
+fn slot_key(key: Keycode) -> Option<u8> {
+	let keys = [
+		Keycode::NUM_0, Keycode::NUM_1, Keycode::NUM_2, Keycode::NUM_3, Keycode::NUM_4,
+		Keycode::NUM_5, Keycode::NUM_6, Keycode::NUM_7, Keycode::NUM_8, Keycode::NUM_9
+	];
+	keys.iter().position(|&slot| slot == key).map(|slot| slot as u8)
+}
+
+fn run(path: &str, config_path: &str) -> Result<(), String> {
+	let config = Config::load_or_default(config_path).map_err(|error| error.to_string())?;
+	let mut nes = Nes::from_path(path).map_err(|error| format!("{}: {}", path, error))?;
+	config.apply(&mut nes).map_err(|error| error.to_string())?;
+	// Audio queued ahead before frames are dropped from it, about 100ms of f32 samples
+	let max_queued_bytes = config.audio.sample_rate / 10 * 4;
+	let states = StateManager::new("states", &nes);
+	let mut slot = 0;
+
+	let sdl = sdl2::init()?;
+	let video = sdl.video()?;
+	let window = video.window("nessy", WIDTH as u32 * SCALE, HEIGHT as u32 * SCALE)
+		.position_centered()
+		.resizable()
+		.build()
+		.map_err(|error| error.to_string())?;
+	let mut canvas = window.into_canvas().build().map_err(|error| error.to_string())?;
+	canvas.set_logical_size(WIDTH as u32, HEIGHT as u32).map_err(|error| error.to_string())?;
+	let texture_creator = canvas.texture_creator();
+	let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, WIDTH as u32, HEIGHT as u32)
+		.map_err(|error| error.to_string())?;
+
+	let audio = sdl.audio()?;
+	let spec = AudioSpecDesired {
+		freq: Some(config.audio.sample_rate as i32),
+		channels: Some(1),
+		samples: Some(1024)
+	};
+	let queue: AudioQueue<f32> = audio.open_queue(None, &spec)?;
+	queue.resume();
+
+	let game_controller = sdl.game_controller()?;
+	// Kept open for their events
+	let mut controllers: Vec<GameController> = Vec::new();
+
+	let mut timer = FrameTimer::for_region(nes.region());
+	let mut events = sdl.event_pump()?;
+
+	'running: loop {
+		for event in events.poll_iter() {
+			match event {
+				Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::ESCAPE), .. } => break 'running,
+				Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+					if let Some((player, button)) = keyboard_button(&config, key) {
+						set_button(&mut nes, player, button, true);
+					} else if let Some(number) = slot_key(key) {
+						slot = number;
+						println!("State slot {}", slot);
+					} else {
+						match key {
+							Keycode::F1 => nes.reset(),
+							Keycode::F5 => match states.save(&nes, slot) {
+								Ok(()) => println!("Saved slot {}", slot),
+								Err(error) => eprintln!("Could not save slot {}: {}", slot, error)
+							},
+							Keycode::F7 => match states.load(&mut nes, slot) {
+								Ok(()) => println!("Loaded slot {}", slot),
+								Err(error) => eprintln!("Could not load slot {}: {}", slot, error)
+							},
+							Keycode::P if nes.is_paused() => {
+								nes.resume();
+								timer.reset();
+							},
+							Keycode::P => {
+								nes.pause();
+								queue.clear();
+							},
+							Keycode::N => {
+								nes.frame_advance();
+							},
+							Keycode::TAB => timer.set_speed(Speed::Scaled(FAST_FORWARD)),
+							_ => {}
+						}
+					}
+				},
+				Event::KeyUp { keycode: Some(key), .. } => {
+					if let Some((player, button)) = keyboard_button(&config, key) {
+						set_button(&mut nes, player, button, false);
+					} else if key == Keycode::TAB {
+						timer.set_speed(Speed::NORMAL);
+					}
+				},
+				Event::ControllerDeviceAdded { which, .. } => {
+					match game_controller.open(which) {
+						Ok(controller) => controllers.push(controller),
+						Err(error) => eprintln!("Could not open controller {}: {}", which, error)
+					}
+				},
+				Event::ControllerButtonDown { button, .. } => {
+					if let Some(button) = controller_button(button) {
+						nes.set_button(button, true);
+					}
+				},
+				Event::ControllerButtonUp { button, .. } => {
+					if let Some(button) = controller_button(button) {
+						nes.set_button(button, false);
+					}
+				},
+				_ => {}
+			}
+		}
+
+		let frame = nes.run_frame();
+		texture.update(None, &frame.data, frame.width * 3).map_err(|error| error.to_string())?;
+		canvas.clear();
+		canvas.copy(&texture, None, None)?;
+		canvas.present();
+
+		// Fast-forwarded audio is dropped instead of piling up
+		let samples = nes.audio_samples();
+		if queue.size() < max_queued_bytes {
+			queue.queue_audio(&samples)?;
+		}
+
+		timer.wait();
+	}
+
+	Ok(())
+}
+
+fn main() {
+	let Some(path) = env::args().nth(1) else {
+		eprintln!("Usage: nessy-sdl <rom> [config.toml]");
+		process::exit(2);
+	};
+	let config = env::args().nth(2).unwrap_or_else(|| String::from(CONFIG));
+
+	if let Err(error) = run(&path, &config) {
+		eprintln!("{}", error);
+		process::exit(1);
+	}
+}
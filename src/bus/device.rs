@@ -0,0 +1,34 @@
+use std::ops::RangeInclusive;
+
+// Hardware attached to the CPU bus, answering before the console address decode
+pub trait BusDevice: BusDeviceClone {
+	// CPU addresses decoded by the device
+	fn range(&self) -> RangeInclusive<u16>;
+
+	fn read(&mut self, adress: u16) -> u8;
+	fn write(&mut self, adress: u16, value: u8);
+
+	// Read without side effects, for debuggers, None leaves the bus floating
+	fn peek(&self, _adress: u16) -> Option<u8> {
+		None
+	}
+
+	// One CPU cycle
+	fn tick(&mut self) {}
+}
+
+pub trait BusDeviceClone {
+	fn clone_box(&self) -> Box<dyn BusDevice>;
+}
+
+impl<T: BusDevice + Clone + 'static> BusDeviceClone for T {
+	fn clone_box(&self) -> Box<dyn BusDevice> {
+		Box::new(self.clone())
+	}
+}
+
+impl Clone for Box<dyn BusDevice> {
+	fn clone(&self) -> Self {
+		self.clone_box()
+	}
+}
@@ -0,0 +1,1422 @@
+pub mod device;
+pub mod scheduler;
+pub mod watch;
+
+use std::any::Any;
+use std::fmt;
+
+use crate::{rom::{Rom, info::RomInfo}, mapper::Mapper, cheats::Cheats, debugger::cdl::{self, CodeDataLog}, state::{ensure, StateError, StateReader, StateWriter}, region::Region, ppu::Ppu, ppu::frame::Frame, apu::Apu, joypad::Joypad, input::{InputDevice, Port, Unplugged}};
+use device::BusDevice;
+use scheduler::{BusEvent, Interrupt, Scheduler};
+use watch::{WatchEvent, WatchId, WatchKind, Watchpoints};
+
+const RAM: u16 = 0x0000;
+const RAM_MIRROR_END: u16 = 0x1FFF;
+const PPU: u16 = 0x2000;
+const PPU_MIRROR_END: u16 = 0x3FFF;
+const CARTRIDGE: u16 = 0x4020;
+const PRG_RAM: u16 = 0x6000;
+const PRG_RAM_END: u16 = 0x7FFF;
+const CARTRIDGE_END: u16 = 0xFFFF;
+
+const OAM_DMA_CYCLES: u16 = 513;
+const DMC_DMA_CYCLES: u16 = 4;
+// DMC fetch landing during an OAM DMA only steals the cycles to realign
+const DMC_DMA_CYCLES_DURING_OAM: u16 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusError {
+	// Nothing is mapped at the address
+	Unmapped(u16),
+	// Read of a register that can only be written
+	WriteOnly(u16),
+	// Write to a register that can only be read
+	ReadOnly(u16)
+}
+
+impl fmt::Display for BusError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			BusError::Unmapped(adress) => write!(f, "{:#06x} is not mapped", adress),
+			BusError::WriteOnly(adress) => write!(f, "{:#06x} is write-only", adress),
+			BusError::ReadOnly(adress) => write!(f, "{:#06x} is read-only", adress)
+		}
+	}
+}
+
+impl std::error::Error for BusError {}
+
+// How `read` and `write` treat suspicious accesses, `try_read`/`try_write` always report them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusConfig {
+	// Panic on accesses where nothing is mapped instead of open bus / ignored writes
+	pub strict_unmapped: bool,
+	// Panic on reads of write-only registers instead of open bus, and on writes to read-only ones
+	pub strict_write_only: bool
+}
+
+impl BusConfig {
+	// Real hardware behavior, for playing games
+	pub fn permissive() -> BusConfig {
+		BusConfig {
+			strict_unmapped: false,
+			strict_write_only: false
+		}
+	}
+
+	// Catch stray accesses while developing homebrew
+	pub fn strict() -> BusConfig {
+		BusConfig {
+			strict_unmapped: true,
+			strict_write_only: true
+		}
+	}
+
+	fn is_fatal(&self, error: &BusError) -> bool {
+		match error {
+			BusError::Unmapped(_) => self.strict_unmapped,
+			BusError::WriteOnly(_) | BusError::ReadOnly(_) => self.strict_write_only
+		}
+	}
+}
+
+impl Default for BusConfig {
+	fn default() -> Self {
+		Self::permissive()
+	}
+}
+
+#[derive(Clone)]
+pub struct Bus {
+	cpu_ram: [u8; 2048],
+	rom: Rom,
+	ppu: Ppu,
+	apu: Apu,
+	port1: Box<dyn InputDevice>,
+	port2: Box<dyn InputDevice>,
+	expansion_port: Box<dyn InputDevice>,
+	prg_ram: Vec<u8>,
+	devices: Vec<Box<dyn BusDevice>>,
+	config: BusConfig,
+
+	// Last value driven on the CPU data bus
+	open_bus: u8,
+
+	watchpoints: Watchpoints,
+	// CPU accesses since the last take_accesses, when enabled
+	access_log: Option<Vec<WatchEvent>>,
+	// Address of the instruction being executed, reported to watchpoints
+	current_pc: u16,
+
+	// Patches applied to CPU reads
+	cheats: Cheats,
+
+	// PRG ROM accesses, when enabled
+	code_data_log: Option<CodeDataLog>,
+	// Bytes of the current instruction, its reads are code and not data
+	instruction_length: u8,
+	// Flags given to the data reads of the current instruction
+	data_flags: u8,
+
+	// CPU cycles since power on
+	cycle: u64,
+	scheduler: Scheduler,
+	// First cycle after the running OAM DMA
+	oam_dma_end: u64,
+	stalled: u16,
+
+	region: Region,
+	// Master clock ticks since power on, the scheduler timeline
+	master_clock: u64,
+	// Master clock tick the PPU caught up to, under one dot behind
+	ppu_clock: u64
+}
+
+// Console state of a Bus, the ROM and the plugged devices are not part of it.
+// The mapper registers and PRG RAM are kept in the save state layout
+#[cfg(feature = "serde")]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct BusState {
+	pub cpu_ram: Vec<u8>,
+	pub open_bus: u8,
+	pub cycle: u64,
+	pub scheduler: Scheduler,
+	pub oam_dma_end: u64,
+	pub stalled: u16,
+	pub region: Region,
+	pub master_clock: u64,
+	pub ppu_clock: u64,
+	pub ppu: Ppu,
+	pub apu: Apu,
+	pub expansion_audio: Vec<u8>,
+	pub cartridge: Vec<u8>
+}
+
+// Serialized as its BusState, deserialize a BusState and apply it to a Bus of the same ROM
+#[cfg(feature = "serde")]
+impl serde::Serialize for Bus {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		self.to_state().serialize(serializer)
+	}
+}
+
+impl Bus {
+	// Region of the ROM header, NTSC when the header does not say
+	pub fn new(rom: Rom) -> Bus {
+		let ppu = Ppu::new(rom.mapper.mirroring().unwrap_or(rom.mirroring));
+		let prg_ram = vec![0; rom.prg_ram_size];
+		let mut apu = Apu::new();
+		apu.set_expansion_audio(rom.mapper.expansion_audio());
+		let mut bus = Bus {
+			cpu_ram: [0; 2048],
+			rom,
+			ppu,
+			apu,
+			port1: Box::new(Joypad::new()),
+			port2: Box::new(Joypad::new()),
+			expansion_port: Box::new(Unplugged),
+			prg_ram,
+			devices: Vec::new(),
+			config: BusConfig::default(),
+			open_bus: 0,
+			watchpoints: Watchpoints::new(),
+			access_log: None,
+			current_pc: 0,
+			cheats: Cheats::new(),
+			code_data_log: None,
+			instruction_length: 1,
+			data_flags: cdl::DATA,
+			cycle: 0,
+			scheduler: Scheduler::new(),
+			oam_dma_end: 0,
+			stalled: 0,
+			region: Region::Ntsc,
+			master_clock: 0,
+			ppu_clock: 0
+		};
+		if let Some(info) = bus.rom.info() {
+			bus.set_region(Region::from_timing(info.timing));
+		}
+		bus.schedule_frame_counter();
+		bus
+	}
+
+	pub fn with_config(rom: Rom, config: BusConfig) -> Bus {
+		let mut bus = Bus::new(rom);
+		bus.config = config;
+		bus
+	}
+
+	pub fn region(&self) -> Region {
+		self.region
+	}
+
+	// Clock ratios, frame length and APU tables, set before the first frame
+	pub fn set_region(&mut self, region: Region) {
+		self.region = region;
+		self.ppu.set_region(region);
+		self.apu.set_region(region);
+		self.schedule_frame_counter();
+	}
+
+	pub fn config(&self) -> BusConfig {
+		self.config
+	}
+
+	pub fn set_config(&mut self, config: BusConfig) {
+		self.config = config;
+	}
+
+	// Unmapped reads see open bus, unless the config is strict
+	pub fn read(&mut self, adress: u16) -> u8 {
+		match self.try_read(adress) {
+			Ok(value) => value,
+			Err(error) if self.config.is_fatal(&error) => panic!("{}", error),
+			Err(_) => self.open_bus
+		}
+	}
+
+	// Reports accesses to unmapped or write-only addresses, for test harnesses
+	pub fn try_read(&mut self, adress: u16) -> Result<u8, BusError> {
+		let mut value = self.decode_read(adress)?;
+		if !self.cheats.is_empty() {
+			value = self.cheats.apply(adress, value);
+		}
+		self.open_bus = value;
+
+		if !self.watchpoints.is_empty() {
+			self.notify_watch(WatchKind::Read, adress, value, value);
+		}
+		if self.access_log.is_some() {
+			self.log_access(WatchKind::Read, adress, value, value);
+		}
+		if self.code_data_log.is_some() && adress.wrapping_sub(self.current_pc) >= u16::from(self.instruction_length) {
+			self.log_prg(adress, self.data_flags);
+		}
+		Ok(value)
+	}
+
+	fn decode_read(&mut self, adress: u16) -> Result<u8, BusError> {
+		if let Some(device) = self.device_at(adress) {
+			return Ok(device.read(adress));
+		}
+
+		match adress {
+			RAM..=RAM_MIRROR_END => {
+				Ok(self.cpu_ram[usize::from(adress & 0x07FF)])
+			},
+			PPU..=PPU_MIRROR_END => {
+				let mirror_down_addr = adress & 0x2007;
+				Ok(self.ppu.read_register(&self.rom, mirror_down_addr))
+			},
+			// Bit 5 is not driven
+			0x4015 => Ok(self.apu.read_status() | (self.open_bus & 0x20)),
+			0x4000..=0x4014 => Err(BusError::WriteOnly(adress)),
+			// Upper bits of the controller ports are not driven
+			0x4016 => {
+				self.port1.observe(&self.ppu);
+				Ok(self.port1.read() | (self.open_bus & 0xE0))
+			},
+			0x4017 => {
+				self.port2.observe(&self.ppu);
+				self.expansion_port.observe(&self.ppu);
+				Ok(self.port2.read() | self.expansion_port.read() | (self.open_bus & 0xE0))
+			},
+			PRG_RAM..=PRG_RAM_END if self.has_prg_ram() => {
+				Ok(self.prg_ram[self.prg_ram_index(adress)])
+			},
+			CARTRIDGE..=CARTRIDGE_END => {
+				Ok(self.rom.mapper.cpu_read(adress))
+			},
+			_ => Err(BusError::Unmapped(adress))
+		}
+	}
+
+	// Read without side effects: no register acknowledge, no open bus update
+	pub fn peek(&self, adress: u16) -> u8 {
+		if let Some(device) = self.devices.iter().find(|device| device.range().contains(&adress)) {
+			return device.peek(adress).unwrap_or(self.open_bus);
+		}
+
+		match adress {
+			RAM..=RAM_MIRROR_END => self.cpu_ram[usize::from(adress & 0x07FF)],
+			PPU..=PPU_MIRROR_END => self.ppu.peek_register(adress & 0x2007),
+			0x4015 => self.apu.peek_status() | (self.open_bus & 0x20),
+			0x4016 => self.port1.peek() | (self.open_bus & 0xE0),
+			0x4017 => self.port2.peek() | self.expansion_port.peek() | (self.open_bus & 0xE0),
+			PRG_RAM..=PRG_RAM_END if self.has_prg_ram() => self.prg_ram[self.prg_ram_index(adress)],
+			CARTRIDGE..=CARTRIDGE_END => self.rom.mapper.cpu_read(adress),
+			_ => self.open_bus
+		}
+	}
+
+	pub fn peek_u16(&self, adress: u16) -> u16 {
+		u16::from(self.peek(adress)) | (u16::from(self.peek(adress.wrapping_add(1))) << 8)
+	}
+
+	// Write memory for debuggers and cheats, I/O registers are left untouched
+	pub fn poke(&mut self, adress: u16, value: u8) {
+		if let Some(device) = self.device_at(adress) {
+			device.write(adress, value);
+			return;
+		}
+
+		match adress {
+			RAM..=RAM_MIRROR_END => self.cpu_ram[usize::from(adress & 0x07FF)] = value,
+			PRG_RAM..=PRG_RAM_END if !self.prg_ram.is_empty() => {
+				let index = self.prg_ram_index(adress);
+				self.prg_ram[index] = value;
+			},
+			// Mapper registers and memory
+			CARTRIDGE..=CARTRIDGE_END => self.rom.mapper.cpu_write(adress, value),
+			_ => {}
+		}
+	}
+
+	// Peek of `start..=end`, inclusive so the range can reach $FFFF
+	pub fn dump_range(&self, start: u16, end: u16) -> Vec<u8> {
+		(start..=end).map(|adress| self.peek(adress)).collect()
+	}
+
+	// Poke of `data` from `start`, stops at $FFFF
+	pub fn load_range(&mut self, start: u16, data: &[u8]) {
+		for (adress, &value) in (start..=0xFFFF).zip(data.iter()) {
+			self.poke(adress, value);
+		}
+	}
+
+	pub fn read_u16(&mut self, adress: u16) -> u16 {
+		let low = self.read(adress) as u16;
+		let high = self.read(adress + 1) as u16;
+		
+		(high << 8) | low
+	}
+
+	// Unmapped writes are ignored, unless the config is strict
+	pub fn write(&mut self, adress: u16, value: u8) {
+		if let Err(error) = self.try_write(adress, value) {
+			if self.config.is_fatal(&error) {
+				panic!("{}", error);
+			}
+		}
+	}
+
+	pub fn try_write(&mut self, adress: u16, value: u8) -> Result<(), BusError> {
+		if !self.watchpoints.is_empty() && self.watchpoints.is_watched(WatchKind::Write, adress) {
+			let old_value = self.peek(adress);
+			self.notify_watch(WatchKind::Write, adress, old_value, value);
+		}
+		if self.access_log.is_some() {
+			let old_value = self.peek(adress);
+			self.log_access(WatchKind::Write, adress, old_value, value);
+		}
+
+		self.open_bus = value;
+
+		let value = if self.cheats.is_empty() { value } else { self.cheats.write(adress, value) };
+		if let Some(device) = self.device_at(adress) {
+			device.write(adress, value);
+			return Ok(());
+		}
+
+		match adress {
+			RAM..=RAM_MIRROR_END => {
+				self.cpu_ram[usize::from(adress & 0x07FF)] = value;
+			},
+			PPU..=PPU_MIRROR_END => {
+				let mirror_down_addr = adress & 0x2007;
+				self.ppu.write_register(&mut self.rom, mirror_down_addr, value);
+				// PPUSTATUS ignores it, but the PPU data latch took the value
+				if mirror_down_addr == 0x2002 {
+					return Err(BusError::ReadOnly(adress));
+				}
+			},
+			0x4000..=0x4013 | 0x4015 => self.apu.write_register(adress, value),
+			0x4017 => {
+				self.apu.write_register(adress, value);
+				self.schedule_frame_counter();
+			},
+			// The DMA starts once the write cycle is over
+			0x4014 => {
+				let tick = self.master_clock + u64::from(self.region.cpu_divider());
+				self.scheduler.schedule(tick, BusEvent::OamDma(value));
+			},
+			// The strobe line is shared by both ports
+			0x4016 => {
+				self.port1.write(value);
+				self.port2.write(value);
+				self.expansion_port.write(value);
+			},
+			PRG_RAM..=PRG_RAM_END if self.has_prg_ram() => {
+				let index = self.prg_ram_index(adress);
+				self.prg_ram[index] = value;
+			},
+			CARTRIDGE..=CARTRIDGE_END => {
+				// Without conflict prevention the ROM drives the data bus at the same time
+				let value = if adress >= 0x8000 && self.rom.mapper.bus_conflicts() {
+					value & self.rom.mapper.cpu_read(adress)
+				} else {
+					value
+				};
+
+				self.rom.mapper.cpu_write(adress, value);
+				self.apu.write_expansion(adress, value);
+				if let Some(mirroring) = self.rom.mapper.mirroring() {
+					self.ppu.set_mirroring(mirroring);
+				}
+			},
+			_ => return Err(BusError::Unmapped(adress))
+		}
+
+		Ok(())
+	}
+
+	pub fn write_u16(&mut self, adress: u16, value: u16) {
+		let low = (value & 0x00FF) as u8;
+		let high = (value >> 8) as u8;
+		
+		self.write(adress, low);
+		self.write(adress + 1, high);
+	}
+
+	// Call `callback` on every CPU write to `adress`, RAM mirrors included
+	pub fn watch_write<F: FnMut(&WatchEvent) + 'static>(&mut self, adress: u16, callback: F) -> WatchId {
+		self.watchpoints.add(WatchKind::Write, adress, Box::new(callback))
+	}
+
+	pub fn watch_read<F: FnMut(&WatchEvent) + 'static>(&mut self, adress: u16, callback: F) -> WatchId {
+		self.watchpoints.add(WatchKind::Read, adress, Box::new(callback))
+	}
+
+	pub fn unwatch(&mut self, id: WatchId) -> bool {
+		self.watchpoints.remove(id)
+	}
+
+	pub fn clear_watchpoints(&mut self) {
+		self.watchpoints.clear();
+	}
+
+	// Record every CPU read and write, for debuggers checking them after each instruction
+	pub fn set_access_log(&mut self, enabled: bool) {
+		self.access_log = if enabled { Some(self.access_log.take().unwrap_or_default()) } else { None };
+	}
+
+	pub fn take_accesses(&mut self) -> Vec<WatchEvent> {
+		self.access_log.as_mut().map(std::mem::take).unwrap_or_default()
+	}
+
+	fn log_access(&mut self, kind: WatchKind, adress: u16, old_value: u8, new_value: u8) {
+		let event = WatchEvent { kind, adress, old_value, new_value, pc: self.current_pc };
+		if let Some(log) = &mut self.access_log {
+			log.push(event);
+		}
+	}
+
+	fn notify_watch(&mut self, kind: WatchKind, adress: u16, old_value: u8, new_value: u8) {
+		self.watchpoints.notify(&WatchEvent {
+			kind,
+			adress,
+			old_value,
+			new_value,
+			pc: self.current_pc
+		});
+	}
+
+	pub fn cheats(&self) -> &Cheats {
+		&self.cheats
+	}
+
+	pub fn cheats_mut(&mut self) -> &mut Cheats {
+		&mut self.cheats
+	}
+
+	// Puts the frozen values back in RAM, for bytes changed behind the CPU such as by DMA.
+	// Stored without a bus write, so no watchpoint, log or mapper register sees it.
+	// Frozen addresses outside RAM are only patched on reads
+	pub fn apply_frozen(&mut self) {
+		let frozen: Vec<(u16, u8)> = self.cheats.frozen().map(|cheat| (cheat.adress, cheat.value)).collect();
+		for (adress, value) in frozen {
+			match adress {
+				RAM..=RAM_MIRROR_END => self.cpu_ram[usize::from(adress & 0x07FF)] = value,
+				PRG_RAM..=PRG_RAM_END if self.has_prg_ram() => {
+					let index = self.prg_ram_index(adress);
+					self.prg_ram[index] = value;
+				},
+				_ => {}
+			}
+		}
+	}
+
+	// Code/Data Log filled by the CPU and DMC accesses to PRG ROM while set
+	pub fn set_code_data_log(&mut self, log: Option<CodeDataLog>) {
+		self.code_data_log = log;
+	}
+
+	// Empty log sized for the inserted ROM
+	pub fn start_code_data_log(&mut self) {
+		self.code_data_log = Some(CodeDataLog::for_rom(&self.rom));
+	}
+
+	pub fn code_data_log(&self) -> Option<&CodeDataLog> {
+		self.code_data_log.as_ref()
+	}
+
+	pub fn take_code_data_log(&mut self) -> Option<CodeDataLog> {
+		self.code_data_log.take()
+	}
+
+	// Called by the CPU once the opcode is decoded, `indirect` for the ($nn,X) and ($nn),Y modes
+	pub(crate) fn log_instruction(&mut self, pc: u16, length: u8, indirect: bool) {
+		if self.code_data_log.is_none() {
+			return;
+		}
+
+		self.instruction_length = length;
+		self.data_flags = if indirect { cdl::DATA | cdl::INDIRECT_DATA } else { cdl::DATA };
+		for i in 0..length {
+			self.log_prg(pc.wrapping_add(u16::from(i)), cdl::CODE);
+		}
+	}
+
+	// Target of a JMP ($nnnn)
+	pub(crate) fn log_indirect_jump(&mut self, target: u16) {
+		self.log_prg(target, cdl::INDIRECT_CODE);
+	}
+
+	// Offset in PRG ROM of the byte at `adress` with the current banks
+	pub fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			CARTRIDGE..=CARTRIDGE_END => self.rom.mapper.prg_rom_offset(adress),
+			_ => None
+		}
+	}
+
+	fn log_prg(&mut self, adress: u16, flags: u8) {
+		if let Some(log) = &mut self.code_data_log {
+			if let Some(offset) = self.rom.mapper.prg_rom_offset(adress) {
+				log.log_prg(offset, adress, flags);
+			}
+		}
+	}
+
+	// Set by the CPU when it starts an instruction
+	pub fn set_current_pc(&mut self, pc: u16) {
+		self.current_pc = pc;
+		self.instruction_length = 1;
+	}
+
+	// Devices attached first take precedence
+	pub fn attach_device(&mut self, device: Box<dyn BusDevice>) {
+		self.devices.push(device);
+	}
+
+	pub fn detach_devices(&mut self) -> Vec<Box<dyn BusDevice>> {
+		std::mem::take(&mut self.devices)
+	}
+
+	fn device_at(&mut self, adress: u16) -> Option<&mut Box<dyn BusDevice>> {
+		self.devices.iter_mut().find(|device| device.range().contains(&adress))
+	}
+
+	fn has_prg_ram(&self) -> bool {
+		!self.prg_ram.is_empty() && self.rom.mapper.prg_ram_enabled()
+	}
+
+	// Smaller RAM chips are mirrored over the 8KB window
+	fn prg_ram_index(&self, adress: u16) -> usize {
+		usize::from(adress - PRG_RAM) % self.prg_ram.len()
+	}
+
+	pub fn has_battery(&self) -> bool {
+		self.rom.battery
+	}
+
+	pub fn cpu_ram(&self) -> &[u8; 2048] {
+		&self.cpu_ram
+	}
+
+	pub fn cpu_ram_mut(&mut self) -> &mut [u8; 2048] {
+		&mut self.cpu_ram
+	}
+
+	pub fn prg_ram(&self) -> &[u8] {
+		&self.prg_ram
+	}
+
+	pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+		&mut self.prg_ram
+	}
+
+	// None for ROMs not loaded from a file
+	pub fn rom_info(&self) -> Option<&RomInfo> {
+		self.rom.info.as_ref()
+	}
+
+	pub fn mapper(&self) -> &dyn Mapper {
+		self.rom.mapper.as_ref()
+	}
+
+	pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+		self.rom.mapper.as_mut()
+	}
+
+	// Console RAM, pending DMAs, PPU and APU for save states, the cartridge is saved apart
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_bytes(&self.cpu_ram);
+		state.write_u8(self.open_bus);
+		state.write_u64(self.cycle);
+		self.scheduler.save_state(state);
+		state.write_u64(self.oam_dma_end);
+		state.write_u16(self.stalled);
+		self.ppu.save_state(state);
+		self.apu.save_state(state);
+		state.write_u64(self.master_clock);
+		state.write_u64(self.ppu_clock);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		state.read_into(&mut self.cpu_ram)?;
+		self.open_bus = state.read_u8()?;
+		self.cycle = state.read_u64()?;
+		self.scheduler.load_state(state)?;
+		self.oam_dma_end = state.read_u64()?;
+		self.stalled = state.read_u16()?;
+		self.ppu.load_state(state)?;
+		self.apu.load_state(state)?;
+		self.master_clock = state.read_u64()?;
+		self.ppu_clock = state.read_u64()?;
+		// The PPU trails the master clock by less than a dot
+		let behind = self.master_clock.checked_sub(self.ppu_clock);
+		ensure(behind.is_some_and(|behind| behind < u64::from(self.region.ppu_divider())), "PPU clock out of sync")?;
+		Ok(())
+	}
+
+	// Take the state loaded into `restored`, a copy of this bus. Watchpoints and the
+	// scanline callback are not cloned and move over, the PRG RAM keeps its buffer,
+	// frontends like libretro write battery saves through a pointer to it
+	pub(crate) fn restore_from(&mut self, mut restored: Bus) {
+		restored.watchpoints = std::mem::take(&mut self.watchpoints);
+		restored.ppu.take_scanline_callback(&mut self.ppu);
+		if self.prg_ram.len() == restored.prg_ram.len() {
+			self.prg_ram.copy_from_slice(&restored.prg_ram);
+			std::mem::swap(&mut self.prg_ram, &mut restored.prg_ram);
+		}
+		*self = restored;
+	}
+
+	// Same content as save_state and the cartridge state, for serde formats
+	#[cfg(feature = "serde")]
+	pub fn to_state(&self) -> BusState {
+		BusState {
+			cpu_ram: self.cpu_ram.to_vec(),
+			open_bus: self.open_bus,
+			cycle: self.cycle,
+			scheduler: self.scheduler.clone(),
+			oam_dma_end: self.oam_dma_end,
+			stalled: self.stalled,
+			region: self.region,
+			master_clock: self.master_clock,
+			ppu_clock: self.ppu_clock,
+			ppu: self.ppu.clone(),
+			apu: self.apu.clone(),
+			expansion_audio: self.apu.expansion_state(),
+			cartridge: self.save_cartridge_state()
+		}
+	}
+
+	// Errors on a cartridge state of another mapper, like load_cartridge_state.
+	// The whole state is checked first, the bus is left as it was on errors
+	#[cfg(feature = "serde")]
+	pub fn apply_state(&mut self, state: BusState) -> Result<(), StateError> {
+		let cpu_ram: [u8; 2048] = state.cpu_ram.as_slice().try_into()
+			.map_err(|_| StateError::Corrupt(format!("{} bytes of CPU RAM, 2048 expected", state.cpu_ram.len())))?;
+		let behind = state.master_clock.checked_sub(state.ppu_clock);
+		ensure(behind.is_some_and(|behind| behind < u64::from(state.region.ppu_divider())), "PPU clock out of sync")?;
+		ensure(state.ppu.region() == state.region && state.apu.region() == state.region, "PPU or APU of another region")?;
+		check_deserialized(&state.ppu, &state.apu)?;
+		let expansion = self.apu.loaded_expansion(&state.expansion_audio)?;
+		let (mapper, prg_ram) = self.read_cartridge_state(&state.cartridge)?;
+
+		// Region first, the restored PPU and APU already follow it
+		self.set_region(state.region);
+		self.cpu_ram = cpu_ram;
+		self.open_bus = state.open_bus;
+		self.cycle = state.cycle;
+		self.scheduler = state.scheduler;
+		self.oam_dma_end = state.oam_dma_end;
+		self.stalled = state.stalled;
+		self.master_clock = state.master_clock;
+		self.ppu_clock = state.ppu_clock;
+		self.ppu.restore(state.ppu);
+		self.apu.restore(state.apu, expansion);
+		self.set_cartridge_state(mapper, &prg_ram);
+		Ok(())
+	}
+
+	// Mapper state followed by the PRG RAM
+	pub fn save_cartridge_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_bytes(&self.rom.mapper.save_state());
+		state.write_bytes(&self.prg_ram);
+		state.finish()
+	}
+
+	pub fn load_cartridge_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let (mapper, prg_ram) = self.read_cartridge_state(data)?;
+		self.set_cartridge_state(mapper, &prg_ram);
+		Ok(())
+	}
+
+	// Copy of the mapper with its state loaded and the PRG RAM, the bus is left as it is
+	fn read_cartridge_state(&self, data: &[u8]) -> Result<(Box<dyn Mapper>, Vec<u8>), StateError> {
+		let mut state = StateReader::new(data);
+		let mut mapper = self.rom.mapper.clone();
+		mapper.load_state(state.read_bytes()?)?;
+		let mut prg_ram = vec![0; self.prg_ram.len()];
+		state.read_into(&mut prg_ram)?;
+		Ok((mapper, prg_ram))
+	}
+
+	fn set_cartridge_state(&mut self, mapper: Box<dyn Mapper>, prg_ram: &[u8]) {
+		self.rom.mapper = mapper;
+		self.prg_ram.copy_from_slice(prg_ram);
+		if let Some(mirroring) = self.rom.mapper.mirroring() {
+			self.ppu.set_mirroring(mirroring);
+		}
+	}
+
+	pub fn open_bus(&self) -> u8 {
+		self.open_bus
+	}
+
+	pub fn read_chr_rom(&self, adress: u16) -> u8 {
+		self.rom.mapper.ppu_read(adress)
+	}
+
+	// Copy a RAM page to OAM, return the cycles the CPU is halted
+	fn oam_dma(&mut self, page: u8, write_cycle: u64) -> u16 {
+		let base = u16::from(page) << 8;
+
+		let mut data = [0u8; 256];
+		for (i, value) in data.iter_mut().enumerate() {
+			*value = self.read(base + i as u16);
+		}
+		self.ppu.write_oam_dma(&data);
+
+		// One more cycle to align on a read cycle after an odd cycle write
+		OAM_DMA_CYCLES + (write_cycle & 1) as u16
+	}
+
+	fn dmc_dma(&mut self, adress: u16) -> u16 {
+		let data_flags = std::mem::replace(&mut self.data_flags, cdl::PCM);
+		let value = self.read(adress);
+		self.data_flags = data_flags;
+		self.apu.dmc_dma_complete(value);
+
+		if self.cycle < self.oam_dma_end { DMC_DMA_CYCLES_DURING_OAM } else { DMC_DMA_CYCLES }
+	}
+
+	pub fn tick(&mut self, cycles: u8) {
+		let mut remaining = u16::from(cycles);
+
+		while remaining > 0 {
+			self.clock();
+			self.cycle += 1;
+			remaining -= 1;
+
+			// DMC sample fetch halts the CPU
+			if let Some(adress) = self.apu.dmc_dma_request() {
+				let event = BusEvent::DmcDma(adress);
+				if !self.scheduler.is_scheduled(event) {
+					self.scheduler.schedule(self.master_clock, event);
+				}
+			}
+
+			while let Some((_, event)) = self.scheduler.pop_due(self.master_clock) {
+				let stall = match event {
+					// Due on the cycle after the $4014 write
+					BusEvent::OamDma(page) => {
+						let stall = self.oam_dma(page, self.cycle - 1);
+						self.oam_dma_end = self.cycle + u64::from(stall);
+						stall
+					},
+					BusEvent::DmcDma(adress) => self.dmc_dma(adress),
+					BusEvent::FrameCounter => {
+						self.apu.run_frame_counter();
+						self.schedule_frame_counter();
+						0
+					}
+				};
+
+				remaining += stall;
+				self.stalled += stall;
+			}
+		}
+	}
+
+	pub fn cycle(&self) -> u64 {
+		self.cycle
+	}
+
+	pub fn scheduler(&self) -> &Scheduler {
+		&self.scheduler
+	}
+
+	pub fn master_clock(&self) -> u64 {
+		self.master_clock
+	}
+
+	// Next frame counter event of the APU on the scheduler timeline
+	fn schedule_frame_counter(&mut self) {
+		let tick = self.master_clock + self.apu.frame_counter_countdown() * u64::from(self.region.cpu_divider());
+		self.scheduler.reschedule(tick, BusEvent::FrameCounter);
+	}
+
+	// One CPU cycle, the PPU runs 3 dots per CPU cycle, 3.2 on PAL
+	fn clock(&mut self) {
+		self.master_clock += u64::from(self.region.cpu_divider());
+		let ppu_divider = u64::from(self.region.ppu_divider());
+		let dots = (self.master_clock - self.ppu_clock) / ppu_divider;
+		self.ppu_clock += dots * ppu_divider;
+		self.ppu.tick(&mut self.rom, dots as u16);
+		self.rom.mapper.clock_cpu();
+		self.apu.step();
+		for device in self.devices.iter_mut() {
+			device.tick();
+		}
+	}
+
+	// Cycles the CPU was halted by DMA since the last call
+	pub fn take_stall_cycles(&mut self) -> u16 {
+		let stalled = self.stalled;
+		self.stalled = 0;
+		stalled
+	}
+
+	pub fn poll_nmi_status(&mut self) -> bool {
+		self.ppu.poll_nmi()
+	}
+
+	// Level triggered IRQ line, shared by the APU and the cartridge
+	pub fn poll_irq_status(&self) -> bool {
+		self.apu.irq() || self.rom.mapper.irq_pending()
+	}
+
+	// Interrupt the CPU takes after the current instruction, NMI wins over IRQ
+	pub fn poll_interrupt(&mut self, irq_inhibit: bool) -> Option<Interrupt> {
+		if self.poll_nmi_status() {
+			Some(Interrupt::Nmi)
+		} else if !irq_inhibit && self.poll_irq_status() {
+			Some(Interrupt::Irq)
+		} else {
+			None
+		}
+	}
+
+	pub fn ppu(&self) -> &Ppu {
+		&self.ppu
+	}
+
+	pub fn ppu_mut(&mut self) -> &mut Ppu {
+		&mut self.ppu
+	}
+
+	pub fn apu(&self) -> &Apu {
+		&self.apu
+	}
+
+	pub fn apu_mut(&mut self) -> &mut Apu {
+		&mut self.apu
+	}
+
+	// Replace the device on `port`, returning the one unplugged.
+	// Ports 1 and 2 start with a standard controller, the expansion port empty
+	pub fn plug(&mut self, port: Port, device: Box<dyn InputDevice>) -> Box<dyn InputDevice> {
+		std::mem::replace(self.port_mut(port), device)
+	}
+
+	fn port_mut(&mut self, port: Port) -> &mut Box<dyn InputDevice> {
+		match port {
+			Port::One => &mut self.port1,
+			Port::Two => &mut self.port2,
+			Port::Expansion => &mut self.expansion_port
+		}
+	}
+
+	pub fn input_device(&self, port: Port) -> &dyn InputDevice {
+		match port {
+			Port::One => self.port1.as_ref(),
+			Port::Two => self.port2.as_ref(),
+			Port::Expansion => self.expansion_port.as_ref()
+		}
+	}
+
+	pub fn input_device_mut(&mut self, port: Port) -> &mut dyn InputDevice {
+		self.port_mut(port).as_mut()
+	}
+
+	// The device on `port` when it is a `T`, such as a Zapper to aim
+	pub fn device<T: InputDevice>(&self, port: Port) -> Option<&T> {
+		(self.input_device(port) as &dyn Any).downcast_ref()
+	}
+
+	pub fn device_mut<T: InputDevice>(&mut self, port: Port) -> Option<&mut T> {
+		(self.input_device_mut(port) as &mut dyn Any).downcast_mut()
+	}
+
+	// None when another device is plugged
+	pub fn joypad1(&self) -> Option<&Joypad> {
+		self.device(Port::One)
+	}
+
+	pub fn joypad1_mut(&mut self) -> Option<&mut Joypad> {
+		self.device_mut(Port::One)
+	}
+
+	pub fn joypad2(&self) -> Option<&Joypad> {
+		self.device(Port::Two)
+	}
+
+	pub fn joypad2_mut(&mut self) -> Option<&mut Joypad> {
+		self.device_mut(Port::Two)
+	}
+
+	pub fn render_pattern_tables(&self, palette: u8) -> (Frame, Frame) {
+		self.ppu.render_pattern_tables(&self.rom, palette)
+	}
+
+	pub fn sprite_thumbnail(&self, index: usize) -> Frame {
+		self.ppu.sprite_thumbnail(&self.rom, index)
+	}
+}
+
+// Serde fills the PPU and APU fields directly, run them through the checks of the state readers
+#[cfg(feature = "serde")]
+fn check_deserialized(ppu: &Ppu, apu: &Apu) -> Result<(), StateError> {
+	let mut state = StateWriter::new();
+	ppu.save_state(&mut state);
+	apu.save_state(&mut state);
+	let data = state.finish();
+	let mut state = StateReader::new(&data);
+	ppu.clone().load_state(&mut state)?;
+	apu.clone().load_state(&mut state)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::rom::test;
+	use crate::joypad::Button;
+
+	#[test]
+	fn cpu_write_and_read() {
+		let mut bus = Bus::new(test::test_rom());
+
+		bus.write(0x06e2, 0x25);
+		assert_eq!(bus.read(0x06e2), 0x25);
+		bus.write(0x06e3, 0x10);
+		assert_eq!(bus.read(0x06e3), 0x10);
+		bus.write(0x06e1, 0x07);
+		assert_eq!(bus.read(0x06e1), 0x07);
+
+		assert_eq!(bus.read(0x06e2), 0x25);
+	}
+
+	#[test]
+	fn cpu_mirroring() {
+		let mut bus = Bus::new(test::test_rom());
+
+		bus.write(0x0000, 0x17);
+		assert_eq!(bus.read(0x0800), 0x17);
+		assert_eq!(bus.read(0x1000), 0x17);
+		assert_eq!(bus.read(0x1800), 0x17);
+
+		bus.write(0x0820, 0x07);
+		assert_eq!(bus.read(0x0020), 0x07);
+		assert_eq!(bus.read(0x1020), 0x07);
+		assert_eq!(bus.read(0x1820), 0x07);
+	}
+
+	#[test]
+	fn tick_ppu_three_times_per_cycle() {
+		let mut bus = Bus::new(test::test_rom());
+
+		bus.tick(2);
+		assert_eq!(bus.ppu().dot(), 6);
+	}
+
+	#[test]
+	fn pal_and_dendy_clocks() {
+		let mut bus = Bus::new(test::test_rom());
+		bus.set_region(Region::Pal);
+		// 3.2 dots per CPU cycle
+		bus.tick(5);
+		assert_eq!(bus.ppu().dot(), 16);
+
+		// 312 scanlines of 341 dots
+		while bus.ppu().frame_count() == 0 {
+			bus.tick(1);
+		}
+		assert_eq!(bus.cycle(), 312 * 341 * 5 / 16 + 1);
+
+		let mut bus = Bus::new(test::test_rom());
+		bus.set_region(Region::Dendy);
+		bus.write(0x2000, 0x80);
+		while !bus.poll_nmi_status() {
+			bus.tick(1);
+		}
+		assert_eq!(bus.ppu().scanline(), 291);
+	}
+
+	#[test]
+	fn vblank_nmi() {
+		let mut bus = Bus::new(test::test_rom());
+		bus.write(0x2000, 0x80); // Generate NMI
+
+		// Scanline 241, dot 1 is 241 * 341 + 1 dots away
+		for _ in 0..=(241 * 341 / 3) {
+			bus.tick(1);
+		}
+		assert!(!bus.poll_nmi_status());
+
+		bus.tick(1);
+		assert!(bus.poll_nmi_status());
+		assert!(!bus.poll_nmi_status());
+
+		assert_eq!(bus.read(0x2002) & 0x80, 0x80);
+		assert_eq!(bus.read(0x2002) & 0x80, 0x00); // Cleared by read
+	}
+
+	#[test]
+	fn oam_dma() {
+		let mut bus = Bus::new(test::test_rom());
+		for i in 0..256 {
+			bus.write(0x0200 + i, i as u8);
+		}
+
+		bus.write(0x2003, 0x00);
+		bus.write(0x4014, 0x02);
+		bus.tick(2);
+		assert_eq!(bus.take_stall_cycles(), 513);
+		assert_eq!(bus.take_stall_cycles(), 0);
+
+		bus.write(0x2003, 0x10);
+		assert_eq!(bus.read(0x2004), 0x10);
+	}
+
+	#[test]
+	fn oam_dma_odd_cycle() {
+		let mut bus = Bus::new(test::test_rom());
+		bus.tick(1);
+
+		bus.write(0x4014, 0x02);
+		bus.tick(1);
+		assert_eq!(bus.take_stall_cycles(), 514);
+	}
+
+	#[test]
+	fn dmc_dma_during_oam_dma() {
+		let mut bus = Bus::new(test::test_rom());
+		bus.write(0x4012, 0x00);
+		bus.write(0x4013, 0x00);
+		bus.write(0x4015, 0x10);
+
+		// Sample fetch lands while the OAM DMA is running
+		bus.write(0x4014, 0x02);
+		bus.tick(1);
+		assert_eq!(bus.take_stall_cycles(), OAM_DMA_CYCLES + DMC_DMA_CYCLES_DURING_OAM);
+	}
+
+	#[test]
+	fn frame_counter_event() {
+		let mut bus = Bus::new(test::test_rom());
+		assert_eq!(bus.scheduler().next_at(BusEvent::FrameCounter), Some(7457 * 12));
+
+		while !bus.poll_irq_status() {
+			bus.tick(1);
+		}
+		assert_eq!(bus.cycle(), 29828);
+
+		// Five-step mode, the next event is the write applying 3 or 4 cycles later
+		bus.write(0x4017, 0xC0);
+		assert!(!bus.poll_irq_status());
+		let due = bus.scheduler().next_at(BusEvent::FrameCounter).unwrap();
+		assert!(due - bus.master_clock() <= 4 * 12);
+	}
+
+	#[test]
+	fn dmc_dma_stall() {
+		let mut bus = Bus::new(test::test_rom());
+
+		bus.write(0x4012, 0x00);
+		bus.write(0x4013, 0x00);
+		bus.write(0x4015, 0x10);
+		bus.tick(1);
+
+		assert_eq!(bus.take_stall_cycles(), DMC_DMA_CYCLES);
+		assert_eq!(bus.apu().dmc_dma_request(), None);
+	}
+
+	#[test]
+	fn ppu_open_bus() {
+		let mut bus = Bus::new(test::test_rom());
+
+		bus.write(0x2001, 0x1F);
+		assert_eq!(bus.read(0x2000), 0x1F);
+		assert_eq!(bus.read(0x2002), 0x1F); // Low bits of status are open bus
+		assert_eq!(bus.read(0x200D), 0x1F);
+	}
+
+	#[test]
+	fn unmapped_access() {
+		let mut bus = Bus::new(test::test_rom());
+
+		assert_eq!(bus.try_read(0x4000), Err(BusError::WriteOnly(0x4000)));
+		assert_eq!(bus.try_read(0x4018), Err(BusError::Unmapped(0x4018)));
+		assert_eq!(bus.try_write(0x401F, 0x00), Err(BusError::Unmapped(0x401F)));
+		assert_eq!(bus.try_read(0x0000), Ok(0x00));
+
+		// Plain accessors don't panic
+		bus.read(0x4018);
+		bus.write(0x401F, 0x00);
+	}
+
+	#[test]
+	fn bus_device() {
+		#[derive(Clone)]
+		struct DebugPort {
+			last: u8
+		}
+		impl BusDevice for DebugPort {
+			fn range(&self) -> std::ops::RangeInclusive<u16> {
+				0x4018..=0x401F
+			}
+			fn read(&mut self, _adress: u16) -> u8 {
+				self.last
+			}
+			fn write(&mut self, _adress: u16, value: u8) {
+				self.last = value;
+			}
+		}
+
+		let mut bus = Bus::new(test::test_rom());
+		bus.attach_device(Box::new(DebugPort { last: 0 }));
+
+		bus.write(0x401A, 0x42);
+		bus.write(0x0000, 0x00);
+		assert_eq!(bus.try_read(0x401F), Ok(0x42));
+
+		let devices = bus.detach_devices();
+		assert_eq!(devices.len(), 1);
+		assert_eq!(bus.try_read(0x401F), Err(BusError::Unmapped(0x401F)));
+	}
+
+	#[test]
+	fn peek_has_no_side_effects() {
+		let mut bus = Bus::new(test::test_rom());
+		bus.poke(0x0010, 0x77);
+		assert_eq!(bus.peek(0x0810), 0x77);
+
+		// Reading $2007 would move the VRAM address and the buffer
+		bus.write(0x2006, 0x20);
+		bus.write(0x2006, 0x00);
+		bus.write(0x2007, 0x12);
+		bus.write(0x2006, 0x20);
+		bus.write(0x2006, 0x00);
+		bus.read(0x2007);
+		assert_eq!(bus.peek(0x2007), 0x12);
+		assert_eq!(bus.peek(0x2007), 0x12);
+		assert_eq!(bus.read(0x2007), 0x12);
+
+		// Frame IRQ flag is not acknowledged
+		for _ in 0..117 {
+			bus.tick(255);
+		}
+		assert_eq!(bus.peek(0x4015) & 0x40, 0x40);
+		assert_eq!(bus.read(0x4015) & 0x40, 0x40);
+		assert_eq!(bus.peek(0x4015) & 0x40, 0x00);
+	}
+
+	#[test]
+	fn controller_port() {
+		let mut bus = Bus::new(test::test_rom());
+		bus.joypad1_mut().unwrap().set_buttons(0b0000_0010); // B
+
+		bus.write(0x4016, 1);
+		bus.write(0x4016, 0);
+		assert_eq!(bus.read(0x4016) & 0x01, 0);
+		assert_eq!(bus.read(0x4016) & 0x01, 1);
+		assert_eq!(bus.read(0x4016) & 0x01, 0);
+	}
+
+	#[test]
+	fn second_controller() {
+		let mut bus = Bus::new(test::test_rom());
+		bus.joypad1_mut().unwrap().set_button(Button::A, true);
+		bus.joypad2_mut().unwrap().set_button(Button::Select, true);
+
+		bus.write(0x4016, 1);
+		bus.write(0x4016, 0);
+		let port2: Vec<u8> = (0..4).map(|_| bus.read(0x4017) & 0x01).collect();
+		assert_eq!(port2, [0, 0, 1, 0]);
+		// Port 1 shifts on its own
+		assert_eq!(bus.read(0x4016) & 0x01, 1);
+		assert_eq!(bus.peek(0x4017) & 0x01, 0);
+	}
+
+	#[test]
+	fn plug_devices() {
+		use crate::input::paddle::ArkanoidPaddle;
+
+		let mut bus = Bus::new(test::test_rom());
+		let mut paddle = ArkanoidPaddle::new();
+		paddle.set_button(true);
+		bus.plug(Port::Two, Box::new(paddle));
+		assert!(bus.joypad2().is_none());
+		assert!(bus.joypad1().is_some());
+
+		bus.write(0x4016, 1);
+		bus.write(0x4016, 0);
+		assert_eq!(bus.read(0x4017) & 0x08, 0x08);
+
+		bus.device_mut::<ArkanoidPaddle>(Port::Two).unwrap().set_button(false);
+		assert_eq!(bus.read(0x4017) & 0x08, 0x00);
+	}
+
+	#[test]
+	fn prg_ram() {
+		let mut bus = Bus::new(test::test_rom());
+
+		bus.write(0x6000, 0x12);
+		bus.write(0x7FFF, 0x34);
+		assert_eq!(bus.read(0x6000), 0x12);
+		assert_eq!(bus.read(0x7FFF), 0x34);
+		assert_eq!(bus.prg_ram()[0x1FFF], 0x34);
+	}
+
+	#[test]
+	fn bus_conflicts() {
+		use crate::mapper::axrom::Axrom;
+		use crate::rom::{Mirroring, Rom};
+
+		// Bank number in every byte of each 32KB bank
+		let pgr_rom = (0..8u8).flat_map(|bank| vec![bank | 0x10; 32768]).collect();
+		let mut axrom = Axrom::new(pgr_rom, vec![0; 8192], true);
+		axrom.set_bus_conflicts(true);
+		let mut bus = Bus::new(Rom { mapper: Box::new(axrom), mirroring: Mirroring::Vertical, prg_ram_size: 0, battery: false, info: None });
+
+		// Bank 0 reads $10, so the bank bits are cleared
+		bus.write(0x8000, 0x13);
+		assert_eq!(bus.read(0x8000), 0x10);
+		assert_eq!(bus.ppu().mirroring(), Mirroring::SingleScreenUpper);
+
+		bus.mapper_mut().cpu_write(0x8000, 0x03);
+		bus.write(0x8000, 0x03);
+		assert_eq!(bus.read(0x8000), 0x13);
+	}
+
+	#[test]
+	fn cartridge_state() {
+		use crate::mapper::mmc3::Mmc3;
+		use crate::rom::{Mirroring, Rom};
+
+		let rom = || Rom {
+			mapper: Box::new(Mmc3::new((0..8u8).flat_map(|bank| vec![bank; 8192]).collect(), vec![0; 8192], true)),
+			mirroring: Mirroring::Vertical,
+			prg_ram_size: 8192,
+			battery: false,
+			info: None
+		};
+
+		let mut bus = Bus::new(rom());
+		bus.write(0x8000, 6);
+		bus.write(0x8001, 3);
+		bus.write(0xA000, 1);
+		bus.write(0x6123, 0x42);
+		let state = bus.save_cartridge_state();
+
+		let mut restored = Bus::new(rom());
+		restored.load_cartridge_state(&state).unwrap();
+		assert_eq!(restored.read(0x8000), 3);
+		assert_eq!(restored.read(0x6123), 0x42);
+		assert_eq!(restored.ppu().mirroring(), Mirroring::Horizontal);
+	}
+
+	#[test]
+	fn watchpoints() {
+		use std::{cell::RefCell, rc::Rc};
+
+		let mut bus = Bus::new(test::test_rom());
+		bus.write(0x00FE, 3);
+
+		let events = Rc::new(RefCell::new(Vec::new()));
+		let log = Rc::clone(&events);
+		let id = bus.watch_write(0x00FE, move |event| log.borrow_mut().push(*event));
+
+		bus.set_current_pc(0x8123);
+		bus.write(0x08FE, 2); // Mirror
+		bus.write(0x00FF, 9);
+		assert_eq!(*events.borrow(), [WatchEvent {
+			kind: WatchKind::Write,
+			adress: 0x08FE,
+			old_value: 3,
+			new_value: 2,
+			pc: 0x8123
+		}]);
+
+		assert!(bus.unwatch(id));
+		bus.write(0x00FE, 1);
+		assert_eq!(events.borrow().len(), 1);
+	}
+
+	#[test]
+	fn dump_and_load_range() {
+		let mut bus = Bus::new(test::test_rom());
+
+		bus.load_range(0x0300, &[1, 2, 3, 4]);
+		assert_eq!(bus.dump_range(0x0300, 0x0303), [1, 2, 3, 4]);
+		assert_eq!(bus.dump_range(0x0B01, 0x0B02), [2, 3]);
+
+		let ram = bus.dump_range(0x0000, 0x07FF);
+		assert_eq!(ram.len(), 2048);
+		assert_eq!(bus.dump_range(0xFFFF, 0xFFFF).len(), 1);
+		assert_eq!(bus.cpu_ram()[0x0301], 2);
+	}
+
+	#[test]
+	#[should_panic(expected = "0x4018 is not mapped")]
+	fn strict_config() {
+		let mut bus = Bus::with_config(test::test_rom(), BusConfig::strict());
+		bus.read(0x0000);
+		bus.read(0x4018);
+	}
+
+	#[test]
+	fn cpu_open_bus() {
+		let mut bus = Bus::new(test::test_rom());
+
+		bus.write(0x0010, 0xA5);
+		bus.read(0x0010);
+		assert_eq!(bus.read(0x4018), 0xA5);
+		assert_eq!(bus.read(0x4000), 0xA5);
+		assert_eq!(bus.read(0x4015), 0x20);
+
+		bus.write(0x401F, 0x5A);
+		assert_eq!(bus.read(0x4018), 0x5A);
+	}
+
+	#[test]
+	fn code_data_log() {
+		// $8000: LDA $8010; LDA ($00),Y; JMP ($8020)
+		let mut program = vec![0xAD, 0x10, 0x80, 0xB1, 0x00, 0x6C, 0x20, 0x80];
+		program.resize(0x20, 0);
+		program.extend([0x00, 0x80]);
+
+		let mut bus = Bus::new(Rom::from_ines(&test::nrom(&program, [0, 0x8000, 0])).unwrap());
+		bus.start_code_data_log();
+		bus.write(0x0000, 0x11);
+		bus.write(0x0001, 0x80);
+		let mut cpu = crate::cpu::Cpu::new();
+		cpu.pc = 0x8000;
+		for _ in 0..3 {
+			cpu.step(&mut bus);
+		}
+
+		let log = bus.take_code_data_log().unwrap();
+		assert_eq!(log.prg()[0], cdl::CODE | cdl::INDIRECT_CODE);
+		assert!(log.prg()[1..8].iter().all(|&flags| flags == cdl::CODE));
+		assert_eq!(log.prg()[0x10], cdl::DATA);
+		assert_eq!(log.prg()[0x11], cdl::DATA | cdl::INDIRECT_DATA);
+		assert_eq!(&log.prg()[0x20..0x22], &[cdl::DATA, cdl::DATA]);
+		assert_eq!(log.chr().len(), 8192);
+		assert_eq!(log.code_bytes(), 8);
+	}
+
+	#[test]
+	#[should_panic(expected = "0x200a is read-only")]
+	fn strict_read_only() {
+		let mut bus = Bus::new(test::test_rom());
+		assert_eq!(bus.try_write(0x2002, 0x00), Err(BusError::ReadOnly(0x2002)));
+		bus.write(0x2002, 0x00);
+
+		bus.set_config(BusConfig::strict());
+		bus.write(0x2000, 0x00);
+		bus.write(0x200A, 0x00);
+	}
+
+	#[test]
+	fn frozen_without_bus_writes() {
+		use std::{cell::Cell, rc::Rc};
+
+		let mut bus = Bus::new(test::test_rom());
+		bus.cheats_mut().add("0810=05").unwrap();
+		let writes = Rc::new(Cell::new(0));
+		let counter = Rc::clone(&writes);
+		bus.watch_write(0x0010, move |_| counter.set(counter.get() + 1));
+
+		bus.apply_frozen();
+		assert_eq!(bus.cpu_ram()[0x0010], 0x05);
+		assert_eq!(writes.get(), 0);
+	}
+}
@@ -0,0 +1,163 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// Timed bus events, timestamped in master clock ticks so that the CPU cycles of every
+// region share one timeline. The PPU and the mapper counters still run dot by dot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BusEvent {
+	// Copy of a RAM page to OAM after a $4014 write
+	OamDma(u8),
+	// Sample byte fetch of the DMC
+	DmcDma(u16),
+	// Next step, IRQ or $4017 write of the APU frame counter
+	FrameCounter
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+	Nmi,
+	Irq
+}
+
+impl Interrupt {
+	pub fn vector(self) -> u16 {
+		match self {
+			Interrupt::Nmi => 0xFFFA,
+			Interrupt::Irq => 0xFFFE
+		}
+	}
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Scheduler {
+	// Sorted by master clock tick, events at the same tick keep their order
+	events: Vec<(u64, BusEvent)>
+}
+
+impl Scheduler {
+	pub fn new() -> Scheduler {
+		Scheduler {
+			events: Vec::new()
+		}
+	}
+
+	pub fn schedule(&mut self, tick: u64, event: BusEvent) {
+		let index = self.events.partition_point(|&(at, _)| at <= tick);
+		self.events.insert(index, (tick, event));
+	}
+
+	// Drop the pending `event`, return whether it was scheduled
+	pub fn cancel(&mut self, event: BusEvent) -> bool {
+		let count = self.events.len();
+		self.events.retain(|&(_, pending)| pending != event);
+		self.events.len() != count
+	}
+
+	// Move `event` to `tick`, for timers that change when their registers are written
+	pub fn reschedule(&mut self, tick: u64, event: BusEvent) {
+		self.cancel(event);
+		self.schedule(tick, event);
+	}
+
+	// Next event due at or before `tick`, with its timestamp
+	pub fn pop_due(&mut self, tick: u64) -> Option<(u64, BusEvent)> {
+		match self.events.first() {
+			Some(&(at, _)) if at <= tick => Some(self.events.remove(0)),
+			_ => None
+		}
+	}
+
+	// Timestamp of the pending `event`
+	pub fn next_at(&self, event: BusEvent) -> Option<u64> {
+		self.events.iter().find(|&&(_, pending)| pending == event).map(|&(at, _)| at)
+	}
+
+	pub fn is_scheduled(&self, event: BusEvent) -> bool {
+		self.events.iter().any(|&(_, pending)| pending == event)
+	}
+
+	pub fn pending(&self) -> &[(u64, BusEvent)] {
+		&self.events
+	}
+
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_u32(self.events.len() as u32);
+		for &(tick, event) in &self.events {
+			state.write_u64(tick);
+			match event {
+				BusEvent::OamDma(page) => {
+					state.write_u8(0);
+					state.write_u16(u16::from(page));
+				},
+				BusEvent::DmcDma(adress) => {
+					state.write_u8(1);
+					state.write_u16(adress);
+				},
+				BusEvent::FrameCounter => {
+					state.write_u8(2);
+					state.write_u16(0);
+				}
+			}
+		}
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		let count = state.read_u32()?;
+		self.events = (0..count).map(|_| {
+			let tick = state.read_u64()?;
+			let kind = state.read_u8()?;
+			let value = state.read_u16()?;
+			let event = match kind {
+				0 => BusEvent::OamDma(value as u8),
+				1 => BusEvent::DmcDma(value),
+				2 => BusEvent::FrameCounter,
+				kind => return Err(StateError::Corrupt(format!("invalid bus event {}", kind)))
+			};
+			Ok((tick, event))
+		}).collect::<Result<_, StateError>>()?;
+		Ok(())
+	}
+}
+
+impl Default for Scheduler {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ordering() {
+		let mut scheduler = Scheduler::new();
+		scheduler.schedule(10, BusEvent::OamDma(2));
+		scheduler.schedule(5, BusEvent::DmcDma(0xC000));
+		scheduler.schedule(10, BusEvent::DmcDma(0xC001));
+
+		assert_eq!(scheduler.pop_due(4), None);
+		assert_eq!(scheduler.pop_due(10), Some((5, BusEvent::DmcDma(0xC000))));
+		assert_eq!(scheduler.pop_due(10), Some((10, BusEvent::OamDma(2))));
+		assert!(scheduler.is_scheduled(BusEvent::DmcDma(0xC001)));
+		assert_eq!(scheduler.pop_due(10), Some((10, BusEvent::DmcDma(0xC001))));
+		assert!(scheduler.pending().is_empty());
+	}
+
+	#[test]
+	fn reschedule() {
+		let mut scheduler = Scheduler::new();
+		scheduler.schedule(120, BusEvent::FrameCounter);
+		scheduler.schedule(60, BusEvent::OamDma(2));
+
+		scheduler.reschedule(36, BusEvent::FrameCounter);
+		assert_eq!(scheduler.next_at(BusEvent::FrameCounter), Some(36));
+		assert_eq!(scheduler.pending().len(), 2);
+		assert_eq!(scheduler.pop_due(48), Some((36, BusEvent::FrameCounter)));
+
+		assert!(scheduler.cancel(BusEvent::OamDma(2)));
+		assert!(!scheduler.cancel(BusEvent::OamDma(2)));
+		assert_eq!(scheduler.next_at(BusEvent::OamDma(2)), None);
+	}
+}
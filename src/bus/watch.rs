@@ -0,0 +1,104 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+	Read,
+	Write
+}
+
+// Access seen by a watchpoint, `old_value` equals `new_value` for reads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchEvent {
+	pub kind: WatchKind,
+	pub adress: u16,
+	pub old_value: u8,
+	pub new_value: u8,
+	// Instruction doing the access
+	pub pc: u16
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WatchId(u32);
+
+pub type WatchCallback = dyn FnMut(&WatchEvent);
+
+struct Watchpoint {
+	id: WatchId,
+	kind: WatchKind,
+	adress: u16,
+	callback: Box<WatchCallback>
+}
+
+pub struct Watchpoints {
+	watchpoints: Vec<Watchpoint>,
+	next_id: u32
+}
+
+impl Watchpoints {
+	pub fn new() -> Watchpoints {
+		Watchpoints {
+			watchpoints: Vec::new(),
+			next_id: 0
+		}
+	}
+
+	// RAM mirrors hit the same watchpoint
+	fn canonical(adress: u16) -> u16 {
+		if adress < 0x2000 { adress & 0x07FF } else { adress }
+	}
+
+	pub fn add(&mut self, kind: WatchKind, adress: u16, callback: Box<WatchCallback>) -> WatchId {
+		let id = WatchId(self.next_id);
+		self.next_id += 1;
+
+		self.watchpoints.push(Watchpoint {
+			id,
+			kind,
+			adress: Watchpoints::canonical(adress),
+			callback
+		});
+		id
+	}
+
+	pub fn remove(&mut self, id: WatchId) -> bool {
+		let len = self.watchpoints.len();
+		self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+		self.watchpoints.len() != len
+	}
+
+	pub fn clear(&mut self) {
+		self.watchpoints.clear();
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.watchpoints.is_empty()
+	}
+
+	pub fn is_watched(&self, kind: WatchKind, adress: u16) -> bool {
+		let adress = Watchpoints::canonical(adress);
+		self.watchpoints.iter().any(|watchpoint| watchpoint.kind == kind && watchpoint.adress == adress)
+	}
+
+	pub fn notify(&mut self, event: &WatchEvent) {
+		let adress = Watchpoints::canonical(event.adress);
+		for watchpoint in self.watchpoints.iter_mut() {
+			if watchpoint.kind == event.kind && watchpoint.adress == adress {
+				(watchpoint.callback)(event);
+			}
+		}
+	}
+}
+
+// Callbacks are not carried over to a cloned bus
+impl Clone for Watchpoints {
+	fn clone(&self) -> Self {
+		Watchpoints {
+			watchpoints: Vec::new(),
+			next_id: self.next_id
+		}
+	}
+}
+
+impl Default for Watchpoints {
+	fn default() -> Self {
+		Self::new()
+	}
+}
@@ -0,0 +1,261 @@
+use std::fmt;
+
+// Game Genie letters, by value
+const GAME_GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+// Result bit set by each step of the Pro Action Rocky decryption, last step first:
+// address in bits 0-14, compare value in bits 16-23 and value in bits 24-31
+const PRO_ACTION_ROCKY_BITS: [u8; 31] = [
+	3, 13, 14, 1, 6, 9, 5, 0, 12, 7, 2, 8, 10, 11, 4,
+	19, 21, 23, 22, 20, 17, 16, 18,
+	29, 31, 24, 26, 25, 30, 27, 28
+];
+const PRO_ACTION_ROCKY_KEY: u32 = 0xFCBDD274;
+const PRO_ACTION_ROCKY_XOR: u32 = 0xB8309722;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheatError(pub String);
+
+impl fmt::Display for CheatError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "invalid cheat code: {}", self.0)
+	}
+}
+
+impl std::error::Error for CheatError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatKind {
+	// Replaces what the CPU reads at the address
+	ReadPatch,
+	// Keeps a RAM byte at the value: writes store it instead, and it is written back every frame
+	Freeze
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+	// As entered, upper case
+	pub code: String,
+	pub kind: CheatKind,
+	pub adress: u16,
+	pub value: u8,
+	// Only patch when the original byte is this one, for bank switched ROMs
+	pub compare: Option<u8>
+}
+
+impl Cheat {
+	// Game Genie codes (`GOSSIP`, `ZEXPYGLA`), 8 hex digit Pro Action Rocky codes,
+	// raw `AAAA:VV` patches or `AAAA=VV` freezes
+	pub fn parse(code: &str) -> Result<Cheat, CheatError> {
+		let code = code.trim().to_ascii_uppercase();
+		let error = |message: &str| CheatError(format!("{} ({})", message, code));
+
+		if let Some((adress, value)) = code.split_once('=') {
+			let adress = u16::from_str_radix(adress, 16).map_err(|_| error("bad address"))?;
+			let value = u8::from_str_radix(value, 16).map_err(|_| error("bad value"))?;
+			return Ok(Cheat::freeze(adress, value));
+		}
+
+		let (adress, value, compare) = if let Some((adress, value)) = code.split_once(':') {
+			let adress = u16::from_str_radix(adress, 16).map_err(|_| error("bad address"))?;
+			let value = u8::from_str_radix(value, 16).map_err(|_| error("bad value"))?;
+			(adress, value, None)
+		} else if let Some(letters) = game_genie_letters(&code) {
+			decode_game_genie(&letters).ok_or_else(|| error("Game Genie codes have 6 or 8 letters"))?
+		} else if code.len() == 8 && code.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+			let digits = u32::from_str_radix(&code, 16).map_err(|_| error("bad digits"))?;
+			decode_pro_action_rocky(digits)
+		} else {
+			return Err(error("not a Game Genie, Pro Action Rocky or AAAA:VV code"));
+		};
+
+		Ok(Cheat { code, kind: CheatKind::ReadPatch, adress, value, compare })
+	}
+
+	// Infinite lives and the like, for values the game keeps in RAM
+	pub fn freeze(adress: u16, value: u8) -> Cheat {
+		Cheat {
+			code: format!("{:04X}={:02X}", adress, value),
+			kind: CheatKind::Freeze,
+			adress,
+			value,
+			compare: None
+		}
+	}
+
+	// Byte seen by the CPU instead of `original`
+	pub fn apply(&self, adress: u16, original: u8) -> u8 {
+		if self.kind == CheatKind::ReadPatch && adress == self.adress && self.compare.is_none_or(|compare| compare == original) {
+			self.value
+		} else {
+			original
+		}
+	}
+}
+
+fn game_genie_letters(code: &str) -> Option<Vec<u8>> {
+	code.bytes().map(|letter| GAME_GENIE_LETTERS.iter().position(|&l| l == letter).map(|value| value as u8)).collect()
+}
+
+// Address, value and compare value of a 6 or 8 letter code, the bits are shuffled across the letters
+fn decode_game_genie(n: &[u8]) -> Option<(u16, u8, Option<u8>)> {
+	if n.len() != 6 && n.len() != 8 {
+		return None;
+	}
+
+	let adress = 0x8000
+		| (u16::from(n[3] & 7) << 12)
+		| (u16::from(n[5] & 7) << 8) | (u16::from(n[4] & 8) << 8)
+		| (u16::from(n[2] & 7) << 4) | (u16::from(n[1] & 8) << 4)
+		| u16::from(n[4] & 7) | u16::from(n[3] & 8);
+	let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+
+	if n.len() == 6 {
+		Some((adress, value | (n[5] & 8), None))
+	} else {
+		let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+		Some((adress, value | (n[7] & 8), Some(compare)))
+	}
+}
+
+// Address, value and compare value of an encrypted code, the key changes with each decrypted bit
+fn decode_pro_action_rocky(code: u32) -> (u16, u8, Option<u8>) {
+	let mut key = PRO_ACTION_ROCKY_KEY;
+	let mut code = code;
+	let mut result = 0u32;
+	for &bit in PRO_ACTION_ROCKY_BITS.iter().rev() {
+		if (key ^ code) & 0x8000_0000 != 0 {
+			result |= 1 << bit;
+			key ^= PRO_ACTION_ROCKY_XOR;
+		}
+		code <<= 1;
+		key <<= 1;
+	}
+
+	(0x8000 | (result & 0x7FFF) as u16, (result >> 24) as u8, Some((result >> 16) as u8))
+}
+
+// Active cheats, applied by the bus to CPU reads
+#[derive(Clone, Debug, Default)]
+pub struct Cheats {
+	cheats: Vec<Cheat>
+}
+
+impl Cheats {
+	pub fn new() -> Cheats {
+		Cheats::default()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.cheats.is_empty()
+	}
+
+	// A code already active is replaced
+	pub fn add(&mut self, code: &str) -> Result<&Cheat, CheatError> {
+		let cheat = Cheat::parse(code)?;
+		self.cheats.retain(|active| active.code != cheat.code);
+		self.cheats.push(cheat);
+		Ok(&self.cheats[self.cheats.len() - 1])
+	}
+
+	// False when the code was not active
+	pub fn remove(&mut self, code: &str) -> bool {
+		let code = code.trim().to_ascii_uppercase();
+		let count = self.cheats.len();
+		self.cheats.retain(|cheat| cheat.code != code);
+		self.cheats.len() != count
+	}
+
+	pub fn list(&self) -> &[Cheat] {
+		&self.cheats
+	}
+
+	pub fn clear(&mut self) {
+		self.cheats.clear();
+	}
+
+	pub fn apply(&self, adress: u16, original: u8) -> u8 {
+		self.cheats.iter().fold(original, |value, cheat| cheat.apply(adress, value))
+	}
+
+	// Byte stored instead of `value` by a CPU write
+	pub fn write(&self, adress: u16, value: u8) -> u8 {
+		self.frozen().find(|cheat| cheat.adress == adress).map_or(value, |cheat| cheat.value)
+	}
+
+	pub fn frozen(&self) -> impl Iterator<Item = &Cheat> {
+		self.cheats.iter().filter(|cheat| cheat.kind == CheatKind::Freeze)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn game_genie() {
+		let cheat = Cheat::parse("gossip").unwrap();
+		assert_eq!((cheat.adress, cheat.value, cheat.compare), (0xD1DD, 0x14, None));
+		assert_eq!(cheat.code, "GOSSIP");
+
+		// Super Mario Bros., start on world 8
+		let cheat = Cheat::parse("AEKPIAAA").unwrap();
+		assert_eq!(cheat.compare, Some(0x00));
+		assert_eq!(cheat.apply(cheat.adress, 0x00), cheat.value);
+		assert_eq!(cheat.apply(cheat.adress, 0x01), 0x01);
+
+		assert!(Cheat::parse("GOSSI").is_err());
+	}
+
+	#[test]
+	fn raw_codes() {
+		let cheat = Cheat::parse("0075:09").unwrap();
+		assert_eq!((cheat.adress, cheat.value), (0x0075, 0x09));
+
+
+		assert!(Cheat::parse("0075:1FF").is_err());
+		assert!(Cheat::parse("nothing").is_err());
+	}
+
+	#[test]
+	fn freeze() {
+		let cheat = Cheat::parse("0075=09").unwrap();
+		assert_eq!(cheat, Cheat::freeze(0x0075, 0x09));
+		// Reads are left alone, the RAM holds the value
+		assert_eq!(cheat.apply(0x0075, 3), 3);
+
+		let mut cheats = Cheats::new();
+		cheats.add("0075=09").unwrap();
+		cheats.add("0076:01").unwrap();
+		assert_eq!(cheats.write(0x0075, 2), 9);
+		assert_eq!(cheats.write(0x0076, 2), 2);
+		assert_eq!(cheats.frozen().count(), 1);
+	}
+
+	#[test]
+	fn list() {
+		let mut cheats = Cheats::new();
+		cheats.add("0075:09").unwrap();
+		cheats.add("0075:09").unwrap();
+		cheats.add("0076:01").unwrap();
+		assert_eq!(cheats.list().len(), 2);
+		assert_eq!(cheats.apply(0x0075, 3), 9);
+		assert_eq!(cheats.apply(0x0077, 3), 3);
+
+		assert!(cheats.remove("0075:09"));
+		assert!(!cheats.remove("0075:09"));
+		assert_eq!(cheats.apply(0x0075, 3), 3);
+	}
+
+	#[test]
+	fn pro_action_rocky() {
+		// The key itself decrypts to all zero bits
+		let cheat = Cheat::parse("FCBDD274").unwrap();
+		assert_eq!((cheat.adress, cheat.value, cheat.compare), (0x8000, 0x00, Some(0x00)));
+
+		let cheat = Cheat::parse("12345678").unwrap();
+		assert_eq!((cheat.adress, cheat.value, cheat.compare), (0xAD85, 0x3C, Some(0xC1)));
+		assert_eq!(cheat.apply(0xAD85, 0xC1), 0x3C);
+		assert_eq!(cheat.apply(0xAD85, 0x00), 0x00);
+	}
+}
@@ -0,0 +1,137 @@
+use std::ops::RangeInclusive;
+
+use crate::bus::Bus;
+
+// CPU RAM, where games keep lives, health and timers
+pub const CPU_RAM: RangeInclusive<u16> = 0x0000..=0x07FF;
+// Work RAM of the cartridge
+pub const PRG_RAM: RangeInclusive<u16> = 0x6000..=0x7FFF;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchFilter {
+	// Current value
+	Equal(u8),
+	NotEqual(u8),
+	Less(u8),
+	Greater(u8),
+	// Compared with the previous snapshot
+	Increased,
+	Decreased,
+	Unchanged,
+	Changed,
+	IncreasedBy(u8),
+	DecreasedBy(u8)
+}
+
+impl SearchFilter {
+	fn matches(self, previous: u8, current: u8) -> bool {
+		match self {
+			SearchFilter::Equal(value) => current == value,
+			SearchFilter::NotEqual(value) => current != value,
+			SearchFilter::Less(value) => current < value,
+			SearchFilter::Greater(value) => current > value,
+			SearchFilter::Increased => current > previous,
+			SearchFilter::Decreased => current < previous,
+			SearchFilter::Unchanged => current == previous,
+			SearchFilter::Changed => current != previous,
+			SearchFilter::IncreasedBy(delta) => current == previous.wrapping_add(delta),
+			SearchFilter::DecreasedBy(delta) => current == previous.wrapping_sub(delta)
+		}
+	}
+}
+
+// Narrows down the addresses holding a value: snapshot, play, filter, and again.
+// Memory is read with Bus::peek, searching does not disturb the game
+#[derive(Clone, Debug)]
+pub struct CheatSearch {
+	range: RangeInclusive<u16>,
+	snapshot: Vec<u8>,
+	candidates: Vec<u16>
+}
+
+impl CheatSearch {
+	// Every CPU RAM address is a candidate
+	pub fn new(bus: &Bus) -> CheatSearch {
+		CheatSearch::with_range(bus, CPU_RAM)
+	}
+
+	pub fn with_range(bus: &Bus, range: RangeInclusive<u16>) -> CheatSearch {
+		let mut search = CheatSearch {
+			range,
+			snapshot: Vec::new(),
+			candidates: Vec::new()
+		};
+		search.reset(bus);
+		search
+	}
+
+	// Start over from all addresses of the range
+	pub fn reset(&mut self, bus: &Bus) {
+		self.candidates = self.range.clone().collect();
+		self.snapshot = self.range.clone().map(|adress| bus.peek(adress)).collect();
+	}
+
+	// Keep the candidates matching `filter`, the current memory becomes the new snapshot
+	pub fn filter(&mut self, bus: &Bus, filter: SearchFilter) -> &[u16] {
+		let start = *self.range.start();
+		let snapshot: Vec<u8> = self.range.clone().map(|adress| bus.peek(adress)).collect();
+
+		self.candidates.retain(|&adress| {
+			let index = usize::from(adress - start);
+			filter.matches(self.snapshot[index], snapshot[index])
+		});
+		self.snapshot = snapshot;
+		&self.candidates
+	}
+
+	pub fn candidates(&self) -> &[u16] {
+		&self.candidates
+	}
+
+	// Value at the last snapshot
+	pub fn value(&self, adress: u16) -> Option<u8> {
+		if !self.range.contains(&adress) {
+			return None;
+		}
+		Some(self.snapshot[usize::from(adress - self.range.start())])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::rom::test;
+
+	#[test]
+	fn find_lives_counter() {
+		let mut bus = Bus::new(test::test_rom());
+		bus.write(0x0042, 3);
+		bus.write(0x0100, 3);
+		let mut search = CheatSearch::new(&bus);
+
+		search.filter(&bus, SearchFilter::Equal(3));
+		assert_eq!(search.candidates(), &[0x0042, 0x0100]);
+
+		// A life lost
+		bus.write(0x0042, 2);
+		assert_eq!(search.filter(&bus, SearchFilter::Decreased), &[0x0042]);
+		assert_eq!(search.value(0x0042), Some(2));
+
+		bus.write(0x0042, 1);
+		assert_eq!(search.filter(&bus, SearchFilter::DecreasedBy(1)), &[0x0042]);
+		assert_eq!(search.filter(&bus, SearchFilter::Changed), &[] as &[u16]);
+
+		search.reset(&bus);
+		assert_eq!(search.candidates().len(), 2048);
+	}
+
+	#[test]
+	fn prg_ram() {
+		let mut bus = Bus::new(test::test_rom());
+		let mut search = CheatSearch::with_range(&bus, PRG_RAM);
+		bus.write(0x7000, 9);
+
+		assert_eq!(search.filter(&bus, SearchFilter::Increased), &[0x7000]);
+		assert_eq!(search.value(0x0000), None);
+	}
+}
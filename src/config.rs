@@ -0,0 +1,284 @@
+// Emulator options read from a TOML file by the CLI and nessy-sdl. Missing keys take
+// their default, so a config file only lists what it changes:
+//
+//   region = "pal"
+//   ram_init = "ones"
+//
+//   [audio]
+//   sample_rate = 48000
+//
+//   [input.player1]
+//   a = "S"
+//   b = "A"
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::apu::mixer::FilterConfig;
+use crate::bus::BusConfig;
+use crate::joypad::Button;
+use crate::nes::Nes;
+use crate::ppu::palette::Palette;
+use crate::region::Region;
+
+#[derive(Debug)]
+pub enum ConfigError {
+	Io(PathBuf, io::Error),
+	Parse(String),
+	Palette(PathBuf)
+}
+
+impl fmt::Display for ConfigError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ConfigError::Io(path, error) => write!(f, "{}: {}", path.display(), error),
+			ConfigError::Parse(message) => write!(f, "invalid config: {}", message),
+			ConfigError::Palette(path) => write!(f, "{}: not a .pal file, 192 bytes at least", path.display())
+		}
+	}
+}
+
+impl std::error::Error for ConfigError {}
+
+// Content of the CPU RAM at power on, which some games read before writing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RamInit {
+	#[default]
+	Zeros,
+	Ones,
+	// 4 bytes of $00 then 4 of $FF, like FCEUX
+	Alternating,
+	// Same bytes for the same seed, so movies stay in sync
+	Random(u32)
+}
+
+impl RamInit {
+	pub fn fill(self, ram: &mut [u8]) {
+		match self {
+			RamInit::Zeros => ram.fill(0x00),
+			RamInit::Ones => ram.fill(0xFF),
+			RamInit::Alternating => {
+				for (i, byte) in ram.iter_mut().enumerate() {
+					*byte = if i & 4 == 0 { 0x00 } else { 0xFF };
+				}
+			},
+			RamInit::Random(seed) => {
+				// xorshift32, never seeded with 0
+				let mut state = seed.max(1);
+				for byte in ram {
+					state ^= state << 13;
+					state ^= state >> 17;
+					state ^= state << 5;
+					*byte = state as u8;
+				}
+			}
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+	pub sample_rate: u32,
+	// High and low pass filters of the console output
+	pub filters: bool
+}
+
+impl Default for AudioConfig {
+	fn default() -> Self {
+		AudioConfig {
+			sample_rate: 44_100,
+			filters: true
+		}
+	}
+}
+
+// Frontend key names of one controller, an empty name leaves the button unbound
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Bindings {
+	pub a: String,
+	pub b: String,
+	pub select: String,
+	pub start: String,
+	pub up: String,
+	pub down: String,
+	pub left: String,
+	pub right: String
+}
+
+impl Bindings {
+	// Arrows, Z and X for B and A
+	pub fn keyboard() -> Bindings {
+		Bindings {
+			a: String::from("X"),
+			b: String::from("Z"),
+			select: String::from("Right Shift"),
+			start: String::from("Return"),
+			up: String::from("Up"),
+			down: String::from("Down"),
+			left: String::from("Left"),
+			right: String::from("Right")
+		}
+	}
+
+	pub fn key(&self, button: Button) -> &str {
+		match button {
+			Button::A => &self.a,
+			Button::B => &self.b,
+			Button::Select => &self.select,
+			Button::Start => &self.start,
+			Button::Up => &self.up,
+			Button::Down => &self.down,
+			Button::Left => &self.left,
+			Button::Right => &self.right
+		}
+	}
+
+	// Button bound to `key`, names are compared ignoring case
+	pub fn button(&self, key: &str) -> Option<Button> {
+		Button::ALL.into_iter().find(|&button| !self.key(button).is_empty() && self.key(button).eq_ignore_ascii_case(key))
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+	pub player1: Bindings,
+	pub player2: Bindings
+}
+
+impl Default for InputConfig {
+	fn default() -> Self {
+		InputConfig {
+			player1: Bindings::keyboard(),
+			player2: Bindings::default()
+		}
+	}
+}
+
+// Hardware behaviors that help developing homebrew but break some games
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccuracyConfig {
+	// Panic on accesses where nothing is mapped
+	pub strict_unmapped: bool,
+	// Panic on reads of write-only registers and writes to read-only ones
+	pub strict_write_only: bool
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	// Console the game runs on, the one of the ROM header when missing
+	pub region: Option<Region>,
+	// .pal file replacing the built-in NTSC palette
+	pub palette: Option<PathBuf>,
+	pub ram_init: RamInit,
+	pub audio: AudioConfig,
+	pub input: InputConfig,
+	pub accuracy: AccuracyConfig
+}
+
+impl Config {
+	pub fn from_toml(text: &str) -> Result<Config, ConfigError> {
+		toml::from_str(text).map_err(|error| ConfigError::Parse(error.to_string()))
+	}
+
+	pub fn to_toml(&self) -> String {
+		toml::to_string(self).expect("the config types all serialize to TOML")
+	}
+
+	pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+		let path = path.as_ref();
+		let text = fs::read_to_string(path).map_err(|error| ConfigError::Io(path.to_path_buf(), error))?;
+		Config::from_toml(&text)
+	}
+
+	// Defaults when the file does not exist yet
+	pub fn load_or_default<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+		match Config::load(path) {
+			Err(ConfigError::Io(_, error)) if error.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+			result => result
+		}
+	}
+
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfigError> {
+		let path = path.as_ref();
+		fs::write(path, self.to_toml()).map_err(|error| ConfigError::Io(path.to_path_buf(), error))
+	}
+
+	// Set up a console fresh from Nes::new, before its first frame
+	pub fn apply(&self, nes: &mut Nes) -> Result<(), ConfigError> {
+		if let Some(region) = self.region {
+			nes.set_region(region);
+		}
+		if let Some(path) = &self.palette {
+			let bytes = fs::read(path).map_err(|error| ConfigError::Io(path.clone(), error))?;
+			let palette = Palette::from_pal_bytes(&bytes).ok_or_else(|| ConfigError::Palette(path.clone()))?;
+			nes.bus_mut().ppu_mut().set_palette(palette);
+		}
+
+		self.ram_init.fill(nes.bus_mut().cpu_ram_mut());
+
+		let apu = nes.bus_mut().apu_mut();
+		apu.set_sample_rate(self.audio.sample_rate);
+		apu.set_filter_config(if self.audio.filters { FilterConfig::nes() } else { FilterConfig::disabled() });
+
+		nes.bus_mut().set_config(BusConfig {
+			strict_unmapped: self.accuracy.strict_unmapped,
+			strict_write_only: self.accuracy.strict_write_only
+		});
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		let config = Config::from_toml("").unwrap();
+		assert_eq!(config, Config::default());
+		assert_eq!(Config::from_toml(&config.to_toml()).unwrap(), config);
+
+		let config = Config::from_toml(r#"
+			region = "pal"
+			ram_init = { random = 7 }
+
+			[audio]
+			sample_rate = 48000
+
+			[input.player2]
+			a = "K"
+		"#).unwrap();
+		assert_eq!(config.region, Some(Region::Pal));
+		assert_eq!(config.ram_init, RamInit::Random(7));
+		assert_eq!(config.audio, AudioConfig { sample_rate: 48000, filters: true });
+		assert_eq!(config.input.player1, Bindings::keyboard());
+		assert_eq!(config.input.player2.button("k"), Some(Button::A));
+		assert_eq!(config.input.player2.button(""), None);
+		assert_eq!(Config::from_toml(&config.to_toml()).unwrap(), config);
+
+		assert!(matches!(Config::from_toml("region = \"secam\""), Err(ConfigError::Parse(_))));
+		assert!(matches!(Config::from_toml("[audio]\nvolume = 2"), Err(ConfigError::Parse(_))));
+	}
+
+	#[test]
+	fn ram_init() {
+		let mut ram = [0x55; 16];
+		RamInit::Alternating.fill(&mut ram);
+		assert_eq!(ram[..8], [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+		let mut other = [0; 16];
+		RamInit::Random(3).fill(&mut ram);
+		RamInit::Random(3).fill(&mut other);
+		assert_eq!(ram, other);
+		assert!(ram.iter().any(|&byte| byte != ram[0]));
+	}
+}
@@ -2,7 +2,12 @@ use core::panic;
 use std::fmt;
 
 use crate::bus::Bus;
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::debugger::labels::Labels;
+use crate::debugger::call_stack::{CallFrame, CallKind, CallStack, StackMismatch};
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cpu {
 	pub pc: u16,
 	sp: u8,
@@ -21,7 +26,12 @@ pub struct Cpu {
 	z: u8,
 	c: u8,
 
-	extra_cycle: u8
+	extra_cycle: u8,
+	cycles: u64,
+
+	// Shadow call stack, only kept when enabled
+	#[cfg_attr(feature = "serde", serde(skip))]
+	call_stack: Option<CallStack>
 }
 
 #[derive(Debug)]
@@ -121,6 +131,12 @@ enum AddrMode {
 	None
 }
 
+impl Default for Cpu {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
 impl Cpu {
 	pub fn new() -> Cpu {
 		Cpu {
@@ -140,6 +156,9 @@ impl Cpu {
 			c: 0,
 
 			extra_cycle: 0,
+			cycles: 0,
+
+			call_stack: None
 		}
 	}
 
@@ -148,6 +167,125 @@ impl Cpu {
 		self.set_status(0b100100);
 
 		self.pc = bus.read_u16(0xFFFC);
+		if let Some(call_stack) = &mut self.call_stack {
+			*call_stack = CallStack::new();
+		}
+
+		// Reset sequence takes 7 cycles
+		self.cycles = 7;
+		bus.tick(7);
+	}
+
+	pub fn cycles(&self) -> u64 {
+		self.cycles
+	}
+
+	pub fn a(&self) -> u8 {
+		self.a
+	}
+
+	pub fn set_a(&mut self, value: u8) {
+		self.a = value;
+	}
+
+	pub fn x(&self) -> u8 {
+		self.x
+	}
+
+	pub fn set_x(&mut self, value: u8) {
+		self.x = value;
+	}
+
+	pub fn y(&self) -> u8 {
+		self.y
+	}
+
+	pub fn set_y(&mut self, value: u8) {
+		self.y = value;
+	}
+
+	pub fn sp(&self) -> u8 {
+		self.sp
+	}
+
+	pub fn set_sp(&mut self, value: u8) {
+		self.sp = value;
+	}
+
+	// P register, NV-BDIZC
+	pub fn status(&self) -> u8 {
+		self.get_status()
+	}
+
+	// Registers and cycle count for save states, the call stack is debugger state and is kept
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_u16(self.pc);
+		state.write_u8(self.sp);
+		state.write_u8(self.a);
+		state.write_u8(self.x);
+		state.write_u8(self.y);
+		state.write_u8(self.get_status());
+		state.write_u8(self.extra_cycle);
+		state.write_u64(self.cycles);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.pc = state.read_u16()?;
+		self.sp = state.read_u8()?;
+		self.a = state.read_u8()?;
+		self.x = state.read_u8()?;
+		self.y = state.read_u8()?;
+		self.set_status(state.read_u8()?);
+		self.extra_cycle = state.read_u8()?;
+		self.cycles = state.read_u64()?;
+		Ok(())
+	}
+
+	// Take over deserialized registers, the call stack tracking stays as it is
+	#[cfg(feature = "serde")]
+	pub(crate) fn restore(&mut self, state: Cpu) {
+		let call_stack = self.call_stack.take();
+		*self = state;
+		self.call_stack = call_stack;
+	}
+
+	pub fn set_call_stack_tracking(&mut self, enabled: bool) {
+		self.call_stack = enabled.then(CallStack::new);
+	}
+
+	// Innermost frame last, empty when tracking is off
+	pub fn call_stack(&self) -> &[CallFrame] {
+		self.call_stack.as_ref().map_or(&[], |call_stack| call_stack.frames())
+	}
+
+	// Last RTS or RTI that did not return to its caller
+	pub fn stack_mismatch(&self) -> Option<StackMismatch> {
+		self.call_stack.as_ref().and_then(|call_stack| call_stack.last_mismatch())
+	}
+
+	fn enter_frame(&mut self, kind: CallKind, return_adress: u16, sp: u8) {
+		let target = self.pc;
+		if let Some(call_stack) = &mut self.call_stack {
+			call_stack.enter(CallFrame { kind, target, return_adress, sp });
+		}
+	}
+
+	fn leave_frame(&mut self, pc: u16) {
+		let (sp, actual) = (self.sp, self.pc);
+		if let Some(call_stack) = &mut self.call_stack {
+			call_stack.leave(pc, sp, actual);
+		}
+	}
+
+	// Jump to a subroutine as if a JSR at `return_adress` - 3 was executed, RTS resumes at `return_adress`
+	pub fn call(&mut self, bus: &mut Bus, adress: u16, return_adress: u16) {
+		let sp = self.sp;
+		let return_pc = return_adress.wrapping_sub(1);
+		self.stack_push(bus, (return_pc >> 8) as u8);
+		self.stack_push(bus, (return_pc & 0x00FF) as u8);
+
+		self.pc = adress;
+		self.enter_frame(CallKind::Jsr, return_adress, sp);
 	}
 
 	pub fn run(&mut self, bus: &mut Bus)
@@ -162,20 +300,87 @@ impl Cpu {
 		loop {
 			callback(self, bus);
 
+			bus.set_current_pc(self.pc);
 			let opcode = self.fetch(bus);
 
-			let (instr, addr_mode, _, _) = self.decode(opcode);
+			let (instr, addr_mode, length, cycles) = self.decode(opcode);
 			if let Instruction::Brk = instr {
 				break;
 			}
 
-			self.extra_cycle = 0;
-			self.execute(bus, &instr, &addr_mode);
+			self.run_instruction(bus, &instr, &addr_mode, length, cycles);
+		}
+	}
+
+	// Execute the next instruction, return the number of cycles it took
+	pub fn step(&mut self, bus: &mut Bus) -> u16 {
+		bus.set_current_pc(self.pc);
+		let opcode = self.fetch(bus);
+		let (instr, addr_mode, length, cycles) = self.decode(opcode);
+
+		self.run_instruction(bus, &instr, &addr_mode, length, cycles)
+	}
+
+	fn run_instruction(&mut self, bus: &mut Bus, instr: &Instruction, addr_mode: &AddrMode, length: u8, cycles: u8) -> u16 {
+		// CLI, SEI and PLP only affect IRQ polling after the next instruction, RTI right away
+		let previous_i = self.i;
+
+		let indirect = matches!(addr_mode, AddrMode::XIndexedZeroPageIndirect | AddrMode::ZeroPageIndirectYIndexed);
+		bus.log_instruction(self.pc.wrapping_sub(1), length, indirect);
+
+		self.extra_cycle = 0;
+		self.execute(bus, instr, addr_mode);
+		if let (Instruction::Jmp, AddrMode::AbsoluteIndirect) = (instr, addr_mode) {
+			bus.log_indirect_jump(self.pc);
+		}
+
+		let cycles = cycles + self.additional_cycles(instr);
+		bus.tick(cycles);
+		let mut cycles = u16::from(cycles) + bus.take_stall_cycles();
+
+		let irq_inhibit = if let Instruction::Rti = instr { self.i } else { previous_i };
+		if let Some(interrupt) = bus.poll_interrupt(irq_inhibit != 0) {
+			cycles += self.interrupt(bus, interrupt.vector());
+		}
+
+		self.cycles += u64::from(cycles);
+		cycles
+	}
+
+	// Page crossing and taken branches cost extra cycles
+	fn additional_cycles(&self, instr: &Instruction) -> u8 {
+		match instr {
+			Instruction::Adc | Instruction::And | Instruction::Cmp | Instruction::Eor |
+			Instruction::Lda | Instruction::Ldx | Instruction::Ldy | Instruction::Ora |
+			Instruction::Sbc | Instruction::Lax |
+			Instruction::Bcc | Instruction::Bcs | Instruction::Beq | Instruction::Bmi |
+			Instruction::Bne | Instruction::Bpl | Instruction::Bvc | Instruction::Bvs => self.extra_cycle,
+			_ => 0
 		}
 	}
 
+	// NMI and IRQ sequence, `vector` holds the handler address
+	fn interrupt(&mut self, bus: &mut Bus, vector: u16) -> u16 {
+		let (return_adress, sp) = (self.pc, self.sp);
+		let low_pc = (self.pc & 0x00FF) as u8;
+		let high_pc = (self.pc >> 8) as u8;
+
+		self.stack_push(bus, high_pc);
+		self.stack_push(bus, low_pc);
+		let p = self.get_status();
+		self.stack_push(bus, p & 0b1110_1111); // Clear B
+		self.i = 1;
+
+		self.pc = bus.read_u16(vector);
+		let kind = if vector == 0xFFFA { CallKind::Nmi } else { CallKind::Irq };
+		self.enter_frame(kind, return_adress, sp);
+		bus.tick(7);
+
+		7
+	}
+
 	#[allow(dead_code)]
-	pub fn load_and_run(&mut self, bus: &mut Bus, pgr: &Vec<u8>) {
+	pub fn load_and_run(&mut self, bus: &mut Bus, pgr: &[u8]) {
 		for i in 0..(pgr.len() as u16) {
 			bus.write(0x0200 + i, pgr[i as usize]);
 		}
@@ -198,7 +403,7 @@ impl Cpu {
 		bus.read(0x0100 + u16::from(self.sp))
 	}
 
-	fn set_status(&mut self, p: u8) {
+	pub fn set_status(&mut self, p: u8) {
 		self.n = p >> 7;
 		self.v = (p & 0x40) >> 6;
 		self.b = (p & 0x10) >> 4;
@@ -298,7 +503,7 @@ impl Cpu {
 		adress
 	}
 
-	fn decode(&mut self, opcode: u8) -> (Instruction, AddrMode, u8, u8) {
+	fn decode(&self, opcode: u8) -> (Instruction, AddrMode, u8, u8) {
 		match opcode {
 			0x69 => (Instruction::Adc, AddrMode::Immediate, 2, 2),
 			0x6D => (Instruction::Adc, AddrMode::Absolute, 3, 4),
@@ -801,6 +1006,7 @@ impl Cpu {
 	}
 
 	fn apply_brk_op(&mut self, bus: &mut Bus) {
+		let sp = self.sp;
 		self.pc += 2;
 		let low_pc = u8::try_from(self.pc & 0x00FF).unwrap();
 		let high_pc = u8::try_from((self.pc & 0xFF00) >> 8).unwrap();
@@ -810,7 +1016,9 @@ impl Cpu {
 		//let p = self.get_status();
 		//self.stack_push(bus, p);
 
+		let return_adress = self.pc;
 		self.pc = bus.read_u16(0xFFFE);
+		self.enter_frame(CallKind::Brk, return_adress, sp);
 	}
 
 	fn apply_cmp_op(&mut self, register: u8, bus: &mut Bus, addr_mode: &AddrMode) {
@@ -893,6 +1101,7 @@ impl Cpu {
 
 	fn apply_jsr_op(&mut self, bus: &mut Bus, addr_mode: &AddrMode) {
 		let adress = self.get_op_adress(bus, addr_mode);
+		let (return_adress, sp) = (self.pc, self.sp);
 		let low_pc = u8::try_from((self.pc - 1) & 0x00FF).unwrap();
 		let high_pc = u8::try_from(((self.pc - 1) & 0xFF00) >> 8).unwrap();
 
@@ -900,6 +1109,7 @@ impl Cpu {
 		self.stack_push(bus, low_pc);
 
 		self.pc = adress;
+		self.enter_frame(CallKind::Jsr, return_adress, sp);
 	}
 
 	fn apply_ld_op(&mut self, bus: &mut Bus, addr_mode: &AddrMode) -> u8 {
@@ -1008,19 +1218,23 @@ impl Cpu {
 	}
 
 	fn apply_rti_op(&mut self, bus: &mut Bus) {
+		let pc = self.pc.wrapping_sub(1);
 		let p = self.stack_pop(bus);
 		let low_pc = u16::from(self.stack_pop(bus));
 		let high_pc = u16::from(self.stack_pop(bus));
 
 		self.pc = (high_pc << 8) + low_pc;
 		self.set_status(p);
+		self.leave_frame(pc);
 	}
 
 	fn apply_rts_op(&mut self, bus: &mut Bus) {
+		let pc = self.pc.wrapping_sub(1);
 		let low_pc = u16::from(self.stack_pop(bus));
 		let high_pc = u16::from(self.stack_pop(bus));
 
 		self.pc = (high_pc << 8) + low_pc + 1;
+		self.leave_frame(pc);
 	}
 
 	fn apply_sbc_op(&mut self, bus: &mut Bus, addr_mode: &AddrMode) {
@@ -1035,7 +1249,7 @@ impl Cpu {
 		let (result, overflowed_2) = u8::overflowing_add(temp, self.c);
 		
 		self.c = u8::from(overflowed_1 || overflowed_2);
-		self.v =  u8::from(!(((self.a ^ value) & 0x80) != 0) && (((self.a ^ result) & 0x80) != 0));
+		self.v =  u8::from((((self.a ^ value) & 0x80) == 0) && (((self.a ^ result) & 0x80) != 0));
 		self.n = result >> 7;
 		self.z = u8::from(result == 0);
 		
@@ -1094,7 +1308,7 @@ impl Cpu {
 		let result = value << 1;
 		bus.write(adress, result);
 
-		self.a = self.a | result;
+		self.a |= result;
 		self.z = u8::from(self.a == 0);
 		self.n = self.a >> 7;
 		self.c = value >> 7;
@@ -1108,7 +1322,7 @@ impl Cpu {
 
 		self.c = value & 0x01;
 		// EOR
-		self.a = self.a ^ result;
+		self.a ^= result;
 		self.z = u8::from(self.a == 0);
 		self.n = self.a >> 7;
 	}
@@ -1119,7 +1333,7 @@ impl Cpu {
 		let result = value << 1 | (self.c & 0x01);
 		bus.write(adress, result);
 
-		self.a = self.a & result;
+		self.a &= result;
 		self.z = u8::from(self.a == 0);
 		self.n = self.a >> 7;
 		self.c = value >> 7;
@@ -1137,10 +1351,51 @@ impl Cpu {
 	}
 }
 
-pub fn trace(cpu: &mut Cpu, bus: &mut Bus) -> String {
+// Effective address of the instruction at `pc`, read with peek so tracing has no side effects
+fn peek_op_adress(cpu: &Cpu, bus: &Bus, pc: u16, addr_mode: &AddrMode) -> u16 {
+	let arg = bus.peek(pc.wrapping_add(1));
+	let absolute = u16::from(arg) | (u16::from(bus.peek(pc.wrapping_add(2))) << 8);
+	let peek_u16_zero_page = |pointer: u8| u16::from(bus.peek(u16::from(pointer))) | (u16::from(bus.peek(u16::from(pointer.wrapping_add(1)))) << 8);
+
+	match addr_mode {
+		AddrMode::Immediate => pc.wrapping_add(1),
+		AddrMode::Absolute => absolute,
+		AddrMode::XIndexedAbsolute => absolute.wrapping_add(u16::from(cpu.x)),
+		AddrMode::YIndexedAbsolute => absolute.wrapping_add(u16::from(cpu.y)),
+		AddrMode::AbsoluteIndirect => {
+			let high_indirect = (absolute & 0xFF00) | (absolute.wrapping_add(1) & 0x00FF); // Do not increment page
+			u16::from(bus.peek(absolute)) | (u16::from(bus.peek(high_indirect)) << 8)
+		},
+		AddrMode::ZeroPage => u16::from(arg),
+		AddrMode::XIndexedZeroPage => u16::from(arg.wrapping_add(cpu.x)),
+		AddrMode::YIndexedZeroPage => u16::from(arg.wrapping_add(cpu.y)),
+		AddrMode::XIndexedZeroPageIndirect => peek_u16_zero_page(arg.wrapping_add(cpu.x)),
+		AddrMode::ZeroPageIndirectYIndexed => peek_u16_zero_page(arg).wrapping_add(u16::from(cpu.y)),
+		AddrMode::Relative => pc.wrapping_add(2).wrapping_add(arg as i8 as u16),
+		_ => panic!("Adress mode '{:?}' not usable to get adress", addr_mode)
+	}
+}
+
+pub fn trace(cpu: &Cpu, bus: &Bus) -> String {
+	trace_with_labels(cpu, bus, &Labels::new())
+}
+
+// Operand addresses with a label are shown by name, `JSR reset_handler` for `JSR $8000`
+pub fn trace_with_labels(cpu: &Cpu, bus: &Bus, labels: &Labels) -> String {
+	let (hex_str, asm_str) = disassemble(cpu, bus, labels);
+
+	// Same columns as nestest.log, PPU is the scanline and dot before the instruction
+	format!("{:04X}  {:<8} {:<31}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+		cpu.pc, hex_str, asm_str, cpu.a, cpu.x, cpu.y, cpu.get_status(), cpu.sp, bus.ppu().scanline(), bus.ppu().dot(), cpu.cycles)
+}
+
+// Bytes and assembly of the instruction at PC, with the nestest annotations of the operand
+pub(crate) fn disassemble(cpu: &Cpu, bus: &Bus, labels: &Labels) -> (String, String) {
 	let pc = cpu.pc;
+	let zero_page = |adress: u8| labels.get(u16::from(adress), bus).map_or_else(|| format!("${:02X}", adress), String::from);
+	let absolute = |adress: u16| labels.get(adress, bus).map_or_else(|| format!("${:04X}", adress), String::from);
 	
-	let opcode = cpu.fetch(bus);
+	let opcode = bus.peek(pc);
 
 	let (instr, addr_mode, size, _) = cpu.decode(opcode);
 
@@ -1151,42 +1406,42 @@ pub fn trace(cpu: &mut Cpu, bus: &mut Bus) -> String {
 			_ => String::from("")
 		},
 		2 => {
-			let arg = bus.read(pc + 1);
+			let arg = bus.peek(pc + 1);
 			hex_codes.push(arg);
 
-			let adress = cpu.get_op_adress(bus, &addr_mode);
+			let adress = peek_op_adress(cpu, bus, pc, &addr_mode);
 			match addr_mode {
-				AddrMode::Immediate => format!("#${:02x}", arg),
-				AddrMode::ZeroPage => format!("${:02x} = {:02x}", arg, bus.read(adress)),
-				AddrMode::XIndexedZeroPage => format!("${:02x},X @ {:02x} = {:02x}", arg, adress, bus.read(adress)),
-				AddrMode::YIndexedZeroPage => format!("${:02x},Y @ {:02x} = {:02x}", arg, adress, bus.read(adress)),
-				AddrMode::XIndexedZeroPageIndirect => format!("(${:02x},X) @ {:02x} = {:04x} = {:02x}", arg, cpu.x.wrapping_add(arg), adress, bus.read(adress)),
+				AddrMode::Immediate => format!("#${:02X}", arg),
+				AddrMode::ZeroPage => format!("{} = {:02X}", zero_page(arg), bus.peek(adress)),
+				AddrMode::XIndexedZeroPage => format!("{},X @ {:02X} = {:02X}", zero_page(arg), adress, bus.peek(adress)),
+				AddrMode::YIndexedZeroPage => format!("{},Y @ {:02X} = {:02X}", zero_page(arg), adress, bus.peek(adress)),
+				AddrMode::XIndexedZeroPageIndirect => format!("({},X) @ {:02X} = {:04X} = {:02X}", zero_page(arg), cpu.x.wrapping_add(arg), adress, bus.peek(adress)),
 				AddrMode::ZeroPageIndirectYIndexed => {
-					let lo = u16::from(bus.read(arg as u16));
-					let hi = u16::from(bus.read(arg.wrapping_add(1) as u16));
+					let lo = u16::from(bus.peek(arg as u16));
+					let hi = u16::from(bus.peek(arg.wrapping_add(1) as u16));
 					let indirect = lo + (hi << 8);
-					format!("(${:02x}),Y = {:04x} @ {:04x} = {:02x}", arg, indirect, adress, bus.read(adress))
+					format!("({}),Y = {:04X} @ {:04X} = {:02X}", zero_page(arg), indirect, adress, bus.peek(adress))
 				},
-				AddrMode::Relative =>  format!("${:04x}", adress),
+				AddrMode::Relative => absolute(adress),
 				_ => panic!("Unexpected addressing mode {:?} with instruction's size {}", addr_mode, size)
 			}
 		},
 		3 => {
-			let lo_byte = bus.read(pc + 1);
-			let hi_byte = bus.read(pc + 2);
+			let lo_byte = bus.peek(pc + 1);
+			let hi_byte = bus.peek(pc + 2);
 			hex_codes.push(lo_byte);
 			hex_codes.push(hi_byte);
 			let arg = u16::from(lo_byte) + (u16::from(hi_byte) << 8);
 
-			let adress = cpu.get_op_adress(bus, &addr_mode);
+			let adress = peek_op_adress(cpu, bus, pc, &addr_mode);
 			match addr_mode {
 				AddrMode::Absolute => match instr {
-					Instruction::Jmp | Instruction::Jsr => format!("${:04x}", adress),
-					_ => format!("${:04x} = {:02x}", adress, bus.read(adress))
+					Instruction::Jmp | Instruction::Jsr => absolute(adress),
+					_ => format!("{} = {:02X}", absolute(adress), bus.peek(adress))
 				},
-				AddrMode::XIndexedAbsolute => format!("${:04x},X @ {:04x} = {:02x}", arg, adress, bus.read(adress)),
-				AddrMode::YIndexedAbsolute => format!("${:04x},Y @ {:04x} = {:02x}", arg, adress, bus.read(adress)),
-				AddrMode::AbsoluteIndirect => format!("(${:04x}) = {:04x}", arg, adress),
+				AddrMode::XIndexedAbsolute => format!("{},X @ {:04X} = {:02X}", absolute(arg), adress, bus.peek(adress)),
+				AddrMode::YIndexedAbsolute => format!("{},Y @ {:04X} = {:02X}", absolute(arg), adress, bus.peek(adress)),
+				AddrMode::AbsoluteIndirect => format!("({}) = {:04X}", absolute(arg), adress),
 				_ => panic!("Unexpected addressing mode {:?} with instruction's size {}", addr_mode, size)
 			}
 		},
@@ -1199,15 +1454,16 @@ pub fn trace(cpu: &mut Cpu, bus: &mut Bus) -> String {
 		_ => " "
 	};
 
-	let hex_str = hex_codes.iter().map(|i| format!("{:02x}", i)).collect::<Vec<String>>().join(" ");
-	let asm_str = format!("{}{} {}", instr_prefix, instr, asm_suffix);
+	let hex_str = hex_codes.iter().map(|i| format!("{:02X}", i)).collect::<Vec<String>>().join(" ");
+	// Labels keep their case
+	let asm_str = format!("{}{} {}", instr_prefix, instr.to_string().to_ascii_uppercase(), asm_suffix);
 
-	cpu.pc = pc;
-
-	format!("{:04x}  {:<8} {:<31}  A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}", pc, hex_str, asm_str, cpu.a, cpu.x, cpu.y, cpu.get_status(), cpu.sp).to_ascii_uppercase()
+	(hex_str, asm_str)
 }
 
 #[cfg(test)]
+// Tests kept as written before clippy ran on the crate
+#[allow(clippy::bool_assert_comparison, clippy::useless_vec)]
 mod tests {
 	use crate::rom::test;
 
@@ -1377,4 +1633,118 @@ mod tests {
 		assert_eq!(cpu.i, 1);
 		assert_eq!(cpu.get_status(), 0b0010_0100);
     }
+
+	#[test]
+	fn irq() {
+		let mut cpu = Cpu::new();
+		let mut bus = Bus::new(test::test_rom());
+		bus.write(0x0200, 0xEA); // NOP
+		bus.write(0x0201, 0xEA);
+		cpu.pc = 0x0200;
+
+		// Frame IRQ of the APU
+		for _ in 0..117 {
+			bus.tick(255);
+		}
+		assert!(bus.poll_irq_status());
+
+		// Masked by the I flag
+		cpu.i = 1;
+		cpu.step(&mut bus);
+		assert_eq!(cpu.pc, 0x0201);
+
+		cpu.i = 0;
+		assert_eq!(cpu.step(&mut bus), 2 + 7);
+		assert_eq!(cpu.pc, 0x0000); // Vector of the empty test ROM
+		assert_eq!(cpu.i, 1);
+		assert_eq!(bus.read(0x01FB) & 0x10, 0x00); // B clear in pushed status
+		assert_eq!(bus.read(0x01FC), 0x02);
+		assert_eq!(bus.read(0x01FD), 0x02);
+	}
+
+	#[test]
+	fn call_stack() {
+		let mut cpu = Cpu::new();
+		let mut bus = Bus::new(test::test_rom());
+		// $0200: JSR $0210; $0210: JSR $0220; $0220: RTS
+		for (adress, value) in [(0x0200, 0x20), (0x0201, 0x10), (0x0202, 0x02), (0x0210, 0x20), (0x0211, 0x20), (0x0212, 0x02), (0x0220, 0x60)] {
+			bus.write(adress, value);
+		}
+		cpu.pc = 0x0200;
+
+		// Off by default
+		cpu.step(&mut bus);
+		assert!(cpu.call_stack().is_empty());
+
+		cpu.pc = 0x0200;
+		cpu.sp = 0xFD;
+		cpu.set_call_stack_tracking(true);
+		cpu.step(&mut bus);
+		cpu.step(&mut bus);
+		let frames = cpu.call_stack();
+		assert_eq!(frames.len(), 2);
+		assert_eq!(frames[0], CallFrame { kind: CallKind::Jsr, target: 0x0210, return_adress: 0x0203, sp: 0xFD });
+		assert_eq!(frames[1], CallFrame { kind: CallKind::Jsr, target: 0x0220, return_adress: 0x0213, sp: 0xFB });
+
+		cpu.step(&mut bus);
+		assert_eq!(cpu.pc, 0x0213);
+		assert_eq!(cpu.call_stack().len(), 1);
+		assert_eq!(cpu.stack_mismatch(), None);
+	}
+
+	#[test]
+	fn trace_timing_columns() {
+		let mut cpu = Cpu::new();
+		let mut bus = Bus::new(test::test_rom());
+		bus.write(0x0200, 0xEA); // NOP
+		cpu.reset(&mut bus);
+		cpu.pc = 0x0200;
+
+		// nestest.log starts at 7 cycles, dot 21
+		let line = trace(&cpu, &bus);
+		assert!(line.ends_with("P:24 SP:FD PPU:  0, 21 CYC:7"), "{}", line);
+
+		cpu.step(&mut bus);
+		let line = trace(&cpu, &bus);
+		assert!(line.ends_with("PPU:  0, 27 CYC:9"), "{}", line);
+	}
+
+	#[test]
+	fn trace_labels() {
+		let mut cpu = Cpu::new();
+		let mut bus = Bus::new(test::test_rom());
+		bus.write(0x0200, 0x20); // JSR $0280
+		bus.write(0x0201, 0x80);
+		bus.write(0x0202, 0x02);
+		bus.write(0x0280, 0xA5); // LDA $10
+		bus.write(0x0281, 0x10);
+		cpu.pc = 0x0200;
+
+		let mut labels = Labels::new();
+		labels.insert(0x0280, "update_player");
+		labels.insert(0x0010, "player_x");
+
+		let line = trace_with_labels(&cpu, &bus, &labels);
+		assert!(line.starts_with("0200  20 80 02  JSR update_player"), "{}", line);
+		cpu.pc = 0x0280;
+		let line = trace_with_labels(&cpu, &bus, &labels);
+		assert!(line.starts_with("0280  A5 10     LDA player_x = 00"), "{}", line);
+	}
+
+	#[test]
+	fn trace_does_not_step() {
+		let mut cpu = Cpu::new();
+		let mut bus = Bus::new(test::test_rom());
+		bus.write(0x0200, 0xB1); // LDA ($10),Y
+		bus.write(0x0201, 0x10);
+		bus.write(0x0010, 0x00);
+		bus.write(0x0011, 0x03);
+		bus.write(0x0302, 0x5A);
+		cpu.pc = 0x0200;
+		cpu.y = 0x02;
+
+		let line = trace(&cpu, &bus);
+		assert!(line.starts_with("0200  B1 10     LDA ($10),Y = 0300 @ 0302 = 5A"), "{}", line);
+		assert_eq!(cpu.pc, 0x0200);
+	}
 }
\ No newline at end of file
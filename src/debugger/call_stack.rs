@@ -0,0 +1,108 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallKind {
+	Jsr,
+	Nmi,
+	Irq,
+	Brk
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallFrame {
+	pub kind: CallKind,
+	// Subroutine or handler entered
+	pub target: u16,
+	// Where the matching RTS or RTI should resume
+	pub return_adress: u16,
+	// Stack pointer before the return adress was pushed
+	pub sp: u8
+}
+
+// A return that did not land where the shadow stack expected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StackMismatch {
+	// RTS or RTI instruction
+	pub pc: u16,
+	// None when no frame was open at that depth, like an RTS jump table
+	pub expected: Option<u16>,
+	pub actual: u16
+}
+
+// Shadow stack of the calls and interrupts the CPU went through, kept next to the real one
+#[derive(Clone, Default)]
+pub struct CallStack {
+	frames: Vec<CallFrame>,
+	last_mismatch: Option<StackMismatch>
+}
+
+impl CallStack {
+	pub fn new() -> CallStack {
+		CallStack::default()
+	}
+
+	pub fn frames(&self) -> &[CallFrame] {
+		&self.frames
+	}
+
+	pub fn last_mismatch(&self) -> Option<StackMismatch> {
+		self.last_mismatch
+	}
+
+	pub(crate) fn enter(&mut self, frame: CallFrame) {
+		self.frames.push(frame);
+	}
+
+	// `sp` and `actual` are the stack pointer and PC once the return is done
+	pub(crate) fn leave(&mut self, pc: u16, sp: u8, actual: u16) {
+		// Frames at or below the new depth are gone, even when the code dropped them by hand
+		let mut matched = None;
+		while let Some(frame) = self.frames.last().copied() {
+			if frame.sp > sp {
+				break;
+			}
+			self.frames.pop();
+			if frame.sp == sp {
+				matched = Some(frame);
+				break;
+			}
+		}
+
+		let expected = matched.map(|frame| frame.return_adress);
+		if expected != Some(actual) {
+			self.last_mismatch = Some(StackMismatch { pc, expected, actual });
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn jsr(target: u16, return_adress: u16, sp: u8) -> CallFrame {
+		CallFrame { kind: CallKind::Jsr, target, return_adress, sp }
+	}
+
+	#[test]
+	fn matched_returns() {
+		let mut stack = CallStack::new();
+		stack.enter(jsr(0x8100, 0x8003, 0xFD));
+		stack.enter(jsr(0x8200, 0x8103, 0xFB));
+
+		stack.leave(0x8200, 0xFB, 0x8103);
+		assert_eq!(stack.frames(), &[jsr(0x8100, 0x8003, 0xFD)]);
+		stack.leave(0x8105, 0xFD, 0x8003);
+		assert!(stack.frames().is_empty());
+		assert_eq!(stack.last_mismatch(), None);
+	}
+
+	#[test]
+	fn corrupted_return() {
+		let mut stack = CallStack::new();
+		stack.enter(jsr(0x8100, 0x8003, 0xFD));
+		stack.enter(jsr(0x8200, 0x8103, 0xFB));
+
+		// The inner return adress was pulled off, the RTS leaves both frames
+		stack.leave(0x8205, 0xFD, 0x9000);
+		assert!(stack.frames().is_empty());
+		assert_eq!(stack.last_mismatch(), Some(StackMismatch { pc: 0x8205, expected: Some(0x8003), actual: 0x9000 }));
+	}
+}
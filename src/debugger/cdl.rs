@@ -0,0 +1,155 @@
+use std::fmt;
+
+use crate::rom::Rom;
+
+// PRG byte flags of the FCEUX .cdl format
+pub const CODE: u8 = 0x01;
+pub const DATA: u8 = 0x02;
+// Window the byte was mapped in when last accessed, 0 for $8000 to 3 for $E000
+pub const BANK_MASK: u8 = 0x0C;
+// Jumped to through JMP ($nnnn)
+pub const INDIRECT_CODE: u8 = 0x10;
+// Read through ($nn,X) or ($nn),Y
+pub const INDIRECT_DATA: u8 = 0x20;
+// Fetched by the DMC as sample data
+pub const PCM: u8 = 0x40;
+
+// CHR byte flags
+pub const RENDERED: u8 = 0x01;
+pub const CHR_READ: u8 = 0x02;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CdlError {
+	pub expected: usize,
+	pub got: usize
+}
+
+impl fmt::Display for CdlError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "code/data log is {} bytes but the ROM needs {}", self.got, self.expected)
+	}
+}
+
+impl std::error::Error for CdlError {}
+
+// Code/Data Log: one flag byte per PRG ROM byte followed by one per CHR ROM byte.
+// CHR bytes are kept for compatibility but not logged, the PPU fetches are not tracked
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeDataLog {
+	prg: Vec<u8>,
+	chr: Vec<u8>
+}
+
+impl CodeDataLog {
+	pub fn new(prg_size: usize, chr_size: usize) -> CodeDataLog {
+		CodeDataLog {
+			prg: vec![0; prg_size],
+			chr: vec![0; chr_size]
+		}
+	}
+
+	// Sized from the ROM header, empty for ROMs not loaded from a file
+	pub fn for_rom(rom: &Rom) -> CodeDataLog {
+		match &rom.info {
+			Some(info) => CodeDataLog::new(info.prg_rom_size, info.chr_rom_size),
+			None => CodeDataLog::new(0, 0)
+		}
+	}
+
+	// .cdl file contents, which do not record the split between PRG and CHR
+	pub fn parse(data: &[u8], prg_size: usize, chr_size: usize) -> Result<CodeDataLog, CdlError> {
+		if data.len() != prg_size + chr_size {
+			return Err(CdlError { expected: prg_size + chr_size, got: data.len() });
+		}
+
+		Ok(CodeDataLog {
+			prg: data[..prg_size].to_vec(),
+			chr: data[prg_size..].to_vec()
+		})
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		[self.prg.as_slice(), self.chr.as_slice()].concat()
+	}
+
+	pub fn prg(&self) -> &[u8] {
+		&self.prg
+	}
+
+	pub fn chr(&self) -> &[u8] {
+		&self.chr
+	}
+
+	// Adds the flags of a log of the same ROM, from another run
+	pub fn merge(&mut self, other: &CodeDataLog) {
+		for (flags, other) in self.prg.iter_mut().zip(&other.prg) {
+			*flags |= other;
+		}
+		for (flags, other) in self.chr.iter_mut().zip(&other.chr) {
+			*flags |= other;
+		}
+	}
+
+	pub fn clear(&mut self) {
+		self.prg.fill(0);
+		self.chr.fill(0);
+	}
+
+	// `adress` is where the CPU saw the byte at `offset` in PRG ROM
+	pub fn log_prg(&mut self, offset: usize, adress: u16, flags: u8) {
+		if let Some(byte) = self.prg.get_mut(offset) {
+			let bank = ((adress >> 13) & 0x03) as u8;
+			*byte = (*byte & !BANK_MASK) | flags | (bank << 2);
+		}
+	}
+
+	pub fn code_bytes(&self) -> usize {
+		self.prg.iter().filter(|&&flags| flags & CODE != 0).count()
+	}
+
+	pub fn data_bytes(&self) -> usize {
+		self.prg.iter().filter(|&&flags| flags & (DATA | PCM) != 0).count()
+	}
+
+	// Part of the PRG ROM accessed at all, from 0 to 1
+	pub fn coverage(&self) -> f32 {
+		if self.prg.is_empty() {
+			return 0.0;
+		}
+
+		let logged = self.prg.iter().filter(|&&flags| flags & (CODE | DATA | PCM) != 0).count();
+		logged as f32 / self.prg.len() as f32
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		let mut cdl = CodeDataLog::new(4, 2);
+		cdl.log_prg(0, 0xE000, CODE);
+		cdl.log_prg(3, 0x8003, DATA | INDIRECT_DATA);
+
+		let bytes = cdl.to_bytes();
+		assert_eq!(bytes, vec![0x0D, 0x00, 0x00, 0x22, 0x00, 0x00]);
+		assert_eq!(CodeDataLog::parse(&bytes, 4, 2), Ok(cdl));
+		assert_eq!(CodeDataLog::parse(&bytes, 4, 4), Err(CdlError { expected: 8, got: 6 }));
+	}
+
+	#[test]
+	fn counts_and_merge() {
+		let mut cdl = CodeDataLog::new(4, 0);
+		cdl.log_prg(0, 0x8000, CODE);
+		cdl.log_prg(1, 0x8001, CODE);
+		let mut other = CodeDataLog::new(4, 0);
+		other.log_prg(1, 0x8001, DATA);
+		other.log_prg(2, 0x8002, PCM);
+
+		cdl.merge(&other);
+		assert_eq!(cdl.code_bytes(), 2);
+		assert_eq!(cdl.data_bytes(), 2);
+		assert_eq!(cdl.coverage(), 0.75);
+	}
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConditionError(pub String);
+
+impl fmt::Display for ConditionError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "invalid condition: {}", self.0)
+	}
+}
+
+impl std::error::Error for ConditionError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operand {
+	A,
+	X,
+	Y,
+	Sp,
+	Pc,
+	P,
+	// Byte read or written by the access that hit the breakpoint
+	Value,
+	// Memory byte, read with Bus::peek
+	Memory(u16),
+	Number(u16)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Comparison {
+	Equal,
+	NotEqual,
+	Less,
+	LessOrEqual,
+	Greater,
+	GreaterOrEqual
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Compare {
+	left: Operand,
+	comparison: Comparison,
+	right: Operand
+}
+
+// Breakpoint condition: comparisons joined by && and ||, && binding tighter.
+// Operands are A, X, Y, SP, PC, P, VALUE, [adress] and numbers in decimal, $hex or 0xhex:
+//
+//     A == 0x20 && [$0300] != 0
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+	// Any of the groups with all comparisons true
+	any: Vec<Vec<Compare>>
+}
+
+impl Condition {
+	pub fn parse(text: &str) -> Result<Condition, ConditionError> {
+		let mut any = Vec::new();
+
+		for group in text.split("||") {
+			let mut all = Vec::new();
+			for compare in group.split("&&") {
+				all.push(parse_compare(compare.trim())?);
+			}
+			any.push(all);
+		}
+
+		Ok(Condition { any })
+	}
+
+	// `value` is the byte of the memory access, 0 for execute breakpoints
+	pub fn evaluate(&self, cpu: &Cpu, bus: &Bus, value: u8) -> bool {
+		let operand = |operand: Operand| -> u16 {
+			match operand {
+				Operand::A => u16::from(cpu.a()),
+				Operand::X => u16::from(cpu.x()),
+				Operand::Y => u16::from(cpu.y()),
+				Operand::Sp => u16::from(cpu.sp()),
+				Operand::Pc => cpu.pc,
+				Operand::P => u16::from(cpu.status()),
+				Operand::Value => u16::from(value),
+				Operand::Memory(adress) => u16::from(bus.peek(adress)),
+				Operand::Number(number) => number
+			}
+		};
+
+		self.any.iter().any(|all| all.iter().all(|compare| {
+			let (left, right) = (operand(compare.left), operand(compare.right));
+			match compare.comparison {
+				Comparison::Equal => left == right,
+				Comparison::NotEqual => left != right,
+				Comparison::Less => left < right,
+				Comparison::LessOrEqual => left <= right,
+				Comparison::Greater => left > right,
+				Comparison::GreaterOrEqual => left >= right
+			}
+		}))
+	}
+}
+
+fn parse_compare(text: &str) -> Result<Compare, ConditionError> {
+	// Two character operators first, so that <= is not read as <
+	const OPERATORS: [(&str, Comparison); 6] = [
+		("==", Comparison::Equal),
+		("!=", Comparison::NotEqual),
+		("<=", Comparison::LessOrEqual),
+		(">=", Comparison::GreaterOrEqual),
+		("<", Comparison::Less),
+		(">", Comparison::Greater)
+	];
+
+	for (operator, comparison) in OPERATORS {
+		if let Some((left, right)) = text.split_once(operator) {
+			return Ok(Compare {
+				left: parse_operand(left.trim())?,
+				comparison,
+				right: parse_operand(right.trim())?
+			});
+		}
+	}
+
+	Err(ConditionError(format!("no comparison in '{}'", text)))
+}
+
+fn parse_operand(text: &str) -> Result<Operand, ConditionError> {
+	if let Some(adress) = text.strip_prefix('[').and_then(|text| text.strip_suffix(']')) {
+		return Ok(Operand::Memory(parse_number(adress.trim())?));
+	}
+
+	Ok(match text.to_ascii_uppercase().as_str() {
+		"A" => Operand::A,
+		"X" => Operand::X,
+		"Y" => Operand::Y,
+		"SP" => Operand::Sp,
+		"PC" => Operand::Pc,
+		"P" => Operand::P,
+		"VALUE" => Operand::Value,
+		_ => Operand::Number(parse_number(text)?)
+	})
+}
+
+fn parse_number(text: &str) -> Result<u16, ConditionError> {
+	let parsed = if let Some(hex) = text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+		u16::from_str_radix(hex, 16)
+	} else {
+		text.parse()
+	};
+
+	parsed.map_err(|_| ConditionError(format!("unknown operand '{}'", text)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::rom::test;
+
+	#[test]
+	fn evaluate() {
+		let mut cpu = Cpu::new();
+		let mut bus = Bus::new(test::test_rom());
+		cpu.set_a(0x20);
+		bus.write(0x0300, 7);
+
+		let check = |text: &str, value: u8| Condition::parse(text).unwrap().evaluate(&cpu, &bus, value);
+		assert!(check("A == 0x20", 0));
+		assert!(check("a==32", 0));
+		assert!(!check("A != $20", 0));
+		assert!(check("[$0300] >= 7 && X < 1", 0));
+		assert!(check("X == 1 || VALUE == 5", 5));
+		assert!(!check("X == 1 || VALUE == 5 && A == 0", 5));
+	}
+
+	#[test]
+	fn errors() {
+		assert_eq!(Condition::parse("A").unwrap_err(), ConditionError(String::from("no comparison in 'A'")));
+		assert!(Condition::parse("Q == 1").is_err());
+		assert!(Condition::parse("[zz] == 1").is_err());
+	}
+}
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::bus::Bus;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LabelError {
+	// 1-based
+	pub line: usize,
+	pub message: String
+}
+
+impl fmt::Display for LabelError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+impl std::error::Error for LabelError {}
+
+// Names for addresses, substituted by trace_with_labels.
+// Labels on PRG ROM offsets follow the bank switching, others are fixed CPU addresses
+#[derive(Clone, Debug, Default)]
+pub struct Labels {
+	cpu: HashMap<u16, String>,
+	prg: HashMap<usize, String>
+}
+
+impl Labels {
+	pub fn new() -> Labels {
+		Labels::default()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.cpu.is_empty() && self.prg.is_empty()
+	}
+
+	pub fn insert(&mut self, adress: u16, name: &str) {
+		self.cpu.insert(adress, name.to_string());
+	}
+
+	pub fn insert_prg(&mut self, offset: usize, name: &str) {
+		self.prg.insert(offset, name.to_string());
+	}
+
+	// Later labels replace earlier ones at the same place
+	pub fn extend(&mut self, other: Labels) {
+		self.cpu.extend(other.cpu);
+		self.prg.extend(other.prg);
+	}
+
+	// Name of the byte the CPU sees at `adress` with the current banks
+	pub fn get(&self, adress: u16, bus: &Bus) -> Option<&str> {
+		if !self.prg.is_empty() {
+			let label = bus.prg_rom_offset(adress).and_then(|offset| self.prg.get(&offset));
+			if let Some(label) = label {
+				return Some(label);
+			}
+		}
+		self.cpu.get(&adress).map(String::as_str)
+	}
+
+	// FCEUX .nl file, `$C000#Name#Comment` per line, `$C000/10#Name#` for arrays.
+	// The bank of the file is not known here, addresses are taken as CPU addresses
+	pub fn parse_nl(text: &str) -> Result<Labels, LabelError> {
+		let mut labels = Labels::new();
+		for (index, line) in text.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() {
+				continue;
+			}
+
+			let error = |message: &str| LabelError { line: index + 1, message: message.to_string() };
+			let mut fields = line.splitn(3, '#');
+			let adress = fields.next().unwrap_or_default();
+			let name = fields.next().ok_or_else(|| error("missing name"))?;
+			let adress = adress.strip_prefix('$').ok_or_else(|| error("address must start with $"))?;
+			let adress = adress.split('/').next().unwrap_or_default();
+			let adress = u16::from_str_radix(adress, 16).map_err(|_| error("invalid address"))?;
+
+			if !name.is_empty() {
+				labels.insert(adress, name);
+			}
+		}
+		Ok(labels)
+	}
+
+	// Mesen .mlb file, `Type:Address[-End]:Name[:Comment]` per line
+	pub fn parse_mlb(text: &str) -> Result<Labels, LabelError> {
+		let mut labels = Labels::new();
+		for (index, line) in text.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() {
+				continue;
+			}
+
+			let error = |message: &str| LabelError { line: index + 1, message: message.to_string() };
+			let mut fields = line.splitn(4, ':');
+			let kind = fields.next().unwrap_or_default();
+			let adress = fields.next().ok_or_else(|| error("missing address"))?;
+			let name = fields.next().ok_or_else(|| error("missing name"))?;
+			let adress = adress.split('-').next().unwrap_or_default();
+			let adress = usize::from_str_radix(adress, 16).map_err(|_| error("invalid address"))?;
+
+			// Comment only lines
+			if name.is_empty() {
+				continue;
+			}
+
+			match kind {
+				"P" => labels.insert_prg(adress, name),
+				// Internal RAM and registers
+				"R" | "G" if adress <= 0xFFFF => labels.insert(adress as u16, name),
+				// Save and work RAM at $6000
+				"S" | "W" if adress < 0x2000 => labels.insert(0x6000 + adress as u16, name),
+				"R" | "G" | "S" | "W" => return Err(error("address out of range")),
+				// CHR and other memories are not shown in traces
+				_ => {}
+			}
+		}
+		Ok(labels)
+	}
+
+	// ca65/ld65 debug file, from its `sym` lines of type `lab`
+	pub fn parse_dbg(text: &str) -> Result<Labels, LabelError> {
+		let mut labels = Labels::new();
+		for (index, line) in text.lines().enumerate() {
+			let Some(attributes) = line.strip_prefix("sym\t") else {
+				continue;
+			};
+
+			let error = |message: &str| LabelError { line: index + 1, message: message.to_string() };
+			let mut name = None;
+			let mut value = None;
+			let mut label = false;
+			for attribute in attributes.split(',') {
+				match attribute.split_once('=') {
+					Some(("name", quoted)) => name = Some(quoted.trim_matches('"')),
+					Some(("val", number)) => value = Some(number),
+					Some(("type", kind)) => label = kind == "lab",
+					_ => {}
+				}
+			}
+			if !label {
+				continue;
+			}
+
+			let name = name.ok_or_else(|| error("symbol without a name"))?;
+			let value = value.ok_or_else(|| error("label without a value"))?;
+			let value = value.strip_prefix("0x").ok_or_else(|| error("value must be hexadecimal"))?;
+			let adress = u16::from_str_radix(value, 16).map_err(|_| error("invalid value"))?;
+			labels.insert(adress, name);
+		}
+		Ok(labels)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::rom::test;
+
+	#[test]
+	fn nl() {
+		let labels = Labels::parse_nl("$8000#reset_handler#Entry point\n$0300/10#buffer#\n\n").unwrap();
+		let bus = Bus::new(test::test_rom());
+		assert_eq!(labels.get(0x8000, &bus), Some("reset_handler"));
+		assert_eq!(labels.get(0x0300, &bus), Some("buffer"));
+		assert_eq!(labels.get(0x0301, &bus), None);
+
+		assert_eq!(Labels::parse_nl("8000#reset#").unwrap_err().line, 1);
+	}
+
+	#[test]
+	fn mlb() {
+		let labels = Labels::parse_mlb("P:0010:nmi:Vblank\nR:0012:player_x\nW:0000:save\nG:2000:PPUCTRL\nR:0013::comment").unwrap();
+		let bus = Bus::new(test::test_rom());
+		// NROM-256, PRG offset $10 is at $8010
+		assert_eq!(labels.get(0x8010, &bus), Some("nmi"));
+		assert_eq!(labels.get(0x0012, &bus), Some("player_x"));
+		assert_eq!(labels.get(0x6000, &bus), Some("save"));
+		assert_eq!(labels.get(0x2000, &bus), Some("PPUCTRL"));
+		assert_eq!(labels.get(0x0013, &bus), None);
+
+		assert!(Labels::parse_mlb("R:zz:name").is_err());
+	}
+
+	#[test]
+	fn dbg() {
+		let text = "version\tmajor=2,minor=0\n\
+			sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=1,ref=4,val=0x8000,seg=0,type=lab\n\
+			sym\tid=1,name=\"SPEED\",addrsize=zeropage,scope=0,def=2,val=0x4,type=equ\n";
+		let labels = Labels::parse_dbg(text).unwrap();
+		let bus = Bus::new(test::test_rom());
+		assert_eq!(labels.get(0x8000, &bus), Some("reset"));
+		assert_eq!(labels.get(0x0004, &bus), None);
+	}
+}
@@ -0,0 +1,230 @@
+pub mod call_stack;
+pub mod cdl;
+pub mod condition;
+pub mod labels;
+pub mod trace;
+mod stepping;
+
+use crate::bus::Bus;
+use crate::bus::watch::{WatchEvent, WatchKind};
+use crate::cpu::Cpu;
+use condition::{Condition, ConditionError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakKind {
+	// Before the instruction at the adress runs
+	Execute,
+	// After the instruction doing the access
+	Read,
+	Write
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BreakpointId(u32);
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+	pub id: BreakpointId,
+	pub kind: BreakKind,
+	pub adress: u16,
+	pub condition: Option<Condition>,
+	pub enabled: bool
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BreakpointHit {
+	pub id: BreakpointId,
+	pub kind: BreakKind,
+	pub adress: u16,
+	// Byte read or written, 0 for execute breakpoints
+	pub value: u8,
+	// Instruction that hit the breakpoint
+	pub pc: u16
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepResult {
+	// CPU cycles taken by the instruction
+	Ran(u16),
+	Stopped(BreakpointHit)
+}
+
+// Breakpoints checked by Cpu::step_debug and Cpu::run_debug
+#[derive(Clone)]
+pub struct Debugger {
+	breakpoints: Vec<Breakpoint>,
+	next_id: u32,
+	// Execute breakpoint just reported, the instruction runs on the next step
+	resume_pc: Option<u16>
+}
+
+impl Debugger {
+	pub fn new() -> Debugger {
+		Debugger {
+			breakpoints: Vec::new(),
+			next_id: 0,
+			resume_pc: None
+		}
+	}
+
+	// `condition` as parsed by Condition::parse, the breakpoint only hits when it holds
+	pub fn add_breakpoint(&mut self, kind: BreakKind, adress: u16, condition: Option<&str>) -> Result<BreakpointId, ConditionError> {
+		let condition = condition.map(Condition::parse).transpose()?;
+		let id = BreakpointId(self.next_id);
+		self.next_id += 1;
+
+		self.breakpoints.push(Breakpoint { id, kind, adress, condition, enabled: true });
+		Ok(id)
+	}
+
+	pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+		let len = self.breakpoints.len();
+		self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+		self.breakpoints.len() != len
+	}
+
+	pub fn set_enabled(&mut self, id: BreakpointId, enabled: bool) -> bool {
+		match self.breakpoints.iter_mut().find(|breakpoint| breakpoint.id == id) {
+			Some(breakpoint) => {
+				breakpoint.enabled = enabled;
+				true
+			},
+			None => false
+		}
+	}
+
+	pub fn breakpoints(&self) -> &[Breakpoint] {
+		&self.breakpoints
+	}
+
+	pub fn clear(&mut self) {
+		self.breakpoints.clear();
+		self.resume_pc = None;
+	}
+
+	fn watches_memory(&self) -> bool {
+		self.breakpoints.iter().any(|breakpoint| breakpoint.enabled && breakpoint.kind != BreakKind::Execute)
+	}
+
+	fn find(&self, kind: BreakKind, adress: u16, value: u8, cpu: &Cpu, bus: &Bus) -> Option<BreakpointId> {
+		self.breakpoints.iter()
+			.find(|breakpoint| breakpoint.enabled && breakpoint.kind == kind && breakpoint.adress == adress
+				&& breakpoint.condition.as_ref().is_none_or(|condition| condition.evaluate(cpu, bus, value)))
+			.map(|breakpoint| breakpoint.id)
+	}
+
+	// Stop before the instruction at PC, unless resuming from that very stop
+	pub fn check_execute(&mut self, cpu: &Cpu, bus: &Bus) -> Option<BreakpointHit> {
+		if self.resume_pc.take() == Some(cpu.pc) {
+			return None;
+		}
+
+		let id = self.find(BreakKind::Execute, cpu.pc, 0, cpu, bus)?;
+		self.resume_pc = Some(cpu.pc);
+		Some(BreakpointHit { id, kind: BreakKind::Execute, adress: cpu.pc, value: 0, pc: cpu.pc })
+	}
+
+	pub fn check_access(&self, access: &WatchEvent, cpu: &Cpu, bus: &Bus) -> Option<BreakpointHit> {
+		let kind = match access.kind {
+			WatchKind::Read => BreakKind::Read,
+			WatchKind::Write => BreakKind::Write
+		};
+
+		let id = self.find(kind, access.adress, access.new_value, cpu, bus)?;
+		Some(BreakpointHit { id, kind, adress: access.adress, value: access.new_value, pc: access.pc })
+	}
+}
+
+impl Default for Debugger {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Cpu {
+	// Execute the next instruction unless a breakpoint stops before it, or right after it for memory breakpoints
+	pub fn step_debug(&mut self, bus: &mut Bus, debugger: &mut Debugger) -> StepResult {
+		if let Some(hit) = debugger.check_execute(self, bus) {
+			return StepResult::Stopped(hit);
+		}
+
+		let watching = debugger.watches_memory();
+		if watching {
+			bus.set_access_log(true);
+		}
+
+		let cycles = self.step(bus);
+
+		if watching {
+			let accesses = bus.take_accesses();
+			bus.set_access_log(false);
+			if let Some(hit) = accesses.iter().find_map(|access| debugger.check_access(access, self, bus)) {
+				return StepResult::Stopped(hit);
+			}
+		}
+
+		StepResult::Ran(cycles)
+	}
+
+	// Run until a breakpoint hits
+	pub fn run_debug(&mut self, bus: &mut Bus, debugger: &mut Debugger) -> BreakpointHit {
+		loop {
+			if let StepResult::Stopped(hit) = self.step_debug(bus, debugger) {
+				return hit;
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::rom::test;
+
+	// LDX #0; loop: INX; STX $10; JMP loop
+	fn counting(bus: &mut Bus) -> Cpu {
+		let program = [0xA2, 0x00, 0xE8, 0x86, 0x10, 0x4C, 0x02, 0x02];
+		for (i, &byte) in program.iter().enumerate() {
+			bus.write(0x0200 + i as u16, byte);
+		}
+
+		let mut cpu = Cpu::new();
+		cpu.pc = 0x0200;
+		cpu
+	}
+
+	#[test]
+	fn execute_breakpoint() {
+		let mut bus = Bus::new(test::test_rom());
+		let mut cpu = counting(&mut bus);
+		let mut debugger = Debugger::new();
+		let id = debugger.add_breakpoint(BreakKind::Execute, 0x0205, Some("X == 3")).unwrap();
+
+		let hit = cpu.run_debug(&mut bus, &mut debugger);
+		assert_eq!(hit, BreakpointHit { id, kind: BreakKind::Execute, adress: 0x0205, value: 0, pc: 0x0205 });
+		assert_eq!(cpu.x(), 3);
+
+		// Resuming runs the instruction
+		assert_eq!(cpu.step_debug(&mut bus, &mut debugger), StepResult::Ran(3));
+		assert_eq!(cpu.pc, 0x0202);
+
+		debugger.set_enabled(id, false);
+		for _ in 0..20 {
+			assert!(matches!(cpu.step_debug(&mut bus, &mut debugger), StepResult::Ran(_)));
+		}
+	}
+
+	#[test]
+	fn write_breakpoint() {
+		let mut bus = Bus::new(test::test_rom());
+		let mut cpu = counting(&mut bus);
+		let mut debugger = Debugger::new();
+		debugger.add_breakpoint(BreakKind::Write, 0x0010, Some("value >= 5")).unwrap();
+
+		let hit = cpu.run_debug(&mut bus, &mut debugger);
+		assert_eq!((hit.kind, hit.value, hit.pc), (BreakKind::Write, 5, 0x0203));
+		// Stopped after the STX
+		assert_eq!(cpu.pc, 0x0205);
+		assert_eq!(bus.read(0x0010), 5);
+	}
+}
@@ -0,0 +1,128 @@
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use crate::debugger::{BreakpointHit, Debugger, StepResult};
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const RTI: u8 = 0x40;
+
+// Debugger commands, each returns the breakpoint that stopped it early, if any.
+// Calls are followed through the stack pointer, so interrupts taken on the way are stepped over
+impl Cpu {
+	// One instruction
+	pub fn step_into(&mut self, bus: &mut Bus, debugger: &mut Debugger) -> Option<BreakpointHit> {
+		self.step_checked(bus, debugger).err()
+	}
+
+	// One instruction, a JSR runs until its subroutine returns
+	pub fn step_over(&mut self, bus: &mut Bus, debugger: &mut Debugger) -> Option<BreakpointHit> {
+		if bus.peek(self.pc) != JSR {
+			return self.step_into(bus, debugger);
+		}
+
+		let return_pc = self.pc.wrapping_add(3);
+		let sp = self.sp();
+		loop {
+			if let Err(hit) = self.step_checked(bus, debugger) {
+				return Some(hit);
+			}
+			if self.pc == return_pc && self.sp() == sp {
+				return None;
+			}
+		}
+	}
+
+	// Run until the current subroutine or interrupt handler returns
+	pub fn step_out(&mut self, bus: &mut Bus, debugger: &mut Debugger) -> Option<BreakpointHit> {
+		let sp = self.sp();
+		loop {
+			let opcode = bus.peek(self.pc);
+			if let Err(hit) = self.step_checked(bus, debugger) {
+				return Some(hit);
+			}
+
+			// Returning pops the stack above where we started, nested calls stay below
+			if (opcode == RTS || opcode == RTI) && self.sp() > sp {
+				return None;
+			}
+		}
+	}
+
+	fn step_checked(&mut self, bus: &mut Bus, debugger: &mut Debugger) -> Result<(), BreakpointHit> {
+		match self.step_debug(bus, debugger) {
+			StepResult::Ran(_) => Ok(()),
+			StepResult::Stopped(hit) => Err(hit)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::debugger::BreakKind;
+	use crate::rom::test;
+
+	// $0200: JSR $0210; LDA #1; JMP $0205
+	// $0210: JSR $0220; INX; RTS
+	// $0220: INY; RTS
+	fn calls(bus: &mut Bus) -> Cpu {
+		let code: [(u16, &[u8]); 3] = [
+			(0x0200, &[0x20, 0x10, 0x02, 0xA9, 0x01, 0x4C, 0x05, 0x02]),
+			(0x0210, &[0x20, 0x20, 0x02, 0xE8, 0x60]),
+			(0x0220, &[0xC8, 0x60])
+		];
+		for (adress, bytes) in code {
+			for (i, &byte) in bytes.iter().enumerate() {
+				bus.write(adress + i as u16, byte);
+			}
+		}
+
+		let mut cpu = Cpu::new();
+		cpu.pc = 0x0200;
+		cpu
+	}
+
+	#[test]
+	fn step_over_and_into() {
+		let mut bus = Bus::new(test::test_rom());
+		let mut cpu = calls(&mut bus);
+		let mut debugger = Debugger::new();
+
+		assert_eq!(cpu.step_over(&mut bus, &mut debugger), None);
+		assert_eq!(cpu.pc, 0x0203);
+		assert_eq!((cpu.x(), cpu.y()), (1, 1));
+
+		let mut cpu = calls(&mut bus);
+		cpu.step_into(&mut bus, &mut debugger);
+		assert_eq!(cpu.pc, 0x0210);
+	}
+
+	#[test]
+	fn step_out() {
+		let mut bus = Bus::new(test::test_rom());
+		let mut cpu = calls(&mut bus);
+		let mut debugger = Debugger::new();
+
+		// Into $0210 then $0220
+		cpu.step_into(&mut bus, &mut debugger);
+		cpu.step_into(&mut bus, &mut debugger);
+		assert_eq!(cpu.pc, 0x0220);
+
+		assert_eq!(cpu.step_out(&mut bus, &mut debugger), None);
+		assert_eq!(cpu.pc, 0x0213);
+		assert_eq!(cpu.step_out(&mut bus, &mut debugger), None);
+		assert_eq!(cpu.pc, 0x0203);
+	}
+
+	#[test]
+	fn breakpoint_inside_step_over() {
+		let mut bus = Bus::new(test::test_rom());
+		let mut cpu = calls(&mut bus);
+		let mut debugger = Debugger::new();
+		debugger.add_breakpoint(BreakKind::Execute, 0x0220, None).unwrap();
+
+		let hit = cpu.step_over(&mut bus, &mut debugger).unwrap();
+		assert_eq!(hit.pc, 0x0220);
+		assert_eq!(cpu.y(), 0);
+	}
+}
@@ -0,0 +1,279 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::bus::Bus;
+use crate::cpu::{self, Cpu};
+use crate::debugger::labels::Labels;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+	// Same as nestest.log, `C000  4C F5 C5  JMP $C5F5  A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+	Nestest,
+	// `A:00 X:00 Y:00 S:FD P:nvUbdIzc  $C000:4C F5 C5  JMP $C5F5`
+	Fceux,
+	// `C000  JMP $C5F5  A:00 X:00 Y:00 S:FD P:nvUbdIzc`
+	Mesen
+}
+
+// Writes a line per instruction, to a writer and to a history of the last instructions.
+// Call log before each instruction, from Cpu::run_with_callback for example
+pub struct TraceLogger {
+	format: TraceFormat,
+	cycles: bool,
+	ppu_position: bool,
+	labels: Labels,
+	// Only instructions in these ranges are logged, all when empty
+	ranges: Vec<RangeInclusive<u16>>,
+	// Only these mnemonics are logged, all when empty
+	instructions: Vec<String>,
+	output: Option<Box<dyn Write>>,
+	history: VecDeque<String>,
+	history_size: usize
+}
+
+impl TraceLogger {
+	// Timing columns start enabled for nestest, to diff against nestest.log
+	pub fn new(format: TraceFormat) -> TraceLogger {
+		let nestest = format == TraceFormat::Nestest;
+		TraceLogger {
+			format,
+			cycles: nestest,
+			ppu_position: nestest,
+			labels: Labels::new(),
+			ranges: Vec::new(),
+			instructions: Vec::new(),
+			output: None,
+			history: VecDeque::new(),
+			history_size: 0
+		}
+	}
+
+	pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+		self.output = Some(Box::new(output));
+	}
+
+	// Flushes and gives back the writer
+	pub fn take_output(&mut self) -> io::Result<Option<Box<dyn Write>>> {
+		if let Some(output) = &mut self.output {
+			output.flush()?;
+		}
+		Ok(self.output.take())
+	}
+
+	// Keep the last `size` lines, 0 to keep none
+	pub fn set_history_size(&mut self, size: usize) {
+		self.history_size = size;
+		while self.history.len() > size {
+			self.history.pop_front();
+		}
+	}
+
+	pub fn history(&self) -> impl Iterator<Item = &str> {
+		self.history.iter().map(String::as_str)
+	}
+
+	// Writes the history, oldest first, after a crash for example
+	pub fn dump_history<W: Write>(&self, output: &mut W) -> io::Result<()> {
+		for line in &self.history {
+			writeln!(output, "{}", line)?;
+		}
+		Ok(())
+	}
+
+	// CPU cycles since power on
+	pub fn set_cycles(&mut self, enabled: bool) {
+		self.cycles = enabled;
+	}
+
+	// Scanline and dot of the PPU
+	pub fn set_ppu_position(&mut self, enabled: bool) {
+		self.ppu_position = enabled;
+	}
+
+	pub fn set_labels(&mut self, labels: Labels) {
+		self.labels = labels;
+	}
+
+	pub fn add_range(&mut self, range: RangeInclusive<u16>) {
+		self.ranges.push(range);
+	}
+
+	// Mnemonics such as "JSR" or "RTS"
+	pub fn set_instructions(&mut self, mnemonics: &[&str]) {
+		self.instructions = mnemonics.iter().map(|mnemonic| mnemonic.to_ascii_uppercase()).collect();
+	}
+
+	pub fn clear_filters(&mut self) {
+		self.ranges.clear();
+		self.instructions.clear();
+	}
+
+	// Logs the instruction at PC, unless filtered out
+	pub fn log(&mut self, cpu: &Cpu, bus: &Bus) -> io::Result<()> {
+		if !self.ranges.is_empty() && !self.ranges.iter().any(|range| range.contains(&cpu.pc)) {
+			return Ok(());
+		}
+
+		let (bytes, assembly) = cpu::disassemble(cpu, bus, &self.labels);
+		if !self.instructions.is_empty() {
+			// After the `*` marking undocumented opcodes
+			let mnemonic = assembly[1..].split_whitespace().next().unwrap_or_default();
+			if !self.instructions.iter().any(|instruction| instruction == mnemonic) {
+				return Ok(());
+			}
+		}
+
+		let line = self.format_line(cpu, bus, &bytes, &assembly);
+		if let Some(output) = &mut self.output {
+			writeln!(output, "{}", line)?;
+		}
+		if self.history_size > 0 {
+			if self.history.len() == self.history_size {
+				self.history.pop_front();
+			}
+			self.history.push_back(line);
+		}
+		Ok(())
+	}
+
+	fn format_line(&self, cpu: &Cpu, bus: &Bus, bytes: &str, assembly: &str) -> String {
+		let (scanline, dot) = (bus.ppu().scanline(), bus.ppu().dot());
+		let mut line = match self.format {
+			TraceFormat::Nestest => {
+				let mut line = format!("{:04X}  {:<8} {:<31}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+					cpu.pc, bytes, assembly, cpu.a(), cpu.x(), cpu.y(), cpu.status(), cpu.sp());
+				if self.ppu_position {
+					line += &format!(" PPU:{:>3},{:>3}", scanline, dot);
+				}
+				line
+			},
+			TraceFormat::Fceux => {
+				let prefix = if self.cycles { format!("c{:<11} ", cpu.cycles()) } else { String::new() };
+				format!("{}A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}  ${:04X}:{:<9} {}",
+					prefix, cpu.a(), cpu.x(), cpu.y(), cpu.sp(), flags(cpu.status()), cpu.pc, bytes, assembly.trim())
+			},
+			TraceFormat::Mesen => {
+				let mut line = format!("{:04X}  {:<31} A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}",
+					cpu.pc, assembly.trim(), cpu.a(), cpu.x(), cpu.y(), cpu.sp(), flags(cpu.status()));
+				if self.ppu_position {
+					line += &format!(" V:{} H:{}", scanline, dot);
+				}
+				line
+			}
+		};
+
+		match self.format {
+			TraceFormat::Nestest => if self.cycles { line += &format!(" CYC:{}", cpu.cycles()) },
+			TraceFormat::Mesen => if self.cycles { line += &format!(" Cycle:{}", cpu.cycles()) },
+			TraceFormat::Fceux => {}
+		}
+		line
+	}
+}
+
+// NV-BDIZC, upper case when set
+fn flags(status: u8) -> String {
+	"nvubdizc".chars().enumerate().map(|(i, flag)| {
+		if status & (0x80 >> i) != 0 { flag.to_ascii_uppercase() } else { flag }
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use crate::rom::test;
+
+	// Writer shared with the test
+	#[derive(Clone, Default)]
+	struct Shared(Rc<RefCell<Vec<u8>>>);
+
+	impl Write for Shared {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.0.borrow_mut().extend_from_slice(buf);
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	// $0200: LDX #$01; INX; JMP $0202
+	fn program(bus: &mut Bus) -> Cpu {
+		for (i, &byte) in [0xA2, 0x01, 0xE8, 0x4C, 0x02, 0x02].iter().enumerate() {
+			bus.write(0x0200 + i as u16, byte);
+		}
+		let mut cpu = Cpu::new();
+		cpu.pc = 0x0200;
+		cpu
+	}
+
+	fn run(logger: &mut TraceLogger, cpu: &mut Cpu, bus: &mut Bus, instructions: usize) {
+		for _ in 0..instructions {
+			logger.log(cpu, bus).unwrap();
+			cpu.step(bus);
+		}
+	}
+
+	#[test]
+	fn formats() {
+		let mut bus = Bus::new(test::test_rom());
+		let cpu = program(&mut bus);
+
+		let mut logger = TraceLogger::new(TraceFormat::Fceux);
+		logger.set_history_size(1);
+		logger.log(&cpu, &bus).unwrap();
+		assert_eq!(logger.history().next(), Some("A:00 X:00 Y:00 S:FD P:nvUbdizc  $0200:A2 01     LDX #$01"));
+
+		let mut logger = TraceLogger::new(TraceFormat::Mesen);
+		logger.set_history_size(1);
+		logger.set_cycles(true);
+		logger.set_ppu_position(true);
+		logger.log(&cpu, &bus).unwrap();
+		let line = logger.history().next().unwrap();
+		assert!(line.starts_with("0200  LDX #$01"), "{}", line);
+		assert!(line.ends_with("S:FD P:nvUbdizc V:0 H:0 Cycle:0"), "{}", line);
+	}
+
+	#[test]
+	fn output_and_history() {
+		let mut bus = Bus::new(test::test_rom());
+		let mut cpu = program(&mut bus);
+		let output = Shared::default();
+
+		let mut logger = TraceLogger::new(TraceFormat::Nestest);
+		logger.set_output(output.clone());
+		logger.set_history_size(2);
+		run(&mut logger, &mut cpu, &mut bus, 4);
+
+		let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+		assert_eq!(text.lines().count(), 4);
+		assert!(text.starts_with("0200  A2 01     LDX #$01"));
+
+		let history: Vec<&str> = logger.history().collect();
+		assert_eq!(history.len(), 2);
+		assert!(history[0].starts_with("0203  4C 02 02  JMP $0202"));
+		assert!(history[1].starts_with("0202  E8        INX"));
+	}
+
+	#[test]
+	fn filters() {
+		let mut bus = Bus::new(test::test_rom());
+		let mut cpu = program(&mut bus);
+
+		let mut logger = TraceLogger::new(TraceFormat::Nestest);
+		logger.set_history_size(10);
+		logger.set_instructions(&["inx"]);
+		run(&mut logger, &mut cpu, &mut bus, 5);
+		assert_eq!(logger.history().count(), 2);
+
+		let mut logger = TraceLogger::new(TraceFormat::Nestest);
+		logger.set_history_size(10);
+		logger.add_range(0x0200..=0x0201);
+		run(&mut logger, &mut cpu, &mut bus, 5);
+		assert_eq!(logger.history().count(), 0);
+	}
+}
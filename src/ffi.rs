@@ -0,0 +1,400 @@
+// Stable C ABI for embedding nessy in C, C++, C# or Unity hosts, declared in include/nessy.h
+// which build.rs generates from this file. An emulator is an opaque handle from nessy_create,
+// every other function takes it first and must be called from one thread at a time
+use std::any::Any;
+use std::ffi::{c_char, c_int, c_uint, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::nes::Nes;
+use crate::rom::Rom;
+
+// Literals, cbindgen only reads this file
+pub const NESSY_WIDTH: c_int = 256;
+pub const NESSY_HEIGHT: c_int = 240;
+
+pub const NESSY_OK: c_int = 0;
+pub const NESSY_ERROR_INVALID_ARGUMENT: c_int = -1;
+pub const NESSY_ERROR_ROM: c_int = -2;
+pub const NESSY_ERROR_NO_ROM: c_int = -3;
+pub const NESSY_ERROR_STATE: c_int = -4;
+/// The emulator panicked, on an opcode it does not implement for example. The ROM is unloaded
+pub const NESSY_ERROR_PANIC: c_int = -5;
+
+/// Controller bits for nessy_set_input
+pub const NESSY_BUTTON_A: u8 = 0x01;
+pub const NESSY_BUTTON_B: u8 = 0x02;
+pub const NESSY_BUTTON_SELECT: u8 = 0x04;
+pub const NESSY_BUTTON_START: u8 = 0x08;
+pub const NESSY_BUTTON_UP: u8 = 0x10;
+pub const NESSY_BUTTON_DOWN: u8 = 0x20;
+pub const NESSY_BUTTON_LEFT: u8 = 0x40;
+pub const NESSY_BUTTON_RIGHT: u8 = 0x80;
+
+pub struct NessyEmulator {
+	nes: Option<Nes>,
+	// Samples produced since the last nessy_read_audio
+	audio: Vec<f32>,
+	sample_rate: u32,
+	last_error: CString
+}
+
+impl NessyEmulator {
+	fn fail(&mut self, code: c_int, message: String) -> c_int {
+		// Messages come from Display impls, which have no NUL in them
+		self.last_error = CString::new(message).unwrap_or_default();
+		code
+	}
+
+	fn nes(&mut self) -> Result<&mut Nes, c_int> {
+		if self.nes.is_none() {
+			return Err(self.fail(NESSY_ERROR_NO_ROM, "no ROM loaded".to_string()));
+		}
+		Ok(self.nes.as_mut().unwrap())
+	}
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+	match payload.downcast_ref::<&str>() {
+		Some(message) => message,
+		None => payload.downcast_ref::<String>().map_or("unknown panic", String::as_str)
+	}
+}
+
+// Runs an entry point body without unwinding into the host. After a panic the emulator
+// may be half way through an instruction, so the ROM is dropped and `failed` returned
+unsafe fn guard<T>(emulator: *mut NessyEmulator, failed: T, body: impl FnOnce() -> T) -> T {
+	match panic::catch_unwind(AssertUnwindSafe(body)) {
+		Ok(value) => value,
+		Err(payload) => {
+			if let Some(emulator) = emulator.as_mut() {
+				emulator.nes = None;
+				emulator.audio.clear();
+				emulator.fail(NESSY_ERROR_PANIC, format!("emulator panicked: {}", panic_message(payload.as_ref())));
+			}
+			failed
+		}
+	}
+}
+
+#[no_mangle]
+pub extern "C" fn nessy_version() -> *const c_char {
+	concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast()
+}
+
+#[no_mangle]
+pub extern "C" fn nessy_create() -> *mut NessyEmulator {
+	unsafe {
+		guard(ptr::null_mut(), ptr::null_mut(), || Box::into_raw(Box::new(NessyEmulator {
+			nes: None,
+			audio: Vec::new(),
+			sample_rate: 44_100,
+			last_error: CString::default()
+		})))
+	}
+}
+
+/// # Safety
+/// `emulator` must come from nessy_create and not be used afterwards, null is ignored
+#[no_mangle]
+pub unsafe extern "C" fn nessy_destroy(emulator: *mut NessyEmulator) {
+	// Nothing is left to report the error on
+	guard(ptr::null_mut(), (), || {
+		if !emulator.is_null() {
+			drop(Box::from_raw(emulator));
+		}
+	})
+}
+
+/// Message of the last error, empty before the first one
+///
+/// # Safety
+/// `emulator` must come from nessy_create
+#[no_mangle]
+pub unsafe extern "C" fn nessy_last_error(emulator: *const NessyEmulator) -> *const c_char {
+	guard(emulator.cast_mut(), c"".as_ptr(), || match emulator.as_ref() {
+		Some(emulator) => emulator.last_error.as_ptr(),
+		None => c"".as_ptr()
+	})
+}
+
+/// iNES or NES 2.0 file content, copied
+///
+/// # Safety
+/// `emulator` must come from nessy_create and `data` point to `size` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn nessy_load_rom(emulator: *mut NessyEmulator, data: *const u8, size: usize) -> c_int {
+	guard(emulator, NESSY_ERROR_PANIC, || {
+		let Some(emulator) = emulator.as_mut() else {
+			return NESSY_ERROR_INVALID_ARGUMENT;
+		};
+		if data.is_null() {
+			return emulator.fail(NESSY_ERROR_INVALID_ARGUMENT, "no ROM data".to_string());
+		}
+
+		match Rom::from_ines(slice::from_raw_parts(data, size)) {
+			Ok(rom) => {
+				let mut nes = Nes::new(rom);
+				nes.bus_mut().apu_mut().set_sample_rate(emulator.sample_rate);
+				emulator.nes = Some(nes);
+				emulator.audio.clear();
+				NESSY_OK
+			},
+			Err(error) => emulator.fail(NESSY_ERROR_ROM, error.to_string())
+		}
+	})
+}
+
+/// # Safety
+/// `emulator` must come from nessy_create
+#[no_mangle]
+pub unsafe extern "C" fn nessy_reset(emulator: *mut NessyEmulator) -> c_int {
+	guard(emulator, NESSY_ERROR_PANIC, || {
+		let Some(emulator) = emulator.as_mut() else {
+			return NESSY_ERROR_INVALID_ARGUMENT;
+		};
+		match emulator.nes() {
+			Ok(nes) => {
+				nes.reset();
+				NESSY_OK
+			},
+			Err(code) => code
+		}
+	})
+}
+
+/// # Safety
+/// `emulator` must come from nessy_create
+#[no_mangle]
+pub unsafe extern "C" fn nessy_run_frame(emulator: *mut NessyEmulator) -> c_int {
+	guard(emulator, NESSY_ERROR_PANIC, || {
+		let Some(emulator) = emulator.as_mut() else {
+			return NESSY_ERROR_INVALID_ARGUMENT;
+		};
+		let samples = match emulator.nes() {
+			Ok(nes) => {
+				nes.run_frame();
+				nes.audio_samples()
+			},
+			Err(code) => return code
+		};
+		emulator.audio.extend(samples);
+		NESSY_OK
+	})
+}
+
+/// NESSY_WIDTH * NESSY_HEIGHT RGB pixels, 3 bytes each, valid until the next call taking the emulator.
+/// Null when no ROM is loaded
+///
+/// # Safety
+/// `emulator` must come from nessy_create
+#[no_mangle]
+pub unsafe extern "C" fn nessy_framebuffer(emulator: *const NessyEmulator) -> *const u8 {
+	guard(emulator.cast_mut(), ptr::null(), || match emulator.as_ref().and_then(|emulator| emulator.nes.as_ref()) {
+		Some(nes) => nes.frame().data.as_ptr(),
+		None => ptr::null()
+	})
+}
+
+/// `port` 0 or 1, `buttons` a mask of NESSY_BUTTON_*
+///
+/// # Safety
+/// `emulator` must come from nessy_create
+#[no_mangle]
+pub unsafe extern "C" fn nessy_set_input(emulator: *mut NessyEmulator, port: c_uint, buttons: u8) -> c_int {
+	guard(emulator, NESSY_ERROR_PANIC, || {
+		let Some(emulator) = emulator.as_mut() else {
+			return NESSY_ERROR_INVALID_ARGUMENT;
+		};
+		let nes = match emulator.nes() {
+			Ok(nes) => nes,
+			Err(code) => return code
+		};
+		let joypad = match port {
+			0 => nes.bus_mut().joypad1_mut(),
+			1 => nes.bus_mut().joypad2_mut(),
+			_ => return emulator.fail(NESSY_ERROR_INVALID_ARGUMENT, format!("no controller port {}", port))
+		};
+		// Another device is plugged in, like the Zapper
+		if let Some(joypad) = joypad {
+			joypad.set_buttons(buttons);
+		}
+		NESSY_OK
+	})
+}
+
+/// # Safety
+/// `emulator` must come from nessy_create
+#[no_mangle]
+pub unsafe extern "C" fn nessy_set_sample_rate(emulator: *mut NessyEmulator, sample_rate: c_uint) -> c_int {
+	guard(emulator, NESSY_ERROR_PANIC, || {
+		let Some(emulator) = emulator.as_mut() else {
+			return NESSY_ERROR_INVALID_ARGUMENT;
+		};
+		if sample_rate == 0 {
+			return emulator.fail(NESSY_ERROR_INVALID_ARGUMENT, "sample rate of 0".to_string());
+		}
+		emulator.sample_rate = sample_rate;
+		if let Some(nes) = &mut emulator.nes {
+			nes.bus_mut().apu_mut().set_sample_rate(sample_rate);
+		}
+		NESSY_OK
+	})
+}
+
+/// Moves up to `capacity` mono samples into `samples`, returns how many
+///
+/// # Safety
+/// `emulator` must come from nessy_create and `samples` point to `capacity` writable floats
+#[no_mangle]
+pub unsafe extern "C" fn nessy_read_audio(emulator: *mut NessyEmulator, samples: *mut f32, capacity: usize) -> usize {
+	guard(emulator, 0, || {
+		let Some(emulator) = emulator.as_mut() else {
+			return 0;
+		};
+		if samples.is_null() {
+			return 0;
+		}
+		let count = emulator.audio.len().min(capacity);
+		slice::from_raw_parts_mut(samples, count).copy_from_slice(&emulator.audio[..count]);
+		emulator.audio.drain(..count);
+		count
+	})
+}
+
+/// Size of the save state, written to `buffer` only when `capacity` is large enough,
+/// so a first call with a null buffer gives the size to allocate. 0 when no ROM is loaded
+///
+/// # Safety
+/// `emulator` must come from nessy_create and `buffer` point to `capacity` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn nessy_save_state(emulator: *mut NessyEmulator, buffer: *mut u8, capacity: usize) -> usize {
+	guard(emulator, 0, || {
+		let Some(nes) = emulator.as_mut().and_then(|emulator| emulator.nes.as_ref()) else {
+			return 0;
+		};
+		let state = nes.save_state();
+		if !buffer.is_null() && capacity >= state.len() {
+			slice::from_raw_parts_mut(buffer, state.len()).copy_from_slice(&state);
+		}
+		state.len()
+	})
+}
+
+/// # Safety
+/// `emulator` must come from nessy_create and `data` point to `size` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn nessy_load_state(emulator: *mut NessyEmulator, data: *const u8, size: usize) -> c_int {
+	guard(emulator, NESSY_ERROR_PANIC, || {
+		let Some(emulator) = emulator.as_mut() else {
+			return NESSY_ERROR_INVALID_ARGUMENT;
+		};
+		if data.is_null() {
+			return emulator.fail(NESSY_ERROR_INVALID_ARGUMENT, "no state data".to_string());
+		}
+		let result = match emulator.nes() {
+			Ok(nes) => nes.load_state(slice::from_raw_parts(data, size)),
+			Err(code) => return code
+		};
+		match result {
+			Ok(()) => NESSY_OK,
+			Err(error) => emulator.fail(NESSY_ERROR_STATE, error.to_string())
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::ffi::CStr;
+
+	use crate::ppu::frame::{HEIGHT, WIDTH};
+	use crate::rom::test::nrom;
+
+	#[test]
+	fn emulator() {
+		// JMP $8000
+		let ines = nrom(&[0x4C, 0x00, 0x80], [0, 0x8000, 0]);
+
+		unsafe {
+			let emulator = nessy_create();
+			assert_eq!(nessy_run_frame(emulator), NESSY_ERROR_NO_ROM);
+			assert!(nessy_framebuffer(emulator).is_null());
+			assert_eq!(nessy_load_rom(emulator, ines.as_ptr(), 10), NESSY_ERROR_ROM);
+			assert!(!CStr::from_ptr(nessy_last_error(emulator)).to_bytes().is_empty());
+
+			assert_eq!(nessy_load_rom(emulator, ines.as_ptr(), ines.len()), NESSY_OK);
+			assert_eq!(nessy_set_input(emulator, 0, 0b1000_1001), NESSY_OK);
+			assert_eq!(nessy_set_input(emulator, 2, 0), NESSY_ERROR_INVALID_ARGUMENT);
+			assert_eq!(nessy_run_frame(emulator), NESSY_OK);
+			assert!(!nessy_framebuffer(emulator).is_null());
+			assert_eq!((*emulator).nes.as_mut().unwrap().bus_mut().joypad1_mut().unwrap().buttons(), 0b1000_1001);
+
+			let mut samples = vec![0.0; 100_000];
+			let count = nessy_read_audio(emulator, samples.as_mut_ptr(), samples.len());
+			assert!(count > 0);
+			assert_eq!(nessy_read_audio(emulator, samples.as_mut_ptr(), samples.len()), 0);
+
+			let size = nessy_save_state(emulator, ptr::null_mut(), 0);
+			let mut state = vec![0; size];
+			assert_eq!(nessy_save_state(emulator, state.as_mut_ptr(), state.len()), size);
+			assert_eq!(nessy_load_state(emulator, state.as_ptr(), state.len()), NESSY_OK);
+			assert_eq!(nessy_load_state(emulator, state.as_ptr(), 4), NESSY_ERROR_STATE);
+
+			nessy_destroy(emulator);
+		}
+	}
+
+	// Every exported function is declared in the header shipped to C hosts
+	#[test]
+	fn header() {
+		let header = include_str!("../include/nessy.h");
+		let source = include_str!("ffi.rs");
+		let exports: Vec<&str> = source.lines()
+			.filter_map(|line| line.split("extern \"C\" fn ").nth(1))
+			.filter_map(|rest| rest.split('(').next())
+			.collect();
+		assert!(exports.len() >= 10);
+		for name in exports {
+			assert!(header.contains(&format!("{}(", name)), "{} missing from nessy.h", name);
+		}
+		for (name, value) in [
+			("NESSY_OK", NESSY_OK),
+			("NESSY_ERROR_INVALID_ARGUMENT", NESSY_ERROR_INVALID_ARGUMENT),
+			("NESSY_ERROR_ROM", NESSY_ERROR_ROM),
+			("NESSY_ERROR_NO_ROM", NESSY_ERROR_NO_ROM),
+			("NESSY_ERROR_STATE", NESSY_ERROR_STATE),
+			("NESSY_ERROR_PANIC", NESSY_ERROR_PANIC),
+			("NESSY_WIDTH", NESSY_WIDTH),
+			("NESSY_HEIGHT", NESSY_HEIGHT)
+		] {
+			assert!(header.contains(&format!("#define {} ({})", name, value)) || header.contains(&format!("#define {} {}", name, value)), "{}", name);
+		}
+	}
+
+	#[test]
+	fn frame_size() {
+		assert_eq!(NESSY_WIDTH as usize, WIDTH);
+		assert_eq!(NESSY_HEIGHT as usize, HEIGHT);
+	}
+
+	// A jammed CPU panics, which is reported instead of unwinding into the host
+	#[test]
+	fn panic() {
+		// JAM
+		let ines = nrom(&[0x02], [0, 0x8000, 0]);
+
+		unsafe {
+			let emulator = nessy_create();
+			assert_eq!(nessy_load_rom(emulator, ines.as_ptr(), ines.len()), NESSY_OK);
+			assert_eq!(nessy_run_frame(emulator), NESSY_ERROR_PANIC);
+			let message = CStr::from_ptr(nessy_last_error(emulator)).to_str().unwrap();
+			assert!(message.contains("not implemented"), "{}", message);
+			assert!(nessy_framebuffer(emulator).is_null());
+			assert_eq!(nessy_run_frame(emulator), NESSY_ERROR_NO_ROM);
+			nessy_destroy(emulator);
+		}
+	}
+}
@@ -0,0 +1,157 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::region::Region;
+use crate::rom::header::Timing;
+
+// Frames per second of the 2C02 and 2C07 PPUs
+pub const NTSC_FRAME_RATE: f64 = 60.0988;
+pub const PAL_FRAME_RATE: f64 = 50.0070;
+
+// Deadlines this many frames in the past are dropped instead of caught up with
+const MAX_LAG_FRAMES: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+	// Multiple of the console rate: 2.0 and 4.0 for fast-forward, 0.5 for slow motion
+	Scaled(f64),
+	// As fast as the host runs
+	Unlimited
+}
+
+impl Speed {
+	pub const NORMAL: Speed = Speed::Scaled(1.0);
+}
+
+// Paces run_frame to the console frame rate. Deadlines follow each other
+// so sleeping late on one frame is made up on the next ones
+pub struct FrameTimer {
+	frame_rate: f64,
+	speed: Speed,
+	// End of the current frame
+	deadline: Option<Instant>
+}
+
+impl FrameTimer {
+	pub fn new(frame_rate: f64) -> FrameTimer {
+		FrameTimer {
+			frame_rate,
+			speed: Speed::NORMAL,
+			deadline: None
+		}
+	}
+
+	pub fn ntsc() -> FrameTimer {
+		FrameTimer::new(NTSC_FRAME_RATE)
+	}
+
+	// PAL and Dendy consoles both run at 50 frames per second
+	pub fn pal() -> FrameTimer {
+		FrameTimer::new(PAL_FRAME_RATE)
+	}
+
+	pub fn for_timing(timing: Timing) -> FrameTimer {
+		match timing {
+			Timing::Ntsc | Timing::MultiRegion => FrameTimer::ntsc(),
+			Timing::Pal | Timing::Dendy => FrameTimer::pal()
+		}
+	}
+
+	pub fn for_region(region: Region) -> FrameTimer {
+		FrameTimer::new(region.frame_rate())
+	}
+
+	pub fn frame_rate(&self) -> f64 {
+		self.frame_rate
+	}
+
+	pub fn speed(&self) -> Speed {
+		self.speed
+	}
+
+	// Takes effect from the next frame
+	pub fn set_speed(&mut self, speed: Speed) {
+		self.speed = speed;
+	}
+
+	// Time between frames at the current speed, None when unlimited
+	pub fn frame_duration(&self) -> Option<Duration> {
+		match self.speed {
+			Speed::Scaled(scale) if scale > 0.0 => Some(Duration::from_secs_f64(1.0 / (self.frame_rate * scale))),
+			Speed::Scaled(_) | Speed::Unlimited => None
+		}
+	}
+
+	// Forget the deadlines, after a pause
+	pub fn reset(&mut self) {
+		self.deadline = None;
+	}
+
+	// Called after each frame, how long to wait before running the next one
+	pub fn tick(&mut self, now: Instant) -> Duration {
+		let Some(duration) = self.frame_duration() else {
+			self.deadline = None;
+			return Duration::ZERO;
+		};
+
+		let mut deadline = self.deadline.unwrap_or(now) + duration;
+		// Too far behind after a stall, start again from now
+		if deadline + duration * MAX_LAG_FRAMES < now {
+			deadline = now;
+		}
+		self.deadline = Some(deadline);
+		deadline.saturating_duration_since(now)
+	}
+
+	// Sleep until the next frame is due
+	pub fn wait(&mut self) {
+		let delay = self.tick(Instant::now());
+		if !delay.is_zero() {
+			thread::sleep(delay);
+		}
+	}
+}
+
+impl Default for FrameTimer {
+	fn default() -> Self {
+		Self::ntsc()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn drift_correction() {
+		let mut timer = FrameTimer::new(50.0);
+		let start = Instant::now();
+		let frame = Duration::from_millis(20);
+		assert_eq!(timer.tick(start), frame);
+
+		// Woke up 5ms late, the next frame is shorter
+		assert_eq!(timer.tick(start + frame + Duration::from_millis(5)), Duration::from_millis(15));
+		// Running behind
+		assert_eq!(timer.tick(start + frame * 3), Duration::ZERO);
+
+		// Stalled for a second, the timer resynchronizes
+		let late = start + Duration::from_secs(1);
+		assert_eq!(timer.tick(late), Duration::ZERO);
+		assert_eq!(timer.tick(late), frame);
+	}
+
+	#[test]
+	fn speed() {
+		let mut timer = FrameTimer::for_timing(Timing::Pal);
+		assert_eq!(timer.frame_rate(), PAL_FRAME_RATE);
+
+		timer.set_speed(Speed::Scaled(2.0));
+		let fast = timer.frame_duration().unwrap();
+		timer.set_speed(Speed::NORMAL);
+		assert_eq!(timer.frame_duration().unwrap().as_nanos() / 2, fast.as_nanos());
+
+		timer.set_speed(Speed::Unlimited);
+		assert_eq!(timer.frame_duration(), None);
+		assert_eq!(timer.tick(Instant::now()), Duration::ZERO);
+	}
+}
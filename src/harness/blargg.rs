@@ -0,0 +1,133 @@
+use std::fmt;
+
+use crate::nes::Nes;
+use crate::rom::Rom;
+
+const STATUS: u16 = 0x6000;
+// At $6001-$6003 once the status byte is valid
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+// Zero terminated text output
+const TEXT: u16 = 0x6004;
+const TEXT_END: u16 = 0x7FFF;
+
+const RUNNING: u8 = 0x80;
+// The test asks for the reset button to be pressed
+const NEEDS_RESET: u8 = 0x81;
+// The reset must come at least 100ms after the request
+const RESET_DELAY: u32 = 6;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlarggResult {
+	// 0 when passed, otherwise the number of the failed test
+	pub code: u8,
+	pub message: String
+}
+
+impl BlarggResult {
+	pub fn passed(&self) -> bool {
+		self.code == 0
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlarggTimeout {
+	pub frames: u32,
+	// Text written so far, empty if the ROM never started reporting
+	pub message: String
+}
+
+impl fmt::Display for BlarggTimeout {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "no result after {} frames", self.frames)?;
+		if !self.message.is_empty() {
+			write!(f, ": {}", self.message.trim_end())?;
+		}
+		Ok(())
+	}
+}
+
+impl std::error::Error for BlarggTimeout {}
+
+// Run a blargg test ROM reporting through $6000 until it finishes, for at most `max_frames`
+pub fn run(rom: Rom, max_frames: u32) -> Result<BlarggResult, BlarggTimeout> {
+	let mut nes = Nes::new(rom);
+	let mut reset_at = None;
+
+	for frame in 0..max_frames {
+		nes.run_frame();
+		if !has_signature(&nes) {
+			continue;
+		}
+
+		match nes.bus().peek(STATUS) {
+			RUNNING => {},
+			NEEDS_RESET => match reset_at {
+				None => reset_at = Some(frame + RESET_DELAY),
+				Some(at) if frame >= at => {
+					nes.reset();
+					reset_at = None;
+				},
+				Some(_) => {}
+			},
+			code => return Ok(BlarggResult { code, message: message(&nes) })
+		}
+	}
+
+	let message = if has_signature(&nes) { message(&nes) } else { String::new() };
+	Err(BlarggTimeout { frames: max_frames, message })
+}
+
+fn has_signature(nes: &Nes) -> bool {
+	(0..3).all(|i| nes.bus().peek(STATUS + 1 + i) == SIGNATURE[usize::from(i)])
+}
+
+fn message(nes: &Nes) -> String {
+	let bytes: Vec<u8> = (TEXT..=TEXT_END)
+		.map(|adress| nes.bus().peek(adress))
+		.take_while(|&byte| byte != 0)
+		.collect();
+	String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::rom::test::nrom;
+
+	// Writes the signature, "ok" and status `code` to $6000, then spins
+	fn reporting_rom(code: u8) -> Rom {
+		let mut program = Vec::new();
+		let mut store = |adress: u16, value: u8| {
+			// LDA #value; STA adress
+			program.extend([0xA9, value, 0x8D, adress as u8, (adress >> 8) as u8]);
+		};
+		store(0x6001, 0xDE);
+		store(0x6002, 0xB0);
+		store(0x6003, 0x61);
+		store(0x6004, b'o');
+		store(0x6005, b'k');
+		store(0x6006, 0);
+		store(0x6000, code);
+		let spin = 0x8000 + program.len() as u16;
+		program.extend([0x4C, spin as u8, (spin >> 8) as u8]);
+
+		Rom::from_ines(&nrom(&program, [0, 0x8000, 0])).unwrap()
+	}
+
+	#[test]
+	fn reports_result() {
+		let result = run(reporting_rom(0), 10).unwrap();
+		assert!(result.passed());
+		assert_eq!(result.message, "ok");
+
+		let result = run(reporting_rom(3), 10).unwrap();
+		assert_eq!(result.code, 3);
+	}
+
+	#[test]
+	fn times_out() {
+		let timeout = run(reporting_rom(RUNNING), 10).unwrap_err();
+		assert_eq!(timeout, BlarggTimeout { frames: 10, message: String::from("ok") });
+	}
+}
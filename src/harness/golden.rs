@@ -0,0 +1,107 @@
+use std::fmt;
+
+use crate::input::FrameInput;
+use crate::nes::Nes;
+use crate::rom::Rom;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GoldenError {
+	// 1-based line of the golden file that is not a hash
+	Parse(usize),
+	// First frame whose hash differs, 0-based
+	Mismatch { frame: usize, expected: u32, got: u32 },
+	Length { expected: usize, got: usize }
+}
+
+impl fmt::Display for GoldenError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			GoldenError::Parse(line) => write!(f, "line {}: expected a hexadecimal hash", line),
+			GoldenError::Mismatch { frame, expected, got } => write!(f, "frame {} hashes to {:08x}, expected {:08x}", frame, got, expected),
+			GoldenError::Length { expected, got } => write!(f, "{} frames hashed, expected {}", got, expected)
+		}
+	}
+}
+
+impl std::error::Error for GoldenError {}
+
+// Hash of each of the first `frames` frames, with `inputs[n]` held during frame n
+// and nothing pressed once they run out
+pub fn frame_hashes(rom: Rom, inputs: &[FrameInput], frames: usize) -> Vec<u32> {
+	let mut nes = Nes::new(rom);
+	(0..frames).map(|frame| {
+		let input = inputs.get(frame).copied().unwrap_or_default();
+		nes.step(input).frame.hash()
+	}).collect()
+}
+
+// One hash per line, as written by format_hashes
+pub fn parse_hashes(text: &str) -> Result<Vec<u32>, GoldenError> {
+	text.lines()
+		.enumerate()
+		.filter(|(_, line)| !line.trim().is_empty())
+		.map(|(index, line)| u32::from_str_radix(line.trim(), 16).map_err(|_| GoldenError::Parse(index + 1)))
+		.collect()
+}
+
+pub fn format_hashes(hashes: &[u32]) -> String {
+	hashes.iter().map(|hash| format!("{:08x}\n", hash)).collect()
+}
+
+pub fn compare(hashes: &[u32], golden: &[u32]) -> Result<(), GoldenError> {
+	if let Some(frame) = hashes.iter().zip(golden).position(|(got, expected)| got != expected) {
+		return Err(GoldenError::Mismatch { frame, expected: golden[frame], got: hashes[frame] });
+	}
+	if hashes.len() != golden.len() {
+		return Err(GoldenError::Length { expected: golden.len(), got: hashes.len() });
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::rom::test::nrom;
+
+	// Shows the A button of controller 1 as the backdrop color
+	fn button_rom() -> Rom {
+		let program = [
+			0xA9, 0x0A, 0x8D, 0x01, 0x20, // Show the background, all backdrop with empty CHR
+			0xA9, 0x01, 0x8D, 0x16, 0x40, // Strobe the controllers
+			0xA9, 0x00, 0x8D, 0x16, 0x40,
+			0xAD, 0x16, 0x40, // A button in bit 0
+			0x29, 0x01,
+			0x0A, 0x0A, 0x0A, 0x0A, // Color $00 or $10
+			0xA2, 0x3F, 0x8E, 0x06, 0x20, // Backdrop at $3F00
+			0xA2, 0x00, 0x8E, 0x06, 0x20,
+			0x8D, 0x07, 0x20,
+			0x4C, 0x00, 0x80
+		];
+
+		Rom::from_ines(&nrom(&program, [0, 0x8000, 0])).unwrap()
+	}
+
+	#[test]
+	fn deterministic_hashes() {
+		let inputs = [FrameInput::default(), FrameInput::default(), FrameInput::new(0x01, 0), FrameInput::new(0x01, 0)];
+		let hashes = frame_hashes(button_rom(), &inputs, 5);
+		assert_eq!(hashes, frame_hashes(button_rom(), &inputs, 5));
+
+		// The backdrop follows the button
+		assert_ne!(hashes[1], hashes[2]);
+		assert_eq!(hashes[2], hashes[3]);
+		assert_eq!(hashes[1], hashes[4]);
+	}
+
+	#[test]
+	fn golden_files() {
+		let golden = parse_hashes(&format_hashes(&[0x1234, 0xDEADBEEF])).unwrap();
+		assert_eq!(golden, vec![0x1234, 0xDEADBEEF]);
+		assert_eq!(parse_hashes("1234\nxyz\n"), Err(GoldenError::Parse(2)));
+
+		assert_eq!(compare(&golden, &golden), Ok(()));
+		assert_eq!(compare(&[0x1234, 0x5678], &golden), Err(GoldenError::Mismatch { frame: 1, expected: 0xDEADBEEF, got: 0x5678 }));
+		assert_eq!(compare(&golden[..1], &golden), Err(GoldenError::Length { expected: 2, got: 1 }));
+	}
+}
@@ -0,0 +1,222 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::bus::Bus;
+use crate::bus::device::BusDevice;
+use crate::cpu::Cpu;
+use crate::harness::json::Json;
+use crate::rom::test;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HarteError(pub String);
+
+impl fmt::Display for HarteError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "invalid test vectors: {}", self.0)
+	}
+}
+
+impl std::error::Error for HarteError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HarteState {
+	pub pc: u16,
+	pub s: u8,
+	pub a: u8,
+	pub x: u8,
+	pub y: u8,
+	pub p: u8,
+	// Bytes set before the instruction or expected after it
+	pub ram: Vec<(u16, u8)>
+}
+
+// One instruction of the SingleStepTests/ProcessorTests vectors
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HarteCase {
+	pub name: String,
+	pub initial: HarteState,
+	pub expected: HarteState,
+	// Bus cycles the instruction takes
+	pub cycles: usize
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HarteFailure {
+	pub name: String,
+	pub message: String
+}
+
+impl fmt::Display for HarteFailure {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}: {}", self.name, self.message)
+	}
+}
+
+// Parse a file of test cases, such as `nes6502/v1/a9.json`
+pub fn parse(text: &str) -> Result<Vec<HarteCase>, HarteError> {
+	let json = Json::parse(text).map_err(HarteError)?;
+	let cases = json.as_array().ok_or_else(|| HarteError(String::from("expected an array of tests")))?;
+	cases.iter().map(parse_case).collect()
+}
+
+fn parse_case(json: &Json) -> Result<HarteCase, HarteError> {
+	let name = json.get("name").and_then(Json::as_str).unwrap_or_default().to_string();
+	let error = |field: &str| HarteError(format!("{}: missing or invalid {}", name, field));
+
+	let initial = parse_state(json.get("initial").ok_or_else(|| error("initial"))?).ok_or_else(|| error("initial"))?;
+	let expected = parse_state(json.get("final").ok_or_else(|| error("final"))?).ok_or_else(|| error("final"))?;
+	let cycles = json.get("cycles").and_then(Json::as_array).ok_or_else(|| error("cycles"))?.len();
+
+	Ok(HarteCase { name, initial, expected, cycles })
+}
+
+fn parse_state(json: &Json) -> Option<HarteState> {
+	let byte = |key: &str| json.get(key).and_then(Json::as_u64).and_then(|value| u8::try_from(value).ok());
+	let ram = json.get("ram")?.as_array()?.iter().map(|pair| {
+		let pair = pair.as_array()?;
+		let adress = u16::try_from(pair.first()?.as_u64()?).ok()?;
+		let value = u8::try_from(pair.get(1)?.as_u64()?).ok()?;
+		Some((adress, value))
+	}).collect::<Option<Vec<_>>>()?;
+
+	Some(HarteState {
+		pc: u16::try_from(json.get("pc")?.as_u64()?).ok()?,
+		s: byte("s")?,
+		a: byte("a")?,
+		x: byte("x")?,
+		y: byte("y")?,
+		p: byte("p")?,
+		ram
+	})
+}
+
+// 64KB of RAM over the whole CPU address space
+#[derive(Clone)]
+struct FlatMemory(Box<[u8]>);
+
+impl BusDevice for FlatMemory {
+	fn range(&self) -> RangeInclusive<u16> {
+		0x0000..=0xFFFF
+	}
+
+	fn read(&mut self, adress: u16) -> u8 {
+		self.0[usize::from(adress)]
+	}
+
+	fn write(&mut self, adress: u16, value: u8) {
+		self.0[usize::from(adress)] = value;
+	}
+
+	fn peek(&self, adress: u16) -> Option<u8> {
+		Some(self.0[usize::from(adress)])
+	}
+}
+
+// Runs cases one after the other on the same CPU and flat memory bus.
+// Registers, memory and the number of cycles are checked, not the accesses of each cycle
+pub struct HarteRunner {
+	cpu: Cpu,
+	bus: Bus
+}
+
+impl HarteRunner {
+	pub fn new() -> HarteRunner {
+		let mut bus = Bus::new(test::test_rom());
+		// Silence the APU frame IRQ before the flat memory hides $4017
+		bus.write(0x4017, 0x40);
+		bus.attach_device(Box::new(FlatMemory(vec![0; 0x10000].into_boxed_slice())));
+
+		HarteRunner { cpu: Cpu::new(), bus }
+	}
+
+	pub fn run(&mut self, case: &HarteCase) -> Result<(), HarteFailure> {
+		let failure = |message: String| HarteFailure { name: case.name.clone(), message };
+
+		let initial = &case.initial;
+		self.cpu.pc = initial.pc;
+		self.cpu.set_sp(initial.s);
+		self.cpu.set_a(initial.a);
+		self.cpu.set_x(initial.x);
+		self.cpu.set_y(initial.y);
+		self.cpu.set_status(initial.p);
+		for &(adress, value) in &initial.ram {
+			self.bus.write(adress, value);
+		}
+
+		// Opcodes nessy does not implement panic
+		let (cpu, bus) = (&mut self.cpu, &mut self.bus);
+		let cycles = panic::catch_unwind(AssertUnwindSafe(|| cpu.step(bus)))
+			.map_err(|_| failure(String::from("the CPU panicked")))?;
+
+		let expected = &case.expected;
+		let registers = [
+			("PC", self.cpu.pc, expected.pc),
+			("S", u16::from(self.cpu.sp()), u16::from(expected.s)),
+			("A", u16::from(self.cpu.a()), u16::from(expected.a)),
+			("X", u16::from(self.cpu.x()), u16::from(expected.x)),
+			("Y", u16::from(self.cpu.y()), u16::from(expected.y)),
+			// The unused bit always reads as set
+			("P", u16::from(self.cpu.status()), u16::from(expected.p | 0x20))
+		];
+		if let Some((register, got, want)) = registers.iter().find(|(_, got, want)| got != want) {
+			return Err(failure(format!("{} is ${:02X}, expected ${:02X}", register, got, want)));
+		}
+
+		for &(adress, value) in &expected.ram {
+			let got = self.bus.peek(adress);
+			if got != value {
+				return Err(failure(format!("${:04X} is ${:02X}, expected ${:02X}", adress, got, value)));
+			}
+		}
+
+		if usize::from(cycles) != case.cycles {
+			return Err(failure(format!("took {} cycles, expected {}", cycles, case.cycles)));
+		}
+		Ok(())
+	}
+
+	// Failures of every case in a file
+	pub fn run_file(&mut self, text: &str) -> Result<Vec<HarteFailure>, HarteError> {
+		Ok(parse(text)?.iter().filter_map(|case| self.run(case).err()).collect())
+	}
+}
+
+impl Default for HarteRunner {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const LDA_IMMEDIATE: &str = r#"[{
+		"name": "a9 42 00",
+		"initial": {"pc": 512, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36, "ram": [[512, 169], [513, 128]]},
+		"final": {"pc": 514, "s": 253, "a": 128, "x": 0, "y": 0, "p": 164, "ram": [[512, 169], [513, 128]]},
+		"cycles": [[512, 169, "read"], [513, 128, "read"]]
+	}]"#;
+
+	#[test]
+	fn passing_case() {
+		let mut runner = HarteRunner::new();
+		assert_eq!(runner.run_file(LDA_IMMEDIATE), Ok(Vec::new()));
+	}
+
+	#[test]
+	fn failing_case() {
+		let mut cases = parse(LDA_IMMEDIATE).unwrap();
+		cases[0].expected.a = 0x7F;
+		cases[0].expected.ram.push((0x0300, 0x01));
+
+		let mut runner = HarteRunner::new();
+		let failure = runner.run(&cases[0]).unwrap_err();
+		assert_eq!(failure.message, "A is $80, expected $7F");
+
+		cases[0].expected.a = 0x80;
+		let failure = runner.run(&cases[0]).unwrap_err();
+		assert_eq!(failure.message, "$0300 is $00, expected $01");
+	}
+}
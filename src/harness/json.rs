@@ -0,0 +1,209 @@
+// Just enough JSON for test vector files
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+	Null,
+	Bool(bool),
+	Number(f64),
+	String(String),
+	Array(Vec<Json>),
+	Object(Vec<(String, Json)>)
+}
+
+impl Json {
+	pub(crate) fn parse(text: &str) -> Result<Json, String> {
+		let mut parser = Parser { bytes: text.as_bytes(), position: 0 };
+		let value = parser.value()?;
+		parser.skip_whitespace();
+		if parser.position != parser.bytes.len() {
+			return Err(parser.error("trailing characters"));
+		}
+		Ok(value)
+	}
+
+	pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+		match self {
+			Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+			_ => None
+		}
+	}
+
+	pub(crate) fn as_array(&self) -> Option<&[Json]> {
+		match self {
+			Json::Array(values) => Some(values),
+			_ => None
+		}
+	}
+
+	pub(crate) fn as_str(&self) -> Option<&str> {
+		match self {
+			Json::String(value) => Some(value),
+			_ => None
+		}
+	}
+
+	pub(crate) fn as_u64(&self) -> Option<u64> {
+		match self {
+			Json::Number(value) if value.fract() == 0.0 && *value >= 0.0 => Some(*value as u64),
+			_ => None
+		}
+	}
+}
+
+struct Parser<'a> {
+	bytes: &'a [u8],
+	position: usize
+}
+
+impl Parser<'_> {
+	fn error(&self, message: &str) -> String {
+		format!("{} at byte {}", message, self.position)
+	}
+
+	fn skip_whitespace(&mut self) {
+		while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+			self.position += 1;
+		}
+	}
+
+	fn expect(&mut self, expected: u8) -> Result<(), String> {
+		self.skip_whitespace();
+		if self.bytes.get(self.position) != Some(&expected) {
+			return Err(self.error(&format!("expected '{}'", char::from(expected))));
+		}
+		self.position += 1;
+		Ok(())
+	}
+
+	fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, String> {
+		if !self.bytes[self.position..].starts_with(keyword.as_bytes()) {
+			return Err(self.error("unexpected character"));
+		}
+		self.position += keyword.len();
+		Ok(value)
+	}
+
+	fn value(&mut self) -> Result<Json, String> {
+		self.skip_whitespace();
+		match self.bytes.get(self.position) {
+			Some(b'{') => self.object(),
+			Some(b'[') => self.array(),
+			Some(b'"') => self.string().map(Json::String),
+			Some(b't') => self.keyword("true", Json::Bool(true)),
+			Some(b'f') => self.keyword("false", Json::Bool(false)),
+			Some(b'n') => self.keyword("null", Json::Null),
+			Some(_) => self.number(),
+			None => Err(self.error("unexpected end"))
+		}
+	}
+
+	fn object(&mut self) -> Result<Json, String> {
+		self.expect(b'{')?;
+		let mut members = Vec::new();
+		self.skip_whitespace();
+		if self.bytes.get(self.position) == Some(&b'}') {
+			self.position += 1;
+			return Ok(Json::Object(members));
+		}
+
+		loop {
+			self.skip_whitespace();
+			let key = self.string()?;
+			self.expect(b':')?;
+			members.push((key, self.value()?));
+
+			self.skip_whitespace();
+			match self.bytes.get(self.position) {
+				Some(b',') => self.position += 1,
+				Some(b'}') => {
+					self.position += 1;
+					return Ok(Json::Object(members));
+				},
+				_ => return Err(self.error("expected ',' or '}'"))
+			}
+		}
+	}
+
+	fn array(&mut self) -> Result<Json, String> {
+		self.expect(b'[')?;
+		let mut values = Vec::new();
+		self.skip_whitespace();
+		if self.bytes.get(self.position) == Some(&b']') {
+			self.position += 1;
+			return Ok(Json::Array(values));
+		}
+
+		loop {
+			values.push(self.value()?);
+
+			self.skip_whitespace();
+			match self.bytes.get(self.position) {
+				Some(b',') => self.position += 1,
+				Some(b']') => {
+					self.position += 1;
+					return Ok(Json::Array(values));
+				},
+				_ => return Err(self.error("expected ',' or ']'"))
+			}
+		}
+	}
+
+	fn string(&mut self) -> Result<String, String> {
+		self.expect(b'"')?;
+		let mut value = Vec::new();
+		loop {
+			let byte = *self.bytes.get(self.position).ok_or_else(|| self.error("unterminated string"))?;
+			self.position += 1;
+			match byte {
+				b'"' => break,
+				b'\\' => {
+					let escaped = *self.bytes.get(self.position).ok_or_else(|| self.error("unterminated string"))?;
+					self.position += 1;
+					match escaped {
+						b'n' => value.push(b'\n'),
+						b't' => value.push(b'\t'),
+						b'r' => value.push(b'\r'),
+						b'u' => {
+							let hex = self.bytes.get(self.position..self.position + 4).ok_or_else(|| self.error("short escape"))?;
+							let code = std::str::from_utf8(hex).ok().and_then(|hex| u32::from_str_radix(hex, 16).ok());
+							let character = code.and_then(char::from_u32).ok_or_else(|| self.error("invalid escape"))?;
+							self.position += 4;
+							value.extend(character.to_string().bytes());
+						},
+						other => value.push(other)
+					}
+				},
+				_ => value.push(byte)
+			}
+		}
+		String::from_utf8(value).map_err(|_| self.error("invalid UTF-8"))
+	}
+
+	fn number(&mut self) -> Result<Json, String> {
+		let start = self.position;
+		while self.bytes.get(self.position).is_some_and(|byte| matches!(byte, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E')) {
+			self.position += 1;
+		}
+
+		std::str::from_utf8(&self.bytes[start..self.position]).ok()
+			.and_then(|number| number.parse().ok())
+			.map(Json::Number)
+			.ok_or_else(|| self.error("invalid number"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse() {
+		let json = Json::parse(r#" {"name": "a9 \"x\"", "ram": [[1, 2]], "ok": true, "none": null} "#).unwrap();
+		assert_eq!(json.get("name").and_then(Json::as_str), Some("a9 \"x\""));
+		let pair = json.get("ram").and_then(Json::as_array).unwrap()[0].as_array().unwrap();
+		assert_eq!(pair[1].as_u64(), Some(2));
+		assert_eq!(json.get("ok"), Some(&Json::Bool(true)));
+
+		assert!(Json::parse("[1, 2").is_err());
+		assert!(Json::parse("{} x").is_err());
+	}
+}
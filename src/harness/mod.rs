@@ -0,0 +1,5 @@
+// Runners for test ROMs and test vectors, used by the integration tests
+pub mod blargg;
+pub mod golden;
+pub mod harte;
+mod json;
@@ -0,0 +1,266 @@
+use std::fmt;
+use std::fmt::Write;
+
+use crate::input::FrameInput;
+
+// Gamepad columns, from bit 7 down to bit 0
+const GAMEPAD_COLUMNS: &[u8; 8] = b"RLDUTSBA";
+
+// FM2 input devices
+const DEVICE_NONE: u8 = 0;
+const DEVICE_GAMEPAD: u8 = 1;
+
+// Frame commands
+pub const SOFT_RESET: u8 = 0x01;
+pub const HARD_RESET: u8 = 0x02;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fm2Error {
+	// 1-based
+	pub line: usize,
+	pub message: String
+}
+
+impl fmt::Display for Fm2Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+impl std::error::Error for Fm2Error {}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Fm2Frame {
+	// SOFT_RESET, HARD_RESET, and FCEUX commands nessy does not replay
+	pub commands: u8,
+	pub input: FrameInput
+}
+
+// FCEUX text movie, with standard controllers or nothing on the ports:
+//
+//     version 3
+//     rerecordCount 12
+//     port0 1
+//     |0|R......A|........||
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fm2Movie {
+	pub version: u32,
+	pub emu_version: u32,
+	pub rerecord_count: u32,
+	pub pal: bool,
+	pub rom_filename: String,
+	// base64:MD5 of the ROM, as FCEUX writes it
+	pub rom_checksum: String,
+	pub guid: String,
+	// Gamepad or nothing on port 0 and 1
+	pub port1: bool,
+	pub port2: bool,
+	pub comments: Vec<String>,
+	// Header lines kept as they are, such as subtitles
+	pub extra: Vec<(String, String)>,
+	pub frames: Vec<Fm2Frame>
+}
+
+impl Fm2Movie {
+	pub fn new() -> Fm2Movie {
+		Fm2Movie {
+			version: 3,
+			emu_version: 22020,
+			rerecord_count: 0,
+			pal: false,
+			rom_filename: String::new(),
+			rom_checksum: String::new(),
+			guid: String::from("00000000-0000-0000-0000-000000000000"),
+			port1: true,
+			port2: true,
+			comments: Vec::new(),
+			extra: Vec::new(),
+			frames: Vec::new()
+		}
+	}
+
+	// Movie of frames logged by a Recorder, starting from power on
+	pub fn from_inputs(inputs: &[FrameInput]) -> Fm2Movie {
+		Fm2Movie {
+			frames: inputs.iter().map(|&input| Fm2Frame { commands: 0, input }).collect(),
+			..Fm2Movie::new()
+		}
+	}
+
+	// Controller states for input::Player
+	pub fn inputs(&self) -> Vec<FrameInput> {
+		self.frames.iter().map(|frame| frame.input).collect()
+	}
+
+	pub fn parse(text: &str) -> Result<Fm2Movie, Fm2Error> {
+		let mut movie = Fm2Movie::new();
+		movie.port1 = false;
+		movie.port2 = false;
+
+		for (index, line) in text.lines().enumerate() {
+			let error = |message: String| Fm2Error { line: index + 1, message };
+			let line = line.trim_end_matches('\r');
+
+			if let Some(record) = line.strip_prefix('|') {
+				movie.frames.push(movie.parse_frame(record).map_err(error)?);
+				continue;
+			}
+			if line.trim().is_empty() {
+				continue;
+			}
+
+			let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+			let number = || value.trim().parse::<u32>().map_err(|_| error(format!("invalid {} {}", key, value)));
+			let device = || match number()? {
+				0 => Ok(false),
+				1 => Ok(true),
+				device => Err(error(format!("unsupported {} device {}", key, device)))
+			};
+
+			match key {
+				"version" => movie.version = number()?,
+				"emuVersion" => movie.emu_version = number()?,
+				"rerecordCount" => movie.rerecord_count = number()?,
+				"palFlag" => movie.pal = number()? != 0,
+				"romFilename" => movie.rom_filename = value.to_string(),
+				"romChecksum" => movie.rom_checksum = value.to_string(),
+				"guid" => movie.guid = value.to_string(),
+				"comment" => movie.comments.push(value.to_string()),
+				"port0" => movie.port1 = device()?,
+				"port1" => movie.port2 = device()?,
+				"binary" | "fourscore" | "port2" => {
+					if number()? != 0 {
+						return Err(error(format!("{} movies are not supported", key)));
+					}
+				},
+				_ => movie.extra.push((key.to_string(), value.to_string()))
+			}
+		}
+
+		Ok(movie)
+	}
+
+	fn parse_frame(&self, record: &str) -> Result<Fm2Frame, String> {
+		let mut fields = record.split('|');
+		let commands = fields.next().unwrap_or("");
+		let commands = commands.parse().map_err(|_| format!("invalid commands {}", commands))?;
+
+		let mut gamepad = |present: bool| -> Result<u8, String> {
+			let field = fields.next().ok_or("missing controller column")?;
+			if !present {
+				return Ok(0);
+			}
+			if field.len() != GAMEPAD_COLUMNS.len() {
+				return Err(format!("invalid gamepad column {}", field));
+			}
+
+			// Any character but '.' and ' ' is a pressed button
+			Ok(field.bytes().enumerate()
+				.filter(|&(_, column)| column != b'.' && column != b' ')
+				.fold(0, |buttons, (index, _)| buttons | (0x80 >> index)))
+		};
+
+		let port1 = gamepad(self.port1)?;
+		let port2 = gamepad(self.port2)?;
+		Ok(Fm2Frame { commands, input: FrameInput::new(port1, port2) })
+	}
+
+	pub fn to_fm2(&self) -> String {
+		let device = |present: bool| if present { DEVICE_GAMEPAD } else { DEVICE_NONE };
+
+		let mut text = String::new();
+		let _ = writeln!(text, "version {}", self.version);
+		let _ = writeln!(text, "emuVersion {}", self.emu_version);
+		let _ = writeln!(text, "rerecordCount {}", self.rerecord_count);
+		let _ = writeln!(text, "palFlag {}", u8::from(self.pal));
+		let _ = writeln!(text, "romFilename {}", self.rom_filename);
+		let _ = writeln!(text, "romChecksum {}", self.rom_checksum);
+		let _ = writeln!(text, "guid {}", self.guid);
+		let _ = writeln!(text, "fourscore 0");
+		let _ = writeln!(text, "port0 {}", device(self.port1));
+		let _ = writeln!(text, "port1 {}", device(self.port2));
+		let _ = writeln!(text, "port2 0");
+		for (key, value) in &self.extra {
+			let _ = writeln!(text, "{} {}", key, value);
+		}
+		for comment in &self.comments {
+			let _ = writeln!(text, "comment {}", comment);
+		}
+
+		let gamepad = |present: bool, buttons: u8| -> String {
+			if !present {
+				return String::new();
+			}
+			GAMEPAD_COLUMNS.iter().enumerate()
+				.map(|(index, &column)| if buttons & (0x80 >> index) != 0 { column as char } else { '.' })
+				.collect()
+		};
+		for frame in &self.frames {
+			let _ = writeln!(text, "|{}|{}|{}||", frame.commands,
+				gamepad(self.port1, frame.input.port1), gamepad(self.port2, frame.input.port2));
+		}
+
+		text
+	}
+}
+
+impl Default for Fm2Movie {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MOVIE: &str = "version 3\n\
+		emuVersion 22020\n\
+		rerecordCount 42\n\
+		palFlag 0\n\
+		romFilename Super Mario Bros.\n\
+		romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==\n\
+		guid 52DD5C8E-0D3A-4D3B-9DE7-8A0A5E6D9B1B\n\
+		fourscore 0\n\
+		microphone 0\n\
+		port0 1\n\
+		port1 0\n\
+		port2 0\n\
+		comment author someone\n\
+		|1|........|||\n\
+		|0|R......A|||\n\
+		|0|...UT...|||\n";
+
+	#[test]
+	fn parse() {
+		let movie = Fm2Movie::parse(MOVIE).unwrap();
+		assert_eq!(movie.rerecord_count, 42);
+		assert_eq!(movie.rom_filename, "Super Mario Bros.");
+		assert_eq!(movie.comments, ["author someone"]);
+		assert_eq!(movie.extra, [(String::from("microphone"), String::from("0"))]);
+		assert!(movie.port1 && !movie.port2);
+
+		assert_eq!(movie.frames.len(), 3);
+		assert_eq!(movie.frames[0].commands, SOFT_RESET);
+		assert_eq!(movie.inputs(), [FrameInput::new(0, 0), FrameInput::new(0x81, 0), FrameInput::new(0x18, 0)]);
+	}
+
+	#[test]
+	fn round_trip() {
+		let mut movie = Fm2Movie::from_inputs(&[FrameInput::new(0x01, 0x80), FrameInput::new(0x00, 0x03)]);
+		movie.rerecord_count = 7;
+
+		let text = movie.to_fm2();
+		assert!(text.contains("|0|.......A|R.......||\n"));
+		assert_eq!(Fm2Movie::parse(&text).unwrap(), movie);
+	}
+
+	#[test]
+	fn errors() {
+		let error = Fm2Movie::parse("port0 2\n").unwrap_err();
+		assert_eq!(error.to_string(), "line 1: unsupported port0 device 2");
+
+		let error = Fm2Movie::parse("port0 1\n|0|RL|||\n").unwrap_err();
+		assert_eq!(error.line, 2);
+	}
+}
@@ -0,0 +1,164 @@
+use crate::input::InputDevice;
+
+const ROWS: usize = 9;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+	F1, F2, F3, F4, F5, F6, F7, F8,
+	Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9, Num0,
+	A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+	Minus, Caret, Yen, Stop,
+	Escape, At, LeftBracket, Return,
+	Control, Semicolon, Colon, RightBracket, Kana,
+	LeftShift, Comma, Period, Slash, Underscore, RightShift,
+	Graph, Space,
+	ClearHome, Insert, Delete,
+	Up, Down, Left, Right
+}
+
+// Keys of each row, column 0 then column 1, from $4017 bit 4 down to bit 1
+const MATRIX: [[[Key; 4]; 2]; ROWS] = [
+	[[Key::RightBracket, Key::LeftBracket, Key::Return, Key::F8], [Key::Stop, Key::Yen, Key::RightShift, Key::Kana]],
+	[[Key::Semicolon, Key::Colon, Key::At, Key::F7], [Key::Caret, Key::Minus, Key::Slash, Key::Underscore]],
+	[[Key::K, Key::L, Key::O, Key::F6], [Key::Num0, Key::P, Key::Comma, Key::Period]],
+	[[Key::J, Key::U, Key::I, Key::F5], [Key::Num8, Key::Num9, Key::N, Key::M]],
+	[[Key::H, Key::G, Key::Y, Key::F4], [Key::Num6, Key::Num7, Key::V, Key::B]],
+	[[Key::D, Key::R, Key::T, Key::F3], [Key::Num4, Key::Num5, Key::C, Key::F]],
+	[[Key::A, Key::S, Key::W, Key::F2], [Key::Num3, Key::E, Key::Z, Key::X]],
+	[[Key::Control, Key::Q, Key::Escape, Key::F1], [Key::Num2, Key::Num1, Key::Graph, Key::LeftShift]],
+	[[Key::Left, Key::Right, Key::Up, Key::ClearHome], [Key::Insert, Key::Delete, Key::Space, Key::Down]]
+];
+
+impl Key {
+	// Row, column and $4017 bit
+	fn position(self) -> (usize, usize, u8) {
+		for (row, columns) in MATRIX.iter().enumerate() {
+			for (column, keys) in columns.iter().enumerate() {
+				if let Some(index) = keys.iter().position(|&key| key == self) {
+					return (row, column, 4 - index as u8);
+				}
+			}
+		}
+		unreachable!("{:?} is not in the keyboard matrix", self)
+	}
+}
+
+// Family BASIC keyboard on the Famicom expansion port. $4016 writes: bit 0 goes back to row 0,
+// bit 1 selects the column and moves to the next row when cleared, bit 2 enables the matrix.
+// $4017 reads the 4 keys of the selected row and column on bits 1-4, low when pressed
+#[derive(Clone)]
+pub struct FamilyKeyboard {
+	// Bits 1-4 of each row and column, set when pressed
+	pressed: [[u8; 2]; ROWS],
+	row: usize,
+	column: usize,
+	enabled: bool
+}
+
+impl FamilyKeyboard {
+	pub fn new() -> FamilyKeyboard {
+		FamilyKeyboard {
+			pressed: [[0; 2]; ROWS],
+			row: 0,
+			column: 0,
+			enabled: false
+		}
+	}
+
+	pub fn set_key(&mut self, key: Key, pressed: bool) {
+		let (row, column, bit) = key.position();
+		if pressed {
+			self.pressed[row][column] |= 1 << bit;
+		} else {
+			self.pressed[row][column] &= !(1 << bit);
+		}
+	}
+
+	pub fn is_pressed(&self, key: Key) -> bool {
+		let (row, column, bit) = key.position();
+		self.pressed[row][column] & (1 << bit) != 0
+	}
+
+	pub fn release_all(&mut self) {
+		self.pressed = [[0; 2]; ROWS];
+	}
+}
+
+impl InputDevice for FamilyKeyboard {
+	fn strobe(&mut self, _high: bool) {}
+
+	fn write(&mut self, value: u8) {
+		let previous_column = self.column;
+		self.column = usize::from((value >> 1) & 0x01);
+		self.enabled = value & 0x04 != 0;
+
+		if self.enabled {
+			if self.column == 0 && previous_column == 1 {
+				// A 10th row reads as nothing pressed, the BASIC uses it to detect the keyboard
+				self.row = (self.row + 1) % (ROWS + 1);
+			}
+			if value & 0x01 != 0 {
+				self.row = 0;
+			}
+		}
+	}
+
+	fn read(&mut self) -> u8 {
+		self.peek()
+	}
+
+	fn peek(&self) -> u8 {
+		if !self.enabled {
+			return 0;
+		}
+
+		match self.pressed.get(self.row) {
+			Some(row) => !row[self.column] & 0x1E,
+			None => 0x1E
+		}
+	}
+}
+
+impl Default for FamilyKeyboard {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Reset to row 0, then go through the rows as Family BASIC does
+	fn scan(keyboard: &mut FamilyKeyboard) -> Vec<u8> {
+		keyboard.write(0x05);
+		let mut reads = Vec::new();
+		for _ in 0..ROWS + 1 {
+			keyboard.write(0x04);
+			reads.push(keyboard.read());
+			keyboard.write(0x06);
+			reads.push(keyboard.read());
+		}
+		reads
+	}
+
+	#[test]
+	fn matrix() {
+		let mut keyboard = FamilyKeyboard::new();
+		keyboard.set_key(Key::Return, true);
+		keyboard.set_key(Key::Space, true);
+		keyboard.set_key(Key::LeftShift, true);
+		keyboard.set_key(Key::LeftShift, false);
+		assert!(keyboard.is_pressed(Key::Space));
+
+		let reads = scan(&mut keyboard);
+		// Row 0 column 0, Return on bit 2
+		assert_eq!(reads[0], 0x1A);
+		// Row 8 column 1, Space on bit 2
+		assert_eq!(reads[17], 0x1A);
+		assert!(reads.iter().enumerate().all(|(i, &read)| i == 0 || i == 17 || read == 0x1E));
+
+		keyboard.write(0x00);
+		assert_eq!(keyboard.read(), 0);
+	}
+}
@@ -0,0 +1,77 @@
+pub mod zapper;
+pub mod paddle;
+pub mod power_pad;
+pub mod keyboard;
+pub mod recording;
+pub mod fm2;
+
+use std::any::Any;
+
+pub use recording::{FrameInput, Player, Recorder};
+
+use crate::ppu::Ppu;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Port {
+	// Read at $4016
+	One,
+	// Read at $4017
+	Two,
+	// Famicom expansion port, read at $4017 along with port 2
+	Expansion
+}
+
+// Controller plugged into a port. All ports see the $4016 writes,
+// reads return the data lines D0-D4 of the port, the upper bits are open bus
+pub trait InputDevice: Any + InputDeviceClone {
+	// $4016 write bit 0
+	fn strobe(&mut self, high: bool);
+
+	// Whole $4016 write, for expansion devices also using the OUT1 and OUT2 lines
+	fn write(&mut self, value: u8) {
+		self.strobe(value & 0x01 != 0);
+	}
+
+	fn read(&mut self) -> u8;
+
+	// Read without shifting
+	fn peek(&self) -> u8;
+
+	// Called by the bus before each read, for devices looking at the screen
+	fn observe(&mut self, _ppu: &Ppu) {}
+
+	// Called once per frame, by Nes::run_frame
+	fn end_frame(&mut self) {}
+}
+
+pub trait InputDeviceClone {
+	fn clone_box(&self) -> Box<dyn InputDevice>;
+}
+
+impl<T: InputDevice + Clone> InputDeviceClone for T {
+	fn clone_box(&self) -> Box<dyn InputDevice> {
+		Box::new(self.clone())
+	}
+}
+
+impl Clone for Box<dyn InputDevice> {
+	fn clone(&self) -> Self {
+		self.clone_box()
+	}
+}
+
+// Empty port, the data lines read as 0
+#[derive(Clone)]
+pub struct Unplugged;
+
+impl InputDevice for Unplugged {
+	fn strobe(&mut self, _high: bool) {}
+
+	fn read(&mut self) -> u8 {
+		0
+	}
+
+	fn peek(&self) -> u8 {
+		0
+	}
+}
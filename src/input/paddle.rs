@@ -0,0 +1,94 @@
+use crate::input::InputDevice;
+
+// Knob range of the NES Vaus controller
+pub const PADDLE_MIN: u8 = 0x62;
+pub const PADDLE_MAX: u8 = 0xF2;
+
+// Arkanoid Vaus controller: the strobe latches the knob position, which shifts out
+// inverted and MSB first on D4, D3 is the fire button
+#[derive(Clone)]
+pub struct ArkanoidPaddle {
+	position: u8,
+	button: bool,
+	strobe: bool,
+	shift: u8
+}
+
+impl ArkanoidPaddle {
+	pub fn new() -> ArkanoidPaddle {
+		ArkanoidPaddle {
+			position: PADDLE_MIN,
+			button: false,
+			strobe: false,
+			shift: 0
+		}
+	}
+
+	// Clamped to PADDLE_MIN..=PADDLE_MAX
+	pub fn set_position(&mut self, position: u8) {
+		self.position = position.clamp(PADDLE_MIN, PADDLE_MAX);
+	}
+
+	pub fn position(&self) -> u8 {
+		self.position
+	}
+
+	pub fn set_button(&mut self, pressed: bool) {
+		self.button = pressed;
+	}
+}
+
+impl InputDevice for ArkanoidPaddle {
+	fn strobe(&mut self, high: bool) {
+		self.strobe = high;
+		if high {
+			self.shift = !self.position;
+		}
+	}
+
+	fn read(&mut self) -> u8 {
+		let value = self.peek();
+		if !self.strobe {
+			// Zeros shift in, read back as ones
+			self.shift <<= 1;
+		}
+		value
+	}
+
+	fn peek(&self) -> u8 {
+		let data = if self.shift & 0x80 != 0 { 0x00 } else { 0x10 };
+		let button = if self.button { 0x08 } else { 0x00 };
+		data | button
+	}
+}
+
+impl Default for ArkanoidPaddle {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn position_shift() {
+		let mut paddle = ArkanoidPaddle::new();
+		paddle.set_position(0xA5);
+		paddle.set_button(true);
+
+		paddle.strobe(true);
+		paddle.strobe(false);
+		let position = (0..8).fold(0, |position, _| {
+			let value = paddle.read();
+			assert_eq!(value & 0x08, 0x08);
+			(position << 1) | (value >> 4)
+		});
+		assert_eq!(position, 0xA5);
+		assert_eq!(paddle.read() & 0x10, 0x10);
+
+		paddle.set_position(0);
+		assert_eq!(paddle.position(), PADDLE_MIN);
+	}
+}
@@ -0,0 +1,105 @@
+use crate::input::InputDevice;
+
+// Order of the buttons (numbered 1-12 on side B) on D3, then on D4
+const D3_ORDER: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D4_ORDER: [u8; 4] = [4, 3, 12, 8];
+
+// Power Pad floor mat: two shift registers read on D3 and D4, pressed buttons read as 1
+#[derive(Clone)]
+pub struct PowerPad {
+	// Bit n - 1 for button n
+	buttons: u16,
+	strobe: bool,
+	shift_d3: u8,
+	shift_d4: u8
+}
+
+impl PowerPad {
+	pub fn new() -> PowerPad {
+		PowerPad {
+			buttons: 0,
+			strobe: false,
+			shift_d3: 0,
+			shift_d4: 0
+		}
+	}
+
+	// `button` is 1 to 12
+	pub fn set_button(&mut self, button: u8, pressed: bool) {
+		if !(1..=12).contains(&button) {
+			return;
+		}
+
+		let mask = 1 << (button - 1);
+		if pressed {
+			self.buttons |= mask;
+		} else {
+			self.buttons &= !mask;
+		}
+	}
+
+	pub fn is_pressed(&self, button: u8) -> bool {
+		(1..=12).contains(&button) && self.buttons & (1 << (button - 1)) != 0
+	}
+
+	fn latch(&mut self) {
+		self.shift_d3 = D3_ORDER.iter().enumerate()
+			.filter(|&(_, &button)| self.is_pressed(button))
+			.fold(0, |shift, (bit, _)| shift | (1 << bit));
+		// Only 4 buttons on D4, ones after them
+		self.shift_d4 = D4_ORDER.iter().enumerate()
+			.filter(|&(_, &button)| self.is_pressed(button))
+			.fold(0xF0, |shift, (bit, _)| shift | (1 << bit));
+	}
+}
+
+impl InputDevice for PowerPad {
+	fn strobe(&mut self, high: bool) {
+		self.strobe = high;
+		if high {
+			self.latch();
+		}
+	}
+
+	fn read(&mut self) -> u8 {
+		if self.strobe {
+			self.latch();
+		}
+
+		let value = self.peek();
+		if !self.strobe {
+			// Ones shift in once all buttons are read
+			self.shift_d3 = (self.shift_d3 >> 1) | 0x80;
+			self.shift_d4 = (self.shift_d4 >> 1) | 0x80;
+		}
+		value
+	}
+
+	fn peek(&self) -> u8 {
+		((self.shift_d3 & 0x01) << 3) | ((self.shift_d4 & 0x01) << 4)
+	}
+}
+
+impl Default for PowerPad {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn serial_order() {
+		let mut pad = PowerPad::new();
+		pad.set_button(1, true);
+		pad.set_button(12, true);
+
+		pad.strobe(true);
+		pad.strobe(false);
+		let reads: Vec<u8> = (0..9).map(|_| pad.read()).collect();
+		// Button 1 is second on D3, 12 third on D4
+		assert_eq!(reads, [0x00, 0x08, 0x10, 0x00, 0x10, 0x10, 0x10, 0x10, 0x18]);
+	}
+}
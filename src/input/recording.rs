@@ -0,0 +1,151 @@
+use crate::bus::Bus;
+use crate::input::Port;
+
+// Buttons of both standard controllers during one frame, bit 0 is A up to bit 7 for Right
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameInput {
+	pub port1: u8,
+	pub port2: u8
+}
+
+impl FrameInput {
+	pub fn new(port1: u8, port2: u8) -> FrameInput {
+		FrameInput { port1, port2 }
+	}
+
+	// Buttons the console sees, after turbo. Ports without a standard controller read as 0
+	pub fn capture(bus: &Bus) -> FrameInput {
+		FrameInput {
+			port1: bus.joypad1().map_or(0, |joypad| joypad.output()),
+			port2: bus.joypad2().map_or(0, |joypad| joypad.output())
+		}
+	}
+
+	pub fn apply(self, bus: &mut Bus) {
+		if let Some(joypad) = bus.joypad1_mut() {
+			joypad.set_buttons(self.port1);
+		}
+		if let Some(joypad) = bus.joypad2_mut() {
+			joypad.set_buttons(self.port2);
+		}
+	}
+
+	pub fn port(self, port: Port) -> u8 {
+		match port {
+			Port::One => self.port1,
+			Port::Two => self.port2,
+			Port::Expansion => 0
+		}
+	}
+}
+
+// Controller states logged once per frame, from power on or a save state
+#[derive(Clone)]
+pub struct Recorder {
+	frames: Vec<FrameInput>
+}
+
+impl Recorder {
+	pub fn new() -> Recorder {
+		Recorder { frames: Vec::new() }
+	}
+
+	// Call at the start of each frame, once the inputs for it are set
+	pub fn record(&mut self, bus: &Bus) {
+		self.frames.push(FrameInput::capture(bus));
+	}
+
+	pub fn push(&mut self, input: FrameInput) {
+		self.frames.push(input);
+	}
+
+	pub fn frames(&self) -> &[FrameInput] {
+		&self.frames
+	}
+
+	pub fn into_frames(self) -> Vec<FrameInput> {
+		self.frames
+	}
+}
+
+impl Default for Recorder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+// Feeds recorded inputs back frame by frame, turbo must be off on the controllers
+// since the recorded states already include it
+#[derive(Clone)]
+pub struct Player {
+	frames: Vec<FrameInput>,
+	position: usize
+}
+
+impl Player {
+	pub fn new(frames: Vec<FrameInput>) -> Player {
+		Player { frames, position: 0 }
+	}
+
+	// Set the controllers for the next frame, false once all frames were played
+	pub fn apply(&mut self, bus: &mut Bus) -> bool {
+		match self.next_frame() {
+			Some(input) => {
+				input.apply(bus);
+				true
+			},
+			None => false
+		}
+	}
+
+	pub fn next_frame(&mut self) -> Option<FrameInput> {
+		let input = self.frames.get(self.position).copied()?;
+		self.position += 1;
+		Some(input)
+	}
+
+	// Frames already played
+	pub fn position(&self) -> usize {
+		self.position
+	}
+
+	pub fn len(&self) -> usize {
+		self.frames.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.frames.is_empty()
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.position >= self.frames.len()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::joypad::Button;
+	use crate::rom::test;
+
+	#[test]
+	fn record_and_play() {
+		let mut bus = Bus::new(test::test_rom());
+		let mut recorder = Recorder::new();
+
+		recorder.record(&bus);
+		bus.joypad1_mut().unwrap().set_button(Button::A, true);
+		bus.joypad2_mut().unwrap().set_button(Button::Left, true);
+		recorder.record(&bus);
+		assert_eq!(recorder.frames(), [FrameInput::new(0, 0), FrameInput::new(0x01, 0x40)]);
+
+		let mut bus = Bus::new(test::test_rom());
+		let mut player = Player::new(recorder.into_frames());
+		assert!(player.apply(&mut bus));
+		assert_eq!(bus.joypad1().unwrap().buttons(), 0);
+		assert!(player.apply(&mut bus));
+		assert_eq!(FrameInput::capture(&bus), FrameInput::new(0x01, 0x40));
+		assert!(player.is_finished());
+		assert!(!player.apply(&mut bus));
+	}
+}
@@ -0,0 +1,102 @@
+use crate::input::InputDevice;
+use crate::ppu::Ppu;
+use crate::ppu::frame::{WIDTH, HEIGHT};
+
+// The photodiode keeps seeing light for about 26 scanlines after the beam passed
+const LIGHT_SCANLINES: u16 = 26;
+// Sum of the RGB components for a pixel to count as lit
+const BRIGHTNESS_THRESHOLD: u16 = 0x180;
+
+// Light gun: D3 is low while the aimed pixel is bright, D4 is high while the trigger is pulled
+#[derive(Clone)]
+pub struct Zapper {
+	// None when pointing away from the screen
+	aim: Option<(usize, usize)>,
+	trigger: bool,
+	light: bool
+}
+
+impl Zapper {
+	pub fn new() -> Zapper {
+		Zapper {
+			aim: None,
+			trigger: false,
+			light: false
+		}
+	}
+
+	// Screen coordinates in the 256x240 frame
+	pub fn aim(&mut self, x: usize, y: usize) {
+		self.aim = if x < WIDTH && y < HEIGHT { Some((x, y)) } else { None };
+	}
+
+	pub fn aim_off_screen(&mut self) {
+		self.aim = None;
+	}
+
+	pub fn set_trigger(&mut self, pulled: bool) {
+		self.trigger = pulled;
+	}
+
+	fn senses_light(&self, ppu: &Ppu) -> bool {
+		let Some((x, y)) = self.aim else {
+			return false;
+		};
+
+		// Lines are drawn at dot 256, the aimed one must have been drawn recently this frame
+		let scanline = ppu.scanline();
+		let y = y as u16;
+		let drawn = scanline > y || (scanline == y && ppu.dot() > 256);
+		if !drawn || scanline - y >= LIGHT_SCANLINES || scanline >= HEIGHT as u16 + LIGHT_SCANLINES {
+			return false;
+		}
+
+		let pixel = ppu.frame_buffer()[usize::from(y) * WIDTH + x];
+		let (r, g, b) = ppu.palette().emphasized_color((pixel & 0x3F) as u8, (pixel >> 6) as u8);
+		u16::from(r) + u16::from(g) + u16::from(b) >= BRIGHTNESS_THRESHOLD
+	}
+}
+
+impl InputDevice for Zapper {
+	fn strobe(&mut self, _high: bool) {}
+
+	fn read(&mut self) -> u8 {
+		self.peek()
+	}
+
+	fn peek(&self) -> u8 {
+		let light = if self.light { 0x00 } else { 0x08 };
+		let trigger = if self.trigger { 0x10 } else { 0x00 };
+		light | trigger
+	}
+
+	fn observe(&mut self, ppu: &Ppu) {
+		self.light = self.senses_light(ppu);
+	}
+}
+
+impl Default for Zapper {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::rom::Mirroring;
+
+	#[test]
+	fn trigger_and_light() {
+		let mut zapper = Zapper::new();
+		assert_eq!(zapper.read(), 0x08);
+
+		zapper.set_trigger(true);
+		assert_eq!(zapper.read(), 0x18);
+
+		// Nothing drawn yet at power on
+		zapper.aim(128, 120);
+		zapper.observe(&Ppu::new(Mirroring::Horizontal));
+		assert_eq!(zapper.read() & 0x08, 0x08);
+	}
+}
@@ -0,0 +1,235 @@
+use crate::input::InputDevice;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Button {
+	A,
+	B,
+	Select,
+	Start,
+	Up,
+	Down,
+	Left,
+	Right
+}
+
+impl Button {
+	pub const ALL: [Button; 8] = [Button::A, Button::B, Button::Select, Button::Start, Button::Up, Button::Down, Button::Left, Button::Right];
+
+	// Bit in the shift register, in read order
+	pub fn mask(self) -> u8 {
+		1 << (self as u8)
+	}
+}
+
+// Auto-fire of a held button: pressed for `on_frames`, released for `off_frames`, and again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Turbo {
+	pub on_frames: u8,
+	pub off_frames: u8
+}
+
+impl Turbo {
+	pub fn new(on_frames: u8, off_frames: u8) -> Turbo {
+		Turbo {
+			on_frames: on_frames.max(1),
+			off_frames: off_frames.max(1)
+		}
+	}
+
+	fn period(self) -> u16 {
+		u16::from(self.on_frames) + u16::from(self.off_frames)
+	}
+}
+
+// Standard controller, buttons shift out in order A, B, Select, Start, Up, Down, Left, Right
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Joypad {
+	strobe: bool,
+	button_index: u8,
+	// Buttons held by the player, before turbo
+	button_status: u8,
+	turbo: [Option<Turbo>; 8],
+	// Frames since the turbo button was pressed, within its period
+	turbo_phase: [u16; 8]
+}
+
+impl Joypad {
+	pub fn new() -> Joypad {
+		Joypad {
+			strobe: false,
+			button_index: 0,
+			button_status: 0,
+			turbo: [None; 8],
+			turbo_phase: [0; 8]
+		}
+	}
+
+	// $4016 write, bit 0 reloads the shift register while set
+	pub fn write(&mut self, value: u8) {
+		self.strobe = value & 0x01 != 0;
+		if self.strobe {
+			self.button_index = 0;
+		}
+	}
+
+	// Serial read, bit 0 is the current button
+	pub fn read(&mut self) -> u8 {
+		let value = self.peek();
+		if !self.strobe && self.button_index < 8 {
+			self.button_index += 1;
+		}
+		value
+	}
+
+	pub fn peek(&self) -> u8 {
+		// Official pads return 1 once all buttons were read
+		if self.button_index > 7 {
+			return 1;
+		}
+
+		(self.output() >> self.button_index) & 0x01
+	}
+
+	// Buttons as the console sees them, turbo buttons are released during their off frames
+	pub fn output(&self) -> u8 {
+		let mut status = self.button_status;
+		for (bit, turbo) in self.turbo.iter().enumerate() {
+			if turbo.is_some_and(|turbo| self.turbo_phase[bit] >= u16::from(turbo.on_frames)) {
+				status &= !(1 << bit);
+			}
+		}
+		status
+	}
+
+	// None turns auto-fire off for `button`
+	pub fn set_turbo(&mut self, button: Button, turbo: Option<Turbo>) {
+		self.turbo[button as usize] = turbo;
+		self.turbo_phase[button as usize] = 0;
+	}
+
+	pub fn turbo(&self, button: Button) -> Option<Turbo> {
+		self.turbo[button as usize]
+	}
+
+	// Advance the turbo patterns, called once per frame (Nes::run_frame does)
+	pub fn end_frame(&mut self) {
+		for (bit, turbo) in self.turbo.iter().enumerate() {
+			let phase = &mut self.turbo_phase[bit];
+			match turbo {
+				Some(turbo) if self.button_status & (1 << bit) != 0 => *phase = (*phase + 1) % turbo.period(),
+				// The pattern starts over on the next press
+				_ => *phase = 0
+			}
+		}
+	}
+
+	// Bit 0 is A up to bit 7 for Right
+	pub fn set_buttons(&mut self, status: u8) {
+		self.button_status = status;
+	}
+
+	pub fn buttons(&self) -> u8 {
+		self.button_status
+	}
+
+	pub fn set_button(&mut self, button: Button, pressed: bool) {
+		if pressed {
+			self.button_status |= button.mask();
+		} else {
+			self.button_status &= !button.mask();
+		}
+	}
+
+	pub fn is_pressed(&self, button: Button) -> bool {
+		self.button_status & button.mask() != 0
+	}
+}
+
+impl InputDevice for Joypad {
+	fn strobe(&mut self, high: bool) {
+		self.write(u8::from(high));
+	}
+
+	fn read(&mut self) -> u8 {
+		Joypad::read(self)
+	}
+
+	fn peek(&self) -> u8 {
+		Joypad::peek(self)
+	}
+
+	fn end_frame(&mut self) {
+		Joypad::end_frame(self);
+	}
+}
+
+impl Default for Joypad {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn strobe_and_shift() {
+		let mut joypad = Joypad::new();
+		joypad.set_buttons(0b1000_1001); // A, Start, Right
+
+		// Strobe held: always A
+		joypad.write(1);
+		assert_eq!(joypad.read(), 1);
+		assert_eq!(joypad.read(), 1);
+
+		joypad.write(0);
+		let bits: Vec<u8> = (0..10).map(|_| joypad.read()).collect();
+		assert_eq!(bits, [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+
+		joypad.write(1);
+		joypad.write(0);
+		assert_eq!(joypad.read(), 1);
+		assert_eq!(joypad.read(), 0);
+	}
+
+	#[test]
+	fn set_button() {
+		let mut joypad = Joypad::new();
+		joypad.set_button(Button::B, true);
+		joypad.set_button(Button::Down, true);
+		joypad.set_button(Button::Down, false);
+		assert!(joypad.is_pressed(Button::B));
+		assert!(!joypad.is_pressed(Button::Down));
+
+		joypad.write(1);
+		joypad.write(0);
+		let bits: Vec<u8> = (0..8).map(|_| joypad.read()).collect();
+		assert_eq!(bits, [0, 1, 0, 0, 0, 0, 0, 0]);
+	}
+
+	#[test]
+	fn turbo() {
+		let mut joypad = Joypad::new();
+		joypad.set_turbo(Button::A, Some(Turbo::new(2, 1)));
+		joypad.set_button(Button::A, true);
+		joypad.set_button(Button::B, true);
+
+		let frames: Vec<u8> = (0..7).map(|_| {
+			let output = joypad.output();
+			joypad.end_frame();
+			output
+		}).collect();
+		assert_eq!(frames, [0x03, 0x03, 0x02, 0x03, 0x03, 0x02, 0x03]);
+
+		// Released then pressed again mid pattern, it starts pressed
+		joypad.set_button(Button::A, false);
+		joypad.end_frame();
+		joypad.set_button(Button::A, true);
+		assert_eq!(joypad.output(), 0x03);
+		assert_eq!(joypad.buttons(), 0x03);
+	}
+}
@@ -1,6 +1,31 @@
 pub mod rom;
-// pub mod nes;
+pub mod nes;
 pub mod cpu;
 pub mod bus;
 pub mod mapper;
-pub mod ppu;
\ No newline at end of file
+pub mod ppu;
+pub mod apu;
+pub mod nsf;
+pub mod joypad;
+pub mod input;
+pub mod state;
+pub mod debugger;
+pub mod harness;
+pub mod cheatsearch;
+pub mod cheats;
+pub mod state_manager;
+pub mod rewind;
+pub mod frame_timer;
+pub mod region;
+pub mod netplay;
+pub mod rollback;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "wasm")]
+pub mod wasm;
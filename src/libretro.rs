@@ -0,0 +1,454 @@
+// libretro core, built as the cdylib with the libretro feature so RetroArch can load nessy directly.
+// See libretro.h for the API: the frontend calls retro_* on one thread and pulls video,
+// audio and input through the callbacks it registers
+use std::cell::RefCell;
+use std::ffi::{c_char, c_uint, c_void, CStr};
+use std::ptr;
+use std::slice;
+
+use crate::joypad::Button;
+use crate::nes::Nes;
+use crate::ppu::frame::{HEIGHT, WIDTH};
+use crate::rom::Rom;
+use crate::region::Region;
+
+const RETRO_API_VERSION: c_uint = 1;
+
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+const RETRO_DEVICE_ID_JOYPAD_SELECT: c_uint = 2;
+const RETRO_DEVICE_ID_JOYPAD_START: c_uint = 3;
+const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const RETRO_MEMORY_SAVE_RAM: c_uint = 0;
+const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+
+const RETRO_REGION_NTSC: c_uint = 0;
+const RETRO_REGION_PAL: c_uint = 1;
+
+const SAMPLE_RATE: u32 = 44_100;
+// Room for save states that grow while the game runs, such as pending DMAs
+const SERIALIZE_SLACK: usize = 1024;
+
+const BUTTONS: [(c_uint, Button); 8] = [
+	(RETRO_DEVICE_ID_JOYPAD_A, Button::A),
+	(RETRO_DEVICE_ID_JOYPAD_B, Button::B),
+	(RETRO_DEVICE_ID_JOYPAD_SELECT, Button::Select),
+	(RETRO_DEVICE_ID_JOYPAD_START, Button::Start),
+	(RETRO_DEVICE_ID_JOYPAD_UP, Button::Up),
+	(RETRO_DEVICE_ID_JOYPAD_DOWN, Button::Down),
+	(RETRO_DEVICE_ID_JOYPAD_LEFT, Button::Left),
+	(RETRO_DEVICE_ID_JOYPAD_RIGHT, Button::Right)
+];
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+	pub library_name: *const c_char,
+	pub library_version: *const c_char,
+	pub valid_extensions: *const c_char,
+	pub need_fullpath: bool,
+	pub block_extract: bool
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+	pub base_width: c_uint,
+	pub base_height: c_uint,
+	pub max_width: c_uint,
+	pub max_height: c_uint,
+	pub aspect_ratio: f32
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+	pub fps: f64,
+	pub sample_rate: f64
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+	pub geometry: RetroGameGeometry,
+	pub timing: RetroSystemTiming
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+	pub path: *const c_char,
+	pub data: *const c_void,
+	pub size: usize,
+	pub meta: *const c_char
+}
+
+pub type RetroEnvironment = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type RetroVideoRefresh = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type RetroAudioSample = unsafe extern "C" fn(left: i16, right: i16);
+pub type RetroAudioSampleBatch = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type RetroInputPoll = unsafe extern "C" fn();
+pub type RetroInputState = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[derive(Default)]
+struct Core {
+	nes: Option<Nes>,
+	environment: Option<RetroEnvironment>,
+	video: Option<RetroVideoRefresh>,
+	audio_batch: Option<RetroAudioSampleBatch>,
+	input_poll: Option<RetroInputPoll>,
+	input_state: Option<RetroInputState>,
+	// XRGB8888 copy of the frame handed to the frontend
+	video_buffer: Vec<u32>,
+	// Interleaved stereo
+	audio_buffer: Vec<i16>
+}
+
+thread_local! {
+	static CORE: RefCell<Core> = RefCell::new(Core::default());
+}
+
+fn with_core<T>(f: impl FnOnce(&mut Core) -> T) -> T {
+	CORE.with(|core| f(&mut core.borrow_mut()))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+	RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: RetroEnvironment) {
+	with_core(|core| core.environment = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: RetroVideoRefresh) {
+	with_core(|core| core.video = Some(callback));
+}
+
+// Samples are sent in batches, the single sample callback is not used
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: RetroAudioSample) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: RetroAudioSampleBatch) {
+	with_core(|core| core.audio_batch = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: RetroInputPoll) {
+	with_core(|core| core.input_poll = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: RetroInputState) {
+	with_core(|core| core.input_state = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+	with_core(|core| *core = Core::default());
+}
+
+/// # Safety
+/// `info` must point to a writable retro_system_info
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+	*info = RetroSystemInfo {
+		library_name: c"nessy".as_ptr(),
+		library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast(),
+		valid_extensions: c"nes".as_ptr(),
+		need_fullpath: false,
+		block_extract: false
+	};
+}
+
+/// # Safety
+/// `info` must point to a writable retro_system_av_info
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+	let fps = with_core(|core| core.nes.as_ref().map_or(Region::Ntsc, |nes| nes.region())).frame_rate();
+	*info = RetroSystemAvInfo {
+		geometry: RetroGameGeometry {
+			base_width: WIDTH as c_uint,
+			base_height: HEIGHT as c_uint,
+			max_width: WIDTH as c_uint,
+			max_height: HEIGHT as c_uint,
+			aspect_ratio: 4.0 / 3.0
+		},
+		timing: RetroSystemTiming {
+			fps,
+			sample_rate: f64::from(SAMPLE_RATE)
+		}
+	};
+}
+
+// Only standard controllers are plugged in
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+	with_core(|core| {
+		if let Some(nes) = &mut core.nes {
+			nes.reset();
+		}
+	});
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+	with_core(|core| {
+		let Some(nes) = &mut core.nes else {
+			return;
+		};
+
+		if let Some(poll) = core.input_poll {
+			unsafe { poll() };
+		}
+		if let Some(state) = core.input_state {
+			for (id, button) in BUTTONS {
+				nes.set_button(button, unsafe { state(0, RETRO_DEVICE_JOYPAD, 0, id) } != 0);
+				nes.set_button2(button, unsafe { state(1, RETRO_DEVICE_JOYPAD, 0, id) } != 0);
+			}
+		}
+
+		let frame = nes.run_frame();
+		core.video_buffer.clear();
+		core.video_buffer.extend(frame.data.chunks_exact(3).map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]])));
+		if let Some(video) = core.video {
+			unsafe { video(core.video_buffer.as_ptr().cast(), frame.width as c_uint, frame.height as c_uint, frame.width * 4) };
+		}
+
+		core.audio_buffer.clear();
+		for sample in nes.audio_samples() {
+			let sample = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+			core.audio_buffer.extend([sample, sample]);
+		}
+		if let Some(audio_batch) = core.audio_batch {
+			let mut sent = 0;
+			while sent < core.audio_buffer.len() / 2 {
+				let frames = unsafe { audio_batch(core.audio_buffer[sent * 2..].as_ptr(), core.audio_buffer.len() / 2 - sent) };
+				if frames == 0 {
+					break;
+				}
+				sent += frames;
+			}
+		}
+	});
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+	with_core(|core| core.nes.as_ref().map_or(0, |nes| nes.save_state().len() + SERIALIZE_SLACK))
+}
+
+/// # Safety
+/// `data` must point to `size` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+	with_core(|core| {
+		let Some(nes) = &core.nes else {
+			return false;
+		};
+
+		let state = nes.save_state();
+		// The frontend buffer is filled with a padding section
+		if data.is_null() || state.len() + 8 > size {
+			return false;
+		}
+		let buffer = slice::from_raw_parts_mut(data.cast::<u8>(), size);
+		buffer[..state.len()].copy_from_slice(&state);
+		buffer[state.len()..state.len() + 4].copy_from_slice(b"PAD ");
+		buffer[state.len() + 4..state.len() + 8].copy_from_slice(&((size - state.len() - 8) as u32).to_le_bytes());
+		buffer[state.len() + 8..].fill(0);
+		true
+	})
+}
+
+/// # Safety
+/// `data` must point to `size` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+	with_core(|core| match &mut core.nes {
+		Some(nes) if !data.is_null() => nes.load_state(slice::from_raw_parts(data.cast::<u8>(), size)).is_ok(),
+		_ => false
+	})
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+	with_core(|core| {
+		if let Some(nes) = &mut core.nes {
+			nes.bus_mut().cheats_mut().clear();
+		}
+	});
+}
+
+/// # Safety
+/// `code` must be a NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, enabled: bool, code: *const c_char) {
+	if code.is_null() {
+		return;
+	}
+	let code = CStr::from_ptr(code).to_string_lossy();
+
+	with_core(|core| {
+		let Some(nes) = &mut core.nes else {
+			return;
+		};
+		// Several codes of one cheat are joined with +
+		for code in code.split('+').filter(|code| !code.trim().is_empty()) {
+			if enabled {
+				let _ = nes.add_cheat(code);
+			} else {
+				nes.remove_cheat(code);
+			}
+		}
+	});
+}
+
+/// # Safety
+/// `game` must point to a retro_game_info whose data holds `size` bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+	if game.is_null() || (*game).data.is_null() {
+		return false;
+	}
+	let data = slice::from_raw_parts((*game).data.cast::<u8>(), (*game).size);
+	let Ok(rom) = Rom::from_ines(data) else {
+		return false;
+	};
+
+	with_core(|core| {
+		if let Some(environment) = core.environment {
+			let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+			if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, ptr::addr_of_mut!(format).cast()) {
+				return false;
+			}
+		}
+
+		let mut nes = Nes::new(rom);
+		nes.bus_mut().apu_mut().set_sample_rate(SAMPLE_RATE);
+		core.nes = Some(nes);
+		true
+	})
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+	false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+	with_core(|core| core.nes = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+	with_core(|core| {
+		match core.nes.as_ref().map(|nes| nes.region()) {
+			Some(Region::Pal) | Some(Region::Dendy) => RETRO_REGION_PAL,
+			_ => RETRO_REGION_NTSC
+		}
+	})
+}
+
+// Battery RAM is read and written by the frontend in place
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+	with_core(|core| match (&mut core.nes, id) {
+		(Some(nes), RETRO_MEMORY_SAVE_RAM) if nes.bus().has_battery() && nes.bus().mapper().battery_data().is_none() => {
+			nes.bus_mut().prg_ram_mut().as_mut_ptr().cast()
+		},
+		(Some(nes), RETRO_MEMORY_SYSTEM_RAM) => nes.bus_mut().cpu_ram_mut().as_mut_ptr().cast(),
+		_ => ptr::null_mut()
+	})
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+	with_core(|core| match (&core.nes, id) {
+		(Some(nes), RETRO_MEMORY_SAVE_RAM) if nes.bus().has_battery() && nes.bus().mapper().battery_data().is_none() => {
+			nes.bus().prg_ram().len()
+		},
+		(Some(nes), RETRO_MEMORY_SYSTEM_RAM) => nes.bus().cpu_ram().len(),
+		_ => 0
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	use crate::rom::test::nrom;
+
+	static VIDEO_FRAMES: AtomicUsize = AtomicUsize::new(0);
+	static AUDIO_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+	unsafe extern "C" fn video(_data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+		assert_eq!((width, height, pitch), (256, 240, 1024));
+		VIDEO_FRAMES.fetch_add(1, Ordering::SeqCst);
+	}
+
+	unsafe extern "C" fn audio(_data: *const i16, frames: usize) -> usize {
+		AUDIO_FRAMES.fetch_add(frames, Ordering::SeqCst);
+		frames
+	}
+
+	unsafe extern "C" fn input(_port: c_uint, _device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+		i16::from(id == RETRO_DEVICE_ID_JOYPAD_START)
+	}
+
+	#[test]
+	fn core() {
+		// JMP $8000
+		let mut ines = nrom(&[0x4C, 0x00, 0x80], [0, 0x8000, 0]);
+		ines[6] = 0x02;
+		let game = RetroGameInfo {
+			path: ptr::null(),
+			data: ines.as_ptr().cast(),
+			size: ines.len(),
+			meta: ptr::null()
+		};
+
+		retro_set_video_refresh(video);
+		retro_set_audio_sample_batch(audio);
+		retro_set_input_state(input);
+		unsafe {
+			assert!(retro_load_game(&game));
+		}
+		retro_run();
+		retro_run();
+		assert_eq!(VIDEO_FRAMES.load(Ordering::SeqCst), 2);
+		assert!(AUDIO_FRAMES.load(Ordering::SeqCst) > 1000);
+		with_core(|core| assert_eq!(core.nes.as_ref().unwrap().bus().joypad1().unwrap().buttons(), Button::Start.mask()));
+
+		assert_eq!(retro_get_memory_size(RETRO_MEMORY_SAVE_RAM), 8192);
+		assert_eq!(retro_get_memory_size(RETRO_MEMORY_SYSTEM_RAM), 2048);
+
+		// The frontend keeps the save RAM pointer across states
+		let save_ram = retro_get_memory_data(RETRO_MEMORY_SAVE_RAM);
+		assert!(!save_ram.is_null());
+		let mut state = vec![0u8; retro_serialize_size()];
+		unsafe {
+			assert!(retro_serialize(state.as_mut_ptr().cast(), state.len()));
+			assert!(retro_unserialize(state.as_ptr().cast(), state.len()));
+		}
+		assert_eq!(retro_get_memory_data(RETRO_MEMORY_SAVE_RAM), save_ram);
+
+		retro_unload_game();
+		assert_eq!(retro_serialize_size(), 0);
+	}
+}
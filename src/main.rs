@@ -1,24 +1,267 @@
-use nessy::rom::Rom;
-use nessy::cpu::{Cpu, trace};
+// Headless command line tool, for scripts and test automation:
+//   nessy info rom.nes
+//   nessy trace rom.nes --start C000 --frames 60
+//   nessy run rom.nes --movie x.fm2 --hash-frames
+//   nessy test cpu_tests.nes
+use std::env;
+use std::fmt;
+use std::fs;
+use std::process;
+
 use nessy::bus::Bus;
+use nessy::cpu::{trace, Cpu};
+use nessy::harness::{blargg, golden};
+use nessy::input::fm2::Fm2Movie;
+use nessy::nes::Nes;
+use nessy::rom::Rom;
+
+const USAGE: &str = "\
+Usage: nessy <command> <rom> [options]
+
+Commands:
+  info <rom>     Print the header and hashes of a ROM
+  trace <rom>    Print a nestest style CPU trace
+      --start ADDR          Start at ADDR instead of the reset vector, C000 for nestest
+      --frames N            Stop after N frames, 1 by default
+      --instructions N      Stop after N instructions
+  run <rom>      Run without video or audio output
+      --frames N            Frames to run, the movie length or 60 by default
+      --movie FILE          Play an FCEUX .fm2 movie
+      --hash-frames         Print the hash of every frame
+      --golden FILE         Compare the frame hashes with FILE, fail on the first mismatch
+      --screenshot FILE     Save the last frame, PNG or PPM by extension
+      --config FILE         Apply the RAM init, palette and accuracy options of a TOML config
+  test <rom>     Run a blargg test ROM reporting through $6000, fail unless it passes
+      --frames N            Give up after N frames, 3600 by default";
+
+// Exit codes: 1 when a command or test fails, 2 for invalid arguments
+#[derive(Debug, PartialEq, Eq)]
+enum CliError {
+	Usage(String),
+	Failed(String)
+}
+
+impl fmt::Display for CliError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			CliError::Usage(message) => write!(f, "{}\n\n{}", message, USAGE),
+			CliError::Failed(message) => write!(f, "{}", message)
+		}
+	}
+}
+
+fn failed(message: impl fmt::Display) -> CliError {
+	CliError::Failed(message.to_string())
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Options {
+	start: Option<u16>,
+	frames: Option<u64>,
+	instructions: Option<u64>,
+	movie: Option<String>,
+	hash_frames: bool,
+	golden: Option<String>,
+	screenshot: Option<String>,
+	config: Option<String>
+}
+
+impl Options {
+	fn parse(args: &[String]) -> Result<Options, CliError> {
+		let mut options = Options::default();
+		let mut args = args.iter();
+		while let Some(arg) = args.next() {
+			let mut value = || args.next().ok_or_else(|| CliError::Usage(format!("{} needs a value", arg)));
+			match arg.as_str() {
+				"--start" => options.start = Some(parse_adress(value()?)?),
+				"--frames" => options.frames = Some(parse_number(arg, value()?)?),
+				"--instructions" => options.instructions = Some(parse_number(arg, value()?)?),
+				"--movie" => options.movie = Some(value()?.clone()),
+				"--hash-frames" => options.hash_frames = true,
+				"--golden" => options.golden = Some(value()?.clone()),
+				"--screenshot" => options.screenshot = Some(value()?.clone()),
+				"--config" => options.config = Some(value()?.clone()),
+				_ => return Err(CliError::Usage(format!("unknown option {}", arg)))
+			}
+		}
+		Ok(options)
+	}
+}
+
+// Hexadecimal, with or without a $ or 0x prefix
+fn parse_adress(text: &str) -> Result<u16, CliError> {
+	let digits = text.trim_start_matches('$').trim_start_matches("0x");
+	u16::from_str_radix(digits, 16).map_err(|_| CliError::Usage(format!("{} is not an adress", text)))
+}
+
+fn parse_number(option: &str, text: &str) -> Result<u64, CliError> {
+	text.parse().map_err(|_| CliError::Usage(format!("{} expects a number, not {}", option, text)))
+}
+
+fn load_rom(path: &str) -> Result<Rom, CliError> {
+	Rom::from_path(path).map_err(|error| failed(format!("{}: {}", path, error)))
+}
+
+fn info(path: &str) -> Result<(), CliError> {
+	let rom = load_rom(path)?;
+	let Some(info) = rom.info() else {
+		return Err(failed(format!("{}: no header information", path)));
+	};
 
-use std::io::prelude::*;
-use std::fs::File;
+	println!("Mapper:    {} ({})", info.mapper, info.mapper_name.unwrap_or("unsupported"));
+	println!("Submapper: {}", info.submapper);
+	println!("PRG ROM:   {} KB", info.prg_rom_size / 1024);
+	println!("CHR ROM:   {} KB", info.chr_rom_size / 1024);
+	println!("Mirroring: {:?}", info.mirroring);
+	println!("Battery:   {}", if info.battery { "yes" } else { "no" });
+	println!("Timing:    {:?}", info.timing);
+	println!("CRC32:     {}", info.crc32_hex());
+	println!("SHA-1:     {}", info.sha1_hex());
+	for correction in &info.overrides {
+		println!("Corrected: {}", correction);
+	}
+	Ok(())
+}
+
+fn run_trace(path: &str, options: &Options) -> Result<(), CliError> {
+	let mut bus = Bus::new(load_rom(path)?);
+	let mut cpu = Cpu::new();
+	cpu.reset(&mut bus);
+	if let Some(start) = options.start {
+		cpu.pc = start;
+	}
+
+	let frames = options.frames.unwrap_or(1);
+	let mut instructions = 0;
+	while bus.ppu().frame_count() < frames && options.instructions.is_none_or(|limit| instructions < limit) {
+		println!("{}", trace(&cpu, &bus));
+		cpu.step(&mut bus);
+		instructions += 1;
+	}
+	Ok(())
+}
+
+fn run(path: &str, options: &Options) -> Result<(), CliError> {
+	let mut nes = Nes::new(load_rom(path)?);
+	if let Some(config) = &options.config {
+		apply_config(&mut nes, config)?;
+	}
+
+	let mut movie_frames = None;
+	if let Some(movie) = &options.movie {
+		let text = fs::read_to_string(movie).map_err(|error| failed(format!("{}: {}", movie, error)))?;
+		let inputs = Fm2Movie::parse(&text).map_err(|error| failed(format!("{}: {}", movie, error)))?.inputs();
+		movie_frames = Some(inputs.len() as u64);
+		nes.play(inputs);
+	}
+
+	let golden = match &options.golden {
+		Some(file) => {
+			let text = fs::read_to_string(file).map_err(|error| failed(format!("{}: {}", file, error)))?;
+			Some(golden::parse_hashes(&text).map_err(|error| failed(format!("{}: {}", file, error)))?)
+		},
+		None => None
+	};
+
+	let frames = options.frames.or(movie_frames).or(golden.as_ref().map(|hashes| hashes.len() as u64)).unwrap_or(60);
+	let mut hashes = Vec::new();
+	for _ in 0..frames {
+		let hash = nes.run_frame().hash();
+		if options.hash_frames {
+			print!("{}", golden::format_hashes(&[hash]));
+		}
+		hashes.push(hash);
+	}
+
+	if let Some(screenshot) = &options.screenshot {
+		nes.frame().save(screenshot).map_err(|error| failed(format!("{}: {}", screenshot, error)))?;
+	}
+	if let Some(golden) = golden {
+		golden::compare(&hashes, &golden).map_err(failed)?;
+	}
+	Ok(())
+}
+
+#[cfg(feature = "config")]
+fn apply_config(nes: &mut Nes, path: &str) -> Result<(), CliError> {
+	nessy::config::Config::load(path).and_then(|config| config.apply(nes)).map_err(failed)
+}
+
+#[cfg(not(feature = "config"))]
+fn apply_config(_nes: &mut Nes, path: &str) -> Result<(), CliError> {
+	Err(failed(format!("{}: built without the config feature", path)))
+}
+
+fn test(path: &str, options: &Options) -> Result<(), CliError> {
+	let frames = options.frames.unwrap_or(3600).min(u64::from(u32::MAX)) as u32;
+	let result = blargg::run(load_rom(path)?, frames).map_err(failed)?;
+	println!("{}", result.message.trim_end());
+	if result.passed() {
+		Ok(())
+	} else {
+		Err(failed(format!("failed with code {}", result.code)))
+	}
+}
+
+fn main_with_args(args: &[String]) -> Result<(), CliError> {
+	let (Some(command), Some(path)) = (args.first(), args.get(1)) else {
+		return Err(CliError::Usage(String::from("missing command or ROM")));
+	};
+	let options = Options::parse(&args[2..])?;
+
+	match command.as_str() {
+		"info" => info(path),
+		"trace" => run_trace(path, &options),
+		"run" => run(path, &options),
+		"test" => test(path, &options),
+		_ => Err(CliError::Usage(format!("unknown command {}", command)))
+	}
+}
 
 fn main() {
-    let mut file = File::open("rom/nestest.nes").expect("Could not read the file {}");
-    
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).expect("Could not read bytes");
+	let args: Vec<String> = env::args().skip(1).collect();
+	if args.first().is_some_and(|arg| arg == "--help" || arg == "-h") {
+		println!("{}", USAGE);
+		return;
+	}
+
+	match main_with_args(&args) {
+		Ok(()) => {},
+		Err(error @ CliError::Usage(_)) => {
+			eprintln!("{}", error);
+			process::exit(2);
+		},
+		Err(error) => {
+			eprintln!("{}", error);
+			process::exit(1);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
 
-    let rom = Rom::from_ines(&buffer);
-    let mut bus = Bus::new(rom);
+	fn args(text: &str) -> Vec<String> {
+		text.split_whitespace().map(String::from).collect()
+	}
 
-    let mut cpu = Cpu::new();
-    cpu.reset(&mut bus);
-    cpu.pc = 0xC000;
+	#[test]
+	fn options() {
+		let options = Options::parse(&args("--start $C000 --frames 60 --hash-frames --movie x.fm2")).unwrap();
+		assert_eq!(options, Options {
+			start: Some(0xC000),
+			frames: Some(60),
+			movie: Some(String::from("x.fm2")),
+			hash_frames: true,
+			..Options::default()
+		});
+		assert_eq!(Options::parse(&args("--start 0x8000")).unwrap().start, Some(0x8000));
 
-    cpu.run_with_callback(&mut bus, |cpu: &mut Cpu, bus: &mut Bus| {
-        println!("{}", trace(cpu, bus));
-    });
+		assert!(matches!(Options::parse(&args("--frames")), Err(CliError::Usage(_))));
+		assert!(matches!(Options::parse(&args("--frames ten")), Err(CliError::Usage(_))));
+		assert!(matches!(Options::parse(&args("--verbose")), Err(CliError::Usage(_))));
+		assert!(matches!(main_with_args(&args("play rom.nes")), Err(CliError::Usage(_))));
+		assert!(matches!(main_with_args(&args("info")), Err(CliError::Usage(_))));
+	}
 }
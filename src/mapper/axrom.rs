@@ -0,0 +1,121 @@
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::rom::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 32768;
+const CHR_BANK_SIZE: usize = 8192;
+
+#[derive(Clone)]
+pub struct Axrom {
+	pgr_rom: Vec<u8>,
+	chr_rom: Vec<u8>,
+	chr_ram: bool,
+
+	prg_bank: u8,
+	mirroring: Mirroring,
+	bus_conflicts: bool
+}
+
+impl Axrom {
+	pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Axrom {
+		Axrom {
+			pgr_rom,
+			chr_rom,
+			chr_ram,
+			prg_bank: 0,
+			mirroring: Mirroring::SingleScreenLower,
+			bus_conflicts: false
+		}
+	}
+
+	// AMROM has bus conflicts, ANROM and AOROM prevent them
+	pub fn set_bus_conflicts(&mut self, bus_conflicts: bool) {
+		self.bus_conflicts = bus_conflicts;
+	}
+}
+
+impl Mapper for Axrom {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x8000..=0xFFFF => banked_read(&self.pgr_rom, usize::from(self.prg_bank), PRG_BANK_SIZE, adress),
+			_ => 0
+		}
+	}
+
+	// ---M -PPP: nametable page and 32KB PRG bank
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x8000..=0xFFFF => banked_offset(self.pgr_rom.len(), usize::from(self.prg_bank), PRG_BANK_SIZE, adress),
+			_ => None
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		if adress >= 0x8000 {
+			self.prg_bank = value & 0x07;
+			self.mirroring = if value & 0x10 == 0 { Mirroring::SingleScreenLower } else { Mirroring::SingleScreenUpper };
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		banked_read(&self.chr_rom, 0, CHR_BANK_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		if self.chr_ram {
+			banked_write(&mut self.chr_rom, 0, CHR_BANK_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_u8(self.prg_bank);
+		state.write_mirroring(Some(self.mirroring));
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		self.prg_bank = state.read_u8()?;
+		self.mirroring = state.read_mirroring()?.unwrap_or(Mirroring::SingleScreenLower);
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		Some(self.mirroring)
+	}
+
+	fn bus_conflicts(&self) -> bool {
+		self.bus_conflicts
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn bank_and_nametable_select() {
+		let pgr_rom: Vec<u8> = (0..8).flat_map(|bank| vec![bank as u8; PRG_BANK_SIZE]).collect();
+		let mut axrom = Axrom::new(pgr_rom, vec![0; 8192], true);
+		assert_eq!(axrom.mirroring(), Some(Mirroring::SingleScreenLower));
+
+		axrom.cpu_write(0x8000, 0x15);
+		assert_eq!(axrom.cpu_read(0x8000), 5);
+		assert_eq!(axrom.cpu_read(0xFFFF), 5);
+		assert_eq!(axrom.mirroring(), Some(Mirroring::SingleScreenUpper));
+
+		axrom.ppu_write(0x1234, 0x42);
+		assert_eq!(axrom.ppu_read(0x1234), 0x42);
+	}
+}
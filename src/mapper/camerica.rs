@@ -0,0 +1,131 @@
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::rom::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 16384;
+const CHR_BANK_SIZE: usize = 8192;
+
+// Camerica/Codemasters BF909x (mapper 71), UxROM with the bank register at $C000-$FFFF
+#[derive(Clone)]
+pub struct Camerica {
+	pgr_rom: Vec<u8>,
+	chr_rom: Vec<u8>,
+	chr_ram: bool,
+
+	prg_bank: u8,
+	// Set by Fire Hawk's board through $9000-$9FFF, hardwired on the others
+	mirroring: Option<Mirroring>
+}
+
+impl Camerica {
+	pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Camerica {
+		Camerica {
+			pgr_rom,
+			chr_rom,
+			chr_ram,
+			prg_bank: 0,
+			mirroring: None
+		}
+	}
+
+	fn prg_bank_count(&self) -> usize {
+		(self.pgr_rom.len() / PRG_BANK_SIZE).max(1)
+	}
+}
+
+impl Mapper for Camerica {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x8000..=0xBFFF => banked_read(&self.pgr_rom, usize::from(self.prg_bank), PRG_BANK_SIZE, adress),
+			0xC000..=0xFFFF => banked_read(&self.pgr_rom, self.prg_bank_count() - 1, PRG_BANK_SIZE, adress),
+			_ => 0
+		}
+	}
+
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x8000..=0xBFFF => banked_offset(self.pgr_rom.len(), usize::from(self.prg_bank), PRG_BANK_SIZE, adress),
+			0xC000..=0xFFFF => banked_offset(self.pgr_rom.len(), self.prg_bank_count() - 1, PRG_BANK_SIZE, adress),
+			_ => None
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		match adress {
+			0x9000..=0x9FFF => {
+				self.mirroring = Some(if value & 0x10 == 0 { Mirroring::SingleScreenLower } else { Mirroring::SingleScreenUpper });
+			},
+			0xC000..=0xFFFF => self.prg_bank = value & 0x0F,
+			_ => {}
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		banked_read(&self.chr_rom, 0, CHR_BANK_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		if self.chr_ram {
+			banked_write(&mut self.chr_rom, 0, CHR_BANK_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_u8(self.prg_bank);
+		state.write_mirroring(self.mirroring);
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		self.prg_bank = state.read_u8()?;
+		self.mirroring = state.read_mirroring()?;
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		self.mirroring
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Every byte holds its bank number
+	fn numbered(bank_size: usize, count: usize) -> Vec<u8> {
+		(0..count).flat_map(|bank| vec![bank as u8; bank_size]).collect()
+	}
+
+	#[test]
+	fn prg_banking() {
+		let mut camerica = Camerica::new(numbered(PRG_BANK_SIZE, 8), vec![0; 8192], true);
+		// The register is not at $8000
+		camerica.cpu_write(0x8000, 3);
+		assert_eq!(camerica.cpu_read(0x8000), 0);
+
+		camerica.cpu_write(0xC000, 3);
+		assert_eq!(camerica.cpu_read(0x8000), 3);
+		assert_eq!(camerica.cpu_read(0xC000), 7);
+	}
+
+	#[test]
+	fn fire_hawk_mirroring() {
+		let mut camerica = Camerica::new(numbered(PRG_BANK_SIZE, 8), vec![0; 8192], true);
+		assert_eq!(camerica.mirroring(), None);
+
+		camerica.cpu_write(0x9000, 0x10);
+		assert_eq!(camerica.mirroring(), Some(Mirroring::SingleScreenUpper));
+	}
+}
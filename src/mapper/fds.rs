@@ -0,0 +1,615 @@
+use std::cell::Cell;
+
+use crate::apu::expansion::ExpansionAudio;
+use crate::apu::fds::FdsAudio;
+use crate::mapper::Mapper;
+use crate::rom::{Mirroring, RomError};
+use crate::state::{StateError, StateReader, StateWriter};
+
+pub const BIOS_SIZE: usize = 8192;
+// Side size in .fds images, without gaps and CRCs
+pub const SIDE_SIZE: usize = 65500;
+const FDS_MAGIC: [u8; 4] = [0x46, 0x44, 0x53, 0x1A];
+const FDS_HEADER_SIZE: usize = 16;
+
+const RAM_SIZE: usize = 32768;
+const CHR_RAM_SIZE: usize = 8192;
+
+// Gaps of the real disk, 28300 bits before the first block and 976 bits between blocks
+const LEADING_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const BLOCK_START: u8 = 0x80;
+// Room for the gaps around the 65500 data bytes
+const RAW_SIDE_SIZE: usize = 68000;
+
+// CPU cycles for the head to go back to the start, and per byte at 96.4 kbit/s
+const HEAD_RETURN_DELAY: u32 = 50000;
+const BYTE_DELAY: u32 = 149;
+
+// A side of the disk drive, as listed in .fds images:
+// 0 is disk 1 side A, 1 is disk 1 side B, 2 is disk 2 side A...
+pub trait DiskDrive {
+	fn side_count(&self) -> usize;
+
+	// None when the drive is empty
+	fn inserted_side(&self) -> Option<usize>;
+
+	// False when the image has no such side. Games expect the drive
+	// to be empty for a moment (about a second) between two sides
+	fn insert_side(&mut self, side: usize) -> bool;
+
+	fn eject(&mut self);
+}
+
+// Disk sides of an .fds image, as the drive head sees them: blocks start with a mark
+// and end with a CRC, with gaps around them
+pub struct FdsDisk {
+	sides: Vec<Vec<u8>>
+}
+
+impl FdsDisk {
+	// .fds image with or without the 16 byte fwNES header
+	pub fn parse(buffer: &[u8]) -> Result<FdsDisk, RomError> {
+		let data = if buffer.starts_with(&FDS_MAGIC) {
+			&buffer[FDS_HEADER_SIZE.min(buffer.len())..]
+		} else {
+			buffer
+		};
+
+		if data.is_empty() {
+			return Err(RomError::BadMagic);
+		}
+		if data.len() % SIDE_SIZE != 0 {
+			let expected = data.len().div_ceil(SIDE_SIZE) * SIDE_SIZE;
+			return Err(RomError::Truncated { expected, got: data.len() });
+		}
+
+		Ok(FdsDisk {
+			sides: data.chunks(SIDE_SIZE).map(add_gaps).collect()
+		})
+	}
+
+	pub fn side_count(&self) -> usize {
+		self.sides.len()
+	}
+}
+
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+	let mut raw = vec![0; LEADING_GAP];
+	let mut position = 0;
+	let mut file_size = 0;
+
+	while position < side.len() {
+		let length = match side[position] {
+			// Disk info
+			1 => 56,
+			// File amount
+			2 => 2,
+			// File header, the size of the next file block is at 13-14
+			3 => {
+				if position + 14 < side.len() {
+					file_size = usize::from(side[position + 13]) | (usize::from(side[position + 14]) << 8);
+				}
+				16
+			},
+			// File data
+			4 => 1 + file_size,
+			// Unused space
+			_ => break
+		};
+
+		let end = (position + length).min(side.len());
+		raw.push(BLOCK_START);
+		raw.extend_from_slice(&side[position..end]);
+		// The CRC is not checked, any value works
+		raw.extend_from_slice(&[0x4D, 0x62]);
+		raw.extend_from_slice(&[0; BLOCK_GAP]);
+		position = end;
+	}
+
+	raw.resize(raw.len().max(RAW_SIDE_SIZE), 0);
+	raw
+}
+
+// Famicom Disk System RAM adapter: 32KB of RAM at $6000-$DFFF, the BIOS at $E000-$FFFF,
+// 8KB of CHR RAM, the disk drive and a cycle timer IRQ at $4020-$4033
+#[derive(Clone)]
+pub struct Fds {
+	bios: Vec<u8>,
+	ram: Vec<u8>,
+	chr_ram: Vec<u8>,
+	sides: Vec<Vec<u8>>,
+	side: Option<usize>,
+
+	// $4023 bit 0, the timer and the drive are ignored when off
+	disk_registers: bool,
+
+	timer_reload: u16,
+	timer_counter: u16,
+	timer_repeat: bool,
+	timer_enabled: bool,
+	// Acknowledged by reading $4030
+	timer_irq: Cell<bool>,
+
+	// $4025
+	motor_on: bool,
+	reset_transfer: bool,
+	read_mode: bool,
+	mirroring: Mirroring,
+	crc_control: bool,
+	transfer_enabled: bool,
+	disk_irq_enabled: bool,
+
+	// Acknowledged by reading $4030 or $4031
+	disk_irq: Cell<bool>,
+	transfer_complete: Cell<bool>,
+	read_data: u8,
+	write_data: u8,
+
+	position: usize,
+	delay: u32,
+	scanning: bool,
+	end_of_head: bool,
+	gap_ended: bool
+}
+
+impl Fds {
+	// Starts with side 0 inserted
+	pub fn new(bios: Vec<u8>, disk: FdsDisk) -> Fds {
+		Fds {
+			bios,
+			ram: vec![0; RAM_SIZE],
+			chr_ram: vec![0; CHR_RAM_SIZE],
+			side: if disk.sides.is_empty() { None } else { Some(0) },
+			sides: disk.sides,
+			disk_registers: false,
+			timer_reload: 0,
+			timer_counter: 0,
+			timer_repeat: false,
+			timer_enabled: false,
+			timer_irq: Cell::new(false),
+			motor_on: false,
+			reset_transfer: false,
+			read_mode: true,
+			mirroring: Mirroring::Horizontal,
+			crc_control: false,
+			transfer_enabled: false,
+			disk_irq_enabled: false,
+			disk_irq: Cell::new(false),
+			transfer_complete: Cell::new(false),
+			read_data: 0,
+			write_data: 0,
+			position: 0,
+			delay: 0,
+			scanning: false,
+			end_of_head: true,
+			gap_ended: false
+		}
+	}
+
+	fn read_register(&self, adress: u16) -> u8 {
+		match adress {
+			0x4030 => {
+				let status = u8::from(self.timer_irq.get())
+					| (u8::from(self.transfer_complete.get()) << 1)
+					| (u8::from(self.end_of_head) << 6);
+				self.timer_irq.set(false);
+				self.disk_irq.set(false);
+				self.transfer_complete.set(false);
+				status
+			},
+			0x4031 => {
+				self.disk_irq.set(false);
+				self.transfer_complete.set(false);
+				self.read_data
+			},
+			0x4032 => {
+				let empty = self.side.is_none();
+				// No disk, not ready, write protected
+				u8::from(empty) | (u8::from(empty || !self.scanning) << 1) | (u8::from(empty) << 2)
+			},
+			// Battery good
+			0x4033 => 0x80,
+			_ => 0
+		}
+	}
+
+	fn write_register(&mut self, adress: u16, value: u8) {
+		match adress {
+			0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | u16::from(value),
+			0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | (u16::from(value) << 8),
+			0x4022 => {
+				self.timer_repeat = value & 0x01 != 0;
+				self.timer_enabled = value & 0x02 != 0 && self.disk_registers;
+				if self.timer_enabled {
+					self.timer_counter = self.timer_reload;
+				} else {
+					self.timer_irq.set(false);
+				}
+			},
+			0x4023 => {
+				self.disk_registers = value & 0x01 != 0;
+				if !self.disk_registers {
+					self.timer_enabled = false;
+					self.timer_irq.set(false);
+					self.disk_irq.set(false);
+				}
+			},
+			0x4024 if self.disk_registers => {
+				self.write_data = value;
+				self.transfer_complete.set(false);
+				self.disk_irq.set(false);
+			},
+			0x4025 if self.disk_registers => {
+				self.motor_on = value & 0x01 != 0;
+				self.reset_transfer = value & 0x02 != 0;
+				self.read_mode = value & 0x04 != 0;
+				self.mirroring = if value & 0x08 != 0 { Mirroring::Horizontal } else { Mirroring::Vertical };
+				self.crc_control = value & 0x10 != 0;
+				self.transfer_enabled = value & 0x40 != 0;
+				self.disk_irq_enabled = value & 0x80 != 0;
+				self.disk_irq.set(false);
+			},
+			_ => {}
+		}
+	}
+
+	fn clock_timer(&mut self) {
+		if !self.timer_enabled || !self.disk_registers {
+			return;
+		}
+
+		if self.timer_counter == 0 {
+			self.timer_irq.set(true);
+			self.timer_counter = self.timer_reload;
+			if !self.timer_repeat {
+				self.timer_enabled = false;
+			}
+		} else {
+			self.timer_counter -= 1;
+		}
+	}
+
+	// The head moves one byte every BYTE_DELAY cycles while the motor runs,
+	// and goes back to the start of the side once it reaches the end
+	fn clock_drive(&mut self) {
+		let Some(side) = self.side else {
+			return;
+		};
+
+		if !self.motor_on {
+			self.end_of_head = true;
+			self.scanning = false;
+			return;
+		}
+		if self.reset_transfer && !self.scanning {
+			return;
+		}
+		if self.end_of_head {
+			self.delay = HEAD_RETURN_DELAY;
+			self.end_of_head = false;
+			self.position = 0;
+			self.gap_ended = false;
+			return;
+		}
+		if self.delay > 0 {
+			self.delay -= 1;
+			return;
+		}
+
+		self.scanning = true;
+		let mut irq = self.disk_irq_enabled;
+		let raw = &mut self.sides[side];
+
+		if self.read_mode {
+			let data = raw[self.position];
+			if !self.transfer_enabled {
+				self.gap_ended = false;
+			} else if data != 0 && !self.gap_ended {
+				// Block start mark, no IRQ for it
+				self.gap_ended = true;
+				irq = false;
+			}
+
+			if self.gap_ended {
+				self.transfer_complete.set(true);
+				self.read_data = data;
+				if irq {
+					self.disk_irq.set(true);
+				}
+			}
+		} else {
+			if !self.crc_control {
+				self.transfer_complete.set(true);
+				if irq {
+					self.disk_irq.set(true);
+				}
+			}
+
+			raw[self.position] = if !self.transfer_enabled || self.crc_control {
+				0
+			} else {
+				self.write_data
+			};
+			self.gap_ended = false;
+		}
+
+		self.position += 1;
+		if self.position >= raw.len() {
+			self.motor_on = false;
+		} else {
+			self.delay = BYTE_DELAY;
+		}
+	}
+}
+
+impl DiskDrive for Fds {
+	fn side_count(&self) -> usize {
+		self.sides.len()
+	}
+
+	fn inserted_side(&self) -> Option<usize> {
+		self.side
+	}
+
+	fn insert_side(&mut self, side: usize) -> bool {
+		if side >= self.sides.len() {
+			return false;
+		}
+
+		self.side = Some(side);
+		true
+	}
+
+	fn eject(&mut self) {
+		self.side = None;
+		self.scanning = false;
+		self.end_of_head = true;
+	}
+}
+
+impl Mapper for Fds {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x4030..=0x4033 if self.disk_registers => self.read_register(adress),
+			0x6000..=0xDFFF => self.ram[usize::from(adress - 0x6000)],
+			0xE000..=0xFFFF => self.bios[usize::from(adress - 0xE000) % self.bios.len()],
+			_ => 0
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		match adress {
+			0x4020..=0x4026 => self.write_register(adress, value),
+			0x6000..=0xDFFF => self.ram[usize::from(adress - 0x6000)] = value,
+			_ => {}
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		self.chr_ram[usize::from(adress) % CHR_RAM_SIZE]
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		self.chr_ram[usize::from(adress) % CHR_RAM_SIZE] = value;
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		true
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		Some(self.mirroring)
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.timer_irq.get() || self.disk_irq.get()
+	}
+
+	fn clock_cpu(&mut self) {
+		self.clock_timer();
+		self.clock_drive();
+	}
+
+	fn expansion_audio(&self) -> Option<Box<dyn ExpansionAudio>> {
+		Some(Box::new(FdsAudio::new()))
+	}
+
+	fn disk_drive(&mut self) -> Option<&mut dyn DiskDrive> {
+		Some(self)
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_bytes(&self.ram);
+		state.write_bytes(&self.chr_ram);
+		for side in &self.sides {
+			state.write_bytes(side);
+		}
+		state.write_u8(self.side.map_or(0xFF, |side| side as u8));
+		state.write_bool(self.disk_registers);
+		state.write_u16(self.timer_reload);
+		state.write_u16(self.timer_counter);
+		state.write_bool(self.timer_repeat);
+		state.write_bool(self.timer_enabled);
+		state.write_bool(self.timer_irq.get());
+		state.write_bool(self.motor_on);
+		state.write_bool(self.reset_transfer);
+		state.write_bool(self.read_mode);
+		state.write_mirroring(Some(self.mirroring));
+		state.write_bool(self.crc_control);
+		state.write_bool(self.transfer_enabled);
+		state.write_bool(self.disk_irq_enabled);
+		state.write_bool(self.disk_irq.get());
+		state.write_bool(self.transfer_complete.get());
+		state.write_u8(self.read_data);
+		state.write_u8(self.write_data);
+		state.write_u32(self.position as u32);
+		state.write_u32(self.delay);
+		state.write_bool(self.scanning);
+		state.write_bool(self.end_of_head);
+		state.write_bool(self.gap_ended);
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		state.read_into(&mut self.ram)?;
+		state.read_into(&mut self.chr_ram)?;
+		for side in &mut self.sides {
+			state.read_into(side)?;
+		}
+		self.side = match state.read_u8()? {
+			0xFF => None,
+			side => Some(usize::from(side))
+		};
+		self.disk_registers = state.read_bool()?;
+		self.timer_reload = state.read_u16()?;
+		self.timer_counter = state.read_u16()?;
+		self.timer_repeat = state.read_bool()?;
+		self.timer_enabled = state.read_bool()?;
+		self.timer_irq.set(state.read_bool()?);
+		self.motor_on = state.read_bool()?;
+		self.reset_transfer = state.read_bool()?;
+		self.read_mode = state.read_bool()?;
+		self.mirroring = state.read_mirroring()?.unwrap_or(Mirroring::Horizontal);
+		self.crc_control = state.read_bool()?;
+		self.transfer_enabled = state.read_bool()?;
+		self.disk_irq_enabled = state.read_bool()?;
+		self.disk_irq.set(state.read_bool()?);
+		self.transfer_complete.set(state.read_bool()?);
+		self.read_data = state.read_u8()?;
+		self.write_data = state.read_u8()?;
+		self.position = state.read_u32()? as usize;
+		self.delay = state.read_u32()?;
+		self.scanning = state.read_bool()?;
+		self.end_of_head = state.read_bool()?;
+		self.gap_ended = state.read_bool()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// One side holding the disk info block, a file amount of 1 and a 4 byte file
+	fn image() -> Vec<u8> {
+		let mut side = vec![0; SIDE_SIZE];
+		side[0] = 1;
+		side[1..15].copy_from_slice(b"*NINTENDO-HVC*");
+		side[56] = 2;
+		side[57] = 1;
+		side[58] = 3;
+		side[58 + 13] = 4;
+		side[74] = 4;
+		side[75..79].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+
+		let mut image = vec![0x46, 0x44, 0x53, 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+		image.extend(side);
+		image
+	}
+
+	fn fds() -> Fds {
+		Fds::new(vec![0xEA; BIOS_SIZE], FdsDisk::parse(&image()).unwrap())
+	}
+
+	#[test]
+	fn parse() {
+		let disk = FdsDisk::parse(&image()).unwrap();
+		assert_eq!(disk.side_count(), 1);
+
+		let raw = &disk.sides[0];
+		assert_eq!(raw.len(), RAW_SIDE_SIZE);
+		assert!(raw[..LEADING_GAP].iter().all(|&byte| byte == 0));
+		assert_eq!(raw[LEADING_GAP], BLOCK_START);
+		assert_eq!(raw[LEADING_GAP + 1], 1);
+		// Block 2 after block 1, its CRC and the gap
+		let block2 = LEADING_GAP + 1 + 56 + 2 + BLOCK_GAP;
+		assert_eq!(&raw[block2..block2 + 3], &[BLOCK_START, 2, 1]);
+
+		assert!(matches!(FdsDisk::parse(&[]), Err(RomError::BadMagic)));
+		assert!(matches!(FdsDisk::parse(&[0; 100]), Err(RomError::Truncated { expected: SIDE_SIZE, got: 100 })));
+	}
+
+	#[test]
+	fn memory() {
+		let mut fds = fds();
+		fds.cpu_write(0x6000, 0x12);
+		fds.cpu_write(0xDFFF, 0x34);
+		assert_eq!(fds.cpu_read(0x6000), 0x12);
+		assert_eq!(fds.cpu_read(0xDFFF), 0x34);
+		assert_eq!(fds.cpu_read(0xFFFC), 0xEA);
+		// The BIOS is read only
+		fds.cpu_write(0xE000, 0);
+		assert_eq!(fds.cpu_read(0xE000), 0xEA);
+	}
+
+	#[test]
+	fn timer_irq() {
+		let mut fds = fds();
+		fds.cpu_write(0x4023, 0x01);
+		fds.cpu_write(0x4020, 2);
+		fds.cpu_write(0x4021, 0);
+		fds.cpu_write(0x4022, 0x02);
+
+		fds.clock_cpu();
+		fds.clock_cpu();
+		assert!(!fds.irq_pending());
+		fds.clock_cpu();
+		assert!(fds.irq_pending());
+
+		// Reading $4030 acknowledges
+		assert_eq!(fds.cpu_read(0x4030) & 0x01, 0x01);
+		assert!(!fds.irq_pending());
+
+		// Not repeating
+		for _ in 0..10 {
+			fds.clock_cpu();
+		}
+		assert!(!fds.irq_pending());
+	}
+
+	#[test]
+	fn read_disk() {
+		let mut fds = fds();
+		fds.cpu_write(0x4023, 0x01);
+		assert_eq!(fds.cpu_read(0x4032) & 0x01, 0x00);
+
+		// Motor on, read mode, transfer and IRQs enabled
+		fds.cpu_write(0x4025, 0x01 | 0x04 | 0x40 | 0x80);
+
+		let mut bytes = Vec::new();
+		for _ in 0..HEAD_RETURN_DELAY as usize + (LEADING_GAP + 20) * (BYTE_DELAY as usize + 1) {
+			fds.clock_cpu();
+			if fds.irq_pending() {
+				bytes.push(fds.cpu_read(0x4031));
+			}
+		}
+
+		// The block start mark does not raise an IRQ
+		assert_eq!(&bytes[..15], b"\x01*NINTENDO-HVC*");
+		assert_eq!(fds.cpu_read(0x4032) & 0x02, 0x00);
+	}
+
+	#[test]
+	fn switch_sides() {
+		let mut image = image();
+		image.extend(vec![0; SIDE_SIZE]);
+		let mut fds = Fds::new(vec![0; BIOS_SIZE], FdsDisk::parse(&image).unwrap());
+		fds.cpu_write(0x4023, 0x01);
+
+		let drive = fds.disk_drive().unwrap();
+		assert_eq!(drive.side_count(), 2);
+		assert_eq!(drive.inserted_side(), Some(0));
+
+		drive.eject();
+		assert_eq!(drive.inserted_side(), None);
+		assert_eq!(fds.cpu_read(0x4032) & 0x07, 0x07);
+
+		let drive = fds.disk_drive().unwrap();
+		assert!(!drive.insert_side(2));
+		assert!(drive.insert_side(1));
+		assert_eq!(drive.inserted_side(), Some(1));
+	}
+}
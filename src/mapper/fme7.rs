@@ -0,0 +1,250 @@
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::rom::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+
+// Sunsoft FME-7, registers are set by writing a command at $8000 then its parameter at $A000
+#[derive(Clone)]
+pub struct Fme7 {
+	pgr_rom: Vec<u8>,
+	chr_rom: Vec<u8>,
+	chr_ram: bool,
+
+	command: u8,
+	chr_banks: [u8; 8],
+	// $6000, $8000, $A000 and $C000
+	prg_banks: [u8; 4],
+	// Command 8: bit 6 maps PRG RAM at $6000, bit 7 enables it
+	prg_ram_select: bool,
+	prg_ram_enable: bool,
+	mirroring: Option<Mirroring>,
+
+	irq_enabled: bool,
+	counter_enabled: bool,
+	counter: u16,
+	irq: bool
+}
+
+impl Fme7 {
+	pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Fme7 {
+		Fme7 {
+			pgr_rom,
+			chr_rom,
+			chr_ram,
+			command: 0,
+			chr_banks: [0; 8],
+			prg_banks: [0; 4],
+			prg_ram_select: false,
+			prg_ram_enable: false,
+			mirroring: None,
+			irq_enabled: false,
+			counter_enabled: false,
+			counter: 0,
+			irq: false
+		}
+	}
+
+	fn prg_bank_count(&self) -> usize {
+		(self.pgr_rom.len() / PRG_BANK_SIZE).max(1)
+	}
+
+	fn prg_bank(&self, adress: u16) -> usize {
+		match adress {
+			0xE000..=0xFFFF => self.prg_bank_count() - 1,
+			_ => usize::from(self.prg_banks[usize::from((adress - 0x6000) >> 13)])
+		}
+	}
+
+	fn write_parameter(&mut self, value: u8) {
+		match self.command {
+			0x0..=0x7 => self.chr_banks[usize::from(self.command)] = value,
+			0x8 => {
+				self.prg_banks[0] = value & 0x3F;
+				self.prg_ram_select = value & 0x40 != 0;
+				self.prg_ram_enable = value & 0x80 != 0;
+			},
+			0x9..=0xB => self.prg_banks[usize::from(self.command - 0x8)] = value & 0x3F,
+			0xC => {
+				self.mirroring = Some(match value & 0x03 {
+					0 => Mirroring::Vertical,
+					1 => Mirroring::Horizontal,
+					2 => Mirroring::SingleScreenLower,
+					_ => Mirroring::SingleScreenUpper
+				});
+			},
+			0xD => {
+				self.irq_enabled = value & 0x01 != 0;
+				self.counter_enabled = value & 0x80 != 0;
+				self.irq = false;
+			},
+			0xE => self.counter = (self.counter & 0xFF00) | u16::from(value),
+			_ => self.counter = (self.counter & 0x00FF) | (u16::from(value) << 8)
+		}
+	}
+}
+
+impl Mapper for Fme7 {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			// Reached when PRG RAM is not mapped, a ROM bank may be
+			0x6000..=0x7FFF if self.prg_ram_select => 0,
+			0x6000..=0xFFFF => banked_read(&self.pgr_rom, self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => 0
+		}
+	}
+
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x6000..=0x7FFF if self.prg_ram_select => None,
+			0x6000..=0xFFFF => banked_offset(self.pgr_rom.len(), self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => None
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		match adress {
+			0x8000..=0x9FFF => self.command = value & 0x0F,
+			0xA000..=0xBFFF => self.write_parameter(value),
+			_ => {}
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		let bank = usize::from(self.chr_banks[usize::from(adress >> 10) & 0x07]);
+		banked_read(&self.chr_rom, bank, CHR_BANK_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		if self.chr_ram {
+			let bank = usize::from(self.chr_banks[usize::from(adress >> 10) & 0x07]);
+			banked_write(&mut self.chr_rom, bank, CHR_BANK_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_u8(self.command);
+		state.write_bytes(&self.chr_banks);
+		state.write_bytes(&self.prg_banks);
+		state.write_bool(self.prg_ram_select);
+		state.write_bool(self.prg_ram_enable);
+		state.write_mirroring(self.mirroring);
+		state.write_bool(self.irq_enabled);
+		state.write_bool(self.counter_enabled);
+		state.write_u16(self.counter);
+		state.write_bool(self.irq);
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		self.command = state.read_u8()?;
+		state.read_into(&mut self.chr_banks)?;
+		state.read_into(&mut self.prg_banks)?;
+		self.prg_ram_select = state.read_bool()?;
+		self.prg_ram_enable = state.read_bool()?;
+		self.mirroring = state.read_mirroring()?;
+		self.irq_enabled = state.read_bool()?;
+		self.counter_enabled = state.read_bool()?;
+		self.counter = state.read_u16()?;
+		self.irq = state.read_bool()?;
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		self.mirroring
+	}
+
+	fn prg_ram_enabled(&self) -> bool {
+		self.prg_ram_select && self.prg_ram_enable
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq
+	}
+
+	// The counter decrements every CPU cycle and fires when wrapping past 0
+	fn clock_cpu(&mut self) {
+		if !self.counter_enabled {
+			return;
+		}
+
+		self.counter = self.counter.wrapping_sub(1);
+		if self.counter == 0xFFFF && self.irq_enabled {
+			self.irq = true;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Every byte holds its bank number
+	fn numbered(bank_size: usize, count: usize) -> Vec<u8> {
+		(0..count).flat_map(|bank| vec![bank as u8; bank_size]).collect()
+	}
+
+	fn command(fme7: &mut Fme7, command: u8, value: u8) {
+		fme7.cpu_write(0x8000, command);
+		fme7.cpu_write(0xA000, value);
+	}
+
+	#[test]
+	fn prg_banking() {
+		let mut fme7 = Fme7::new(numbered(PRG_BANK_SIZE, 32), vec![0; 8192], true);
+		command(&mut fme7, 0x9, 3);
+		command(&mut fme7, 0xA, 4);
+		command(&mut fme7, 0xB, 5);
+
+		assert_eq!(fme7.cpu_read(0x8000), 3);
+		assert_eq!(fme7.cpu_read(0xA000), 4);
+		assert_eq!(fme7.cpu_read(0xC000), 5);
+		assert_eq!(fme7.cpu_read(0xE000), 31);
+
+		// ROM at $6000, then RAM
+		command(&mut fme7, 0x8, 7);
+		assert!(!fme7.prg_ram_enabled());
+		assert_eq!(fme7.cpu_read(0x6000), 7);
+		command(&mut fme7, 0x8, 0xC0);
+		assert!(fme7.prg_ram_enabled());
+	}
+
+	#[test]
+	fn chr_banking() {
+		let mut fme7 = Fme7::new(numbered(PRG_BANK_SIZE, 4), numbered(CHR_BANK_SIZE, 64), false);
+		command(&mut fme7, 0x0, 40);
+		command(&mut fme7, 0x7, 9);
+
+		assert_eq!(fme7.ppu_read(0x0000), 40);
+		assert_eq!(fme7.ppu_read(0x1FFF), 9);
+	}
+
+	#[test]
+	fn cycle_irq() {
+		let mut fme7 = Fme7::new(numbered(PRG_BANK_SIZE, 4), vec![0; 8192], true);
+		command(&mut fme7, 0xE, 1);
+		command(&mut fme7, 0xF, 0);
+		command(&mut fme7, 0xD, 0x81);
+
+		fme7.clock_cpu();
+		assert!(!fme7.irq_pending());
+		fme7.clock_cpu();
+		assert!(fme7.irq_pending());
+
+		command(&mut fme7, 0xD, 0x81);
+		assert!(!fme7.irq_pending());
+	}
+}
@@ -0,0 +1,151 @@
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 32768;
+const CHR_BANK_SIZE: usize = 8192;
+
+// Position of the bank numbers in the register
+#[derive(Clone)]
+enum Layout {
+	// Mapper 66: --PP --CC
+	Gxrom,
+	// Mapper 11: CCCC --PP
+	ColorDreams
+}
+
+// One register selecting a 32KB PRG bank and an 8KB CHR bank
+#[derive(Clone)]
+pub struct Gxrom {
+	layout: Layout,
+	pgr_rom: Vec<u8>,
+	chr_rom: Vec<u8>,
+	chr_ram: bool,
+
+	prg_bank: u8,
+	chr_bank: u8,
+	bus_conflicts: bool
+}
+
+impl Gxrom {
+	pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Gxrom {
+		Gxrom::with_layout(Layout::Gxrom, pgr_rom, chr_rom, chr_ram)
+	}
+
+	pub fn color_dreams(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Gxrom {
+		Gxrom::with_layout(Layout::ColorDreams, pgr_rom, chr_rom, chr_ram)
+	}
+
+	fn with_layout(layout: Layout, pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Gxrom {
+		Gxrom {
+			layout,
+			pgr_rom,
+			chr_rom,
+			chr_ram,
+			prg_bank: 0,
+			chr_bank: 0,
+			bus_conflicts: true
+		}
+	}
+
+	pub fn set_bus_conflicts(&mut self, bus_conflicts: bool) {
+		self.bus_conflicts = bus_conflicts;
+	}
+}
+
+impl Mapper for Gxrom {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x8000..=0xFFFF => banked_read(&self.pgr_rom, usize::from(self.prg_bank), PRG_BANK_SIZE, adress),
+			_ => 0
+		}
+	}
+
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x8000..=0xFFFF => banked_offset(self.pgr_rom.len(), usize::from(self.prg_bank), PRG_BANK_SIZE, adress),
+			_ => None
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		if adress < 0x8000 {
+			return;
+		}
+
+		match self.layout {
+			Layout::Gxrom => {
+				self.prg_bank = (value >> 4) & 0x03;
+				self.chr_bank = value & 0x03;
+			},
+			Layout::ColorDreams => {
+				self.prg_bank = value & 0x03;
+				self.chr_bank = value >> 4;
+			}
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		banked_read(&self.chr_rom, usize::from(self.chr_bank), CHR_BANK_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		if self.chr_ram {
+			banked_write(&mut self.chr_rom, usize::from(self.chr_bank), CHR_BANK_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_u8(self.prg_bank);
+		state.write_u8(self.chr_bank);
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		self.prg_bank = state.read_u8()?;
+		self.chr_bank = state.read_u8()?;
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
+	}
+
+	fn bus_conflicts(&self) -> bool {
+		self.bus_conflicts
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn numbered(bank_size: usize, count: usize) -> Vec<u8> {
+		(0..count).flat_map(|bank| vec![bank as u8; bank_size]).collect()
+	}
+
+	#[test]
+	fn gxrom_register() {
+		let mut gxrom = Gxrom::new(numbered(PRG_BANK_SIZE, 4), numbered(CHR_BANK_SIZE, 4), false);
+		gxrom.cpu_write(0x8000, 0x21);
+
+		assert_eq!(gxrom.cpu_read(0x8000), 2);
+		assert_eq!(gxrom.ppu_read(0x0000), 1);
+	}
+
+	#[test]
+	fn color_dreams_register() {
+		let mut color_dreams = Gxrom::color_dreams(numbered(PRG_BANK_SIZE, 4), numbered(CHR_BANK_SIZE, 16), false);
+		color_dreams.cpu_write(0xC000, 0xA3);
+
+		assert_eq!(color_dreams.cpu_read(0xC000), 3);
+		assert_eq!(color_dreams.ppu_read(0x1FFF), 10);
+	}
+}
@@ -0,0 +1,258 @@
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::rom::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+
+#[derive(Clone)]
+pub struct Mmc3 {
+	pgr_rom: Vec<u8>,
+	chr_rom: Vec<u8>,
+	chr_ram: bool,
+
+	bank_select: u8,
+	// R0-R7
+	registers: [u8; 8],
+	mirroring: Option<Mirroring>,
+	prg_ram_enabled: bool,
+
+	irq_latch: u8,
+	irq_counter: u8,
+	irq_reload: bool,
+	irq_enabled: bool,
+	irq: bool
+}
+
+impl Mmc3 {
+	pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Mmc3 {
+		Mmc3 {
+			pgr_rom,
+			chr_rom,
+			chr_ram,
+			bank_select: 0,
+			registers: [0, 2, 4, 5, 6, 7, 0, 1],
+			mirroring: None,
+			prg_ram_enabled: true,
+			irq_latch: 0,
+			irq_counter: 0,
+			irq_reload: false,
+			irq_enabled: false,
+			irq: false
+		}
+	}
+
+	fn prg_bank_count(&self) -> usize {
+		(self.pgr_rom.len() / PRG_BANK_SIZE).max(1)
+	}
+
+	fn prg_bank(&self, adress: u16) -> usize {
+		let second_last = self.prg_bank_count() - 2.min(self.prg_bank_count());
+		let swapped = self.bank_select & 0x40 != 0;
+
+		match (adress >> 13) & 0x03 {
+			0 => if swapped { second_last } else { usize::from(self.registers[6]) },
+			1 => usize::from(self.registers[7]),
+			2 => if swapped { usize::from(self.registers[6]) } else { second_last },
+			_ => self.prg_bank_count() - 1
+		}
+	}
+
+	fn chr_bank(&self, adress: u16) -> usize {
+		// A12 inversion swaps the 2KB and 1KB halves
+		let adress = if self.bank_select & 0x80 != 0 { adress ^ 0x1000 } else { adress };
+		let slot = usize::from(adress >> 10);
+
+		match slot {
+			0 | 1 => usize::from(self.registers[0] & 0xFE) + slot,
+			2 | 3 => usize::from(self.registers[1] & 0xFE) + slot - 2,
+			_ => usize::from(self.registers[slot - 2])
+		}
+	}
+}
+
+impl Mapper for Mmc3 {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x8000..=0xFFFF => banked_read(&self.pgr_rom, self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => 0
+		}
+	}
+
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x8000..=0xFFFF => banked_offset(self.pgr_rom.len(), self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => None
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		let even = adress & 0x01 == 0;
+
+		match (adress, even) {
+			(0x8000..=0x9FFF, true) => self.bank_select = value,
+			(0x8000..=0x9FFF, false) => self.registers[usize::from(self.bank_select & 0x07)] = value,
+			(0xA000..=0xBFFF, true) => {
+				self.mirroring = Some(if value & 0x01 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal });
+			},
+			(0xA000..=0xBFFF, false) => self.prg_ram_enabled = value & 0x80 != 0,
+			(0xC000..=0xDFFF, true) => self.irq_latch = value,
+			(0xC000..=0xDFFF, false) => {
+				self.irq_counter = 0;
+				self.irq_reload = true;
+			},
+			(0xE000..=0xFFFF, true) => {
+				self.irq_enabled = false;
+				self.irq = false;
+			},
+			(0xE000..=0xFFFF, false) => self.irq_enabled = true,
+			_ => {}
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		banked_read(&self.chr_rom, self.chr_bank(adress), CHR_BANK_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		if self.chr_ram {
+			let bank = self.chr_bank(adress);
+			banked_write(&mut self.chr_rom, bank, CHR_BANK_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_u8(self.bank_select);
+		state.write_bytes(&self.registers);
+		state.write_mirroring(self.mirroring);
+		state.write_bool(self.prg_ram_enabled);
+		state.write_u8(self.irq_latch);
+		state.write_u8(self.irq_counter);
+		state.write_bool(self.irq_reload);
+		state.write_bool(self.irq_enabled);
+		state.write_bool(self.irq);
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		self.bank_select = state.read_u8()?;
+		state.read_into(&mut self.registers)?;
+		self.mirroring = state.read_mirroring()?;
+		self.prg_ram_enabled = state.read_bool()?;
+		self.irq_latch = state.read_u8()?;
+		self.irq_counter = state.read_u8()?;
+		self.irq_reload = state.read_bool()?;
+		self.irq_enabled = state.read_bool()?;
+		self.irq = state.read_bool()?;
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		self.mirroring
+	}
+
+	fn prg_ram_enabled(&self) -> bool {
+		self.prg_ram_enabled
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq
+	}
+
+	// A12 rises once per scanline with the usual background/sprite pattern table split
+	fn notify_scanline(&mut self) {
+		if self.irq_counter == 0 || self.irq_reload {
+			self.irq_counter = self.irq_latch;
+			self.irq_reload = false;
+		} else {
+			self.irq_counter -= 1;
+		}
+
+		if self.irq_counter == 0 && self.irq_enabled {
+			self.irq = true;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Every byte holds its bank number
+	fn numbered(bank_size: usize, count: usize) -> Vec<u8> {
+		(0..count).flat_map(|bank| vec![bank as u8; bank_size]).collect()
+	}
+
+	#[test]
+	fn prg_banking() {
+		let mut mmc3 = Mmc3::new(numbered(PRG_BANK_SIZE, 8), vec![0; 8192], true);
+		mmc3.cpu_write(0x8000, 6);
+		mmc3.cpu_write(0x8001, 3);
+		mmc3.cpu_write(0x8000, 7);
+		mmc3.cpu_write(0x8001, 4);
+
+		assert_eq!(mmc3.cpu_read(0x8000), 3);
+		assert_eq!(mmc3.cpu_read(0xA000), 4);
+		assert_eq!(mmc3.cpu_read(0xC000), 6);
+		assert_eq!(mmc3.cpu_read(0xE000), 7);
+
+		// PRG mode 1 swaps $8000 and $C000
+		mmc3.cpu_write(0x8000, 0x46);
+		assert_eq!(mmc3.cpu_read(0x8000), 6);
+		assert_eq!(mmc3.cpu_read(0xC000), 3);
+	}
+
+	#[test]
+	fn chr_banking() {
+		let mut mmc3 = Mmc3::new(numbered(PRG_BANK_SIZE, 4), numbered(CHR_BANK_SIZE, 16), false);
+		mmc3.cpu_write(0x8000, 0);
+		mmc3.cpu_write(0x8001, 5); // 2KB bank, low bit ignored
+		mmc3.cpu_write(0x8000, 2);
+		mmc3.cpu_write(0x8001, 9);
+
+		assert_eq!(mmc3.ppu_read(0x0000), 4);
+		assert_eq!(mmc3.ppu_read(0x0400), 5);
+		assert_eq!(mmc3.ppu_read(0x1000), 9);
+
+		mmc3.cpu_write(0x8000, 0x80);
+		assert_eq!(mmc3.ppu_read(0x1000), 4);
+		assert_eq!(mmc3.ppu_read(0x0000), 9);
+	}
+
+	#[test]
+	fn scanline_irq() {
+		let mut mmc3 = Mmc3::new(numbered(PRG_BANK_SIZE, 4), vec![0; 8192], true);
+		mmc3.cpu_write(0xC000, 2);
+		mmc3.cpu_write(0xC001, 0);
+		mmc3.cpu_write(0xE001, 0);
+
+		mmc3.notify_scanline(); // Reload to 2
+		mmc3.notify_scanline();
+		assert!(!mmc3.irq_pending());
+		mmc3.notify_scanline();
+		assert!(mmc3.irq_pending());
+
+		mmc3.cpu_write(0xE000, 0);
+		assert!(!mmc3.irq_pending());
+	}
+
+	#[test]
+	fn mirroring() {
+		let mut mmc3 = Mmc3::new(numbered(PRG_BANK_SIZE, 4), vec![0; 8192], true);
+		assert_eq!(mmc3.mirroring(), None);
+		mmc3.cpu_write(0xA000, 1);
+		assert_eq!(mmc3.mirroring(), Some(Mirroring::Horizontal));
+	}
+}
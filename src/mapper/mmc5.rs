@@ -0,0 +1,412 @@
+use std::cell::Cell;
+
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::rom::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+const EXRAM_SIZE: usize = 1024;
+
+// Last visible scanline, the counter stops there until the next frame
+const LAST_SCANLINE: u8 = 239;
+
+// Nametable sources selected by $5105
+const CIRAM_LOWER: u8 = 0;
+const CIRAM_UPPER: u8 = 1;
+const EXRAM: u8 = 2;
+const FILL: u8 = 3;
+
+// ExRAM modes of $5104
+const EXRAM_ATTRIBUTES: u8 = 1;
+const EXRAM_READ_ONLY: u8 = 3;
+
+// PRG/CHR banking, ExRAM, fill mode, the scanline IRQ and the multiplier.
+// PRG RAM banking, the split screen and extended attributes are not emulated,
+// and the 8x16 sprite background set ($5128-$512B) is ignored.
+#[derive(Clone)]
+pub struct Mmc5 {
+	pgr_rom: Vec<u8>,
+	chr_rom: Vec<u8>,
+	chr_ram: bool,
+
+	prg_mode: u8,
+	chr_mode: u8,
+	// $5114-$5117
+	prg_banks: [u8; 4],
+	// $5120-$5127, with the upper bits of $5130
+	chr_banks: [u16; 8],
+	chr_upper: u16,
+
+	exram: [u8; EXRAM_SIZE],
+	exram_mode: u8,
+	nametables: u8,
+	fill_tile: u8,
+	fill_attribute: u8,
+
+	irq_compare: u8,
+	irq_enabled: bool,
+	// Acknowledged by reading $5204
+	irq: Cell<bool>,
+	in_frame: bool,
+	scanline: u8,
+
+	multiplicand: u8,
+	multiplier: u8
+}
+
+impl Mmc5 {
+	pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Mmc5 {
+		Mmc5 {
+			pgr_rom,
+			chr_rom,
+			chr_ram,
+			prg_mode: 3,
+			chr_mode: 0,
+			prg_banks: [0, 0, 0, 0xFF],
+			chr_banks: [0; 8],
+			chr_upper: 0,
+			exram: [0; EXRAM_SIZE],
+			exram_mode: 0,
+			nametables: 0,
+			fill_tile: 0,
+			fill_attribute: 0,
+			irq_compare: 0,
+			irq_enabled: false,
+			irq: Cell::new(false),
+			in_frame: false,
+			scanline: 0,
+			multiplicand: 0xFF,
+			multiplier: 0xFF
+		}
+	}
+
+	fn prg_bank(&self, adress: u16) -> usize {
+		let slot = usize::from((adress >> 13) & 0x03);
+		// Bit 7 selects ROM, PRG RAM is not banked here
+		let register = |index: usize| usize::from(self.prg_banks[index] & 0x7F);
+
+		match (self.prg_mode, slot) {
+			(0, _) => (register(3) & !0x03) + slot,
+			(1, 0 | 1) | (2, 0 | 1) => (register(1) & !0x01) + slot,
+			(1, _) => (register(3) & !0x01) + slot - 2,
+			(2, _) | (3, _) => register(slot),
+			_ => unreachable!()
+		}
+	}
+
+	fn chr_bank(&self, adress: u16) -> usize {
+		let slot = usize::from(adress >> 10) & 0x07;
+		let register = |index: usize| usize::from(self.chr_banks[index]);
+
+		match self.chr_mode {
+			0 => register(7) * 8 + slot,
+			1 => register(slot | 0x03) * 4 + (slot & 0x03),
+			2 => register(slot | 0x01) * 2 + (slot & 0x01),
+			_ => register(slot)
+		}
+	}
+
+	// Source of one of the four nametables
+	fn nametable_source(&self, adress: u16) -> u8 {
+		let table = (adress >> 10) & 0x03;
+		(self.nametables >> (table * 2)) & 0x03
+	}
+
+	fn exram_as_nametable(&self) -> bool {
+		self.exram_mode <= EXRAM_ATTRIBUTES
+	}
+}
+
+impl Mapper for Mmc5 {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x5204 => {
+				let status = (u8::from(self.irq.get()) << 7) | (u8::from(self.in_frame) << 6);
+				self.irq.set(false);
+				status
+			},
+			0x5205 => (u16::from(self.multiplicand) * u16::from(self.multiplier)) as u8,
+			0x5206 => ((u16::from(self.multiplicand) * u16::from(self.multiplier)) >> 8) as u8,
+			// Only readable by the CPU when not used as a nametable
+			0x5C00..=0x5FFF if !self.exram_as_nametable() => self.exram[usize::from(adress - 0x5C00)],
+			0x8000..=0xFFFF => banked_read(&self.pgr_rom, self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => 0
+		}
+	}
+
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x8000..=0xFFFF => banked_offset(self.pgr_rom.len(), self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => None
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		match adress {
+			0x5100 => self.prg_mode = value & 0x03,
+			0x5101 => self.chr_mode = value & 0x03,
+			// PRG RAM write protection ($5102/$5103) is not emulated
+			0x5104 => self.exram_mode = value & 0x03,
+			0x5105 => self.nametables = value,
+			0x5106 => self.fill_tile = value,
+			0x5107 => self.fill_attribute = value & 0x03,
+			0x5114..=0x5117 => self.prg_banks[usize::from(adress - 0x5114)] = value,
+			0x5120..=0x5127 => {
+				self.chr_banks[usize::from(adress - 0x5120)] = self.chr_upper | u16::from(value);
+			},
+			0x5130 => self.chr_upper = u16::from(value & 0x03) << 8,
+			0x5203 => self.irq_compare = value,
+			0x5204 => self.irq_enabled = value & 0x80 != 0,
+			0x5205 => self.multiplicand = value,
+			0x5206 => self.multiplier = value,
+			0x5C00..=0x5FFF if self.exram_mode != EXRAM_READ_ONLY => {
+				self.exram[usize::from(adress - 0x5C00)] = value;
+			},
+			_ => {}
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		banked_read(&self.chr_rom, self.chr_bank(adress), CHR_BANK_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		if self.chr_ram {
+			let bank = self.chr_bank(adress);
+			banked_write(&mut self.chr_rom, bank, CHR_BANK_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_u8(self.prg_mode);
+		state.write_u8(self.chr_mode);
+		state.write_bytes(&self.prg_banks);
+		for bank in self.chr_banks {
+			state.write_u16(bank);
+		}
+		state.write_u16(self.chr_upper);
+		state.write_bytes(&self.exram);
+		state.write_u8(self.exram_mode);
+		state.write_u8(self.nametables);
+		state.write_u8(self.fill_tile);
+		state.write_u8(self.fill_attribute);
+		state.write_u8(self.irq_compare);
+		state.write_bool(self.irq_enabled);
+		state.write_bool(self.irq.get());
+		state.write_bool(self.in_frame);
+		state.write_u8(self.scanline);
+		state.write_u8(self.multiplicand);
+		state.write_u8(self.multiplier);
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		self.prg_mode = state.read_u8()?;
+		self.chr_mode = state.read_u8()?;
+		state.read_into(&mut self.prg_banks)?;
+		for bank in self.chr_banks.iter_mut() {
+			*bank = state.read_u16()?;
+		}
+		self.chr_upper = state.read_u16()?;
+		state.read_into(&mut self.exram)?;
+		self.exram_mode = state.read_u8()?;
+		self.nametables = state.read_u8()?;
+		self.fill_tile = state.read_u8()?;
+		self.fill_attribute = state.read_u8()?;
+		self.irq_compare = state.read_u8()?;
+		self.irq_enabled = state.read_bool()?;
+		self.irq.set(state.read_bool()?);
+		self.in_frame = state.read_bool()?;
+		self.scanline = state.read_u8()?;
+		self.multiplicand = state.read_u8()?;
+		self.multiplier = state.read_u8()?;
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
+	}
+
+	// Closest console layout for the nametables left in CIRAM,
+	// the ExRAM and fill nametables are answered by read_nametable
+	fn mirroring(&self) -> Option<Mirroring> {
+		let ciram: Vec<(u16, u8)> = (0..4)
+			.map(|table| (table, self.nametable_source(table << 10)))
+			.filter(|(_, source)| *source <= CIRAM_UPPER)
+			.collect();
+
+		let matches = |layout: [u8; 4]| ciram.iter().all(|(table, source)| layout[usize::from(*table)] == *source);
+
+		Some(if matches([CIRAM_LOWER; 4]) {
+			Mirroring::SingleScreenLower
+		} else if matches([CIRAM_UPPER; 4]) {
+			Mirroring::SingleScreenUpper
+		} else if matches([CIRAM_LOWER, CIRAM_LOWER, CIRAM_UPPER, CIRAM_UPPER]) {
+			Mirroring::Horizontal
+		} else {
+			Mirroring::Vertical
+		})
+	}
+
+	fn read_nametable(&self, adress: u16) -> Option<u8> {
+		let offset = usize::from(adress & 0x03FF);
+
+		match self.nametable_source(adress) {
+			EXRAM if self.exram_as_nametable() => Some(self.exram[offset]),
+			EXRAM => Some(0),
+			FILL if offset >= 0x3C0 => Some(self.fill_attribute * 0x55),
+			FILL => Some(self.fill_tile),
+			_ => None
+		}
+	}
+
+	fn write_nametable(&mut self, adress: u16, value: u8) -> bool {
+		match self.nametable_source(adress) {
+			EXRAM => {
+				if self.exram_as_nametable() {
+					self.exram[usize::from(adress & 0x03FF)] = value;
+				}
+				true
+			},
+			FILL => true,
+			_ => false
+		}
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq.get() && self.irq_enabled
+	}
+
+	// The first notification of a frame comes from the pre-render line,
+	// each following one announces the next visible scanline
+	fn notify_scanline(&mut self) {
+		if !self.in_frame {
+			self.in_frame = true;
+			self.scanline = 0;
+			self.irq.set(false);
+			return;
+		}
+
+		if self.scanline == LAST_SCANLINE {
+			self.in_frame = false;
+			return;
+		}
+
+		self.scanline += 1;
+		if self.scanline == self.irq_compare {
+			self.irq.set(true);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Every byte holds its bank number
+	fn numbered(bank_size: usize, count: usize) -> Vec<u8> {
+		(0..count).flat_map(|bank| vec![bank as u8; bank_size]).collect()
+	}
+
+	#[test]
+	fn prg_banking() {
+		let mut mmc5 = Mmc5::new(numbered(PRG_BANK_SIZE, 16), vec![0; 8192], true);
+		// Power on: mode 3 with the last bank at $E000
+		assert_eq!(mmc5.cpu_read(0xE000), 15);
+
+		mmc5.cpu_write(0x5114, 0x81);
+		mmc5.cpu_write(0x5115, 0x82);
+		mmc5.cpu_write(0x5116, 0x83);
+		mmc5.cpu_write(0x5117, 0x84);
+		assert_eq!(mmc5.cpu_read(0x8000), 1);
+		assert_eq!(mmc5.cpu_read(0xA000), 2);
+		assert_eq!(mmc5.cpu_read(0xC000), 3);
+		assert_eq!(mmc5.cpu_read(0xE000), 4);
+
+		// 16KB + 8KB + 8KB
+		mmc5.cpu_write(0x5100, 2);
+		assert_eq!(mmc5.cpu_read(0x8000), 2);
+		assert_eq!(mmc5.cpu_read(0xA000), 3);
+		assert_eq!(mmc5.cpu_read(0xC000), 3);
+
+		// 32KB
+		mmc5.cpu_write(0x5100, 0);
+		assert_eq!(mmc5.cpu_read(0x8000), 4);
+		assert_eq!(mmc5.cpu_read(0xE000), 7);
+	}
+
+	#[test]
+	fn chr_banking() {
+		let mut mmc5 = Mmc5::new(numbered(PRG_BANK_SIZE, 4), numbered(CHR_BANK_SIZE, 64), false);
+		mmc5.cpu_write(0x5101, 3);
+		mmc5.cpu_write(0x5120, 9);
+		mmc5.cpu_write(0x5127, 33);
+		assert_eq!(mmc5.ppu_read(0x0000), 9);
+		assert_eq!(mmc5.ppu_read(0x1C00), 33);
+
+		// 4KB banks use $5123 and $5127
+		mmc5.cpu_write(0x5101, 1);
+		mmc5.cpu_write(0x5123, 2);
+		assert_eq!(mmc5.ppu_read(0x0400), 9);
+		assert_eq!(mmc5.ppu_read(0x1400), 133 % 64);
+	}
+
+	#[test]
+	fn multiplier() {
+		let mut mmc5 = Mmc5::new(numbered(PRG_BANK_SIZE, 4), vec![0; 8192], true);
+		mmc5.cpu_write(0x5205, 200);
+		mmc5.cpu_write(0x5206, 100);
+
+		assert_eq!(mmc5.cpu_read(0x5205), (20000 & 0xFF) as u8);
+		assert_eq!(mmc5.cpu_read(0x5206), (20000 >> 8) as u8);
+	}
+
+	#[test]
+	fn scanline_irq() {
+		let mut mmc5 = Mmc5::new(numbered(PRG_BANK_SIZE, 4), vec![0; 8192], true);
+		mmc5.cpu_write(0x5203, 2);
+		mmc5.cpu_write(0x5204, 0x80);
+
+		mmc5.notify_scanline(); // Pre-render line
+		mmc5.notify_scanline();
+		assert!(!mmc5.irq_pending());
+		mmc5.notify_scanline();
+		assert!(mmc5.irq_pending());
+
+		assert_eq!(mmc5.cpu_read(0x5204), 0xC0);
+		assert!(!mmc5.irq_pending());
+	}
+
+	#[test]
+	fn nametables() {
+		let mut mmc5 = Mmc5::new(numbered(PRG_BANK_SIZE, 4), vec![0; 8192], true);
+		// CIRAM, CIRAM, ExRAM, fill
+		mmc5.cpu_write(0x5105, 0b11_10_01_00);
+		mmc5.cpu_write(0x5106, 0x42);
+		mmc5.cpu_write(0x5107, 0x02);
+
+		assert_eq!(mmc5.mirroring(), Some(Mirroring::Vertical));
+		assert_eq!(mmc5.read_nametable(0x2000), None);
+
+		assert!(mmc5.write_nametable(0x2805, 0x99));
+		assert_eq!(mmc5.read_nametable(0x2805), Some(0x99));
+
+		assert_eq!(mmc5.read_nametable(0x2C00), Some(0x42));
+		assert_eq!(mmc5.read_nametable(0x2FC0), Some(0xAA));
+
+		// ExRAM is CPU memory in mode 2
+		mmc5.cpu_write(0x5104, 2);
+		mmc5.cpu_write(0x5C05, 0x12);
+		assert_eq!(mmc5.cpu_read(0x5C05), 0x12);
+		assert_eq!(mmc5.read_nametable(0x2805), Some(0));
+	}
+}
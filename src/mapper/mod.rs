@@ -1,21 +1,173 @@
 pub mod nrom;
+pub mod mmc3;
+pub mod mmc5;
+pub mod axrom;
+pub mod gxrom;
+pub mod vrc4;
+pub mod vrc6;
+pub mod fme7;
+pub mod unrom512;
+pub mod camerica;
+pub mod namco118;
+pub mod fds;
+pub mod registry;
+mod vrc_irq;
 
 use nrom::Nrom;
+use crate::rom::Mirroring;
+use crate::apu::expansion::ExpansionAudio;
+use crate::state::StateError;
+use fds::DiskDrive;
 
-pub trait Mapper {
-	fn read(&self, adress: u16) -> u8;
-	fn write(&mut self, adress: u16, value: u8);
+// Cartridge board, seen from the CPU at $4020-$FFFF and from the PPU at $0000-$1FFF
+pub trait Mapper: MapperClone {
+	fn cpu_read(&self, adress: u16) -> u8;
+	fn cpu_write(&mut self, adress: u16, value: u8);
 
-	fn read_chr_rom(&self, adress: u16) -> u8;
+	// Pattern tables
+	fn ppu_read(&self, adress: u16) -> u8;
+	fn ppu_write(&mut self, adress: u16, value: u8);
+
+	fn has_chr_ram(&self) -> bool;
+
+	// Offset in the PRG ROM of the byte the CPU reads at `adress`, None when no ROM answers there
+	fn prg_rom_offset(&self, _adress: u16) -> Option<usize> {
+		None
+	}
+
+	// Mirroring selected by the mapper, None when hardwired by the cartridge
+	fn mirroring(&self) -> Option<Mirroring> {
+		None
+	}
+
+	// Whether the work RAM at $6000-$7FFF answers, mappers can write-protect or disable it
+	fn prg_ram_enabled(&self) -> bool {
+		true
+	}
+
+	// State of the cartridge IRQ line
+	fn irq_pending(&self) -> bool {
+		false
+	}
+
+	// Nametable byte supplied by the cartridge instead of the console VRAM
+	fn read_nametable(&self, _adress: u16) -> Option<u8> {
+		None
+	}
+
+	// True when the cartridge took the write
+	fn write_nametable(&mut self, _adress: u16, _value: u8) -> bool {
+		false
+	}
+
+	// Writes to $8000-$FFFF are ANDed with the ROM byte at that adress,
+	// on discrete logic boards without conflict prevention
+	fn bus_conflicts(&self) -> bool {
+		false
+	}
+
+	// Non-volatile cartridge memory saved in place of the PRG RAM, such as a flash PRG chip
+	fn battery_data(&self) -> Option<&[u8]> {
+		None
+	}
+
+	fn load_battery_data(&mut self, _data: &[u8]) {}
+
+	// Bank registers, IRQ counters and cartridge RAM for save states,
+	// load_state gets back what save_state returned for the same cartridge
+	fn save_state(&self) -> Vec<u8> {
+		Vec::new()
+	}
+
+	fn load_state(&mut self, _data: &[u8]) -> Result<(), StateError> {
+		Ok(())
+	}
+
+	// Called by the bus once per CPU cycle, for cycle based IRQ counters
+	fn clock_cpu(&mut self) {}
+
+	// Called by the PPU once per rendered scanline (dot 260) when rendering is on,
+	// where the MMC3 sees A12 rise from the sprite fetches
+	fn notify_scanline(&mut self) {}
+
+	// Sound channels on the cartridge, registered with the APU when the bus is built
+	fn expansion_audio(&self) -> Option<Box<dyn ExpansionAudio>> {
+		None
+	}
+
+	// Disk drive of the Famicom Disk System, to switch disk sides
+	fn disk_drive(&mut self) -> Option<&mut dyn DiskDrive> {
+		None
+	}
+}
+
+// Lets Box<dyn Mapper> be cloned with the console, implemented for every Mapper that is Clone
+pub trait MapperClone {
+	fn clone_box(&self) -> Box<dyn Mapper>;
+}
+
+impl<T: Mapper + Clone + 'static> MapperClone for T {
+	fn clone_box(&self) -> Box<dyn Mapper> {
+		Box::new(self.clone())
+	}
+}
+
+impl Clone for Box<dyn Mapper> {
+	fn clone(&self) -> Self {
+		self.clone_box()
+	}
+}
+
+// Common name of an iNES mapper number
+pub fn mapper_name(mapper: u16) -> Option<&'static str> {
+	Some(match mapper {
+		0 => "NROM",
+		1 => "MMC1",
+		2 => "UxROM",
+		3 => "CNROM",
+		4 => "MMC3",
+		5 => "MMC5",
+		7 => "AxROM",
+		9 => "MMC2",
+		10 => "MMC4",
+		11 => "Color Dreams",
+		19 => "Namco 163",
+		21 | 23 | 25 => "VRC4",
+		22 => "VRC2",
+		24 | 26 => "VRC6",
+		30 => "UNROM 512",
+		66 => "GxROM",
+		69 => "FME-7",
+		71 => "Camerica",
+		85 => "VRC7",
+		206 => "Namco 118",
+		_ => return None
+	})
+}
+
+// Byte at `adress` within `bank`, banks past the end of the chip wrap around
+pub(crate) fn banked_read(data: &[u8], bank: usize, bank_size: usize, adress: u16) -> u8 {
+	banked_offset(data.len(), bank, bank_size, adress).map_or(0, |offset| data[offset])
 }
 
-impl dyn Mapper {
-	pub fn from_id(id: u8, pgr_rom: Vec<u8>, chr_rom: Vec<u8>) -> Box<dyn Mapper> {
-		match id {
-			0x0 => Box::new(Nrom::new(pgr_rom, chr_rom)),
-			_ => panic!("Mapper {} not implemented", id)
-		}
+// Offset read by banked_read in a chip of `len` bytes
+pub(crate) fn banked_offset(len: usize, bank: usize, bank_size: usize, adress: u16) -> Option<usize> {
+	if len == 0 {
+		return None;
 	}
+
+	let offset = bank * bank_size + usize::from(adress) % bank_size;
+	Some(offset % len)
+}
+
+pub(crate) fn banked_write(data: &mut [u8], bank: usize, bank_size: usize, adress: u16, value: u8) {
+	if data.is_empty() {
+		return;
+	}
+
+	let offset = bank * bank_size + usize::from(adress) % bank_size;
+	let len = data.len();
+	data[offset % len] = value;
 }
 
 pub mod test {
@@ -23,6 +175,6 @@ use super::*;
 
 	pub fn test_mapper() -> Box<dyn Mapper> {
 		// Empty Nrom
-		Box::new(Nrom::new(vec![0; 16384*2], vec![0; 8192]))
+		Box::new(Nrom::new(vec![0; 16384*2], vec![0; 8192], false))
 	}
 }
\ No newline at end of file
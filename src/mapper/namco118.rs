@@ -0,0 +1,144 @@
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+
+// Namco 118 and Nintendo DxROM (mapper 206), the MMC3 register layout without
+// IRQ, mirroring control or mode bits
+#[derive(Clone)]
+pub struct Namco118 {
+	pgr_rom: Vec<u8>,
+	chr_rom: Vec<u8>,
+	chr_ram: bool,
+
+	bank_select: u8,
+	// R0-R7
+	registers: [u8; 8]
+}
+
+impl Namco118 {
+	pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Namco118 {
+		Namco118 {
+			pgr_rom,
+			chr_rom,
+			chr_ram,
+			bank_select: 0,
+			registers: [0, 2, 4, 5, 6, 7, 0, 1]
+		}
+	}
+
+	fn prg_bank_count(&self) -> usize {
+		(self.pgr_rom.len() / PRG_BANK_SIZE).max(1)
+	}
+
+	fn prg_bank(&self, adress: u16) -> usize {
+		match (adress >> 13) & 0x03 {
+			0 => usize::from(self.registers[6] & 0x0F),
+			1 => usize::from(self.registers[7] & 0x0F),
+			2 => self.prg_bank_count() - 2.min(self.prg_bank_count()),
+			_ => self.prg_bank_count() - 1
+		}
+	}
+
+	fn chr_bank(&self, adress: u16) -> usize {
+		let slot = usize::from(adress >> 10) & 0x07;
+
+		match slot {
+			0 | 1 => usize::from(self.registers[0] & 0x3E) + slot,
+			2 | 3 => usize::from(self.registers[1] & 0x3E) + slot - 2,
+			_ => usize::from(self.registers[slot - 2] & 0x3F)
+		}
+	}
+}
+
+impl Mapper for Namco118 {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x8000..=0xFFFF => banked_read(&self.pgr_rom, self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => 0
+		}
+	}
+
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x8000..=0xFFFF => banked_offset(self.pgr_rom.len(), self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => None
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		match (adress, adress & 0x01 == 0) {
+			(0x8000..=0x9FFF, true) => self.bank_select = value & 0x07,
+			(0x8000..=0x9FFF, false) => self.registers[usize::from(self.bank_select)] = value,
+			_ => {}
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		banked_read(&self.chr_rom, self.chr_bank(adress), CHR_BANK_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		if self.chr_ram {
+			let bank = self.chr_bank(adress);
+			banked_write(&mut self.chr_rom, bank, CHR_BANK_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_u8(self.bank_select);
+		state.write_bytes(&self.registers);
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		self.bank_select = state.read_u8()?;
+		state.read_into(&mut self.registers)?;
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Every byte holds its bank number
+	fn numbered(bank_size: usize, count: usize) -> Vec<u8> {
+		(0..count).flat_map(|bank| vec![bank as u8; bank_size]).collect()
+	}
+
+	#[test]
+	fn banking() {
+		let mut namco = Namco118::new(numbered(PRG_BANK_SIZE, 8), numbered(CHR_BANK_SIZE, 64), false);
+		namco.cpu_write(0x8000, 6);
+		namco.cpu_write(0x8001, 2);
+		namco.cpu_write(0x8000, 0);
+		namco.cpu_write(0x8001, 9);
+		namco.cpu_write(0x8000, 5);
+		namco.cpu_write(0x8001, 33);
+
+		assert_eq!(namco.cpu_read(0x8000), 2);
+		assert_eq!(namco.cpu_read(0xC000), 6);
+		assert_eq!(namco.cpu_read(0xE000), 7);
+		assert_eq!(namco.ppu_read(0x0000), 8);
+		assert_eq!(namco.ppu_read(0x0400), 9);
+		assert_eq!(namco.ppu_read(0x1C00), 33);
+
+		// Mode bits of the MMC3 are not there
+		namco.cpu_write(0x8000, 0xC6);
+		assert_eq!(namco.cpu_read(0x8000), 2);
+	}
+}
@@ -1,55 +1,108 @@
-use crate::mapper::Mapper;
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::state::{StateError, StateReader, StateWriter};
 
-enum Variant {
-	Nrom128,
-	Nrom256
-}
+const PRG_SIZE: usize = 32768;
+const CHR_SIZE: usize = 8192;
 
+// NROM-128 and NROM-256, smaller chips are mirrored over their window
+#[derive(Clone)]
 pub struct Nrom {
-	variant: Variant,
 	pgr_rom: Vec<u8>,
-	chr_rom: Vec<u8>
+	chr_rom: Vec<u8>,
+	chr_ram: bool
 }
 
 impl Mapper for Nrom {
-	fn read(&self, adress: u16) -> u8 {
-        match adress {
-			0x0000..=0x1FFF => {
-				self.chr_rom[usize::from(adress)]
-			},
-			0x8000..=0xFFFF => {
-				let effective = match self.variant {
-					Variant::Nrom128 => adress & 0x3FFF,
-					Variant::Nrom256 => adress & 0x7FFF
-				};
-				self.pgr_rom[usize::from(effective)]
-			}
-			_ => panic!("Undefined read mapping for {:#06x}", adress)
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x8000..=0xFFFF => banked_read(&self.pgr_rom, 0, PRG_SIZE, adress),
+			_ => 0 // Nothing answers, the bus keeps its last value
 		}
-    }
-
-	fn write(&mut self, adress: u16, value: u8) {
-        match adress {
-			0x0000..=0x1FFF => {
-				self.chr_rom[usize::from(adress)] = value;
-			},
-			0x8000..=0xFFFF => panic!("Try to write at prg rom cartridge {:#06x}", adress),
-			_ => panic!("Undefined write mapping for {:#06x}", adress)
+	}
+
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x8000..=0xFFFF => banked_offset(self.pgr_rom.len(), 0, PRG_SIZE, adress),
+			_ => None
 		}
-    }
+	}
 
-	fn read_chr_rom(&self, adress: u16) -> u8 {
-		self.chr_rom[adress as usize]
+	fn cpu_write(&mut self, _adress: u16, _value: u8) {
+		// PRG ROM is read-only, CHR RAM is written through ppu_write
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		banked_read(&self.chr_rom, 0, CHR_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		// Writes to CHR ROM are ignored
+		if self.chr_ram {
+			banked_write(&mut self.chr_rom, 0, CHR_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
 	}
 }
 
 impl Nrom {
-	pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>) -> Nrom {
-		let variant = if chr_rom.len() > 8192 { Variant::Nrom256 } else { Variant::Nrom128 };
+	pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Nrom {
 		Nrom {
-			variant,
 			pgr_rom,
-			chr_rom
+			chr_rom,
+			chr_ram
 		}
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cpu_reads_prg_only() {
+		let nrom = Nrom::new(vec![0x11; 16384], vec![0x22; 8192], false);
+
+		assert_eq!(nrom.cpu_read(0x0000), 0);
+		assert_eq!(nrom.cpu_read(0x1FFF), 0);
+		assert_eq!(nrom.cpu_read(0x8000), 0x11);
+	}
+
+	#[test]
+	fn mirrors_small_roms() {
+		let mut pgr_rom = vec![0; 16384];
+		pgr_rom[0x0010] = 0x42;
+		let nrom = Nrom::new(pgr_rom, vec![0x22; 2048], false);
+
+		assert_eq!(nrom.cpu_read(0x8010), 0x42);
+		assert_eq!(nrom.cpu_read(0xC010), 0x42);
+		assert_eq!(nrom.ppu_read(0x1FFF), 0x22);
+	}
+
+	#[test]
+	fn empty_roms() {
+		let mut nrom = Nrom::new(Vec::new(), Vec::new(), true);
+		nrom.ppu_write(0x0000, 0x42);
+
+		assert_eq!(nrom.cpu_read(0xFFFC), 0);
+		assert_eq!(nrom.ppu_read(0x0000), 0);
+	}
+}
@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use crate::mapper::Mapper;
+use crate::mapper::{nrom::Nrom, mmc3::Mmc3, mmc5::Mmc5, axrom::Axrom, gxrom::Gxrom};
+use crate::mapper::{vrc4::{Vrc4, Wiring}, vrc6::Vrc6, fme7::Fme7, unrom512::Unrom512};
+use crate::mapper::{camerica::Camerica, namco118::Namco118};
+use crate::rom::Mirroring;
+
+// Cartridge content handed to a mapper constructor
+pub struct Board {
+	pub mapper: u16,
+	// 0 for iNES files
+	pub submapper: u8,
+	pub pgr_rom: Vec<u8>,
+	pub chr_rom: Vec<u8>,
+	pub chr_ram: bool,
+	// Header mirroring, mapper 30 reports its one-screen board as SingleScreenLower
+	pub mirroring: Mirroring,
+	pub battery: bool
+}
+
+pub type MapperConstructor = Box<dyn Fn(Board) -> Box<dyn Mapper>>;
+
+// Mapper constructors by iNES number, optionally narrowed to a NES 2.0 submapper
+pub struct MapperRegistry {
+	constructors: HashMap<(u16, Option<u8>), MapperConstructor>
+}
+
+impl MapperRegistry {
+	// Registry with the mappers built into nessy
+	pub fn new() -> MapperRegistry {
+		let mut registry = MapperRegistry::empty();
+
+		registry.register(0, |board| Box::new(Nrom::new(board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(4, |board| Box::new(Mmc3::new(board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(5, |board| Box::new(Mmc5::new(board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(7, |board| {
+			let mut axrom = Axrom::new(board.pgr_rom, board.chr_rom, board.chr_ram);
+			// Submapper 2 is AMROM
+			axrom.set_bus_conflicts(board.submapper == 2);
+			Box::new(axrom)
+		});
+		registry.register(11, |board| Box::new(Gxrom::color_dreams(board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(21, |board| Box::new(Vrc4::new(Wiring::Vrc4ac, board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(22, |board| Box::new(Vrc4::new(Wiring::Vrc2a, board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(23, |board| Box::new(Vrc4::new(Wiring::Vrc4ef, board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(24, |board| Box::new(Vrc6::new(board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(25, |board| Box::new(Vrc4::new(Wiring::Vrc4bd, board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(26, |board| Box::new(Vrc6::vrc6b(board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(30, |board| {
+			let one_screen = board.mirroring == Mirroring::SingleScreenLower;
+			// The battery bit marks the self-flashable board
+			let mut unrom = Unrom512::new(board.pgr_rom, board.chr_rom, board.chr_ram, one_screen, board.battery);
+			// Submapper 1 has no bus conflicts
+			if board.submapper == 1 {
+				unrom.set_bus_conflicts(false);
+			}
+			Box::new(unrom)
+		});
+		registry.register(66, |board| Box::new(Gxrom::new(board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(69, |board| Box::new(Fme7::new(board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(71, |board| Box::new(Camerica::new(board.pgr_rom, board.chr_rom, board.chr_ram)));
+		registry.register(206, |board| Box::new(Namco118::new(board.pgr_rom, board.chr_rom, board.chr_ram)));
+
+		registry
+	}
+
+	pub fn empty() -> MapperRegistry {
+		MapperRegistry {
+			constructors: HashMap::new()
+		}
+	}
+
+	// Constructor for every submapper of `mapper`, replaces any previous one
+	pub fn register<F>(&mut self, mapper: u16, constructor: F)
+	where F: Fn(Board) -> Box<dyn Mapper> + 'static {
+		self.constructors.insert((mapper, None), Box::new(constructor));
+	}
+
+	// Constructor for one submapper, preferred over the one registered for all submappers
+	pub fn register_submapper<F>(&mut self, mapper: u16, submapper: u8, constructor: F)
+	where F: Fn(Board) -> Box<dyn Mapper> + 'static {
+		self.constructors.insert((mapper, Some(submapper)), Box::new(constructor));
+	}
+
+	pub fn is_supported(&self, mapper: u16, submapper: u8) -> bool {
+		self.constructor(mapper, submapper).is_some()
+	}
+
+	// None when no constructor is registered for the board
+	pub fn create(&self, board: Board) -> Option<Box<dyn Mapper>> {
+		self.constructor(board.mapper, board.submapper)
+			.map(|constructor| constructor(board))
+	}
+
+	fn constructor(&self, mapper: u16, submapper: u8) -> Option<&MapperConstructor> {
+		self.constructors.get(&(mapper, Some(submapper)))
+			.or_else(|| self.constructors.get(&(mapper, None)))
+	}
+}
+
+impl Default for MapperRegistry {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn board(mapper: u16, submapper: u8) -> Board {
+		Board {
+			mapper,
+			submapper,
+			pgr_rom: vec![0; 32768],
+			chr_rom: vec![0; 8192],
+			chr_ram: false,
+			mirroring: Mirroring::Horizontal,
+			battery: false
+		}
+	}
+
+	// Open bus at $8000, reports which constructor built it
+	#[derive(Clone)]
+	struct Custom(u8);
+	impl Mapper for Custom {
+		fn cpu_read(&self, _adress: u16) -> u8 { self.0 }
+		fn cpu_write(&mut self, _adress: u16, _value: u8) {}
+		fn ppu_read(&self, _adress: u16) -> u8 { 0 }
+		fn ppu_write(&mut self, _adress: u16, _value: u8) {}
+		fn has_chr_ram(&self) -> bool { false }
+	}
+
+	#[test]
+	fn builtin_mappers() {
+		let registry = MapperRegistry::new();
+		assert!(registry.is_supported(0, 0));
+		assert!(registry.is_supported(4, 1));
+		assert!(!registry.is_supported(255, 0));
+		assert!(registry.create(board(255, 0)).is_none());
+	}
+
+	#[test]
+	fn custom_mapper() {
+		let mut registry = MapperRegistry::empty();
+		registry.register(300, |_| Box::new(Custom(1)));
+		registry.register_submapper(300, 2, |_| Box::new(Custom(2)));
+
+		assert_eq!(registry.create(board(300, 0)).unwrap().cpu_read(0x8000), 1);
+		assert_eq!(registry.create(board(300, 2)).unwrap().cpu_read(0x8000), 2);
+	}
+}
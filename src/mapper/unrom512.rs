@@ -0,0 +1,253 @@
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::rom::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 16384;
+const CHR_BANK_SIZE: usize = 8192;
+const CHR_RAM_SIZE: usize = 32768;
+
+// Flash sector cleared by the sector erase command
+const SECTOR_SIZE: usize = 4096;
+
+// Progress through the SST39SF040 command sequences
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FlashState {
+	Idle,
+	Unlock1,
+	Unlock2,
+	Program,
+	Erase,
+	EraseUnlock1,
+	EraseUnlock2
+}
+
+// UNROM 512 (mapper 30), the common homebrew board: 16KB PRG banks, 32KB of CHR RAM
+// and optionally a self-flashable PRG chip, which then holds the saves
+#[derive(Clone)]
+pub struct Unrom512 {
+	pgr_rom: Vec<u8>,
+	chr_rom: Vec<u8>,
+	chr_ram: bool,
+	// Bit 7 selects the nametable page instead of hardwired mirroring
+	one_screen: bool,
+	flashable: bool,
+
+	bus_conflicts: bool,
+
+	// MCCP PPPP
+	register: u8,
+	flash_state: FlashState
+}
+
+impl Unrom512 {
+	pub fn new(pgr_rom: Vec<u8>, mut chr_rom: Vec<u8>, chr_ram: bool, one_screen: bool, flashable: bool) -> Unrom512 {
+		if chr_ram {
+			chr_rom.resize(CHR_RAM_SIZE, 0);
+		}
+
+		Unrom512 {
+			pgr_rom,
+			chr_rom,
+			chr_ram,
+			one_screen,
+			flashable,
+			// The flash chip is isolated from the register, discrete boards are not
+			bus_conflicts: !flashable,
+			register: 0,
+			flash_state: FlashState::Idle
+		}
+	}
+
+	pub fn set_bus_conflicts(&mut self, bus_conflicts: bool) {
+		self.bus_conflicts = bus_conflicts;
+	}
+
+	fn prg_bank_count(&self) -> usize {
+		(self.pgr_rom.len() / PRG_BANK_SIZE).max(1)
+	}
+
+	fn prg_bank(&self) -> usize {
+		usize::from(self.register & 0x1F)
+	}
+
+	fn chr_bank(&self) -> usize {
+		usize::from((self.register >> 5) & 0x03)
+	}
+
+	// Writes to $8000-$BFFF reach the flash chip through the selected bank
+	fn write_flash(&mut self, adress: u16, value: u8) {
+		let offset = (self.prg_bank() * PRG_BANK_SIZE + usize::from(adress & 0x3FFF)) % self.pgr_rom.len();
+		let command = offset & 0x7FFF;
+
+		self.flash_state = match (self.flash_state, command, value) {
+			(FlashState::Program, _, _) => {
+				// Programming can only clear bits
+				self.pgr_rom[offset] &= value;
+				FlashState::Idle
+			},
+			(FlashState::Idle, 0x5555, 0xAA) => FlashState::Unlock1,
+			(FlashState::Unlock1, 0x2AAA, 0x55) => FlashState::Unlock2,
+			(FlashState::Unlock2, 0x5555, 0xA0) => FlashState::Program,
+			(FlashState::Unlock2, 0x5555, 0x80) => FlashState::Erase,
+			(FlashState::Erase, 0x5555, 0xAA) => FlashState::EraseUnlock1,
+			(FlashState::EraseUnlock1, 0x2AAA, 0x55) => FlashState::EraseUnlock2,
+			(FlashState::EraseUnlock2, _, 0x30) => {
+				let sector = offset - offset % SECTOR_SIZE;
+				self.pgr_rom[sector..sector + SECTOR_SIZE].fill(0xFF);
+				FlashState::Idle
+			},
+			(FlashState::EraseUnlock2, 0x5555, 0x10) => {
+				self.pgr_rom.fill(0xFF);
+				FlashState::Idle
+			},
+			_ => FlashState::Idle
+		};
+	}
+}
+
+impl Mapper for Unrom512 {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x8000..=0xBFFF => banked_read(&self.pgr_rom, self.prg_bank(), PRG_BANK_SIZE, adress),
+			0xC000..=0xFFFF => banked_read(&self.pgr_rom, self.prg_bank_count() - 1, PRG_BANK_SIZE, adress),
+			_ => 0
+		}
+	}
+
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x8000..=0xBFFF => banked_offset(self.pgr_rom.len(), self.prg_bank(), PRG_BANK_SIZE, adress),
+			0xC000..=0xFFFF => banked_offset(self.pgr_rom.len(), self.prg_bank_count() - 1, PRG_BANK_SIZE, adress),
+			_ => None
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		match adress {
+			0x8000..=0xBFFF if self.flashable => self.write_flash(adress, value),
+			0x8000..=0xFFFF => self.register = value,
+			_ => {}
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		banked_read(&self.chr_rom, self.chr_bank(), CHR_BANK_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		if self.chr_ram {
+			let bank = self.chr_bank();
+			banked_write(&mut self.chr_rom, bank, CHR_BANK_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_u8(self.register);
+		// The flash content is cartridge state too, a command in progress is dropped
+		if self.flashable {
+			state.write_bytes(&self.pgr_rom);
+		}
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		self.register = state.read_u8()?;
+		self.flash_state = FlashState::Idle;
+		if self.flashable {
+			state.read_into(&mut self.pgr_rom)?;
+		}
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		match (self.one_screen, self.register & 0x80 != 0) {
+			(false, _) => None,
+			(true, false) => Some(Mirroring::SingleScreenLower),
+			(true, true) => Some(Mirroring::SingleScreenUpper)
+		}
+	}
+
+	fn bus_conflicts(&self) -> bool {
+		self.bus_conflicts
+	}
+
+	fn battery_data(&self) -> Option<&[u8]> {
+		if self.flashable { Some(&self.pgr_rom) } else { None }
+	}
+
+	fn load_battery_data(&mut self, data: &[u8]) {
+		if self.flashable {
+			let len = self.pgr_rom.len().min(data.len());
+			self.pgr_rom[..len].copy_from_slice(&data[..len]);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Every byte holds its bank number
+	fn numbered(bank_size: usize, count: usize) -> Vec<u8> {
+		(0..count).flat_map(|bank| vec![bank as u8; bank_size]).collect()
+	}
+
+	#[test]
+	fn banking() {
+		let mut unrom = Unrom512::new(numbered(PRG_BANK_SIZE, 32), vec![0; 8192], true, true, false);
+		unrom.cpu_write(0x8000, 0xC5); // Upper page, CHR bank 2, PRG bank 5
+
+		assert_eq!(unrom.cpu_read(0x8000), 5);
+		assert_eq!(unrom.cpu_read(0xC000), 31);
+		assert_eq!(unrom.mirroring(), Some(Mirroring::SingleScreenUpper));
+
+		unrom.ppu_write(0x0010, 0x42);
+		unrom.cpu_write(0x8000, 0);
+		assert_eq!(unrom.ppu_read(0x0010), 0x00);
+		unrom.cpu_write(0x8000, 0x40);
+		assert_eq!(unrom.ppu_read(0x0010), 0x42);
+	}
+
+	#[test]
+	fn flash_program_and_erase() {
+		let mut unrom = Unrom512::new(vec![0xFF; PRG_BANK_SIZE * 32], vec![0; 8192], true, false, true);
+		let unlock = |unrom: &mut Unrom512| {
+			// $5555 is bank 1 $9555, $2AAA is bank 0 $AAAA
+			unrom.cpu_write(0xC000, 1);
+			unrom.cpu_write(0x9555, 0xAA);
+			unrom.cpu_write(0xC000, 0);
+			unrom.cpu_write(0xAAAA, 0x55);
+			unrom.cpu_write(0xC000, 1);
+		};
+
+		unlock(&mut unrom);
+		unrom.cpu_write(0x9555, 0xA0);
+		unrom.cpu_write(0xC000, 3);
+		unrom.cpu_write(0x8010, 0x42);
+		assert_eq!(unrom.cpu_read(0x8010), 0x42);
+		assert_eq!(unrom.battery_data().unwrap()[3 * PRG_BANK_SIZE + 0x10], 0x42);
+
+		// Writes outside a command sequence are ignored
+		unrom.cpu_write(0x8011, 0x00);
+		assert_eq!(unrom.cpu_read(0x8011), 0xFF);
+
+		unlock(&mut unrom);
+		unrom.cpu_write(0x9555, 0x80);
+		unlock(&mut unrom);
+		unrom.cpu_write(0xC000, 3);
+		unrom.cpu_write(0x8000, 0x30);
+		assert_eq!(unrom.cpu_read(0x8010), 0xFF);
+	}
+}
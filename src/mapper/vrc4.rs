@@ -0,0 +1,296 @@
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::rom::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+
+// CPU address lines wired to the register select pins, each iNES number
+// covers boards with different wirings so both candidates are combined
+#[derive(Clone, Copy)]
+pub enum Wiring {
+	// Mapper 21: VRC4a (A1, A2) and VRC4c (A6, A7)
+	Vrc4ac,
+	// Mapper 22: VRC2a (A1, A0), CHR banks in 2KB units
+	Vrc2a,
+	// Mapper 23: VRC2b/VRC4f (A0, A1) and VRC4e (A2, A3)
+	Vrc4ef,
+	// Mapper 25: VRC2c/VRC4b (A1, A0) and VRC4d (A3, A2)
+	Vrc4bd
+}
+
+impl Wiring {
+	// Register index 0-3 within a $1000 block
+	fn register(self, adress: u16) -> u16 {
+		let line = |bits: &[u16]| bits.iter().fold(0, |line, bit| line | ((adress >> bit) & 0x01));
+
+		let (a0, a1) = match self {
+			Wiring::Vrc4ac => (line(&[1, 6]), line(&[2, 7])),
+			Wiring::Vrc2a => (line(&[1]), line(&[0])),
+			Wiring::Vrc4ef => (line(&[0, 2]), line(&[1, 3])),
+			Wiring::Vrc4bd => (line(&[1, 3]), line(&[0, 2]))
+		};
+
+		(a1 << 1) | a0
+	}
+}
+
+// Konami VRC2 and VRC4, VRC2 boards behave as a VRC4 without the IRQ
+#[derive(Clone)]
+pub struct Vrc4 {
+	wiring: Wiring,
+	pgr_rom: Vec<u8>,
+	chr_rom: Vec<u8>,
+	chr_ram: bool,
+
+	prg_banks: [u8; 2],
+	// Swaps the $8000 bank with the fixed second to last bank
+	prg_swap: bool,
+	chr_banks: [u16; 8],
+	mirroring: Option<Mirroring>,
+
+	irq: VrcIrq
+}
+
+impl Vrc4 {
+	pub fn new(wiring: Wiring, pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Vrc4 {
+		Vrc4 {
+			wiring,
+			pgr_rom,
+			chr_rom,
+			chr_ram,
+			prg_banks: [0; 2],
+			prg_swap: false,
+			chr_banks: [0; 8],
+			mirroring: None,
+			irq: VrcIrq::new()
+		}
+	}
+
+	fn prg_bank_count(&self) -> usize {
+		(self.pgr_rom.len() / PRG_BANK_SIZE).max(1)
+	}
+
+	fn prg_bank(&self, adress: u16) -> usize {
+		let second_last = self.prg_bank_count() - 2.min(self.prg_bank_count());
+
+		match ((adress >> 13) & 0x03, self.prg_swap) {
+			(0, false) | (2, true) => usize::from(self.prg_banks[0]),
+			(0, true) | (2, false) => second_last,
+			(1, _) => usize::from(self.prg_banks[1]),
+			_ => self.prg_bank_count() - 1
+		}
+	}
+
+	fn chr_bank(&self, adress: u16) -> usize {
+		let bank = usize::from(self.chr_banks[usize::from(adress >> 10) & 0x07]);
+
+		match self.wiring {
+			// The low bit of the bank number is not connected
+			Wiring::Vrc2a => bank >> 1,
+			_ => bank
+		}
+	}
+
+	fn write_chr_nibble(&mut self, adress: u16, register: u16, value: u8) {
+		// $B000-$E003, two 1KB banks per $1000 block
+		let index = usize::from(((adress - 0xB000) >> 12) * 2 + (register >> 1));
+		let bank = self.chr_banks[index];
+
+		self.chr_banks[index] = if register & 0x01 == 0 {
+			(bank & 0x1F0) | u16::from(value & 0x0F)
+		} else {
+			(bank & 0x00F) | (u16::from(value & 0x1F) << 4)
+		};
+	}
+}
+
+impl Mapper for Vrc4 {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x8000..=0xFFFF => banked_read(&self.pgr_rom, self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => 0
+		}
+	}
+
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x8000..=0xFFFF => banked_offset(self.pgr_rom.len(), self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => None
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		let register = self.wiring.register(adress);
+
+		match (adress & 0xF000, register) {
+			(0x8000, _) => self.prg_banks[0] = value & 0x1F,
+			(0x9000, 0 | 1) => {
+				self.mirroring = Some(match value & 0x03 {
+					0 => Mirroring::Vertical,
+					1 => Mirroring::Horizontal,
+					2 => Mirroring::SingleScreenLower,
+					_ => Mirroring::SingleScreenUpper
+				});
+			},
+			(0x9000, _) => self.prg_swap = value & 0x02 != 0,
+			(0xA000, _) => self.prg_banks[1] = value & 0x1F,
+			(0xB000..=0xE000, _) => self.write_chr_nibble(adress, register, value),
+			(0xF000, 0) => self.irq.write_latch_low(value),
+			(0xF000, 1) => self.irq.write_latch_high(value),
+			(0xF000, 2) => self.irq.write_control(value),
+			(0xF000, _) => self.irq.acknowledge(),
+			_ => {}
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		banked_read(&self.chr_rom, self.chr_bank(adress), CHR_BANK_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		if self.chr_ram {
+			let bank = self.chr_bank(adress);
+			banked_write(&mut self.chr_rom, bank, CHR_BANK_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_bytes(&self.prg_banks);
+		state.write_bool(self.prg_swap);
+		for bank in self.chr_banks {
+			state.write_u16(bank);
+		}
+		state.write_mirroring(self.mirroring);
+		self.irq.save_state(&mut state);
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		state.read_into(&mut self.prg_banks)?;
+		self.prg_swap = state.read_bool()?;
+		for bank in self.chr_banks.iter_mut() {
+			*bank = state.read_u16()?;
+		}
+		self.mirroring = state.read_mirroring()?;
+		self.irq.load_state(&mut state)?;
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		self.mirroring
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq.pending()
+	}
+
+	fn clock_cpu(&mut self) {
+		self.irq.clock();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Every byte holds its bank number
+	fn numbered(bank_size: usize, count: usize) -> Vec<u8> {
+		(0..count).flat_map(|bank| vec![bank as u8; bank_size]).collect()
+	}
+
+	#[test]
+	fn register_wiring() {
+		// Register 2 of each layout, both wirings of a mapper number
+		assert_eq!(Wiring::Vrc4ac.register(0x9004), 2);
+		assert_eq!(Wiring::Vrc4ac.register(0x9080), 2);
+		assert_eq!(Wiring::Vrc2a.register(0x9001), 2);
+		assert_eq!(Wiring::Vrc4ef.register(0x9002), 2);
+		assert_eq!(Wiring::Vrc4ef.register(0x9008), 2);
+		assert_eq!(Wiring::Vrc4bd.register(0x9001), 2);
+		assert_eq!(Wiring::Vrc4bd.register(0x9004), 2);
+	}
+
+	#[test]
+	fn prg_banking() {
+		let mut vrc4 = Vrc4::new(Wiring::Vrc4ef, numbered(PRG_BANK_SIZE, 16), vec![0; 8192], true);
+		vrc4.cpu_write(0x8000, 3);
+		vrc4.cpu_write(0xA000, 5);
+
+		assert_eq!(vrc4.cpu_read(0x8000), 3);
+		assert_eq!(vrc4.cpu_read(0xA000), 5);
+		assert_eq!(vrc4.cpu_read(0xC000), 14);
+		assert_eq!(vrc4.cpu_read(0xE000), 15);
+
+		vrc4.cpu_write(0x9002, 0x02);
+		assert_eq!(vrc4.cpu_read(0x8000), 14);
+		assert_eq!(vrc4.cpu_read(0xC000), 3);
+	}
+
+	#[test]
+	fn chr_banking() {
+		let mut vrc4 = Vrc4::new(Wiring::Vrc4ef, numbered(PRG_BANK_SIZE, 4), numbered(CHR_BANK_SIZE, 256), false);
+		// Bank 7 low and high nibbles
+		vrc4.cpu_write(0xE002, 0x0A);
+		vrc4.cpu_write(0xE003, 0x03);
+		assert_eq!(vrc4.ppu_read(0x1C00), 0x3A);
+
+		let mut vrc2 = Vrc4::new(Wiring::Vrc2a, numbered(PRG_BANK_SIZE, 4), numbered(CHR_BANK_SIZE, 256), false);
+		vrc2.cpu_write(0xB000, 0x07);
+		assert_eq!(vrc2.ppu_read(0x0000), 3);
+	}
+
+	#[test]
+	fn mirroring() {
+		let mut vrc4 = Vrc4::new(Wiring::Vrc4ac, numbered(PRG_BANK_SIZE, 4), vec![0; 8192], true);
+		assert_eq!(vrc4.mirroring(), None);
+		vrc4.cpu_write(0x9000, 3);
+		assert_eq!(vrc4.mirroring(), Some(Mirroring::SingleScreenUpper));
+	}
+
+	#[test]
+	fn cycle_irq() {
+		let mut vrc4 = Vrc4::new(Wiring::Vrc4ac, numbered(PRG_BANK_SIZE, 4), vec![0; 8192], true);
+		vrc4.cpu_write(0xF000, 0x0F);
+		vrc4.cpu_write(0xF002, 0x0F);
+		vrc4.cpu_write(0xF004, 0x06);
+
+		vrc4.clock_cpu();
+		assert!(vrc4.irq_pending());
+		vrc4.cpu_write(0xF006, 0);
+		assert!(!vrc4.irq_pending());
+	}
+
+	#[test]
+	fn state_round_trip() {
+		let mut vrc4 = Vrc4::new(Wiring::Vrc4ef, numbered(PRG_BANK_SIZE, 16), vec![0; 8192], true);
+		vrc4.cpu_write(0x8000, 3);
+		vrc4.cpu_write(0x9000, 1);
+		vrc4.cpu_write(0xF000, 0x0F);
+		vrc4.cpu_write(0xF001, 0x0F);
+		vrc4.cpu_write(0xF002, 0x06);
+		vrc4.ppu_write(0x0010, 0x42);
+
+		let mut restored = Vrc4::new(Wiring::Vrc4ef, numbered(PRG_BANK_SIZE, 16), vec![0; 8192], true);
+		restored.load_state(&vrc4.save_state()).unwrap();
+		assert_eq!(restored.cpu_read(0x8000), 3);
+		assert_eq!(restored.mirroring(), Some(Mirroring::Horizontal));
+		assert_eq!(restored.ppu_read(0x0010), 0x42);
+
+		restored.clock_cpu();
+		assert!(restored.irq_pending());
+	}
+}
@@ -0,0 +1,306 @@
+use crate::mapper::{Mapper, banked_offset, banked_read, banked_write};
+use crate::mapper::vrc_irq::VrcIrq;
+use crate::apu::{expansion::ExpansionAudio, vrc6::Vrc6Audio};
+use crate::rom::Mirroring;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const PRG_BANK_SIZE: usize = 8192;
+const CHR_BANK_SIZE: usize = 1024;
+
+// Konami VRC6, mapper 24 (VRC6a) and mapper 26 (VRC6b, A0 and A1 swapped).
+// Nametables sourced from CHR ROM ($B003 bit 4) are not emulated.
+#[derive(Clone)]
+pub struct Vrc6 {
+	pgr_rom: Vec<u8>,
+	chr_rom: Vec<u8>,
+	chr_ram: bool,
+	swap_lines: bool,
+
+	// 16KB bank at $8000, 8KB bank at $C000
+	prg_16k: u8,
+	prg_8k: u8,
+	chr_banks: [u8; 8],
+	// $B003
+	ppu_banking: u8,
+	mirroring: Option<Mirroring>,
+	prg_ram_enabled: bool,
+
+	irq: VrcIrq
+}
+
+impl Vrc6 {
+	pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Vrc6 {
+		Vrc6::with_lines(false, pgr_rom, chr_rom, chr_ram)
+	}
+
+	pub fn vrc6b(pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Vrc6 {
+		Vrc6::with_lines(true, pgr_rom, chr_rom, chr_ram)
+	}
+
+	fn with_lines(swap_lines: bool, pgr_rom: Vec<u8>, chr_rom: Vec<u8>, chr_ram: bool) -> Vrc6 {
+		Vrc6 {
+			pgr_rom,
+			chr_rom,
+			chr_ram,
+			swap_lines,
+			prg_16k: 0,
+			prg_8k: 0,
+			chr_banks: [0; 8],
+			ppu_banking: 0,
+			mirroring: None,
+			prg_ram_enabled: true,
+			irq: VrcIrq::new()
+		}
+	}
+
+	fn prg_bank_count(&self) -> usize {
+		(self.pgr_rom.len() / PRG_BANK_SIZE).max(1)
+	}
+
+	fn prg_bank(&self, adress: u16) -> usize {
+		match adress {
+			0x8000..=0xBFFF => usize::from(self.prg_16k) * 2 + usize::from((adress >> 13) & 0x01),
+			0xC000..=0xDFFF => usize::from(self.prg_8k),
+			_ => self.prg_bank_count() - 1
+		}
+	}
+
+	fn chr_bank(&self, adress: u16) -> usize {
+		let slot = usize::from(adress >> 10) & 0x07;
+		// 2KB banks take A10 from the PPU
+		let two_kb = |register: usize| usize::from(self.chr_banks[register] & 0xFE) | (slot & 0x01);
+
+		match (self.ppu_banking & 0x03, slot) {
+			(0, _) => usize::from(self.chr_banks[slot]),
+			(1, _) => two_kb(slot / 2),
+			(_, 0..=3) => usize::from(self.chr_banks[slot]),
+			_ => two_kb(4 + (slot - 4) / 2)
+		}
+	}
+}
+
+// Register at $x000-$x003 as seen by the chip
+fn normalize(adress: u16, swap_lines: bool) -> u16 {
+	let adress = adress & 0xF003;
+	if swap_lines {
+		(adress & 0xF000) | ((adress & 0x01) << 1) | ((adress & 0x02) >> 1)
+	} else {
+		adress
+	}
+}
+
+impl Mapper for Vrc6 {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x8000..=0xFFFF => banked_read(&self.pgr_rom, self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => 0
+		}
+	}
+
+	fn prg_rom_offset(&self, adress: u16) -> Option<usize> {
+		match adress {
+			0x8000..=0xFFFF => banked_offset(self.pgr_rom.len(), self.prg_bank(adress), PRG_BANK_SIZE, adress),
+			_ => None
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		if adress < 0x8000 {
+			return;
+		}
+
+		// $9000-$B002 belong to the audio, written by the APU
+		match normalize(adress, self.swap_lines) {
+			0x8000..=0x8003 => self.prg_16k = value & 0x0F,
+			0xB003 => {
+				self.ppu_banking = value;
+				self.mirroring = Some(match (value >> 2) & 0x03 {
+					0 => Mirroring::Vertical,
+					1 => Mirroring::Horizontal,
+					2 => Mirroring::SingleScreenLower,
+					_ => Mirroring::SingleScreenUpper
+				});
+				self.prg_ram_enabled = value & 0x80 != 0;
+			},
+			0xC000..=0xC003 => self.prg_8k = value & 0x1F,
+			register @ 0xD000..=0xD003 => self.chr_banks[usize::from(register & 0x03)] = value,
+			register @ 0xE000..=0xE003 => self.chr_banks[4 + usize::from(register & 0x03)] = value,
+			0xF000 => self.irq.write_latch(value),
+			0xF001 => self.irq.write_control(value),
+			0xF002 => self.irq.acknowledge(),
+			_ => {}
+		}
+	}
+
+	fn ppu_read(&self, adress: u16) -> u8 {
+		banked_read(&self.chr_rom, self.chr_bank(adress), CHR_BANK_SIZE, adress)
+	}
+
+	fn ppu_write(&mut self, adress: u16, value: u8) {
+		if self.chr_ram {
+			let bank = self.chr_bank(adress);
+			banked_write(&mut self.chr_rom, bank, CHR_BANK_SIZE, adress, value);
+		}
+	}
+
+	fn has_chr_ram(&self) -> bool {
+		self.chr_ram
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		state.write_u8(self.prg_16k);
+		state.write_u8(self.prg_8k);
+		state.write_bytes(&self.chr_banks);
+		state.write_u8(self.ppu_banking);
+		state.write_mirroring(self.mirroring);
+		state.write_bool(self.prg_ram_enabled);
+		self.irq.save_state(&mut state);
+		if self.chr_ram {
+			state.write_bytes(&self.chr_rom);
+		}
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let mut state = StateReader::new(data);
+		self.prg_16k = state.read_u8()?;
+		self.prg_8k = state.read_u8()?;
+		state.read_into(&mut self.chr_banks)?;
+		self.ppu_banking = state.read_u8()?;
+		self.mirroring = state.read_mirroring()?;
+		self.prg_ram_enabled = state.read_bool()?;
+		self.irq.load_state(&mut state)?;
+		if self.chr_ram {
+			state.read_into(&mut self.chr_rom)?;
+		}
+		Ok(())
+	}
+
+	fn mirroring(&self) -> Option<Mirroring> {
+		self.mirroring
+	}
+
+	fn prg_ram_enabled(&self) -> bool {
+		self.prg_ram_enabled
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq.pending()
+	}
+
+	fn clock_cpu(&mut self) {
+		self.irq.clock();
+	}
+
+	fn expansion_audio(&self) -> Option<Box<dyn ExpansionAudio>> {
+		Some(Box::new(Vrc6Pins {
+			audio: Vrc6Audio::new(),
+			swap_lines: self.swap_lines
+		}))
+	}
+}
+
+// Audio registers seen through the board wiring, the APU forwards raw CPU addresses
+#[derive(Clone)]
+struct Vrc6Pins {
+	audio: Vrc6Audio,
+	swap_lines: bool
+}
+
+impl ExpansionAudio for Vrc6Pins {
+	fn clock(&mut self, cycles: u32) {
+		self.audio.clock(cycles);
+	}
+
+	fn output(&self) -> f32 {
+		self.audio.output()
+	}
+
+	fn write(&mut self, adress: u16, value: u8) {
+		if adress >= 0x8000 {
+			self.audio.write(normalize(adress, self.swap_lines), value);
+		}
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = StateWriter::new();
+		self.audio.save_state(&mut state);
+		state.finish()
+	}
+
+	fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		self.audio.load_state(&mut StateReader::new(data))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// Every byte holds its bank number
+	fn numbered(bank_size: usize, count: usize) -> Vec<u8> {
+		(0..count).flat_map(|bank| vec![bank as u8; bank_size]).collect()
+	}
+
+	#[test]
+	fn prg_banking() {
+		let mut vrc6 = Vrc6::new(numbered(PRG_BANK_SIZE, 16), vec![0; 8192], true);
+		vrc6.cpu_write(0x8000, 2);
+		vrc6.cpu_write(0xC000, 9);
+
+		assert_eq!(vrc6.cpu_read(0x8000), 4);
+		assert_eq!(vrc6.cpu_read(0xA000), 5);
+		assert_eq!(vrc6.cpu_read(0xC000), 9);
+		assert_eq!(vrc6.cpu_read(0xE000), 15);
+	}
+
+	#[test]
+	fn chr_banking() {
+		let mut vrc6 = Vrc6::new(numbered(PRG_BANK_SIZE, 4), numbered(CHR_BANK_SIZE, 32), false);
+		vrc6.cpu_write(0xD001, 7);
+		vrc6.cpu_write(0xE003, 12);
+		assert_eq!(vrc6.ppu_read(0x0400), 7);
+		assert_eq!(vrc6.ppu_read(0x1C00), 12);
+
+		// 2KB banks, R1 covers $0800-$0FFF
+		vrc6.cpu_write(0xB003, 0x01);
+		assert_eq!(vrc6.ppu_read(0x0800), 6);
+		assert_eq!(vrc6.ppu_read(0x0C00), 7);
+	}
+
+	#[test]
+	fn swapped_lines() {
+		let mut vrc6 = Vrc6::vrc6b(numbered(PRG_BANK_SIZE, 4), numbered(CHR_BANK_SIZE, 32), false);
+		// $D002 on the chip
+		vrc6.cpu_write(0xD001, 5);
+		assert_eq!(vrc6.ppu_read(0x0800), 5);
+
+		vrc6.cpu_write(0xB003, 0x84);
+		assert_eq!(vrc6.mirroring(), Some(Mirroring::Horizontal));
+		assert!(vrc6.prg_ram_enabled());
+	}
+
+	#[test]
+	fn irq() {
+		let mut vrc6 = Vrc6::new(numbered(PRG_BANK_SIZE, 4), vec![0; 8192], true);
+		vrc6.cpu_write(0xF000, 0xFF);
+		vrc6.cpu_write(0xF001, 0x06);
+
+		vrc6.clock_cpu();
+		assert!(vrc6.irq_pending());
+		vrc6.cpu_write(0xF002, 0);
+		assert!(!vrc6.irq_pending());
+	}
+
+	#[test]
+	fn expansion_audio() {
+		let vrc6 = Vrc6::vrc6b(numbered(PRG_BANK_SIZE, 4), vec![0; 8192], true);
+		let mut audio = vrc6.expansion_audio().unwrap();
+
+		// Pulse 1 at constant volume 15, enabled through $9001 which is $9002 on the chip
+		audio.write(0x9000, 0x8F);
+		audio.write(0x9001, 0x80);
+		assert!(audio.output() > 0.0);
+	}
+}
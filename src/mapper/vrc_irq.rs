@@ -0,0 +1,149 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+// IRQ counter shared by the Konami VRC4, VRC6 and VRC7
+#[derive(Clone)]
+pub(crate) struct VrcIrq {
+	latch: u8,
+	counter: u8,
+	prescaler: i16,
+	enabled: bool,
+	enable_after_ack: bool,
+	// Counts CPU cycles instead of scanlines
+	cycle_mode: bool,
+	irq: bool
+}
+
+const PRESCALER_PERIOD: i16 = 341;
+
+impl VrcIrq {
+	pub(crate) fn new() -> VrcIrq {
+		VrcIrq {
+			latch: 0,
+			counter: 0,
+			prescaler: PRESCALER_PERIOD,
+			enabled: false,
+			enable_after_ack: false,
+			cycle_mode: false,
+			irq: false
+		}
+	}
+
+	pub(crate) fn write_latch(&mut self, value: u8) {
+		self.latch = value;
+	}
+
+	pub(crate) fn write_latch_low(&mut self, value: u8) {
+		self.latch = (self.latch & 0xF0) | (value & 0x0F);
+	}
+
+	pub(crate) fn write_latch_high(&mut self, value: u8) {
+		self.latch = (self.latch & 0x0F) | (value << 4);
+	}
+
+	pub(crate) fn write_control(&mut self, value: u8) {
+		self.enable_after_ack = value & 0x01 != 0;
+		self.enabled = value & 0x02 != 0;
+		self.cycle_mode = value & 0x04 != 0;
+		self.irq = false;
+
+		if self.enabled {
+			self.counter = self.latch;
+			self.prescaler = PRESCALER_PERIOD;
+		}
+	}
+
+	pub(crate) fn acknowledge(&mut self) {
+		self.irq = false;
+		self.enabled = self.enable_after_ack;
+	}
+
+	pub(crate) fn pending(&self) -> bool {
+		self.irq
+	}
+
+	// One CPU cycle, scanline mode divides by 113.667 (341 dots / 3)
+	pub(crate) fn clock(&mut self) {
+		if !self.enabled {
+			return;
+		}
+
+		if self.cycle_mode {
+			self.clock_counter();
+		} else {
+			self.prescaler -= 3;
+			if self.prescaler <= 0 {
+				self.prescaler += PRESCALER_PERIOD;
+				self.clock_counter();
+			}
+		}
+	}
+
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_u8(self.latch);
+		state.write_u8(self.counter);
+		state.write_u16(self.prescaler as u16);
+		state.write_bool(self.enabled);
+		state.write_bool(self.enable_after_ack);
+		state.write_bool(self.cycle_mode);
+		state.write_bool(self.irq);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.latch = state.read_u8()?;
+		self.counter = state.read_u8()?;
+		self.prescaler = state.read_u16()? as i16;
+		self.enabled = state.read_bool()?;
+		self.enable_after_ack = state.read_bool()?;
+		self.cycle_mode = state.read_bool()?;
+		self.irq = state.read_bool()?;
+		Ok(())
+	}
+
+	fn clock_counter(&mut self) {
+		if self.counter == 0xFF {
+			self.counter = self.latch;
+			self.irq = true;
+		} else {
+			self.counter += 1;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn cycle_mode() {
+		let mut irq = VrcIrq::new();
+		irq.write_latch_low(0x0E);
+		irq.write_latch_high(0x0F);
+		irq.write_control(0x06);
+
+		irq.clock();
+		assert!(!irq.pending());
+		irq.clock();
+		assert!(irq.pending());
+
+		irq.acknowledge();
+		assert!(!irq.pending());
+		irq.clock();
+		assert!(!irq.pending(), "Counter stops when A was clear");
+	}
+
+	#[test]
+	fn scanline_mode() {
+		let mut irq = VrcIrq::new();
+		irq.write_latch_low(0x0F);
+		irq.write_latch_high(0x0F);
+		irq.write_control(0x02);
+
+		// 341 / 3 rounded up
+		for _ in 0..113 {
+			irq.clock();
+		}
+		assert!(!irq.pending());
+		irq.clock();
+		assert!(irq.pending());
+	}
+}
@@ -1,28 +1,949 @@
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
 use crate::cpu::Cpu;
-use crate::memory::Memory;
-use crate::cartridge::Cartridge;
-use crate::mapper::Mapper;
-use crate::ppu::Ppu;
+use crate::bus::Bus;
+use crate::rom::Rom;
+use crate::ppu::frame::Frame;
+use crate::joypad::Button;
+use crate::input::{FrameInput, Player, Port, Recorder};
+use crate::mapper::fds::DiskDrive;
+use crate::cheats::{Cheat, CheatError};
+use crate::region::Region;
+use crate::rewind::Rewind;
+use crate::state::{StateError, StateFile, StateReader, StateWriter};
+
+// What an agent sees after each step
+pub struct Observation<'a> {
+	pub frame: &'a Frame,
+	// CPU RAM at $0000-$07FF, where games keep their score, lives and positions
+	pub ram: &'a [u8; 2048],
+	// Frames run since power on
+	pub frame_count: u64
+}
+
+// Content of a save state as serde types, for other formats than StateFile
+#[cfg(feature = "serde")]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct NesState {
+	pub rom_crc32: u32,
+	pub cpu: Cpu,
+	pub bus: crate::bus::BusState
+}
 
+// Console with a cartridge inserted, the entry point for frontends:
+// feed the buttons, run a frame, then draw the frame and queue the audio samples
 pub struct Nes {
 	cpu: Cpu,
-	ppu: Ppu,
-	memory:  Memory,
+	bus: Bus,
+	// Battery save file, written back on drop
+	sav_path: Option<PathBuf>,
+	// The CPU goes through its reset sequence before the first frame
+	powered_on: bool,
+	frame: Frame,
+	recorder: Option<Recorder>,
+	// Inputs of the movie being played replace the frontend ones
+	player: Option<Player>,
+	rewind: Option<Rewind>,
+	// Frames emulated ahead of the shown one, with the current inputs held
+	run_ahead: u32,
+	paused: bool,
+	speed: f64,
+	// Fraction of a frame left from the previous run_frame calls
+	speed_credit: f64
 }
 
 impl Nes {
-	pub fn new(cartridge: &Cartridge) -> Nes {
-		let mapper = <dyn Mapper>::from_id(cartridge.mapper, cartridge.pgr_rom.clone(), cartridge.chr_rom.clone());
-
+	pub fn new(rom: Rom) -> Nes {
 		Nes {
 			cpu: Cpu::new(),
-			ppu: Ppu::new(cartridge.mirroring),
-			memory: Memory::new(mapper),
+			bus: Bus::new(rom),
+			sav_path: None,
+			powered_on: false,
+			frame: Frame::default(),
+			recorder: None,
+			player: None,
+			rewind: None,
+			run_ahead: 0,
+			paused: false,
+			speed: 1.0,
+			speed_credit: 0.0
+		}
+	}
+
+	// Swap the cartridge and power cycle, the battery RAM of the previous one is saved first
+	pub fn insert(&mut self, rom: Rom) {
+		let _ = self.save_sram();
+		self.sav_path = None;
+
+		self.cpu = Cpu::new();
+		self.bus = Bus::new(rom);
+		self.powered_on = false;
+		self.frame = Frame::default();
+
+		// Movies and snapshots of the previous game do not apply to this one
+		self.recorder = None;
+		self.player = None;
+		if let Some(rewind) = &mut self.rewind {
+			rewind.clear();
+		}
+	}
+
+	pub fn reset(&mut self) {
+		self.cpu.reset(&mut self.bus);
+		self.powered_on = true;
+	}
+
+	// Reset sequence of the first frame, done ahead for snapshots taken before it
+	pub fn power_on(&mut self) {
+		if !self.powered_on {
+			self.reset();
 		}
 	}
 
+	// Load a ROM with Rom::from_path, battery backed games also load and save `<rom>.sav`.
+	// Invalid files are reported as InvalidData, wrapping a RomError
+	pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<Nes> {
+		let mut nes = Nes::new(Rom::from_path(path.as_ref())?);
+
+		if nes.bus.has_battery() {
+			let sav_path = path.as_ref().with_extension("sav");
+			match fs::read(&sav_path) {
+				Ok(sram) => nes.load_sram(&sram),
+				Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+				Err(error) => return Err(error)
+			}
+			nes.sav_path = Some(sav_path);
+		}
+
+		Ok(nes)
+	}
+
+	// Run from reset until a BRK, for test ROMs
 	pub fn run(&mut self) {
-		self.cpu.reset(&mut self.memory);
-		self.cpu.run(&mut self.memory);
+		self.reset();
+		self.cpu.run(&mut self.bus);
+	}
+
+	// Run until the PPU completes the next frame. Paused, the last frame is returned again,
+	// and at other speeds than 1 as many frames are run as the speed adds up to
+	pub fn run_frame(&mut self) -> &Frame {
+		if self.paused {
+			return &self.frame;
+		}
+
+		self.speed_credit += self.speed;
+		while self.speed_credit >= 1.0 {
+			self.speed_credit -= 1.0;
+			self.advance();
+		}
+		&self.frame
+	}
+
+	// Run exactly one frame, also while paused
+	pub fn frame_advance(&mut self) -> &Frame {
+		self.advance();
+		&self.frame
+	}
+
+	pub fn pause(&mut self) {
+		self.paused = true;
+		// Samples of the frames before the pause would play as a burst on resume
+		self.bus.apu_mut().take_samples();
+	}
+
+	pub fn resume(&mut self) {
+		self.paused = false;
+	}
+
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
+
+	// Frames emulated per run_frame: 0.5 for slow motion, 2.0 for fast-forward.
+	// The sample rate is scaled so the audio stays continuous, at a lower or higher pitch
+	pub fn set_speed(&mut self, speed: f64) {
+		assert!(speed > 0.0, "speed {} is not positive", speed);
+		let apu = self.bus.apu_mut();
+		let sample_rate = f64::from(apu.sample_rate()) * self.speed;
+		apu.set_sample_rate((sample_rate / speed).round() as u32);
+		self.speed = speed;
+		self.speed_credit = 0.0;
+	}
+
+	pub fn speed(&self) -> f64 {
+		self.speed
+	}
+
+	fn advance(&mut self) {
+		if !self.powered_on {
+			self.reset();
+		}
+
+		if let Some(player) = &mut self.player {
+			if !player.apply(&mut self.bus) {
+				self.player = None;
+			}
+		}
+		if let Some(recorder) = &mut self.recorder {
+			recorder.record(&self.bus);
+		}
+		self.emulate_frame();
+		self.frame = if self.run_ahead > 0 { self.run_ahead_frame() } else { self.bus.ppu().frame_rgb() };
+
+		if self.rewind.as_mut().is_some_and(|rewind| rewind.tick()) {
+			let state = self.save_state();
+			let frame = self.bus.ppu().frame_count();
+			self.rewind.as_mut().unwrap().push(frame, state);
+		}
+	}
+
+	fn emulate_frame(&mut self) {
+		self.bus.apply_frozen();
+
+		let frame = self.bus.ppu().frame_count();
+		while self.bus.ppu().frame_count() == frame {
+			self.cpu.step(&mut self.bus);
+		}
+
+		self.bus.input_device_mut(Port::One).end_frame();
+		self.bus.input_device_mut(Port::Two).end_frame();
+		self.bus.input_device_mut(Port::Expansion).end_frame();
+	}
+
+	// Picture `run_ahead` frames later, emulated on a copy that is thrown away with its audio
+	fn run_ahead_frame(&mut self) -> Frame {
+		let cpu = self.cpu.clone();
+		let bus = self.bus.clone();
+		let real = (mem::replace(&mut self.cpu, cpu), mem::replace(&mut self.bus, bus));
+
+		for _ in 0..self.run_ahead {
+			self.emulate_frame();
+		}
+		let frame = self.bus.ppu().frame_rgb();
+
+		(self.cpu, self.bus) = real;
+		frame
+	}
+
+	// Hide `frames` frames of the game's own input lag: the shown picture is the one
+	// the inputs would give that many frames later. 0 turns it off
+	pub fn set_run_ahead(&mut self, frames: u32) {
+		self.run_ahead = frames;
+	}
+
+	pub fn run_ahead(&self) -> u32 {
+		self.run_ahead
+	}
+
+	// Snapshot every `interval` frames for rewind, `capacity` snapshots at most
+	pub fn enable_rewind(&mut self, interval: u32, capacity: usize) {
+		self.rewind = Some(Rewind::new(interval, capacity));
+	}
+
+	pub fn disable_rewind(&mut self) {
+		self.rewind = None;
+	}
+
+	pub fn rewind_buffer(&self) -> Option<&Rewind> {
+		self.rewind.as_ref()
+	}
+
+	// Go back at least `frames` frames to the closest snapshot, or the oldest one,
+	// returns the frames actually rewound
+	pub fn rewind(&mut self, frames: u64) -> Result<u64, StateError> {
+		let now = self.bus.ppu().frame_count();
+		let Some(rewind) = &mut self.rewind else {
+			return Ok(0);
+		};
+		let Some((frame, state)) = rewind.seek(now.saturating_sub(frames)) else {
+			return Ok(0);
+		};
+		let state = state.to_vec();
+
+		self.load_state(&state)?;
+		Ok(now.saturating_sub(frame))
+	}
+
+	// One deterministic environment step: set both controllers, run a frame.
+	// The same inputs from the same state (see `clone`) always give the same observation
+	pub fn step(&mut self, input: FrameInput) -> Observation<'_> {
+		input.apply(&mut self.bus);
+		self.advance();
+
+		Observation {
+			frame: &self.frame,
+			ram: self.bus.cpu_ram(),
+			frame_count: self.bus.ppu().frame_count()
+		}
+	}
+
+	// Last frame returned by run_frame
+	pub fn frame(&self) -> &Frame {
+		&self.frame
+	}
+
+	// Snapshot of the CPU, console and cartridge, see StateFile for the layout
+	pub fn save_state(&self) -> Vec<u8> {
+		let mut cpu = StateWriter::new();
+		self.cpu.save_state(&mut cpu);
+		let mut console = StateWriter::new();
+		self.bus.save_state(&mut console);
+
+		StateFile::write(self.rom_crc32(), &[
+			(*b"CPU ", cpu.finish()),
+			(*b"BUS ", console.finish()),
+			(*b"CART", self.bus.save_cartridge_state())
+		])
+	}
+
+	// Restore a save_state of the same game, the console is left as it was on errors
+	pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+		let file = StateFile::parse(data)?;
+		let expected = self.rom_crc32();
+		if file.rom_crc32 != expected {
+			return Err(StateError::WrongRom { expected, got: file.rom_crc32 });
+		}
+		let cpu_state = file.required(*b"CPU ")?;
+		let bus_state = file.required(*b"BUS ")?;
+		let cartridge_state = file.required(*b"CART")?;
+
+		// Sections are read into copies, a corrupt one leaves the console untouched
+		let mut cpu = self.cpu.clone();
+		let mut bus = self.bus.clone();
+		cpu.load_state(&mut StateReader::new(cpu_state))?;
+		bus.load_state(&mut StateReader::new(bus_state))?;
+		bus.load_cartridge_state(cartridge_state)?;
+
+		self.cpu = cpu;
+		self.bus.restore_from(bus);
+		self.powered_on = true;
+		self.frame = self.bus.ppu().frame_rgb();
+		Ok(())
+	}
+
+	#[cfg(feature = "serde")]
+	pub fn to_state(&self) -> NesState {
+		NesState {
+			rom_crc32: self.rom_crc32(),
+			cpu: self.cpu.clone(),
+			bus: self.bus.to_state()
+		}
+	}
+
+	// Restore a to_state of the same game, the console is left as it was on errors
+	#[cfg(feature = "serde")]
+	pub fn apply_state(&mut self, state: NesState) -> Result<(), StateError> {
+		let expected = self.rom_crc32();
+		if state.rom_crc32 != expected {
+			return Err(StateError::WrongRom { expected, got: state.rom_crc32 });
+		}
+
+		self.bus.apply_state(state.bus)?;
+		self.cpu.restore(state.cpu);
+		self.powered_on = true;
+		self.frame = self.bus.ppu().frame_rgb();
+		Ok(())
+	}
+
+	fn rom_crc32(&self) -> u32 {
+		self.bus.rom_info().map_or(0, |info| info.crc32)
+	}
+
+	// Game Genie, Pro Action Rocky, AAAA:VV patch or AAAA=VV freeze, see Cheat::parse
+	pub fn add_cheat(&mut self, code: &str) -> Result<&Cheat, CheatError> {
+		self.bus.cheats_mut().add(code)
+	}
+
+	// False when the code was not active
+	pub fn remove_cheat(&mut self, code: &str) -> bool {
+		self.bus.cheats_mut().remove(code)
+	}
+
+	pub fn list_cheats(&self) -> &[Cheat] {
+		self.bus.cheats().list()
+	}
+
+	// What the PPU has drawn so far, including a frame still being rendered
+	pub fn screenshot(&self) -> Frame {
+		self.bus.ppu().frame_rgb()
+	}
+
+	// PNG with the png feature when the path ends in .png, PPM otherwise
+	pub fn save_screenshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		self.screenshot().save(path)
+	}
+
+	// Controller 1, ignored when another device is plugged in port 1
+	pub fn set_button(&mut self, button: Button, pressed: bool) {
+		if let Some(joypad) = self.bus.joypad1_mut() {
+			joypad.set_button(button, pressed);
+		}
+	}
+
+	// Controller 2, for two player games
+	pub fn set_button2(&mut self, button: Button, pressed: bool) {
+		if let Some(joypad) = self.bus.joypad2_mut() {
+			joypad.set_button(button, pressed);
+		}
+	}
+
+	// Log the controllers at the start of every frame from now on
+	pub fn start_recording(&mut self) {
+		self.recorder = Some(Recorder::new());
+	}
+
+	pub fn stop_recording(&mut self) -> Vec<FrameInput> {
+		self.recorder.take().map(Recorder::into_frames).unwrap_or_default()
+	}
+
+	// Feed `frames` to the controllers, one per run_frame, set_button has no effect until it ends
+	pub fn play(&mut self, frames: Vec<FrameInput>) {
+		self.player = Some(Player::new(frames));
+	}
+
+	pub fn is_playing(&self) -> bool {
+		self.player.is_some()
+	}
+
+	// Samples produced since the last call, at the APU sample rate
+	pub fn audio_samples(&mut self) -> Vec<f32> {
+		self.bus.apu_mut().take_samples()
+	}
+
+	// Battery backed RAM, or the flash PRG of self-flashable boards,
+	// None when the cartridge has no battery
+	pub fn sram(&self) -> Option<&[u8]> {
+		if self.bus.has_battery() {
+			Some(self.bus.mapper().battery_data().unwrap_or(self.bus.prg_ram()))
+		} else {
+			None
+		}
+	}
+
+	pub fn load_sram(&mut self, sram: &[u8]) {
+		if self.bus.mapper().battery_data().is_some() {
+			self.bus.mapper_mut().load_battery_data(sram);
+			return;
+		}
+
+		let prg_ram = self.bus.prg_ram_mut();
+		let len = prg_ram.len().min(sram.len());
+		prg_ram[..len].copy_from_slice(&sram[..len]);
+	}
+
+	// Write the battery RAM to the save file, when loaded with `from_path`
+	pub fn save_sram(&self) -> io::Result<()> {
+		match (&self.sav_path, self.sram()) {
+			(Some(path), Some(sram)) => fs::write(path, sram),
+			_ => Ok(())
+		}
+	}
+
+	// From the ROM header, inserting a cartridge sets it again
+	pub fn region(&self) -> Region {
+		self.bus.region()
+	}
+
+	// Run the game on another console model, before the first frame
+	pub fn set_region(&mut self, region: Region) {
+		self.bus.set_region(region);
+	}
+
+	pub fn cpu(&self) -> &Cpu {
+		&self.cpu
+	}
+
+	pub fn cpu_mut(&mut self) -> &mut Cpu {
+		&mut self.cpu
+	}
+
+	pub fn bus(&self) -> &Bus {
+		&self.bus
+	}
+
+	pub fn bus_mut(&mut self) -> &mut Bus {
+		&mut self.bus
+	}
+
+	// None unless running an FDS image
+	pub fn disk_drive(&mut self) -> Option<&mut dyn DiskDrive> {
+		self.bus.mapper_mut().disk_drive()
+	}
+}
+
+// Copy of the whole machine, for branching rollouts. The copy has no save file or rewind
+// history, and debug callbacks (watchpoints, scanline callbacks) are left behind
+impl Clone for Nes {
+	fn clone(&self) -> Self {
+		Nes {
+			cpu: self.cpu.clone(),
+			bus: self.bus.clone(),
+			sav_path: None,
+			powered_on: self.powered_on,
+			frame: self.frame.clone(),
+			recorder: self.recorder.clone(),
+			player: self.player.clone(),
+			rewind: None,
+			run_ahead: self.run_ahead,
+			paused: self.paused,
+			speed: self.speed,
+			speed_credit: self.speed_credit
+		}
+	}
+}
+
+impl Drop for Nes {
+	fn drop(&mut self) {
+		// Nothing to report to from drop, callers wanting errors use save_sram
+		let _ = self.save_sram();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::rom::test;
+
+	#[test]
+	fn region_from_header() {
+		assert_eq!(Nes::new(idle_rom()).region(), Region::Ntsc);
+
+		// NES 2.0 header with PAL timing
+		let mut ines = test::nrom(&[0x4C, 0x00, 0x80], [0, 0x8000, 0]);
+		ines[7] = 0x08;
+		ines[12] = 1;
+		let mut nes = Nes::new(Rom::from_ines(&ines).unwrap());
+		assert_eq!(nes.region(), Region::Pal);
+		assert_eq!(nes.bus().apu().region(), Region::Pal);
+
+		nes.set_region(Region::Dendy);
+		nes.run_frame();
+		nes.run_frame();
+		assert_eq!(nes.bus().ppu().region(), Region::Dendy);
+		// About 35464 CPU cycles per frame at 50Hz
+		let cycles = nes.cpu().cycles();
+		nes.run_frame();
+		// Frames end within the 3 cycles of the JMP
+		assert!((nes.cpu().cycles() - cycles).abs_diff(341 * 312 / 3) < 3);
+	}
+
+	#[test]
+	fn run_clocks_the_ppu() {
+		// Reset vector of the empty test ROM points to a BRK in RAM
+		let mut nes = Nes::new(test::test_rom());
+		nes.run();
+
+		assert_eq!(nes.cpu().cycles(), 7);
+		assert_eq!(nes.bus().ppu().dot(), 21);
+	}
+
+	#[test]
+	fn sram_save_file() {
+		let dir = std::env::temp_dir().join(format!("nessy-sram-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let rom_path = dir.join("game.nes");
+
+		// NROM with battery
+		let mut ines = test::nrom(&[], [0, 0, 0]);
+		ines[6] = 0x02;
+		fs::write(&rom_path, &ines).unwrap();
+
+		{
+			let mut nes = Nes::from_path(&rom_path).unwrap();
+			assert_eq!(nes.sram().unwrap()[0], 0x00);
+			nes.bus_mut().write(0x6000, 0x42);
+		}
+		assert_eq!(fs::read(dir.join("game.sav")).unwrap()[0], 0x42);
+
+		let nes = Nes::from_path(&rom_path).unwrap();
+		assert_eq!(nes.sram().unwrap()[0], 0x42);
+
+		drop(nes);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	// NROM spinning on JMP $8000
+	fn idle_rom() -> Rom {
+		Rom::from_ines(&test::nrom(&[0x4C, 0x00, 0x80], [0, 0x8000, 0])).unwrap()
+	}
+
+	// Increments $10 and $0400 in a loop with rendering on
+	fn rendering_rom() -> Rom {
+		// LDA #$0A, STA $2001, loop: INC $10, INC $0400, JMP loop
+		Rom::from_ines(&test::nrom(&[0xA9, 0x0A, 0x8D, 0x01, 0x20, 0xE6, 0x10, 0xEE, 0x00, 0x04, 0x4C, 0x05, 0x80], [0, 0x8000, 0])).unwrap()
+	}
+
+	#[test]
+	fn save_state() {
+		let mut nes = Nes::new(rendering_rom());
+		for _ in 0..3 {
+			nes.run_frame();
+		}
+		let state = nes.save_state();
+
+		let run = |nes: &mut Nes| {
+			nes.run_frame();
+			nes.run_frame();
+			(nes.cpu().cycles(), nes.bus().peek(0x0010), nes.bus().peek(0x0400), nes.bus().ppu().dot(), nes.frame().hash())
+		};
+		let expected = run(&mut nes);
+
+		nes.load_state(&state).unwrap();
+		assert_eq!(run(&mut nes), expected);
+
+		// Into a fresh console
+		let mut restored = Nes::new(rendering_rom());
+		restored.load_state(&state).unwrap();
+		assert_eq!(run(&mut restored), expected);
+	}
+
+	#[test]
+	fn load_state_errors() {
+		let mut nes = Nes::new(rendering_rom());
+		nes.run_frame();
+		let state = nes.save_state();
+
+		let mut other = Nes::new(idle_rom());
+		assert!(matches!(other.load_state(&state), Err(StateError::WrongRom { .. })));
+		assert_eq!(nes.load_state(b"not a state"), Err(StateError::BadMagic));
+
+		// CPU section cut short
+		let file = StateFile::parse(&state).unwrap();
+		let cut = StateFile::write(file.rom_crc32, &[
+			(*b"CPU ", file.section(*b"CPU ").unwrap()[..4].to_vec()),
+			(*b"BUS ", file.section(*b"BUS ").unwrap().to_vec()),
+			(*b"CART", file.section(*b"CART").unwrap().to_vec())
+		]);
+		let cycles = nes.cpu().cycles();
+		assert!(matches!(nes.load_state(&cut), Err(StateError::Corrupt(_))));
+		assert_eq!(nes.cpu().cycles(), cycles);
+
+		// Cartridge section cut short
+		let cut = StateFile::write(file.rom_crc32, &[
+			(*b"CPU ", file.section(*b"CPU ").unwrap().to_vec()),
+			(*b"BUS ", file.section(*b"BUS ").unwrap().to_vec()),
+			(*b"CART", vec![0xFF, 0xFF, 0, 0])
+		]);
+		assert!(matches!(nes.load_state(&cut), Err(StateError::Corrupt(_))));
+		assert_eq!(nes.cpu().cycles(), cycles);
+
+		// PPU clock ahead of the master clock, the last field of the bus section
+		let mut bus = file.section(*b"BUS ").unwrap().to_vec();
+		let len = bus.len();
+		bus[len - 8..].copy_from_slice(&u64::MAX.to_le_bytes());
+		let corrupt = StateFile::write(file.rom_crc32, &[
+			(*b"CPU ", file.section(*b"CPU ").unwrap().to_vec()),
+			(*b"BUS ", bus),
+			(*b"CART", file.section(*b"CART").unwrap().to_vec())
+		]);
+		assert!(matches!(nes.load_state(&corrupt), Err(StateError::Corrupt(_))));
+		assert_eq!(nes.cpu().cycles(), cycles);
+	}
+
+	#[test]
+	#[cfg(feature = "serde")]
+	fn serde_state() {
+		let mut nes = Nes::new(rendering_rom());
+		for _ in 0..3 {
+			nes.run_frame();
+		}
+		let json = serde_json::to_string(&nes.to_state()).unwrap();
+		let state = nes.save_state();
+
+		let mut restored = Nes::new(rendering_rom());
+		restored.apply_state(serde_json::from_str(&json).unwrap()).unwrap();
+		assert_eq!(restored.save_state(), state);
+		restored.run_frame();
+		nes.run_frame();
+		assert_eq!(restored.frame().hash(), nes.frame().hash());
+
+		let mut other = Nes::new(idle_rom());
+		assert!(matches!(other.apply_state(serde_json::from_str(&json).unwrap()), Err(StateError::WrongRom { .. })));
+	}
+
+	// Shows its frame counter as the backdrop color
+	fn backdrop_rom() -> Rom {
+		Rom::from_ines(&test::nrom(&[
+			0xA9, 0x0A, 0x8D, 0x01, 0x20, // Rendering on
+			0x2C, 0x02, 0x20, 0x10, 0xFB, // Wait for vblank
+			0xA9, 0x3F, 0x8D, 0x06, 0x20, // PPUADDR $3F00
+			0xA9, 0x00, 0x8D, 0x06, 0x20,
+			0xA5, 0x10, 0x8D, 0x07, 0x20, // Backdrop = $10
+			0xE6, 0x10, 0x4C, 0x05, 0x80  // INC $10, JMP
+		], [0, 0x8000, 0])).unwrap()
+	}
+
+	#[test]
+	fn run_ahead() {
+		let mut plain = Nes::new(backdrop_rom());
+		let run = |nes: &mut Nes| (nes.run_frame().hash(), nes.bus().peek(0x0010));
+		let frames: Vec<(u32, u8)> = (0..8).map(|_| run(&mut plain)).collect();
+		assert_ne!(frames[5].0, frames[6].0);
+
+		let mut ahead = Nes::new(backdrop_rom());
+		ahead.set_run_ahead(2);
+		for i in 0..6 {
+			let (hash, counter) = run(&mut ahead);
+			assert_eq!(hash, frames[i + 2].0);
+			// The console itself is not ahead
+			assert_eq!(counter, frames[i].1);
+		}
+	}
+
+	#[test]
+	fn pause_and_speed() {
+		let mut nes = Nes::new(rendering_rom());
+		nes.run_frame();
+		nes.pause();
+		assert!(nes.is_paused());
+		nes.run_frame();
+		assert_eq!(nes.bus().ppu().frame_count(), 1);
+		nes.frame_advance();
+		assert_eq!(nes.bus().ppu().frame_count(), 2);
+		assert!(!nes.audio_samples().is_empty());
+		nes.resume();
+
+		let rate = nes.bus().apu().sample_rate();
+		nes.set_speed(0.5);
+		assert_eq!(nes.bus().apu().sample_rate(), rate * 2);
+		for _ in 0..4 {
+			nes.run_frame();
+		}
+		assert_eq!(nes.bus().ppu().frame_count(), 4);
+
+		nes.set_speed(2.0);
+		nes.run_frame();
+		assert_eq!(nes.bus().ppu().frame_count(), 6);
+		nes.set_speed(1.0);
+		assert_eq!(nes.bus().apu().sample_rate(), rate);
+	}
+
+	#[test]
+	fn rewind() {
+		let mut nes = Nes::new(rendering_rom());
+		assert_eq!(nes.rewind(10), Ok(0));
+
+		nes.enable_rewind(2, 10);
+		let mut counters = Vec::new();
+		for _ in 0..10 {
+			nes.run_frame();
+			counters.push((nes.bus().ppu().frame_count(), nes.bus().peek(0x0400)));
+		}
+		assert_eq!(nes.rewind_buffer().unwrap().len(), 5);
+
+		let now = nes.bus().ppu().frame_count();
+		let rewound = nes.rewind(3).unwrap();
+		assert_eq!(rewound, 4);
+		let frame = now - rewound;
+		assert_eq!(nes.bus().ppu().frame_count(), frame);
+		let counter = counters.iter().find(|&&(at, _)| at == frame).unwrap().1;
+		assert_eq!(nes.bus().peek(0x0400), counter);
+
+		// Back to the oldest snapshot
+		nes.rewind(100).unwrap();
+		assert_eq!(nes.rewind_buffer().unwrap().len(), 1);
+
+		// The snapshots were of the previous game
+		nes.insert(rendering_rom());
+		assert!(nes.rewind_buffer().unwrap().is_empty());
+		assert_eq!(nes.rewind(3), Ok(0));
+	}
+
+	#[test]
+	fn cheats() {
+		let mut nes = Nes::new(idle_rom());
+		// JMP $8000 becomes JMP $8001, a BRK at $8001
+		let cheat = nes.add_cheat("8001:01").unwrap();
+		assert_eq!(cheat.adress, 0x8001);
+		assert_eq!(nes.list_cheats().len(), 1);
+
+		nes.reset();
+		nes.cpu.step(&mut nes.bus);
+		assert_eq!(nes.cpu().pc, 0x8001);
+
+		assert!(nes.remove_cheat("8001:01"));
+		assert!(nes.list_cheats().is_empty());
+		assert!(nes.add_cheat("8001").is_err());
+	}
+
+	#[test]
+	fn frozen_ram() {
+		let mut nes = Nes::new(idle_rom());
+		nes.add_cheat("0042=63").unwrap();
+		nes.run_frame();
+		assert_eq!(nes.bus().peek(0x0042), 0x63);
+
+		// Game writes keep the frozen value
+		nes.bus_mut().write(0x0042, 0x00);
+		assert_eq!(nes.bus().peek(0x0042), 0x63);
+
+		nes.remove_cheat("0042=63");
+		nes.bus_mut().write(0x0042, 0x00);
+		assert_eq!(nes.bus().peek(0x0042), 0x00);
+	}
+
+	#[test]
+	fn screenshot() {
+		let mut nes = Nes::new(idle_rom());
+		nes.run_frame();
+		assert_eq!(nes.screenshot().hash(), nes.frame().hash());
+
+		let path = std::env::temp_dir().join(format!("nessy-screenshot-{}.ppm", std::process::id()));
+		nes.save_screenshot(&path).unwrap();
+		assert_eq!(fs::read(&path).unwrap(), nes.frame().to_ppm());
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn run_frames() {
+		let mut nes = Nes::new(idle_rom());
+
+		let frame = nes.run_frame();
+		assert_eq!((frame.width, frame.height), (256, 240));
+		assert_eq!(nes.bus().ppu().frame_count(), 1);
+		nes.run_frame();
+		assert_eq!(nes.bus().ppu().frame_count(), 2);
+
+		// 44100 Hz at 60 frames per second
+		let samples = nes.audio_samples().len();
+		assert!((1400..1550).contains(&samples), "{} samples", samples);
+
+		nes.insert(idle_rom());
+		assert_eq!(nes.bus().ppu().frame_count(), 0);
+	}
+
+	#[test]
+	fn replay_input() {
+		let mut nes = Nes::new(idle_rom());
+		nes.start_recording();
+		nes.run_frame();
+		nes.set_button(Button::Up, true);
+		nes.run_frame();
+		let frames = nes.stop_recording();
+		assert_eq!(frames, [FrameInput::new(0, 0), FrameInput::new(Button::Up.mask(), 0)]);
+
+		let mut nes = Nes::new(idle_rom());
+		nes.play(frames);
+		nes.run_frame();
+		nes.run_frame();
+		assert_eq!(nes.bus().joypad1().unwrap().buttons(), Button::Up.mask());
+		assert!(nes.is_playing());
+		nes.run_frame();
+		assert!(!nes.is_playing());
+	}
+
+	// Counts frames in RAM and adds the controller 1 buttons read at each NMI
+	fn counter_rom() -> Rom {
+		let program: &[u8] = &[
+			0xA9, 0x80,       // LDA #$80
+			0x8D, 0x00, 0x20, // STA $2000, NMI on
+			0x4C, 0x05, 0x80, // JMP *
+			// NMI handler at $8008
+			0xE6, 0x10,       // INC $10
+			0xA9, 0x01,       // LDA #1
+			0x8D, 0x16, 0x40, // STA $4016
+			0xA9, 0x00,       // LDA #0
+			0x8D, 0x16, 0x40, // STA $4016
+			0xAD, 0x16, 0x40, // LDA $4016, A
+			0x29, 0x01,       // AND #1
+			0x18,             // CLC
+			0x65, 0x11,       // ADC $11
+			0x85, 0x11,       // STA $11
+			0x40              // RTI
+		];
+		Rom::from_ines(&test::nrom(program, [0x8008, 0x8000, 0])).unwrap()
+	}
+
+	#[test]
+	fn deterministic_steps() {
+		let mut nes = Nes::new(counter_rom());
+		for _ in 0..5 {
+			nes.step(FrameInput::default());
+		}
+		let mut branch = nes.clone();
+
+		let a = FrameInput::new(Button::A.mask(), 0);
+		let observation = nes.step(a);
+		assert_eq!(observation.frame_count, 6);
+		let (ram, frame) = (*observation.ram, observation.frame.data.clone());
+		assert_eq!(ram[0x11], 1);
+
+		let observation = branch.step(a);
+		assert_eq!(observation.ram, &ram);
+		assert_eq!(observation.frame.data, frame);
+
+		branch.step(a);
+		assert_eq!(branch.bus().cpu_ram()[0x11], 2);
+		assert_eq!(nes.bus().cpu_ram()[0x11], 1);
+	}
+
+	#[test]
+	fn buttons() {
+		let mut nes = Nes::new(test::test_rom());
+		nes.set_button(Button::Start, true);
+		nes.set_button(Button::Right, true);
+		nes.set_button(Button::Right, false);
+		assert_eq!(nes.bus().joypad1().unwrap().buttons(), Button::Start.mask());
+	}
+
+	#[test]
+	fn no_battery() {
+		let mut nes = Nes::new(test::test_rom());
+		assert!(nes.sram().is_none());
+
+		nes.load_sram(&[0x99; 16]);
+		assert_eq!(nes.bus_mut().read(0x6000), 0x99);
+	}
+
+	#[test]
+	fn load_state_keeps_debug_hooks() {
+		use std::cell::Cell;
+		use std::rc::Rc;
+
+		let mut nes = Nes::new(rendering_rom());
+		let writes = Rc::new(Cell::new(0));
+		let scanlines = Rc::new(Cell::new(0));
+		let counter = Rc::clone(&writes);
+		nes.bus_mut().watch_write(0x0010, move |_| counter.set(counter.get() + 1));
+		let counter = Rc::clone(&scanlines);
+		nes.bus_mut().ppu_mut().on_scanline(move |_, _| counter.set(counter.get() + 1));
+		nes.run_frame();
+		let state = nes.save_state();
+
+		writes.set(0);
+		scanlines.set(0);
+		nes.load_state(&state).unwrap();
+		nes.run_frame();
+		assert!(writes.get() > 0);
+		assert_eq!(scanlines.get(), 262);
+	}
+
+	#[test]
+	#[cfg(feature = "serde")]
+	fn serde_state_errors() {
+		let mut nes = Nes::new(rendering_rom());
+		nes.run_frame();
+		let mut state = nes.to_state();
+		nes.run_frame();
+		let saved = nes.save_state();
+
+		state.bus.cpu_ram.pop();
+		assert!(matches!(nes.apply_state(state.clone()), Err(StateError::Corrupt(_))));
+		state.bus.cpu_ram.push(0);
+		state.bus.cartridge.truncate(2);
+		assert!(matches!(nes.apply_state(state), Err(StateError::Corrupt(_))));
+		assert_eq!(nes.save_state(), saved);
+
+		// The region comes with the state
+		let mut pal = Nes::new(rendering_rom());
+		pal.set_region(Region::Pal);
+		pal.run_frame();
+		nes.apply_state(pal.to_state()).unwrap();
+		assert_eq!(nes.region(), Region::Pal);
+		assert_eq!(nes.save_state(), pal.save_state());
 	}
 }
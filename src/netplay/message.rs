@@ -0,0 +1,142 @@
+use super::NetplayError;
+
+// Messages between the two peers: a kind byte then the little endian fields
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+	// Sent until the peer answers, both must run the same ROM with different roles
+	Hello { version: u16, rom_crc32: u32, host: bool },
+	// Buttons of the sender from `first_frame` on, sent again until acknowledged.
+	// `ack` is the first frame of the receiver's buttons the sender is missing
+	Inputs { ack: u64, first_frame: u64, buttons: Vec<u8> },
+	// CRC32 of the sender's save state at the start of `frame`, `epoch` counts the resyncs
+	Hash { epoch: u32, frame: u64, hash: u32 },
+	// Part of the host save state taken at the start of `frame`
+	StateChunk { epoch: u32, frame: u64, offset: u32, total: u32, data: Vec<u8> },
+	// The guest found a desync, or lost chunks of the state
+	RequestState
+}
+
+impl Message {
+	pub fn encode(&self) -> Vec<u8> {
+		let mut packet = Vec::new();
+		match self {
+			Message::Hello { version, rom_crc32, host } => {
+				packet.push(0);
+				packet.extend_from_slice(&version.to_le_bytes());
+				packet.extend_from_slice(&rom_crc32.to_le_bytes());
+				packet.push(u8::from(*host));
+			},
+			Message::Inputs { ack, first_frame, buttons } => {
+				packet.push(1);
+				packet.extend_from_slice(&ack.to_le_bytes());
+				packet.extend_from_slice(&first_frame.to_le_bytes());
+				packet.extend_from_slice(buttons);
+			},
+			Message::Hash { epoch, frame, hash } => {
+				packet.push(2);
+				packet.extend_from_slice(&epoch.to_le_bytes());
+				packet.extend_from_slice(&frame.to_le_bytes());
+				packet.extend_from_slice(&hash.to_le_bytes());
+			},
+			Message::StateChunk { epoch, frame, offset, total, data } => {
+				packet.push(3);
+				packet.extend_from_slice(&epoch.to_le_bytes());
+				packet.extend_from_slice(&frame.to_le_bytes());
+				packet.extend_from_slice(&offset.to_le_bytes());
+				packet.extend_from_slice(&total.to_le_bytes());
+				packet.extend_from_slice(data);
+			},
+			Message::RequestState => packet.push(4)
+		}
+		packet
+	}
+
+	// Packets come from the network, anything malformed is an error and not a panic
+	pub fn decode(packet: &[u8]) -> Result<Message, NetplayError> {
+		let mut reader = Reader { data: packet };
+		let truncated = || NetplayError::Protocol(String::from("truncated message"));
+
+		let message = match reader.u8().ok_or_else(truncated)? {
+			0 => Message::Hello {
+				version: reader.u16().ok_or_else(truncated)?,
+				rom_crc32: reader.u32().ok_or_else(truncated)?,
+				host: reader.u8().ok_or_else(truncated)? != 0
+			},
+			1 => Message::Inputs {
+				ack: reader.u64().ok_or_else(truncated)?,
+				first_frame: reader.u64().ok_or_else(truncated)?,
+				buttons: reader.rest()
+			},
+			2 => Message::Hash {
+				epoch: reader.u32().ok_or_else(truncated)?,
+				frame: reader.u64().ok_or_else(truncated)?,
+				hash: reader.u32().ok_or_else(truncated)?
+			},
+			3 => Message::StateChunk {
+				epoch: reader.u32().ok_or_else(truncated)?,
+				frame: reader.u64().ok_or_else(truncated)?,
+				offset: reader.u32().ok_or_else(truncated)?,
+				total: reader.u32().ok_or_else(truncated)?,
+				data: reader.rest()
+			},
+			4 => Message::RequestState,
+			kind => return Err(NetplayError::Protocol(format!("unknown message kind {}", kind)))
+		};
+		Ok(message)
+	}
+}
+
+struct Reader<'a> {
+	data: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+	fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+		let bytes = self.data.get(..N)?.try_into().ok()?;
+		self.data = &self.data[N..];
+		Some(bytes)
+	}
+
+	fn u8(&mut self) -> Option<u8> {
+		self.take::<1>().map(|[byte]| byte)
+	}
+
+	fn u16(&mut self) -> Option<u16> {
+		self.take().map(u16::from_le_bytes)
+	}
+
+	fn u32(&mut self) -> Option<u32> {
+		self.take().map(u32::from_le_bytes)
+	}
+
+	fn u64(&mut self) -> Option<u64> {
+		self.take().map(u64::from_le_bytes)
+	}
+
+	fn rest(&mut self) -> Vec<u8> {
+		std::mem::take(&mut self.data).to_vec()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		let messages = [
+			Message::Hello { version: 1, rom_crc32: 0xDEADBEEF, host: true },
+			Message::Inputs { ack: 7, first_frame: 5, buttons: vec![0x01, 0x80] },
+			Message::Hash { epoch: 2, frame: 60, hash: 0x1234_5678 },
+			Message::StateChunk { epoch: 1, frame: 90, offset: 1200, total: 4000, data: vec![9; 16] },
+			Message::RequestState
+		];
+		for message in messages {
+			assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+		}
+
+		assert!(matches!(Message::decode(&[]), Err(NetplayError::Protocol(_))));
+		assert!(matches!(Message::decode(&[2, 0, 0]), Err(NetplayError::Protocol(_))));
+		assert!(matches!(Message::decode(&[42]), Err(NetplayError::Protocol(_))));
+	}
+}
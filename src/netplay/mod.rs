@@ -0,0 +1,595 @@
+// Two player netplay in lockstep: each peer sends the buttons of its player, a frame only
+// runs once both are known, so the two consoles stay identical. Inputs are delayed by a few
+// frames to hide the latency. The peers compare CRCs of their save states every
+// `hash_interval` frames and the guest loads the host state when they differ
+pub mod message;
+pub mod transport;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+use crate::input::FrameInput;
+use crate::nes::Nes;
+use crate::rom::hash;
+use crate::state::StateError;
+use message::Message;
+pub use transport::{TcpTransport, Transport, UdpTransport};
+
+pub const PROTOCOL_VERSION: u16 = 1;
+
+// Below the usual MTU, so UDP datagrams are not fragmented
+const STATE_CHUNK_SIZE: usize = 1200;
+// Larger states are refused, nessy's are around 130 KB
+const MAX_STATE_SIZE: u32 = 1 << 24;
+// Frames of inputs and hashes kept, for the guest replaying the frames after a resync
+const HISTORY: u64 = 600;
+// Polls the guest waits for a requested state before asking again
+const STATE_REQUEST_INTERVAL: u32 = 30;
+
+#[derive(Debug)]
+pub enum NetplayError {
+	Io(io::Error),
+	// The peer sent something that is not a message of this version
+	Protocol(String),
+	// The peer runs another game
+	WrongRom { expected: u32, got: u32 },
+	// Both peers are hosts, or both are guests
+	SameRole,
+	// The host state did not load
+	State(StateError)
+}
+
+impl fmt::Display for NetplayError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			NetplayError::Io(error) => write!(f, "{}", error),
+			NetplayError::Protocol(message) => write!(f, "netplay protocol error: {}", message),
+			NetplayError::WrongRom { expected, got } => write!(f, "the peer runs ROM {:08X}, not {:08X}", got, expected),
+			NetplayError::SameRole => write!(f, "one peer must host and the other join"),
+			NetplayError::State(error) => write!(f, "{}", error)
+		}
+	}
+}
+
+impl std::error::Error for NetplayError {}
+
+impl From<io::Error> for NetplayError {
+	fn from(error: io::Error) -> Self {
+		NetplayError::Io(error)
+	}
+}
+
+// The host plays on port 1 and its console is the reference when the peers desync
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+	Host,
+	Guest
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LockstepConfig {
+	// Frames between reading the local buttons and using them, covers the round trip
+	pub input_delay: u32,
+	// Frames between state hashes, 0 turns desync detection off
+	pub hash_interval: u32
+}
+
+impl Default for LockstepConfig {
+	fn default() -> Self {
+		LockstepConfig {
+			input_delay: 2,
+			hash_interval: 60
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+	// Ran one frame with the buttons of both players
+	Advanced,
+	// Nothing ran, the peer or its inputs are not there yet
+	Waiting,
+	// Guest only, loaded the host state taken at the start of this frame
+	Resynced(u64)
+}
+
+// Host state being received in chunks
+struct IncomingState {
+	epoch: u32,
+	frame: u64,
+	data: Vec<u8>,
+	received: Vec<bool>
+}
+
+pub struct Lockstep<T: Transport> {
+	transport: T,
+	role: Role,
+	config: LockstepConfig,
+	rom_crc32: u32,
+
+	// Next frame to run
+	frame: u64,
+	local: BTreeMap<u64, u8>,
+	remote: BTreeMap<u64, u8>,
+	// First frame of remote buttons missing
+	remote_end: u64,
+	// First frame of our buttons the peer is missing
+	peer_ack: u64,
+	hello_received: bool,
+	// The peer got our hello once it sends inputs
+	peer_ready: bool,
+
+	// Resyncs done, hashes of older epochs were taken before the last one
+	epoch: u32,
+	local_hashes: BTreeMap<u64, u32>,
+	remote_hashes: BTreeMap<u64, u32>,
+	desyncs: u32,
+	// Guest waiting for a host state, polls since it asked
+	state_request: Option<u32>,
+	incoming_state: Option<IncomingState>
+}
+
+impl<T: Transport> Lockstep<T> {
+	// Both peers start from a console fresh from Nes::new with the same ROM
+	pub fn new(transport: T, role: Role, config: LockstepConfig, nes: &Nes) -> Lockstep<T> {
+		let delay = u64::from(config.input_delay);
+		Lockstep {
+			transport,
+			role,
+			config,
+			rom_crc32: nes.bus().rom_info().map_or(0, |info| info.crc32),
+			frame: 0,
+			// No buttons during the first delayed frames
+			local: (0..delay).map(|frame| (frame, 0)).collect(),
+			remote: (0..delay).map(|frame| (frame, 0)).collect(),
+			remote_end: delay,
+			peer_ack: delay,
+			hello_received: false,
+			peer_ready: false,
+			epoch: 0,
+			local_hashes: BTreeMap::new(),
+			remote_hashes: BTreeMap::new(),
+			desyncs: 0,
+			state_request: None,
+			incoming_state: None
+		}
+	}
+
+	pub fn role(&self) -> Role {
+		self.role
+	}
+
+	// Frames run since the start, the next one to run
+	pub fn frame(&self) -> u64 {
+		self.frame
+	}
+
+	pub fn is_connected(&self) -> bool {
+		self.hello_received
+	}
+
+	// Desyncs this peer found, the guest recovers from each one with a host state
+	pub fn desyncs(&self) -> u32 {
+		self.desyncs
+	}
+
+	// Call once per frame with the local player's buttons, bit 0 is A up to bit 7 for Right.
+	// The buttons are used `input_delay` frames later, calls that return Waiting keep the
+	// first buttons given for that frame
+	pub fn advance(&mut self, nes: &mut Nes, buttons: u8) -> Result<Status, NetplayError> {
+		let resynced = self.poll(nes)?;
+		if !self.peer_ready {
+			self.send(&self.hello())?;
+		}
+		if !self.hello_received {
+			return Ok(Status::Waiting);
+		}
+
+		let input_frame = self.frame + u64::from(self.config.input_delay);
+		self.local.entry(input_frame).or_insert(buttons);
+		self.send_inputs()?;
+
+		if let Some(frame) = resynced {
+			return Ok(Status::Resynced(frame));
+		}
+		if let Some(polls) = &mut self.state_request {
+			*polls += 1;
+			if *polls >= STATE_REQUEST_INTERVAL {
+				*polls = 0;
+				self.send(&Message::RequestState)?;
+			}
+			return Ok(Status::Waiting);
+		}
+
+		let (Some(&local), Some(&remote)) = (self.local.get(&self.frame), self.remote.get(&self.frame)) else {
+			return Ok(Status::Waiting);
+		};
+		let input = match self.role {
+			Role::Host => FrameInput::new(local, remote),
+			Role::Guest => FrameInput::new(remote, local)
+		};
+		nes.step(input);
+		self.frame += 1;
+
+		if self.config.hash_interval > 0 && self.frame.is_multiple_of(u64::from(self.config.hash_interval)) {
+			let hash = state_hash(nes);
+			self.local_hashes.insert(self.frame, hash);
+			self.send(&Message::Hash { epoch: self.epoch, frame: self.frame, hash })?;
+			self.compare_hashes(nes)?;
+		}
+		self.prune();
+		Ok(Status::Advanced)
+	}
+
+	// Handle what the peer sent without running a frame, for the frames a frontend
+	// does not call advance. Returns the frame of a host state loaded by the guest
+	pub fn poll(&mut self, nes: &mut Nes) -> Result<Option<u64>, NetplayError> {
+		let mut resynced = None;
+		while let Some(packet) = self.transport.recv()? {
+			match Message::decode(&packet)? {
+				Message::Hello { version, rom_crc32, host } => {
+					if version != PROTOCOL_VERSION {
+						return Err(NetplayError::Protocol(format!("peer speaks version {}, not {}", version, PROTOCOL_VERSION)));
+					}
+					if rom_crc32 != self.rom_crc32 {
+						return Err(NetplayError::WrongRom { expected: self.rom_crc32, got: rom_crc32 });
+					}
+					if host == (self.role == Role::Host) {
+						return Err(NetplayError::SameRole);
+					}
+					self.hello_received = true;
+				},
+				Message::Inputs { ack, first_frame, buttons } => {
+					self.check_frame(ack)?;
+					self.check_frame(first_frame)?;
+					self.peer_ready = true;
+					self.peer_ack = self.peer_ack.max(ack);
+					let frames = (0..).map_while(|offset| first_frame.checked_add(offset));
+					for (frame, buttons) in frames.zip(buttons) {
+						if frame >= self.remote_end {
+							self.remote.insert(frame, buttons);
+						}
+					}
+					while self.remote.contains_key(&self.remote_end) {
+						self.remote_end += 1;
+					}
+				},
+				Message::Hash { epoch, frame, hash } => {
+					self.check_frame(frame)?;
+					// Guest hashes from before the last resync are expected to differ
+					if self.role == Role::Guest || epoch == self.epoch {
+						self.remote_hashes.insert(frame, hash);
+						self.compare_hashes(nes)?;
+					}
+				},
+				Message::StateChunk { epoch, frame, offset, total, data } => {
+					if self.role == Role::Guest {
+						if let Some(frame) = self.receive_chunk(nes, epoch, frame, offset, total, &data)? {
+							resynced = Some(frame);
+						}
+					}
+				},
+				Message::RequestState => {
+					if self.role == Role::Host {
+						self.send_state(nes)?;
+					}
+				}
+			}
+		}
+		Ok(resynced)
+	}
+
+	// The peer runs at most its input delay ahead, frames far beyond come from a broken
+	// peer and would grow the buffers without bound
+	fn check_frame(&self, frame: u64) -> Result<(), NetplayError> {
+		if frame > self.frame + HISTORY {
+			return Err(NetplayError::Protocol(format!("frame {} is too far ahead of {}", frame, self.frame)));
+		}
+		Ok(())
+	}
+
+	fn hello(&self) -> Message {
+		Message::Hello { version: PROTOCOL_VERSION, rom_crc32: self.rom_crc32, host: self.role == Role::Host }
+	}
+
+	fn send(&mut self, message: &Message) -> Result<(), NetplayError> {
+		self.transport.send(&message.encode())?;
+		Ok(())
+	}
+
+	// Every button the peer has not acknowledged, so lost packets are covered by the next ones
+	fn send_inputs(&mut self) -> Result<(), NetplayError> {
+		let buttons = self.local.range(self.peer_ack..).map(|(_, &buttons)| buttons).collect();
+		self.send(&Message::Inputs { ack: self.remote_end, first_frame: self.peer_ack, buttons })
+	}
+
+	fn compare_hashes(&mut self, nes: &Nes) -> Result<(), NetplayError> {
+		let desync = self.remote_hashes.iter()
+			.any(|(frame, hash)| self.local_hashes.get(frame).is_some_and(|local| local != hash));
+		self.remote_hashes.retain(|frame, _| !self.local_hashes.contains_key(frame));
+		if !desync {
+			return Ok(());
+		}
+
+		match self.role {
+			Role::Host => {
+				self.desyncs += 1;
+				self.send_state(nes)
+			},
+			// Already waiting for the host state
+			Role::Guest if self.state_request.is_some() => Ok(()),
+			Role::Guest => {
+				self.desyncs += 1;
+				self.state_request = Some(0);
+				self.send(&Message::RequestState)
+			}
+		}
+	}
+
+	// Host state at the start of the next frame, in a new epoch
+	fn send_state(&mut self, nes: &Nes) -> Result<(), NetplayError> {
+		self.epoch += 1;
+		let state = nes.save_state();
+		for (index, data) in state.chunks(STATE_CHUNK_SIZE).enumerate() {
+			self.send(&Message::StateChunk {
+				epoch: self.epoch,
+				frame: self.frame,
+				offset: (index * STATE_CHUNK_SIZE) as u32,
+				total: state.len() as u32,
+				data: data.to_vec()
+			})?;
+		}
+		Ok(())
+	}
+
+	// Load the host state once all its chunks are there, returns its frame
+	fn receive_chunk(&mut self, nes: &mut Nes, epoch: u32, frame: u64, offset: u32, total: u32, data: &[u8]) -> Result<Option<u64>, NetplayError> {
+		let offset = offset as usize;
+		let bad_length = data.is_empty() || data.len() > STATE_CHUNK_SIZE || offset + data.len() > total as usize;
+		if total > MAX_STATE_SIZE || !offset.is_multiple_of(STATE_CHUNK_SIZE) || offset >= total as usize || bad_length {
+			return Err(NetplayError::Protocol(format!("state chunk of {} bytes at {} of {} bytes", data.len(), offset, total)));
+		}
+		if epoch <= self.epoch {
+			return Ok(None);
+		}
+
+		let incoming = match &mut self.incoming_state {
+			Some(incoming) if incoming.epoch == epoch => incoming,
+			Some(incoming) if incoming.epoch > epoch => return Ok(None),
+			slot => slot.insert(IncomingState {
+				epoch,
+				frame,
+				data: vec![0; total as usize],
+				received: vec![false; (total as usize).div_ceil(STATE_CHUNK_SIZE)]
+			})
+		};
+		if incoming.data.len() != total as usize {
+			return Err(NetplayError::Protocol(String::from("state chunks of different sizes")));
+		}
+		let Some(received) = incoming.received.get_mut(offset / STATE_CHUNK_SIZE) else {
+			return Err(NetplayError::Protocol(format!("state chunk at {} of {} bytes", offset, total)));
+		};
+		*received = true;
+		incoming.data[offset..offset + data.len()].copy_from_slice(data);
+		if !incoming.received.iter().all(|&received| received) {
+			return Ok(None);
+		}
+
+		let incoming = self.incoming_state.take().unwrap();
+		nes.load_state(&incoming.data).map_err(NetplayError::State)?;
+		self.epoch = incoming.epoch;
+		self.frame = incoming.frame;
+		self.state_request = None;
+		// Our hashes came from the desynced console
+		self.local_hashes.clear();
+		self.remote_hashes.retain(|&frame, _| frame > incoming.frame);
+		Ok(Some(incoming.frame))
+	}
+
+	fn prune(&mut self) {
+		let oldest = self.frame.saturating_sub(HISTORY);
+		self.local = self.local.split_off(&oldest);
+		self.remote = self.remote.split_off(&oldest);
+		self.local_hashes = self.local_hashes.split_off(&oldest);
+		self.remote_hashes = self.remote_hashes.split_off(&oldest);
+	}
+}
+
+// Same console state, same hash
+pub fn state_hash(nes: &Nes) -> u32 {
+	hash::crc32(&[&nes.save_state()])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::net::{TcpListener, TcpStream, UdpSocket};
+	use std::sync::mpsc::{channel, Receiver, Sender};
+
+	use crate::rom::Rom;
+	use crate::rom::test::nrom;
+
+	// In memory link dropping one packet out of `loss`, 0 for none
+	struct Pipe {
+		sender: Sender<Vec<u8>>,
+		receiver: Receiver<Vec<u8>>,
+		loss: u32,
+		sent: u32
+	}
+
+	impl Transport for Pipe {
+		fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+			self.sent += 1;
+			if self.loss == 0 || !self.sent.is_multiple_of(self.loss) {
+				let _ = self.sender.send(packet.to_vec());
+			}
+			Ok(())
+		}
+
+		fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+			Ok(self.receiver.try_recv().ok())
+		}
+	}
+
+	fn pipes(loss: u32) -> (Pipe, Pipe) {
+		let (host_sender, guest_receiver) = channel();
+		let (guest_sender, host_receiver) = channel();
+		(
+			Pipe { sender: host_sender, receiver: host_receiver, loss, sent: 0 },
+			Pipe { sender: guest_sender, receiver: guest_receiver, loss, sent: 0 }
+		)
+	}
+
+	struct Peer<T: Transport> {
+		lockstep: Lockstep<T>,
+		nes: Nes
+	}
+
+	// Increments $10 and $0400 in a loop with rendering on
+	fn rom() -> Rom {
+		Rom::from_ines(&nrom(&[0xA9, 0x0A, 0x8D, 0x01, 0x20, 0xE6, 0x10, 0xEE, 0x00, 0x04, 0x4C, 0x05, 0x80], [0, 0x8000, 0])).unwrap()
+	}
+
+	fn peer<T: Transport>(transport: T, role: Role, config: LockstepConfig) -> Peer<T> {
+		let nes = Nes::new(rom());
+		Peer { lockstep: Lockstep::new(transport, role, config, &nes), nes }
+	}
+
+	fn buttons(role: Role, frame: u64) -> u8 {
+		match role {
+			Role::Host => frame as u8,
+			Role::Guest => !(frame as u8)
+		}
+	}
+
+	// Advance below `frames`, poll once there
+	fn step<T: Transport>(peer: &mut Peer<T>, frames: u64) -> Option<Status> {
+		if peer.lockstep.frame() < frames {
+			let buttons = buttons(peer.lockstep.role(), peer.lockstep.frame());
+			Some(peer.lockstep.advance(&mut peer.nes, buttons).unwrap())
+		} else {
+			peer.lockstep.poll(&mut peer.nes).unwrap();
+			None
+		}
+	}
+
+	// Both peers up to `frames`, the one ahead keeps polling for the other
+	fn run<H: Transport, G: Transport>(host: &mut Peer<H>, guest: &mut Peer<G>, frames: u64) -> Vec<Status> {
+		let mut statuses = Vec::new();
+		for _ in 0..100_000 {
+			if host.lockstep.frame() >= frames && guest.lockstep.frame() >= frames && guest.lockstep.state_request.is_none() {
+				return statuses;
+			}
+			statuses.extend(step(host, frames));
+			statuses.extend(step(guest, frames));
+		}
+		panic!("peers stuck at frames {} and {}", host.lockstep.frame(), guest.lockstep.frame());
+	}
+
+	fn assert_in_sync<H: Transport, G: Transport>(host: &Peer<H>, guest: &Peer<G>, frames: u64, delay: u64) {
+		assert_eq!(host.lockstep.frame(), frames);
+		assert_eq!(guest.lockstep.frame(), frames);
+		assert_eq!(host.nes.save_state(), guest.nes.save_state());
+
+		// Buttons of the last frame were read `delay` frames before
+		let expected = FrameInput::new(buttons(Role::Host, frames - 1 - delay), buttons(Role::Guest, frames - 1 - delay));
+		assert_eq!(FrameInput::capture(host.nes.bus()), expected);
+		assert_eq!(FrameInput::capture(guest.nes.bus()), expected);
+	}
+
+	#[test]
+	fn lockstep_over_lossy_link() {
+		let (host_pipe, guest_pipe) = pipes(3);
+		let config = LockstepConfig { input_delay: 2, hash_interval: 10 };
+		let mut host = peer(host_pipe, Role::Host, config);
+		let mut guest = peer(guest_pipe, Role::Guest, config);
+
+		run(&mut host, &mut guest, 40);
+		assert_in_sync(&host, &guest, 40, 2);
+		assert_eq!(host.lockstep.desyncs() + guest.lockstep.desyncs(), 0);
+	}
+
+	#[test]
+	fn resync_after_desync() {
+		let (host_pipe, guest_pipe) = pipes(0);
+		let config = LockstepConfig { input_delay: 1, hash_interval: 10 };
+		let mut host = peer(host_pipe, Role::Host, config);
+		let mut guest = peer(guest_pipe, Role::Guest, config);
+
+		run(&mut host, &mut guest, 15);
+		guest.nes.bus_mut().cpu_ram_mut()[0x0300] ^= 0xFF;
+		let statuses = run(&mut host, &mut guest, 60);
+
+		assert!(statuses.iter().any(|status| matches!(status, Status::Resynced(_))));
+		assert!(guest.lockstep.desyncs() >= 1);
+		assert_in_sync(&host, &guest, 60, 1);
+	}
+
+	#[test]
+	fn bad_state_chunks() {
+		let (_, guest_pipe) = pipes(0);
+		let mut guest = peer(guest_pipe, Role::Guest, LockstepConfig::default());
+		let bad_chunks: [(u32, u32, &[u8]); 5] = [
+			(0, 0, &[]),
+			(1200, 1200, &[]),
+			(2400, 1200, &[1]),
+			(0, 1200, &[1; 1201]),
+			(600, 1200, &[1])
+		];
+		for (offset, total, data) in bad_chunks {
+			let result = guest.lockstep.receive_chunk(&mut guest.nes, 1, 0, offset, total, data);
+			assert!(matches!(result, Err(NetplayError::Protocol(_))), "chunk at {} of {}", offset, total);
+		}
+		assert!(guest.lockstep.incoming_state.is_none());
+	}
+
+	#[test]
+	fn handshake_errors() {
+		let (host_pipe, guest_pipe) = pipes(0);
+		let mut host = peer(host_pipe, Role::Host, LockstepConfig::default());
+		let mut other = Peer { lockstep: Lockstep::new(guest_pipe, Role::Host, LockstepConfig::default(), &host.nes), nes: Nes::new(rom()) };
+
+		assert_eq!(other.lockstep.advance(&mut other.nes, 0).unwrap(), Status::Waiting);
+		assert!(matches!(host.lockstep.advance(&mut host.nes, 0), Err(NetplayError::SameRole)));
+
+		let (host_pipe, mut guest_pipe) = pipes(0);
+		let mut host = peer(host_pipe, Role::Host, LockstepConfig::default());
+		guest_pipe.send(&Message::Hello { version: PROTOCOL_VERSION, rom_crc32: 0x1234, host: false }.encode()).unwrap();
+		assert!(matches!(host.lockstep.advance(&mut host.nes, 0), Err(NetplayError::WrongRom { got: 0x1234, .. })));
+	}
+
+	#[test]
+	fn frames_far_ahead() {
+		let (host_pipe, mut guest_pipe) = pipes(0);
+		let mut host = peer(host_pipe, Role::Host, LockstepConfig::default());
+		guest_pipe.send(&Message::Inputs { ack: 0, first_frame: u64::MAX, buttons: vec![1] }.encode()).unwrap();
+		assert!(matches!(host.lockstep.poll(&mut host.nes), Err(NetplayError::Protocol(_))));
+
+		guest_pipe.send(&Message::Hash { epoch: 0, frame: HISTORY + 1, hash: 0 }.encode()).unwrap();
+		assert!(matches!(host.lockstep.poll(&mut host.nes), Err(NetplayError::Protocol(_))));
+		assert!(host.lockstep.remote_hashes.is_empty());
+	}
+
+	#[test]
+	fn tcp_and_udp() {
+		let config = LockstepConfig::default();
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (server, _) = listener.accept().unwrap();
+		let mut host = peer(TcpTransport::new(server).unwrap(), Role::Host, config);
+		let mut guest = peer(TcpTransport::new(client).unwrap(), Role::Guest, config);
+		run(&mut host, &mut guest, 20);
+		assert_in_sync(&host, &guest, 20, 2);
+
+		let host_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let guest_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let (host_addr, guest_addr) = (host_socket.local_addr().unwrap(), guest_socket.local_addr().unwrap());
+		let mut host = peer(UdpTransport::new(host_socket, guest_addr).unwrap(), Role::Host, config);
+		let mut guest = peer(UdpTransport::new(guest_socket, host_addr).unwrap(), Role::Guest, config);
+		run(&mut host, &mut guest, 20);
+		assert_in_sync(&host, &guest, 20, 2);
+	}
+}
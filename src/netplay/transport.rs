@@ -0,0 +1,143 @@
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+
+// Larger TCP packets are taken for a broken stream, save state chunks stay far below
+const MAX_PACKET: usize = 1 << 20;
+
+// Message channel to the other peer. Neither call blocks, so a frontend can keep
+// drawing and polling its window while it waits for the peer
+pub trait Transport {
+	// Lost packets are fine, Lockstep sends again what has not been acknowledged
+	fn send(&mut self, packet: &[u8]) -> io::Result<()>;
+
+	// Next packet of the peer, None when nothing arrived yet
+	fn recv(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+// Packets prefixed by their u32 length on a TCP stream
+pub struct TcpTransport {
+	stream: TcpStream,
+	incoming: Vec<u8>,
+	// Bytes the socket did not take yet
+	outgoing: Vec<u8>
+}
+
+impl TcpTransport {
+	pub fn new(stream: TcpStream) -> io::Result<TcpTransport> {
+		stream.set_nodelay(true)?;
+		stream.set_nonblocking(true)?;
+		Ok(TcpTransport {
+			stream,
+			incoming: Vec::new(),
+			outgoing: Vec::new()
+		})
+	}
+
+	// Blocks until the other peer connects to `addr`
+	pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<TcpTransport> {
+		let (stream, _) = TcpListener::bind(addr)?.accept()?;
+		TcpTransport::new(stream)
+	}
+
+	pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpTransport> {
+		TcpTransport::new(TcpStream::connect(addr)?)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		while !self.outgoing.is_empty() {
+			match self.stream.write(&self.outgoing) {
+				Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+				Ok(written) => {
+					self.outgoing.drain(..written);
+				},
+				Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+				Err(error) if error.kind() == io::ErrorKind::Interrupted => {},
+				Err(error) => return Err(error)
+			}
+		}
+		Ok(())
+	}
+
+	fn take_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+		let Some(header) = self.incoming.get(..4) else {
+			return Ok(None);
+		};
+		let len = u32::from_le_bytes(header.try_into().unwrap()) as usize;
+		if len > MAX_PACKET {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, format!("packet of {} bytes", len)));
+		}
+		if self.incoming.len() < 4 + len {
+			return Ok(None);
+		}
+
+		let packet = self.incoming[4..4 + len].to_vec();
+		self.incoming.drain(..4 + len);
+		Ok(Some(packet))
+	}
+}
+
+impl Transport for TcpTransport {
+	fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+		self.outgoing.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+		self.outgoing.extend_from_slice(packet);
+		self.flush()
+	}
+
+	fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+		self.flush()?;
+
+		let mut buffer = [0; 4096];
+		loop {
+			if let Some(packet) = self.take_packet()? {
+				return Ok(Some(packet));
+			}
+			match self.stream.read(&mut buffer) {
+				Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the peer closed the connection")),
+				Ok(read) => self.incoming.extend_from_slice(&buffer[..read]),
+				Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+				Err(error) if error.kind() == io::ErrorKind::Interrupted => {},
+				Err(error) => return Err(error)
+			}
+		}
+	}
+}
+
+// One datagram per packet, datagrams from other addresses than the peer are dropped
+pub struct UdpTransport {
+	socket: UdpSocket
+}
+
+impl UdpTransport {
+	pub fn bind<A: ToSocketAddrs, P: ToSocketAddrs>(local: A, peer: P) -> io::Result<UdpTransport> {
+		UdpTransport::new(UdpSocket::bind(local)?, peer)
+	}
+
+	pub fn new<P: ToSocketAddrs>(socket: UdpSocket, peer: P) -> io::Result<UdpTransport> {
+		socket.connect(peer)?;
+		socket.set_nonblocking(true)?;
+		Ok(UdpTransport { socket })
+	}
+
+	pub fn local_addr(&self) -> io::Result<SocketAddr> {
+		self.socket.local_addr()
+	}
+}
+
+impl Transport for UdpTransport {
+	fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+		match self.socket.send(packet) {
+			// Dropped like any datagram, or the peer is not listening yet
+			Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::ConnectionRefused) => Ok(()),
+			result => result.map(|_| ())
+		}
+	}
+
+	fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+		let mut buffer = [0; 65536];
+		match self.socket.recv(&mut buffer) {
+			Ok(len) => Ok(Some(buffer[..len].to_vec())),
+			Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::ConnectionRefused) => Ok(None),
+			Err(error) => Err(error)
+		}
+	}
+}
@@ -0,0 +1,333 @@
+use std::fmt;
+
+use crate::apu::{CPU_FREQUENCY, expansion::ExpansionAudio, vrc6::Vrc6Audio};
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use crate::mapper::Mapper;
+use crate::rom::{Mirroring, Rom};
+
+const HEADER_SIZE: usize = 0x80;
+const BANK_SIZE: usize = 4096;
+
+// Address the init and play routines return to, never executed
+const RETURN_ADRESS: u16 = 0x5FF0;
+// Give up on routines that don't return within a second
+const MAX_CALL_CYCLES: u64 = CPU_FREQUENCY as u64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NsfError {
+	// Not an NSF file
+	BadMagic,
+	// Shorter than the 128 bytes header
+	Truncated { expected: usize, got: usize }
+}
+
+impl fmt::Display for NsfError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			NsfError::BadMagic => write!(f, "not an NSF file"),
+			NsfError::Truncated { expected, got } => write!(f, "file is truncated, {} bytes expected but {} found", expected, got)
+		}
+	}
+}
+
+impl std::error::Error for NsfError {}
+
+pub struct NsfHeader {
+	pub track_count: u8,
+	// 1-based, as in the file
+	pub starting_track: u8,
+	pub load_adress: u16,
+	pub init_adress: u16,
+	pub play_adress: u16,
+	pub title: String,
+	pub artist: String,
+	pub copyright: String,
+	// Microseconds between play calls
+	pub ntsc_speed: u16,
+	pub bank_init: [u8; 8],
+	pub vrc6: bool
+}
+
+impl NsfHeader {
+	pub fn parse(buffer: &[u8]) -> Result<NsfHeader, NsfError> {
+		if buffer.len() < 5 || buffer[0..=4] != [0x4e, 0x45, 0x53, 0x4d, 0x1a] {
+			return Err(NsfError::BadMagic);
+		}
+		if buffer.len() < HEADER_SIZE {
+			return Err(NsfError::Truncated { expected: HEADER_SIZE, got: buffer.len() });
+		}
+
+		let word = |offset: usize| u16::from_le_bytes([buffer[offset], buffer[offset + 1]]);
+		let text = |offset: usize| {
+			let field = &buffer[offset..offset + 32];
+			let end = field.iter().position(|&c| c == 0).unwrap_or(field.len());
+			String::from_utf8_lossy(&field[..end]).into_owned()
+		};
+
+		let mut bank_init = [0; 8];
+		bank_init.copy_from_slice(&buffer[0x70..0x78]);
+
+		Ok(NsfHeader {
+			track_count: buffer[0x06],
+			starting_track: buffer[0x07],
+			load_adress: word(0x08),
+			init_adress: word(0x0A),
+			play_adress: word(0x0C),
+			title: text(0x0E),
+			artist: text(0x2E),
+			copyright: text(0x4E),
+			ntsc_speed: word(0x6E),
+			bank_init,
+			vrc6: buffer[0x7B] & 0x01 != 0
+		})
+	}
+
+	pub fn is_bankswitched(&self) -> bool {
+		self.bank_init.iter().any(|&bank| bank != 0)
+	}
+}
+
+// Program data in 4KB banks at $8000-$FFFF, switched at $5FF8-$5FFF, and 8KB of RAM at $6000-$7FFF
+#[derive(Clone)]
+struct NsfMapper {
+	data: Vec<u8>,
+	banks: [u8; 8],
+	bankswitched: bool,
+	ram: [u8; 8192],
+	vrc6: bool
+}
+
+impl NsfMapper {
+	fn new(header: &NsfHeader, program: &[u8]) -> NsfMapper {
+		let bankswitched = header.is_bankswitched();
+
+		// Pad so that bank boundaries line up with the load address
+		let padding = if bankswitched {
+			usize::from(header.load_adress & 0x0FFF)
+		} else {
+			usize::from(header.load_adress.saturating_sub(0x8000))
+		};
+		let mut data = vec![0; padding];
+		data.extend_from_slice(program);
+
+		NsfMapper {
+			data,
+			banks: if bankswitched { header.bank_init } else { [0, 1, 2, 3, 4, 5, 6, 7] },
+			bankswitched,
+			ram: [0; 8192],
+			vrc6: header.vrc6
+		}
+	}
+}
+
+impl Mapper for NsfMapper {
+	fn cpu_read(&self, adress: u16) -> u8 {
+		match adress {
+			0x6000..=0x7FFF => self.ram[usize::from(adress - 0x6000)],
+			0x8000..=0xFFFF => {
+				let bank = usize::from(self.banks[usize::from((adress - 0x8000) >> 12)]);
+				let offset = bank * BANK_SIZE + usize::from(adress & 0x0FFF);
+				self.data.get(offset).copied().unwrap_or(0)
+			},
+			_ => 0
+		}
+	}
+
+	fn cpu_write(&mut self, adress: u16, value: u8) {
+		match adress {
+			0x5FF8..=0x5FFF if self.bankswitched => self.banks[usize::from(adress - 0x5FF8)] = value,
+			0x6000..=0x7FFF => self.ram[usize::from(adress - 0x6000)] = value,
+			_ => {}
+		}
+	}
+
+	fn ppu_read(&self, _adress: u16) -> u8 {
+		0
+	}
+
+	fn ppu_write(&mut self, _adress: u16, _value: u8) {}
+
+	fn has_chr_ram(&self) -> bool {
+		false
+	}
+
+	fn expansion_audio(&self) -> Option<Box<dyn ExpansionAudio>> {
+		if self.vrc6 {
+			Some(Box::new(Vrc6Audio::new()))
+		} else {
+			None
+		}
+	}
+}
+
+// Plays NSF tracks with the CPU and APU, the PPU stays idle with rendering off
+pub struct NsfPlayer {
+	header: NsfHeader,
+	cpu: Cpu,
+	bus: Bus,
+	track: u8,
+	play_cycles: u64
+}
+
+impl NsfPlayer {
+	pub fn new(buffer: &[u8]) -> Result<NsfPlayer, NsfError> {
+		let header = NsfHeader::parse(buffer)?;
+		let rom = Rom {
+			mapper: Box::new(NsfMapper::new(&header, &buffer[HEADER_SIZE..])),
+			mirroring: Mirroring::Horizontal,
+			// The NSF mapper holds its own RAM
+			prg_ram_size: 0,
+			battery: false,
+			info: None
+		};
+
+		let speed = if header.ntsc_speed == 0 { 16639 } else { header.ntsc_speed };
+		let play_cycles = (f64::from(speed) * CPU_FREQUENCY / 1_000_000.0) as u64;
+
+		let mut player = NsfPlayer {
+			header,
+			cpu: Cpu::new(),
+			bus: Bus::new(rom),
+			track: 0,
+			play_cycles
+		};
+		player.select_track(player.header.starting_track.saturating_sub(1));
+		Ok(player)
+	}
+
+	pub fn header(&self) -> &NsfHeader {
+		&self.header
+	}
+
+	pub fn track_count(&self) -> u8 {
+		self.header.track_count
+	}
+
+	// 0-based
+	pub fn current_track(&self) -> u8 {
+		self.track
+	}
+
+	// Reset the machine and run the init routine of the 0-based `track`
+	pub fn select_track(&mut self, track: u8) {
+		self.track = track.min(self.header.track_count.saturating_sub(1));
+
+		for adress in 0x0000..0x0800 {
+			self.bus.write(adress, 0);
+		}
+		for adress in 0x6000..0x8000 {
+			self.bus.write(adress, 0);
+		}
+		for adress in 0x4000..0x4014 {
+			self.bus.write(adress, 0);
+		}
+		self.bus.write(0x4015, 0x0F);
+		self.bus.write(0x4017, 0x40);
+
+		if self.header.is_bankswitched() {
+			for (i, &bank) in self.header.bank_init.iter().enumerate() {
+				self.bus.write(0x5FF8 + i as u16, bank);
+			}
+		}
+
+		self.cpu = Cpu::new();
+		self.cpu.set_a(self.track);
+		self.cpu.set_x(0); // NTSC
+		self.call(self.header.init_adress);
+	}
+
+	// Run the play routine and the idle time until the next call
+	pub fn run_frame(&mut self) {
+		let start = self.cpu.cycles();
+		self.call(self.header.play_adress);
+
+		let elapsed = self.cpu.cycles() - start;
+		for _ in elapsed..self.play_cycles {
+			self.bus.tick(1);
+		}
+	}
+
+	fn call(&mut self, adress: u16) {
+		self.cpu.call(&mut self.bus, adress, RETURN_ADRESS);
+
+		let start = self.cpu.cycles();
+		while self.cpu.pc != RETURN_ADRESS && self.cpu.cycles() - start < MAX_CALL_CYCLES {
+			self.cpu.step(&mut self.bus);
+		}
+	}
+
+	pub fn take_samples(&mut self) -> Vec<f32> {
+		self.bus.apu_mut().take_samples()
+	}
+
+	pub fn bus_mut(&mut self) -> &mut Bus {
+		&mut self.bus
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn test_nsf() -> Vec<u8> {
+		let mut nsf = vec![0; HEADER_SIZE];
+		nsf[0..5].copy_from_slice(&[0x4e, 0x45, 0x53, 0x4d, 0x1a]);
+		nsf[0x06] = 3;
+		nsf[0x07] = 1;
+		nsf[0x08..0x0A].copy_from_slice(&0x8000u16.to_le_bytes());
+		nsf[0x0A..0x0C].copy_from_slice(&0x8000u16.to_le_bytes());
+		nsf[0x0C..0x0E].copy_from_slice(&0x8003u16.to_le_bytes());
+		nsf[0x0E..0x13].copy_from_slice(b"Title");
+		nsf[0x6E..0x70].copy_from_slice(&16639u16.to_le_bytes());
+
+		// init: STA $00, RTS / play: INC $01, RTS
+		nsf.extend_from_slice(&[0x85, 0x00, 0x60, 0xE6, 0x01, 0x60]);
+		nsf
+	}
+
+	#[test]
+	fn header() {
+		let header = NsfHeader::parse(&test_nsf()).unwrap();
+
+		assert_eq!(header.track_count, 3);
+		assert_eq!(header.title, "Title");
+		assert_eq!(header.play_adress, 0x8003);
+		assert!(!header.is_bankswitched());
+	}
+
+	#[test]
+	fn init_and_play() {
+		let mut player = NsfPlayer::new(&test_nsf()).unwrap();
+		player.select_track(2);
+		assert_eq!(player.bus_mut().read(0x0000), 2);
+
+		for _ in 0..3 {
+			player.run_frame();
+		}
+		assert_eq!(player.bus_mut().read(0x0001), 3);
+
+		// A frame produces a 60th of a second of audio
+		player.take_samples();
+		player.run_frame();
+		assert!((733..=735).contains(&player.take_samples().len()));
+	}
+
+	#[test]
+	fn bankswitching() {
+		let header = NsfHeader::parse(&test_nsf()).unwrap();
+		let mut mapper = NsfMapper::new(&NsfHeader { bank_init: [0, 1, 0, 0, 0, 0, 0, 0], ..header }, &[0xAA; BANK_SIZE * 2]);
+
+		mapper.cpu_write(0x5FF8, 1);
+		assert_eq!(mapper.cpu_read(0x8000), 0xAA);
+		mapper.cpu_write(0x5FF8, 5);
+		assert_eq!(mapper.cpu_read(0x8000), 0x00);
+	}
+
+	#[test]
+	fn invalid_files() {
+		assert!(matches!(NsfHeader::parse(b"NESM"), Err(NsfError::BadMagic)));
+		assert!(matches!(NsfHeader::parse(&[0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0]), Err(NsfError::BadMagic)));
+		assert!(matches!(NsfPlayer::new(&test_nsf()[..0x40]), Err(NsfError::Truncated { expected: 0x80, got: 0x40 })));
+	}
+}
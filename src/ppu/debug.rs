@@ -0,0 +1,261 @@
+use crate::rom::Rom;
+
+use super::Ppu;
+use super::frame::Frame;
+
+pub const PATTERN_TABLE_SIZE: usize = 128;
+
+// Decoded OAM entry
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SpriteInfo {
+	pub index: u8,
+	pub x: u8,
+	pub y: u8,
+	pub tile: u8,
+	pub palette: u8,
+	pub behind_background: bool,
+	pub flip_horizontal: bool,
+	pub flip_vertical: bool,
+	pub on_screen: bool
+}
+
+// Registers as left at the end of a scanline
+#[derive(Clone, Copy, Debug)]
+pub struct ScanlineState {
+	pub ctrl: u8,
+	pub mask: u8,
+	pub status: u8,
+	pub vram_addr: u16,
+	pub temp_addr: u16,
+	pub fine_x: u8
+}
+
+impl ScanlineState {
+	// Horizontal scroll of the next line, from 0 to 511 across both nametables
+	pub fn scroll_x(&self) -> u16 {
+		let coarse_x = self.temp_addr & 0x001F;
+		let nametable_x = (self.temp_addr >> 10) & 0x01;
+		nametable_x * 256 + coarse_x * 8 + u16::from(self.fine_x)
+	}
+
+	// Vertical position of the next fetched line, from 0 to 479 across both nametables
+	pub fn scroll_y(&self) -> u16 {
+		let fine_y = (self.vram_addr >> 12) & 0x07;
+		let coarse_y = (self.vram_addr >> 5) & 0x1F;
+		let nametable_y = (self.vram_addr >> 11) & 0x01;
+		nametable_y * 240 + coarse_y * 8 + fine_y
+	}
+}
+
+pub type ScanlineCallback = dyn FnMut(u16, &ScanlineState);
+
+// Callback slot, callbacks are not carried over to a cloned PPU
+#[derive(Default)]
+pub(crate) struct ScanlineHook(pub(crate) Option<Box<ScanlineCallback>>);
+
+impl Clone for ScanlineHook {
+	fn clone(&self) -> Self {
+		ScanlineHook(None)
+	}
+}
+
+impl Ppu {
+	// Called with the scanline number and register state each time a scanline ends
+	pub fn on_scanline<F>(&mut self, callback: F)
+	where
+		F: FnMut(u16, &ScanlineState) + 'static
+	{
+		self.scanline_callback.0 = Some(Box::new(callback));
+	}
+
+	pub fn clear_scanline_callback(&mut self) {
+		self.scanline_callback.0 = None;
+	}
+
+	// Move the callback of `from` over, for a PPU taking over the state of another
+	pub(crate) fn take_scanline_callback(&mut self, from: &mut Ppu) {
+		self.scanline_callback = std::mem::take(&mut from.scanline_callback);
+	}
+
+	pub fn scanline_state(&self) -> ScanlineState {
+		ScanlineState {
+			ctrl: self.ctrl.get(),
+			mask: self.mask.get(),
+			status: self.status.get(),
+			vram_addr: self.addr.vram_addr(),
+			temp_addr: self.addr.temp_addr(),
+			fine_x: self.addr.fine_x()
+		}
+	}
+
+	// Both 4KB pattern tables as 128x128 images, colored with one of the 8 palettes
+	pub fn render_pattern_tables(&self, rom: &Rom, palette: u8) -> (Frame, Frame) {
+		(
+			self.render_pattern_table(rom, 0x0000, palette),
+			self.render_pattern_table(rom, 0x1000, palette)
+		)
+	}
+
+	pub fn sprites(&self) -> [SpriteInfo; 64] {
+		std::array::from_fn(|i| {
+			let y = self.oam_data[i * 4];
+			let attributes = self.oam_data[i * 4 + 2];
+
+			SpriteInfo {
+				index: i as u8,
+				x: self.oam_data[i * 4 + 3],
+				y,
+				tile: self.oam_data[i * 4 + 1],
+				palette: attributes & 0x03,
+				behind_background: attributes & 0x20 != 0,
+				flip_horizontal: attributes & 0x40 != 0,
+				flip_vertical: attributes & 0x80 != 0,
+				on_screen: y < 0xEF // Y is one line above, $EF-$FF are hidden
+			}
+		})
+	}
+
+	// Sprite drawn alone, 8x8 or 8x16 depending on PPUCTRL, transparent pixels are black
+	pub fn sprite_thumbnail(&self, rom: &Rom, index: usize) -> Frame {
+		let sprite = self.sprites()[index];
+		let height = u16::from(self.ctrl.sprite_size());
+		let mut frame = Frame::new(8, usize::from(height));
+
+		for row in 0..height {
+			let source_row = if sprite.flip_vertical { height - 1 - row } else { row };
+			let pattern_addr = if height == 16 {
+				let bank = u16::from(sprite.tile & 0x01) * 0x1000;
+				let tile = u16::from(sprite.tile & 0xFE) + (source_row / 8);
+				bank + tile * 16 + (source_row % 8)
+			} else {
+				self.ctrl.sprite_pattern_addr() + u16::from(sprite.tile) * 16 + source_row
+			};
+			let low = rom.mapper.ppu_read(pattern_addr);
+			let high = rom.mapper.ppu_read(pattern_addr + 8);
+
+			for bit in 0..8u8 {
+				let shift = if sprite.flip_horizontal { bit } else { 7 - bit };
+				let pixel = (((high >> shift) & 0x01) << 1) | ((low >> shift) & 0x01);
+				if pixel == 0 {
+					continue;
+				}
+
+				let index = 0x10 + u16::from(sprite.palette) * 4 + u16::from(pixel);
+				let color = self.palette_table[Ppu::palette_index(index)];
+				frame.set_pixel(usize::from(bit), usize::from(row), self.palette.color(color));
+			}
+		}
+
+		frame
+	}
+
+	fn render_pattern_table(&self, rom: &Rom, base: u16, palette: u8) -> Frame {
+		let mut frame = Frame::new(PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE);
+		let palette_base = u16::from(palette & 0x07) * 4;
+
+		for tile in 0..256u16 {
+			let tile_x = usize::from(tile % 16) * 8;
+			let tile_y = usize::from(tile / 16) * 8;
+
+			for row in 0..8u16 {
+				let low = rom.mapper.ppu_read(base + tile * 16 + row);
+				let high = rom.mapper.ppu_read(base + tile * 16 + row + 8);
+
+				for bit in 0..8 {
+					let pixel = (((high >> (7 - bit)) & 0x01) << 1) | ((low >> (7 - bit)) & 0x01);
+					let index = if pixel == 0 { 0 } else { palette_base + u16::from(pixel) };
+					let color = self.palette_table[Ppu::palette_index(index)];
+
+					frame.set_pixel(tile_x + bit, tile_y + usize::from(row), self.palette.color(color));
+				}
+			}
+		}
+
+		frame
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::mapper::nrom::Nrom;
+	use crate::ppu::palette::SYSTEM_PALETTE;
+	use crate::rom::{Mirroring, test};
+
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	#[test]
+	fn pattern_tables() {
+		// Tile 1 of the right table has its first row set to color 3
+		let mut chr_rom = vec![0; 8192];
+		chr_rom[0x1010] = 0xFF;
+		chr_rom[0x1018] = 0xFF;
+		let mut rom = Rom {
+			mapper: Box::new(Nrom::new(vec![0; 16384], chr_rom, false)),
+			mirroring: Mirroring::Horizontal,
+			prg_ram_size: 0,
+			battery: false,
+			info: None
+		};
+
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+		ppu.write_to_addr(0x3F);
+		ppu.write_to_addr(0x00);
+		for color in [0x0F, 0x01, 0x02, 0x03, 0x0F, 0x11, 0x12, 0x13] {
+			ppu.write(&mut rom, color);
+		}
+
+		let (left, right) = ppu.render_pattern_tables(&rom, 1);
+		assert_eq!(left.pixel(8, 0), SYSTEM_PALETTE[0x0F]);
+		assert_eq!(right.pixel(8, 0), SYSTEM_PALETTE[0x13]);
+		assert_eq!(right.pixel(8, 1), SYSTEM_PALETTE[0x0F]);
+	}
+
+	#[test]
+	fn sprites() {
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+		ppu.write_oam_addr(4);
+		for value in [0x20, 0x05, 0b1110_0010, 0x30] {
+			ppu.write_oam_data(value);
+		}
+
+		let sprites = ppu.sprites();
+		assert_eq!(sprites[1], SpriteInfo {
+			index: 1,
+			x: 0x30,
+			y: 0x20,
+			tile: 0x05,
+			palette: 2,
+			behind_background: true,
+			flip_horizontal: true,
+			flip_vertical: true,
+			on_screen: true
+		});
+		assert!(sprites[0].on_screen);
+
+		ppu.write_oam_addr(0);
+		ppu.write_oam_data(0xF0);
+		assert!(!ppu.sprites()[0].on_screen);
+	}
+
+	#[test]
+	fn scanline_callback() {
+		let mut rom = test::test_rom();
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+
+		let lines = Rc::new(RefCell::new(Vec::new()));
+		let recorded = Rc::clone(&lines);
+		ppu.on_scanline(move |scanline, state| {
+			recorded.borrow_mut().push((scanline, state.scroll_x()));
+		});
+
+		ppu.tick(&mut rom, 341 * 2);
+		ppu.write_to_scroll(0x10);
+		ppu.write_to_scroll(0x00);
+		ppu.tick(&mut rom, 341);
+
+		assert_eq!(*lines.borrow(), vec![(0, 0), (1, 0), (2, 0x10)]);
+	}
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::rom::hash;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+// RGB888 image
+#[derive(Clone)]
+pub struct Frame {
+	pub width: usize,
+	pub height: usize,
+	pub data: Vec<u8>
+}
+
+impl Frame {
+	pub fn new(width: usize, height: usize) -> Frame {
+		Frame {
+			width,
+			height,
+			data: vec![0; width * height * 3]
+		}
+	}
+
+	pub fn set_pixel(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+		let base = (y * self.width + x) * 3;
+		if base + 2 < self.data.len() {
+			self.data[base] = rgb.0;
+			self.data[base + 1] = rgb.1;
+			self.data[base + 2] = rgb.2;
+		}
+	}
+
+	pub fn pixel(&self, x: usize, y: usize) -> (u8, u8, u8) {
+		let base = (y * self.width + x) * 3;
+		(self.data[base], self.data[base + 1], self.data[base + 2])
+	}
+
+	// Keeps one pixel out of `factor` in both directions, for thumbnails
+	pub fn downscale(&self, factor: usize) -> Frame {
+		let mut small = Frame::new(self.width / factor, self.height / factor);
+		for y in 0..small.height {
+			for x in 0..small.width {
+				small.set_pixel(x, y, self.pixel(x * factor, y * factor));
+			}
+		}
+		small
+	}
+
+	// CRC-32 of the pixels, equal frames give equal hashes on every platform
+	pub fn hash(&self) -> u32 {
+		hash::crc32(&[&self.data])
+	}
+
+	pub fn to_rgba(&self) -> Vec<u8> {
+		self.data.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF]).collect()
+	}
+
+	// Binary PPM, readable by most image tools without any dependency
+	pub fn to_ppm(&self) -> Vec<u8> {
+		let mut ppm = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+		ppm.extend_from_slice(&self.data);
+		ppm
+	}
+
+	pub fn save_ppm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		fs::write(path, self.to_ppm())
+	}
+
+	#[cfg(feature = "png")]
+	pub fn write_png<W: io::Write>(&self, output: W) -> io::Result<()> {
+		let mut encoder = png::Encoder::new(output, self.width as u32, self.height as u32);
+		encoder.set_color(png::ColorType::Rgb);
+		encoder.set_depth(png::BitDepth::Eight);
+
+		let mut writer = encoder.write_header().map_err(io::Error::other)?;
+		writer.write_image_data(&self.data).map_err(io::Error::other)?;
+		writer.finish().map_err(io::Error::other)
+	}
+
+	#[cfg(feature = "png")]
+	pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		self.write_png(io::BufWriter::new(fs::File::create(path)?))
+	}
+
+	// PNG when the path ends in .png, PPM otherwise
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let png = path.as_ref().extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png"));
+		if !png {
+			return self.save_ppm(path);
+		}
+
+		self.save_png_if_enabled(path)
+	}
+
+	#[cfg(feature = "png")]
+	fn save_png_if_enabled<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		self.save_png(path)
+	}
+
+	#[cfg(not(feature = "png"))]
+	fn save_png_if_enabled<P: AsRef<Path>>(&self, _path: P) -> io::Result<()> {
+		Err(io::Error::new(io::ErrorKind::Unsupported, "PNG support needs the png feature"))
+	}
+}
+
+impl Default for Frame {
+	fn default() -> Self {
+		Self::new(WIDTH, HEIGHT)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ppm() {
+		let mut frame = Frame::new(2, 1);
+		frame.set_pixel(1, 0, (1, 2, 3));
+		assert_eq!(frame.to_ppm(), b"P6\n2 1\n255\n\0\0\0\x01\x02\x03".to_vec());
+	}
+
+	#[cfg(feature = "png")]
+	#[test]
+	fn png() {
+		let mut frame = Frame::new(2, 2);
+		frame.set_pixel(1, 1, (255, 0, 0));
+
+		let mut bytes = Vec::new();
+		frame.write_png(&mut bytes).unwrap();
+
+		let decoder = png::Decoder::new(io::Cursor::new(bytes));
+		let mut reader = decoder.read_info().unwrap();
+		let mut data = vec![0; reader.output_buffer_size().unwrap()];
+		reader.next_frame(&mut data).unwrap();
+		assert_eq!(data, frame.data);
+	}
+}
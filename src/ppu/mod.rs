@@ -0,0 +1,932 @@
+pub mod registers;
+pub mod palette;
+pub mod frame;
+pub mod debug;
+
+use crate::region::Region;
+use crate::rom::{Mirroring, Rom};
+use crate::state::{ensure, StateError, StateReader, StateWriter};
+
+use registers::*;
+use palette::Palette;
+use debug::ScanlineHook;
+use frame::{Frame, WIDTH, HEIGHT};
+
+#[derive(Clone, Copy)]
+struct SpritePixel {
+	pixel: u8,
+	palette: u8,
+	behind: bool,
+	zero: bool
+}
+
+const IO_LATCH_DECAY_FRAMES: u64 = 36;
+
+pub const DOTS_PER_SCANLINE: u16 = 341;
+// NTSC frame, Region has the PAL and Dendy ones
+pub const SCANLINES_PER_FRAME: u16 = 262;
+pub const VBLANK_SCANLINE: u16 = 241;
+pub const PRE_RENDER_SCANLINE: u16 = 261;
+
+// Serde skips the palette and the scanline callback, which are frontend settings
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ppu {
+	palette_table: [u8; 32],
+	// 2KB of console VRAM, followed by the 2KB cartridge VRAM used in four-screen mode
+	#[cfg_attr(feature = "serde", serde(with = "crate::state::byte_array"))]
+	vram: [u8; 4096],
+	#[cfg_attr(feature = "serde", serde(with = "crate::state::byte_array"))]
+	oam_data: [u8; 256],
+	oam_addr: u8,
+	internal_data_buf: u8,
+	io_latch: u8,
+	io_latch_frame: u64,
+
+	pub addr: AddrRegister,
+	pub ctrl: ControlRegister,
+	pub mask: MaskRegister,
+	pub status: StatusRegister,
+
+	mirroring: Mirroring,
+	region: Region,
+
+	dot: u16,
+	scanline: u16,
+	frame: u64,
+	nmi_interrupt: bool,
+	nmi_delay: u8,
+	suppress_vblank: bool,
+
+	// Palette index (bits 0-5) and emphasis (bits 6-8) of each pixel
+	frame_buffer: Vec<u16>,
+	#[cfg_attr(feature = "serde", serde(skip))]
+	palette: Palette,
+
+	#[cfg_attr(feature = "serde", serde(skip))]
+	scanline_callback: ScanlineHook
+}
+
+impl Ppu {
+	pub fn new(mirroring: Mirroring) -> Ppu {
+		Ppu {
+			palette_table: [0; 32],
+			vram: [0; 4096],
+			oam_data: [0; 256],
+			oam_addr: 0x00,
+			internal_data_buf: 0x00,
+			io_latch: 0x00,
+			io_latch_frame: 0,
+			addr: AddrRegister::new(),
+			ctrl: ControlRegister::new(),
+			mask: MaskRegister::new(),
+			status: StatusRegister::new(),
+			mirroring,
+			region: Region::Ntsc,
+			dot: 0,
+			scanline: 0,
+			frame: 0,
+			nmi_interrupt: false,
+			nmi_delay: 0,
+			suppress_vblank: false,
+			frame_buffer: vec![0; WIDTH * HEIGHT],
+			palette: Palette::ntsc(),
+			scanline_callback: ScanlineHook::default()
+		}
+	}
+
+	// Advance the PPU by `cycles` dots, return true when a new frame begins
+	pub fn tick(&mut self, rom: &mut Rom, cycles: u16) -> bool {
+		let mut new_frame = false;
+
+		for _ in 0..cycles {
+			new_frame |= self.step(rom);
+		}
+
+		new_frame
+	}
+
+	fn step(&mut self, rom: &mut Rom) -> bool {
+		let rendering = self.mask.is_rendering();
+		let pre_render = self.region.pre_render_scanline();
+		let vblank = self.region.vblank_scanline();
+		let render_line = self.scanline < HEIGHT as u16 || self.scanline == pre_render;
+
+		match (self.scanline, self.dot) {
+			(0..=239, 256) => {
+				self.render_scanline(rom);
+				if rendering {
+					self.addr.increment_y();
+				}
+			},
+			(line, 256) if line == pre_render && rendering => self.addr.increment_y(),
+			(_, 257) if render_line && rendering => self.addr.copy_horizontal(),
+			(_, 260) if render_line && rendering => rom.mapper.notify_scanline(),
+			(line, 280..=304) if line == pre_render && rendering => self.addr.copy_vertical(),
+			(line, 1) if line == vblank => {
+				// A $2002 read just before the flag is set hides it for the whole frame
+				if !self.suppress_vblank {
+					self.status.set(VBLANK_STARTED, true);
+					if self.ctrl.contains(GENERATE_NMI) {
+						self.nmi_interrupt = true;
+						self.nmi_delay = 0;
+					}
+				}
+				self.suppress_vblank = false;
+			},
+			(line, 1) if line == pre_render => {
+				self.status.set(VBLANK_STARTED, false);
+				self.status.set(SPRITE_ZERO_HIT, false);
+				self.status.set(SPRITE_OVERFLOW, false);
+				self.nmi_interrupt = false;
+			},
+			_ => {}
+		}
+
+		self.dot += 1;
+
+		// Odd frames skip the last dot of the pre-render line when rendering is on
+		if self.scanline == pre_render && self.dot == DOTS_PER_SCANLINE - 1
+			&& self.frame % 2 == 1 && self.mask.is_rendering() && self.region.skips_odd_frame_dot() {
+			self.dot += 1;
+		}
+
+		if self.dot >= DOTS_PER_SCANLINE {
+			if let Some(mut callback) = self.scanline_callback.0.take() {
+				callback(self.scanline, &self.scanline_state());
+				self.scanline_callback.0 = Some(callback);
+			}
+
+			self.dot = 0;
+			self.scanline += 1;
+
+			if self.scanline >= self.region.scanlines_per_frame() {
+				self.scanline = 0;
+				self.frame += 1;
+				return true;
+			}
+		}
+
+		false
+	}
+
+	pub fn poll_nmi(&mut self) -> bool {
+		if !self.nmi_interrupt {
+			return false;
+		}
+
+		// NMI raised by PPUCTRL is seen by the CPU one instruction later
+		if self.nmi_delay > 0 {
+			self.nmi_delay -= 1;
+			return false;
+		}
+
+		self.nmi_interrupt = false;
+		true
+	}
+
+	pub fn region(&self) -> Region {
+		self.region
+	}
+
+	// Scanline count and vblank position, set before the first frame
+	pub fn set_region(&mut self, region: Region) {
+		self.region = region;
+	}
+
+	pub fn scanline(&self) -> u16 {
+		self.scanline
+	}
+
+	pub fn dot(&self) -> u16 {
+		self.dot
+	}
+
+	pub fn frame_count(&self) -> u64 {
+		self.frame
+	}
+
+	pub fn write_oam_addr(&mut self, value: u8) {
+		self.oam_addr = value;
+	}
+
+	pub fn write_oam_data(&mut self, value: u8) {
+		self.oam_data[self.oam_addr as usize] = value;
+		self.oam_addr = self.oam_addr.wrapping_add(1);
+	}
+
+	pub fn read_oam_data(&self) -> u8 {
+		self.oam_data[self.oam_addr as usize]
+	}
+
+	// Register at $2000-$2007
+	pub fn read_register(&mut self, rom: &Rom, adress: u16) -> u8 {
+		let value = match adress {
+			0x2002 => (self.read_status() & 0xE0) | (self.open_bus() & 0x1F),
+			0x2004 => self.read_oam_data(),
+			0x2007 => self.read(rom),
+			_ => self.open_bus() // Write-only register
+		};
+
+		self.refresh_latch(value);
+		value
+	}
+
+	// Value a register read would return, without clearing flags or moving the VRAM address
+	pub fn peek_register(&self, adress: u16) -> u8 {
+		match adress {
+			0x2002 => (self.status.get() & 0xE0) | (self.open_bus() & 0x1F),
+			0x2004 => self.read_oam_data(),
+			0x2007 => {
+				let addr = self.addr.get();
+				if addr >= 0x3F00 {
+					(self.palette_table[Ppu::palette_index(addr)] & 0x3F) | (self.open_bus() & 0xC0)
+				} else {
+					self.internal_data_buf
+				}
+			},
+			_ => self.open_bus()
+		}
+	}
+
+	// PPU address space, unbuffered
+	pub fn peek(&self, rom: &Rom, addr: u16) -> u8 {
+		let addr = addr & 0x3FFF;
+		match addr {
+			0..=0x1FFF => rom.mapper.ppu_read(addr),
+			0x2000..=0x3EFF => self.read_nametable(rom, addr & 0x2FFF),
+			_ => self.palette_table[Ppu::palette_index(addr)]
+		}
+	}
+
+	pub fn write_register(&mut self, rom: &mut Rom, adress: u16, value: u8) {
+		self.refresh_latch(value);
+
+		match adress {
+			0x2000 => self.write_to_ctrl(value),
+			0x2001 => self.mask.write(value),
+			0x2002 => {}, // Read-only
+			0x2003 => self.write_oam_addr(value),
+			0x2004 => self.write_oam_data(value),
+			0x2005 => self.write_to_scroll(value),
+			0x2006 => self.write_to_addr(value),
+			0x2007 => self.write(rom, value),
+			_ => panic!("{:#06x} is not a PPU register", adress)
+		}
+	}
+
+	// Value left on the PPU data bus, fading after ~600ms
+	fn open_bus(&self) -> u8 {
+		if self.frame - self.io_latch_frame > IO_LATCH_DECAY_FRAMES {
+			0x00
+		} else {
+			self.io_latch
+		}
+	}
+
+	fn refresh_latch(&mut self, value: u8) {
+		self.io_latch = value;
+		self.io_latch_frame = self.frame;
+	}
+
+	pub fn write_to_ctrl(&mut self, value: u8) {
+		let was_enabled = self.ctrl.contains(GENERATE_NMI);
+		self.ctrl.write(value);
+		self.addr.write_nametable(value);
+
+		let enabled = self.ctrl.contains(GENERATE_NMI);
+		if !was_enabled && enabled && self.status.contains(VBLANK_STARTED) {
+			// Enabling NMI during vblank triggers it right away
+			self.nmi_interrupt = true;
+			self.nmi_delay = 1;
+		} else if was_enabled && !enabled && self.is_near_vblank_start() {
+			// Disabling it right as vblank starts cancels it
+			self.nmi_interrupt = false;
+		}
+	}
+
+	// Dots following the vblank flag set where a race with the CPU is possible
+	fn is_near_vblank_start(&self) -> bool {
+		self.scanline == self.region.vblank_scanline() && (1..=3).contains(&self.dot)
+	}
+
+	pub fn write_to_scroll(&mut self, value: u8) {
+		self.addr.write_scroll(value);
+	}
+
+	pub fn write_to_addr(&mut self, value: u8) {
+		self.addr.write(value);
+	}
+
+	pub fn write_oam_dma(&mut self, data: &[u8; 256]) {
+		for value in data.iter() {
+			self.write_oam_data(*value);
+		}
+	}
+
+	pub fn read_status(&mut self) -> u8 {
+		let value = self.status.get();
+
+		if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+			// Read on the dot the flag is set: reads clear and no NMI
+			self.suppress_vblank = true;
+		} else if self.is_near_vblank_start() {
+			// Read right after: flag is seen, but the NMI is cancelled
+			self.nmi_interrupt = false;
+		}
+
+		self.status.set(VBLANK_STARTED, false);
+		self.addr.reset_latch();
+
+		value
+	}
+
+	pub fn increment_vram_addr(&mut self) {
+		self.addr.increment(self.ctrl.vram_addr_increment());
+	}
+
+	pub fn read(&mut self, rom: &Rom) -> u8 {
+		let addr = self.addr.get();
+		self.increment_vram_addr();
+
+		match addr {
+			0..=0x1FFF => {
+				let result = self.internal_data_buf;
+				self.internal_data_buf = rom.mapper.ppu_read(addr);
+				result
+			},
+           	0x2000..=0x2FFF => {
+				let result = self.internal_data_buf;
+				self.internal_data_buf = self.read_nametable(rom, addr);
+				result
+			},
+           	0x3000..=0x3EFF => {
+				// Mirror of $2000-$2EFF
+				let result = self.internal_data_buf;
+				self.internal_data_buf = self.read_nametable(rom, addr - 0x1000);
+				result
+			},
+           	0x3F00..=0x3FFF => {
+				// Palette is not buffered, but the nametable "below" fills the buffer
+				self.internal_data_buf = self.read_nametable(rom, addr - 0x1000);
+				(self.palette_table[Ppu::palette_index(addr)] & 0x3F) | (self.open_bus() & 0xC0)
+           	}
+           	_ => panic!("unexpected access to mirrored space {}", addr),
+		}
+	}
+
+	pub fn write(&mut self, rom: &mut Rom, value: u8) {
+		let addr = self.addr.get();
+		match addr {
+			0..=0x1FFF => rom.mapper.ppu_write(addr, value),
+			0x2000..=0x2FFF => {
+				if !rom.mapper.write_nametable(addr, value) {
+					self.vram[self.mirror_vram_addr(addr) as usize] = value;
+				}
+			},
+			0x3000..=0x3EFF => {
+				if !rom.mapper.write_nametable(addr - 0x1000, value) {
+					self.vram[self.mirror_vram_addr(addr - 0x1000) as usize] = value;
+				}
+			},
+			0x3F00..=0x3FFF => {
+				self.palette_table[Ppu::palette_index(addr)] = value;
+			}
+			_ => panic!("unexpected access to mirrored space {}", addr),
+		}
+
+		self.increment_vram_addr();
+	}
+
+	// Indexed frame: palette index in bits 0-5, PPUMASK emphasis in bits 6-8
+	pub fn frame_buffer(&self) -> &[u16] {
+		&self.frame_buffer
+	}
+
+	pub fn set_palette(&mut self, palette: Palette) {
+		self.palette = palette;
+	}
+
+	pub fn palette(&self) -> &Palette {
+		&self.palette
+	}
+
+	// Nametable RAM, the upper 2KB are the cartridge VRAM of four-screen boards
+	pub fn vram(&self) -> &[u8; 4096] {
+		&self.vram
+	}
+
+	// Palette RAM at $3F00-$3F1F, `palette()` is the RGB conversion table
+	pub fn palette_ram(&self) -> &[u8; 32] {
+		&self.palette_table
+	}
+
+	pub fn oam(&self) -> &[u8; 256] {
+		&self.oam_data
+	}
+
+	pub fn frame_rgb(&self) -> Frame {
+		let mut frame = Frame::new(WIDTH, HEIGHT);
+
+		for (i, pixel) in self.frame_buffer.iter().enumerate() {
+			let rgb = self.palette.emphasized_color((pixel & 0x3F) as u8, (pixel >> 6) as u8);
+			frame.set_pixel(i % WIDTH, i / WIDTH, rgb);
+		}
+
+		frame
+	}
+
+	fn render_scanline(&mut self, rom: &Rom) {
+		let y = usize::from(self.scanline);
+		let mut line = [0u8; WIDTH]; // Palette RAM index of each pixel
+
+		if self.mask.is_rendering() {
+			let background = self.background_line(rom);
+			let sprites = self.sprite_line(rom);
+
+			for x in 0..WIDTH {
+				let (bg_pixel, bg_palette) = background[x];
+				let bg_visible = bg_pixel != 0 && self.mask.contains(SHOW_BACKGROUND)
+					&& (x >= 8 || self.mask.contains(SHOW_BACKGROUND_LEFT));
+
+				let sprite = sprites[x].filter(|_| self.mask.contains(SHOW_SPRITES)
+					&& (x >= 8 || self.mask.contains(SHOW_SPRITES_LEFT)));
+
+				line[x] = match sprite {
+					Some(sprite) => {
+						if sprite.zero && bg_visible && x != 255 {
+							self.status.set(SPRITE_ZERO_HIT, true);
+						}
+
+						if !sprite.behind || !bg_visible {
+							0x10 + sprite.palette * 4 + sprite.pixel
+						} else {
+							bg_palette * 4 + bg_pixel
+						}
+					},
+					None if bg_visible => bg_palette * 4 + bg_pixel,
+					None => 0
+				};
+			}
+		}
+
+		let greyscale = if self.mask.contains(GREYSCALE) { 0x30 } else { 0x3F };
+		let emphasis = u16::from(self.mask.emphasis()) << 6;
+		for (x, index) in line.iter().enumerate() {
+			let color = self.palette_table[Ppu::palette_index(u16::from(*index))] & greyscale;
+			self.frame_buffer[y * WIDTH + x] = u16::from(color) | emphasis;
+		}
+	}
+
+	// Pixel value and palette of the background, read from the current VRAM address
+	fn background_line(&self, rom: &Rom) -> [(u8, u8); WIDTH] {
+		let mut line = [(0u8, 0u8); WIDTH];
+		let mut v = self.addr.vram_addr();
+		let fine_x = usize::from(self.addr.fine_x());
+		let fine_y = (v >> 12) & 0x07;
+		let pattern_base = self.ctrl.background_pattern_addr();
+
+		// 33 tiles cover a scanline when fine X is not zero
+		for tile in 0..33 {
+			let tile_addr = 0x2000 | (v & 0x0FFF);
+			let attr_addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+
+			let tile_index = u16::from(self.read_nametable(rom, tile_addr));
+			let attribute = self.read_nametable(rom, attr_addr);
+			let shift = ((v >> 4) & 0x04) | (v & 0x02);
+			let palette = (attribute >> shift) & 0x03;
+
+			let pattern_addr = pattern_base + tile_index * 16 + fine_y;
+			let low = rom.mapper.ppu_read(pattern_addr);
+			let high = rom.mapper.ppu_read(pattern_addr + 8);
+
+			for bit in 0..8 {
+				let pixel = (((high >> (7 - bit)) & 0x01) << 1) | ((low >> (7 - bit)) & 0x01);
+				let x = (tile * 8 + bit) as isize - fine_x as isize;
+				if (0..WIDTH as isize).contains(&x) {
+					line[x as usize] = (pixel, palette);
+				}
+			}
+
+			// Coarse X increment
+			if (v & 0x001F) == 31 {
+				v &= !0x001F;
+				v ^= 0x0400;
+			} else {
+				v += 1;
+			}
+		}
+
+		line
+	}
+
+	fn sprite_line(&mut self, rom: &Rom) -> [Option<SpritePixel>; WIDTH] {
+		let mut line = [None; WIDTH];
+		let y = self.scanline;
+		let height = u16::from(self.ctrl.sprite_size());
+
+		let mut count = 0;
+		for i in 0..64 {
+			let sprite_y = u16::from(self.oam_data[i * 4]) + 1; // Sprites are drawn one line below
+			if y < sprite_y || y >= sprite_y + height {
+				continue;
+			}
+
+			count += 1;
+			if count > 8 {
+				self.status.set(SPRITE_OVERFLOW, true);
+				break;
+			}
+
+			let tile = self.oam_data[i * 4 + 1];
+			let attributes = self.oam_data[i * 4 + 2];
+			let sprite_x = usize::from(self.oam_data[i * 4 + 3]);
+			let flip_horizontal = attributes & 0x40 != 0;
+			let flip_vertical = attributes & 0x80 != 0;
+
+			let mut row = y - sprite_y;
+			if flip_vertical {
+				row = height - 1 - row;
+			}
+
+			let pattern_addr = if height == 16 {
+				let bank = u16::from(tile & 0x01) * 0x1000;
+				let tile = u16::from(tile & 0xFE) + (row / 8);
+				bank + tile * 16 + (row % 8)
+			} else {
+				self.ctrl.sprite_pattern_addr() + u16::from(tile) * 16 + row
+			};
+			let low = rom.mapper.ppu_read(pattern_addr);
+			let high = rom.mapper.ppu_read(pattern_addr + 8);
+
+			for bit in 0..8 {
+				let shift = if flip_horizontal { bit } else { 7 - bit };
+				let pixel = (((high >> shift) & 0x01) << 1) | ((low >> shift) & 0x01);
+				let x = sprite_x + bit;
+
+				// Lower OAM index has priority
+				if pixel == 0 || x >= WIDTH || line[x].is_some() {
+					continue;
+				}
+
+				line[x] = Some(SpritePixel {
+					pixel,
+					palette: attributes & 0x03,
+					behind: attributes & 0x20 != 0,
+					zero: i == 0
+				});
+			}
+		}
+
+		line
+	}
+
+	// Memories, registers, latches and timing for save states, with the picture being drawn
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_bytes(&self.palette_table);
+		state.write_bytes(&self.vram);
+		state.write_bytes(&self.oam_data);
+		state.write_u8(self.oam_addr);
+		state.write_u8(self.internal_data_buf);
+		state.write_u8(self.io_latch);
+		state.write_u64(self.io_latch_frame);
+		self.addr.save_state(state);
+		state.write_u8(self.ctrl.get());
+		state.write_u8(self.mask.get());
+		state.write_u8(self.status.get());
+		state.write_mirroring(Some(self.mirroring));
+		state.write_u16(self.dot);
+		state.write_u16(self.scanline);
+		state.write_u64(self.frame);
+		state.write_bool(self.nmi_interrupt);
+		state.write_u8(self.nmi_delay);
+		state.write_bool(self.suppress_vblank);
+		let pixels: Vec<u8> = self.frame_buffer.iter().flat_map(|pixel| pixel.to_le_bytes()).collect();
+		state.write_bytes(&pixels);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		state.read_into(&mut self.palette_table)?;
+		state.read_into(&mut self.vram)?;
+		state.read_into(&mut self.oam_data)?;
+		self.oam_addr = state.read_u8()?;
+		self.internal_data_buf = state.read_u8()?;
+		self.io_latch = state.read_u8()?;
+		self.io_latch_frame = state.read_u64()?;
+		self.addr.load_state(state)?;
+		self.ctrl.write(state.read_u8()?);
+		self.mask.write(state.read_u8()?);
+		self.status.restore(state.read_u8()?);
+		self.mirroring = state.read_mirroring()?.unwrap_or(self.mirroring);
+		self.dot = state.read_u16_in(0..=DOTS_PER_SCANLINE - 1)?;
+		// Any region, the longest frames have 312 scanlines
+		self.scanline = state.read_u16_in(0..=311)?;
+		self.frame = state.read_u64()?;
+		ensure(self.io_latch_frame <= self.frame, "PPU latch refreshed after the current frame")?;
+		self.nmi_interrupt = state.read_bool()?;
+		self.nmi_delay = state.read_u8()?;
+		self.suppress_vblank = state.read_bool()?;
+		let mut pixels = vec![0; self.frame_buffer.len() * 2];
+		state.read_into(&mut pixels)?;
+		for (pixel, bytes) in self.frame_buffer.iter_mut().zip(pixels.chunks_exact(2)) {
+			*pixel = u16::from_le_bytes([bytes[0], bytes[1]]);
+		}
+		Ok(())
+	}
+
+	// Take over a deserialized PPU, keeping the palette and the scanline callback
+	#[cfg(feature = "serde")]
+	pub(crate) fn restore(&mut self, mut state: Ppu) {
+		state.palette = self.palette.clone();
+		state.scanline_callback = std::mem::take(&mut self.scanline_callback);
+		*self = state;
+	}
+
+	pub fn set_mirroring(&mut self, mirroring: Mirroring) {
+		self.mirroring = mirroring;
+	}
+
+	pub fn mirroring(&self) -> Mirroring {
+		self.mirroring
+	}
+
+	// $3F20-$3FFF mirror $3F00-$3F1F, and $3F10/$3F14/$3F18/$3F1C mirror the background entries
+	fn palette_index(addr: u16) -> usize {
+		let index = usize::from(addr & 0x1F);
+		if index >= 0x10 && index % 4 == 0 {
+			index - 0x10
+		} else {
+			index
+		}
+	}
+
+	// Nametable byte at $2000-$2FFF, from the cartridge when it maps its own nametables
+	fn read_nametable(&self, rom: &Rom, addr: u16) -> u8 {
+		rom.mapper.read_nametable(addr)
+			.unwrap_or_else(|| self.vram[self.mirror_vram_addr(addr) as usize])
+	}
+
+	pub fn mirror_vram_addr(&self, addr: u16) -> u16 {
+		let mirrored_vram = addr & 0x2FFF; // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
+       	let vram_index = mirrored_vram - 0x2000; // to vram vector
+       	let name_table = vram_index / 0x400; // to the name table index
+       	match (&self.mirroring, name_table) {
+        	(Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => vram_index - 0x800,
+           	(Mirroring::Horizontal, 2) => vram_index - 0x400,
+           	(Mirroring::Horizontal, 1) => vram_index - 0x400,
+           	(Mirroring::Horizontal, 3) => vram_index - 0x800,
+           	(Mirroring::FourScreen, _) => vram_index,
+           	(Mirroring::SingleScreenLower, _) => vram_index & 0x03FF,
+           	(Mirroring::SingleScreenUpper, _) => (vram_index & 0x03FF) + 0x400,
+           	_ => vram_index,
+       }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::rom::test;
+	use crate::mapper::nrom::Nrom;
+
+	#[test]
+	fn mapper_scanline_notification() {
+		use std::{cell::Cell, rc::Rc};
+		use crate::mapper::Mapper;
+
+		#[derive(Clone)]
+		struct ScanlineCounter(Rc<Cell<u32>>);
+		impl Mapper for ScanlineCounter {
+			fn cpu_read(&self, _adress: u16) -> u8 { 0 }
+			fn cpu_write(&mut self, _adress: u16, _value: u8) {}
+			fn ppu_read(&self, _adress: u16) -> u8 { 0 }
+			fn ppu_write(&mut self, _adress: u16, _value: u8) {}
+			fn has_chr_ram(&self) -> bool { false }
+			fn notify_scanline(&mut self) {
+				self.0.set(self.0.get() + 1);
+			}
+		}
+
+		let count = Rc::new(Cell::new(0));
+		let mut rom = Rom {
+			mapper: Box::new(ScanlineCounter(Rc::clone(&count))),
+			mirroring: Mirroring::Horizontal,
+			prg_ram_size: 0,
+			battery: false,
+			info: None
+		};
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+
+		ppu.tick(&mut rom, 341 * 131);
+		ppu.tick(&mut rom, 341 * 131);
+		assert_eq!(count.get(), 0); // Rendering off
+
+		ppu.mask.write(0x18);
+		ppu.tick(&mut rom, 341 * 131);
+		ppu.tick(&mut rom, 341 * 131);
+		assert_eq!(count.get(), 241); // Visible lines and the pre-render line
+	}
+
+	#[test]
+	fn frame_length() {
+		let mut rom = test::test_rom();
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+		ppu.mask.write(SHOW_BACKGROUND);
+
+		// Even frame is complete
+		assert!(!ppu.tick(&mut rom, 341 * 131));
+		assert!(!ppu.tick(&mut rom, 341 * 131 - 1));
+		assert!(ppu.tick(&mut rom, 1));
+
+		// Odd frame skip a dot when rendering
+		assert!(!ppu.tick(&mut rom, 341 * 131));
+		assert!(!ppu.tick(&mut rom, 341 * 131 - 2));
+		assert!(ppu.tick(&mut rom, 1));
+		assert_eq!(ppu.frame_count(), 2);
+	}
+
+	#[test]
+	fn render_background() {
+		// Tile 0 is filled with color 1
+		let mut chr_rom = vec![0; 8192];
+		chr_rom[0..8].copy_from_slice(&[0xFF; 8]);
+		let mut rom = Rom {
+			mapper: Box::new(Nrom::new(vec![0; 16384], chr_rom, false)),
+			mirroring: Mirroring::Horizontal,
+			prg_ram_size: 0,
+			battery: false,
+			info: None
+		};
+
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+		ppu.write_to_addr(0x3F);
+		ppu.write_to_addr(0x00);
+		ppu.write(&mut rom, 0x0F);
+		ppu.write(&mut rom, 0x21);
+		ppu.write_to_addr(0x00);
+		ppu.write_to_addr(0x00);
+		ppu.mask.write(SHOW_BACKGROUND | SHOW_BACKGROUND_LEFT);
+
+		ppu.tick(&mut rom, 341 * 131);
+		ppu.tick(&mut rom, 341 * 131);
+
+		assert_eq!(ppu.frame_buffer()[0], 0x21);
+		assert_eq!(ppu.frame_buffer()[WIDTH * HEIGHT - 1], 0x21);
+		assert_eq!(ppu.frame_rgb().pixel(10, 10), palette::SYSTEM_PALETTE[0x21]);
+
+		ppu.mask.write(SHOW_BACKGROUND | SHOW_BACKGROUND_LEFT | GREYSCALE | EMPHASIZE_RED);
+		ppu.tick(&mut rom, 341 * 131);
+		ppu.tick(&mut rom, 341 * 131);
+		assert_eq!(ppu.frame_buffer()[0], 0x20 | (0x01 << 6));
+	}
+
+	#[test]
+	fn palette_read_fill_buffer() {
+		let mut rom = test::test_rom();
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+
+		ppu.write_to_addr(0x2F);
+		ppu.write_to_addr(0x00);
+		ppu.write(&mut rom, 0x42);
+
+		ppu.write_to_addr(0x3F);
+		ppu.write_to_addr(0x00);
+		ppu.write(&mut rom, 0x15);
+
+		ppu.write_to_addr(0x3F);
+		ppu.write_to_addr(0x00);
+		assert_eq!(ppu.read(&rom), 0x15); // Not delayed
+		ppu.write_to_addr(0x00);
+		ppu.write_to_addr(0x00);
+		assert_eq!(ppu.read(&rom), 0x42); // Buffer holds $2F00
+	}
+
+	#[test]
+	fn palette_mirroring() {
+		let mut rom = test::test_rom();
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+
+		ppu.write_to_addr(0x3F);
+		ppu.write_to_addr(0x10);
+		ppu.write(&mut rom, 0x2A);
+		ppu.write_to_addr(0x3F);
+		ppu.write_to_addr(0x00);
+		assert_eq!(ppu.read(&rom), 0x2A);
+
+		ppu.write_to_addr(0x3F);
+		ppu.write_to_addr(0xE5); // Mirror of $3F05
+		ppu.write(&mut rom, 0x11);
+		ppu.write_to_addr(0x3F);
+		ppu.write_to_addr(0x05);
+		assert_eq!(ppu.read(&rom), 0x11);
+		assert_eq!(ppu.palette_ram()[0x00], 0x2A);
+		assert_eq!(ppu.palette_ram()[0x05], 0x11);
+	}
+
+	#[test]
+	fn chr_ram() {
+		let mut rom = Rom {
+			mapper: Box::new(Nrom::new(vec![0; 16384], vec![0; 8192], true)),
+			mirroring: Mirroring::Horizontal,
+			prg_ram_size: 0,
+			battery: false,
+			info: None
+		};
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+
+		ppu.write_to_addr(0x10);
+		ppu.write_to_addr(0x20);
+		ppu.write(&mut rom, 0x5A);
+
+		ppu.write_to_addr(0x10);
+		ppu.write_to_addr(0x20);
+		ppu.read(&rom);
+		assert_eq!(ppu.read(&rom), 0x5A);
+	}
+
+	#[test]
+	fn nametable_mirroring() {
+		let mut rom = test::test_rom();
+
+		let cases = [
+			(Mirroring::Horizontal, [0x2000, 0x2400, 0x2800, 0x2C00], [0x000, 0x000, 0x400, 0x400]),
+			(Mirroring::Vertical, [0x2000, 0x2400, 0x2800, 0x2C00], [0x000, 0x400, 0x000, 0x400]),
+			(Mirroring::SingleScreenLower, [0x2000, 0x2400, 0x2800, 0x2C00], [0x000, 0x000, 0x000, 0x000]),
+			(Mirroring::SingleScreenUpper, [0x2000, 0x2400, 0x2800, 0x3C00], [0x400, 0x400, 0x400, 0x400]),
+			(Mirroring::FourScreen, [0x2000, 0x2400, 0x2800, 0x3C00], [0x000, 0x400, 0x800, 0xC00])
+		];
+		for (mirroring, addrs, expected) in cases {
+			let mut ppu = Ppu::new(mirroring);
+			for (addr, index) in addrs.iter().zip(expected) {
+				assert_eq!(ppu.mirror_vram_addr(*addr), index);
+			}
+
+			ppu.write_to_addr(0x24);
+			ppu.write_to_addr(0x05);
+			ppu.write(&mut rom, 0x77);
+			assert_eq!(ppu.vram[ppu.mirror_vram_addr(0x2405) as usize], 0x77);
+		}
+	}
+
+	#[test]
+	fn vblank_suppression() {
+		let mut rom = test::test_rom();
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+		ppu.write_to_ctrl(GENERATE_NMI);
+
+		// Just before the flag is set
+		ppu.tick(&mut rom, 341 * 120);
+		ppu.tick(&mut rom, 341 * 121 + 1);
+		assert_eq!(ppu.read_status() & VBLANK_STARTED, 0);
+		ppu.tick(&mut rom, 1);
+		assert_eq!(ppu.read_status() & VBLANK_STARTED, 0);
+		assert!(!ppu.poll_nmi());
+
+		// Right after the flag is set
+		ppu.tick(&mut rom, 341 * 131);
+		ppu.tick(&mut rom, 341 * 131);
+		assert_eq!(ppu.read_status() & VBLANK_STARTED, VBLANK_STARTED);
+		assert!(!ppu.poll_nmi());
+	}
+
+	#[test]
+	fn nmi_enabled_during_vblank() {
+		let mut rom = test::test_rom();
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+
+		ppu.tick(&mut rom, 341 * 120);
+		ppu.tick(&mut rom, 341 * 122);
+		assert!(ppu.status.contains(VBLANK_STARTED));
+		assert!(!ppu.poll_nmi());
+
+		ppu.write_to_ctrl(GENERATE_NMI);
+		assert!(!ppu.poll_nmi()); // Delayed by one instruction
+		assert!(ppu.poll_nmi());
+
+		// Enabling again without toggling does not fire twice
+		ppu.write_to_ctrl(GENERATE_NMI);
+		assert!(!ppu.poll_nmi());
+		assert!(!ppu.poll_nmi());
+	}
+
+	#[test]
+	fn nametable_mirror() {
+		let mut rom = test::test_rom();
+		let mut ppu = Ppu::new(Mirroring::Horizontal);
+
+		ppu.write_register(&mut rom, 0x2006, 0x30);
+		ppu.write_register(&mut rom, 0x2006, 0x05);
+		ppu.write_register(&mut rom, 0x2007, 0x42);
+
+		ppu.write_register(&mut rom, 0x2006, 0x20);
+		ppu.write_register(&mut rom, 0x2006, 0x05);
+		ppu.read(&rom); // Fills the buffer
+		assert_eq!(ppu.read(&rom), 0x42);
+
+		ppu.write_register(&mut rom, 0x2006, 0x30);
+		ppu.write_register(&mut rom, 0x2006, 0x05);
+		ppu.read(&rom);
+		assert_eq!(ppu.read(&rom), 0x42);
+	}
+}
@@ -0,0 +1,328 @@
+use crate::state::{StateError, StateReader, StateWriter};
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AddrRegister {
+	// Internal "loopy" registers, shared by PPUSCROLL and PPUADDR
+	// yyy NN YYYYY XXXXX
+	// ||| || ||||| +++++-- coarse X scroll
+	// ||| || +++++-------- coarse Y scroll
+	// ||| ++-------------- nametable select
+	// +++----------------- fine Y scroll
+	v: u16, // Current VRAM address
+	t: u16, // Temporary VRAM address
+	x: u8, // Fine X scroll
+	w: bool // First or second write toggle
+}
+
+impl AddrRegister {
+	pub fn new() -> AddrRegister {
+		AddrRegister {
+			v: 0x0000,
+			t: 0x0000,
+			x: 0x00,
+			w: false
+		}
+	}
+
+	// PPUADDR ($2006), high byte first
+	pub fn write(&mut self, value: u8) {
+		if !self.w {
+			self.t = ((u16::from(value) & 0x3F) << 8) | (self.t & 0x00FF);
+		} else {
+			self.t = (self.t & 0xFF00) | u16::from(value);
+			self.v = self.t;
+		}
+
+		self.w = !self.w;
+	}
+
+	// PPUSCROLL ($2005), X first
+	pub fn write_scroll(&mut self, value: u8) {
+		if !self.w {
+			self.t = (self.t & 0xFFE0) | (u16::from(value) >> 3);
+			self.x = value & 0x07;
+		} else {
+			self.t = (self.t & 0x8C1F) | ((u16::from(value) & 0x07) << 12) | ((u16::from(value) & 0xF8) << 2);
+		}
+
+		self.w = !self.w;
+	}
+
+	// PPUCTRL ($2000) nametable bits
+	pub fn write_nametable(&mut self, value: u8) {
+		self.t = (self.t & 0xF3FF) | ((u16::from(value) & 0x03) << 10);
+	}
+
+	pub fn increment(&mut self, value: u8) {
+		self.v = self.v.wrapping_add(u16::from(value)) & 0x7FFF;
+	}
+
+	pub fn reset_latch(&mut self) {
+		self.w = false;
+	}
+
+	pub fn get(&self) -> u16 {
+		self.v & 0x3FFF // Mirror down
+	}
+
+	pub fn vram_addr(&self) -> u16 {
+		self.v
+	}
+
+	pub fn temp_addr(&self) -> u16 {
+		self.t
+	}
+
+	pub fn fine_x(&self) -> u8 {
+		self.x
+	}
+
+	pub fn increment_x(&mut self) {
+		if (self.v & 0x001F) == 31 {
+			self.v &= !0x001F;
+			self.v ^= 0x0400; // Switch horizontal nametable
+		} else {
+			self.v += 1;
+		}
+	}
+
+	pub fn increment_y(&mut self) {
+		if (self.v & 0x7000) != 0x7000 {
+			self.v += 0x1000; // Fine Y
+			return;
+		}
+
+		self.v &= !0x7000;
+		let mut coarse_y = (self.v & 0x03E0) >> 5;
+		if coarse_y == 29 {
+			coarse_y = 0;
+			self.v ^= 0x0800; // Switch vertical nametable
+		} else if coarse_y == 31 {
+			coarse_y = 0; // Attribute table overflow, nametable not switched
+		} else {
+			coarse_y += 1;
+		}
+		self.v = (self.v & !0x03E0) | (coarse_y << 5);
+	}
+
+	pub fn copy_horizontal(&mut self) {
+		self.v = (self.v & !0x041F) | (self.t & 0x041F);
+	}
+
+	pub fn copy_vertical(&mut self) {
+		self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+	}
+
+	pub(crate) fn save_state(&self, state: &mut StateWriter) {
+		state.write_u16(self.v);
+		state.write_u16(self.t);
+		state.write_u8(self.x);
+		state.write_bool(self.w);
+	}
+
+	pub(crate) fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+		self.v = state.read_u16()?;
+		self.t = state.read_u16()?;
+		self.x = state.read_u8()?;
+		self.w = state.read_bool()?;
+		Ok(())
+	}
+}
+
+impl Default for AddrRegister {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControlRegister {
+	// 7  bit  0
+	// ---- ----
+	// VPHB SINN
+	// |||| ||||
+	// |||| ||++- Base nametable address
+	// |||| ||    (0 = $2000; 1 = $2400; 2 = $2800; 3 = $2C00)
+	// |||| |+--- VRAM address increment per CPU read/write of PPUDATA
+	// |||| |     (0: add 1, going across; 1: add 32, going down)
+	// |||| +---- Sprite pattern table address for 8x8 sprites
+	// ||||       (0: $0000; 1: $1000; ignored in 8x16 mode)
+	// |||+------ Background pattern table address (0: $0000; 1: $1000)
+	// ||+------- Sprite size (0: 8x8 pixels; 1: 8x16 pixels)
+	// |+-------- PPU master/slave select
+	// |          (0: read backdrop from EXT pins; 1: output color on EXT pins)
+	// +--------- Generate an NMI at the start of the
+	//            vertical blanking interval (0: off; 1: on)
+	value: u8
+}
+
+pub const NAMETABLE1             : u8 = 0b00000001;
+pub const NAMETABLE2             : u8 = 0b00000010;
+pub const VRAM_ADD_INCREMENT     : u8 = 0b00000100;
+pub const SPRITE_PATTERN_ADDR    : u8 = 0b00001000;
+pub const BACKROUND_PATTERN_ADDR : u8 = 0b00010000;
+pub const SPRITE_SIZE            : u8 = 0b00100000;
+pub const MASTER_SLAVE_SELECT    : u8 = 0b01000000;
+pub const GENERATE_NMI           : u8 = 0b10000000;
+
+impl ControlRegister {
+	pub fn new() -> ControlRegister {
+		ControlRegister {
+			value: 0x00
+		}
+	}
+
+	pub fn contains(&self, flag: u8) -> bool {
+		(self.value & flag) != 0
+	}
+
+	pub fn vram_addr_increment(&self) -> u8 {
+		if !self.contains(VRAM_ADD_INCREMENT) {
+			return 1;
+		}
+
+		32
+	}
+
+	pub fn sprite_size(&self) -> u8 {
+		if self.contains(SPRITE_SIZE) { 16 } else { 8 }
+	}
+
+	pub fn sprite_pattern_addr(&self) -> u16 {
+		if self.contains(SPRITE_PATTERN_ADDR) { 0x1000 } else { 0x0000 }
+	}
+
+	pub fn background_pattern_addr(&self) -> u16 {
+		if self.contains(BACKROUND_PATTERN_ADDR) { 0x1000 } else { 0x0000 }
+	}
+
+	pub fn get(&self) -> u8 {
+		self.value
+	}
+
+	pub fn write(&mut self, value: u8) {
+		self.value = value;
+	}
+}
+
+impl Default for ControlRegister {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaskRegister {
+	// 7  bit  0
+	// ---- ----
+	// BGRs bMmG
+	// |||| ||||
+	// |||| |||+- Greyscale (0: normal color, 1: produce a greyscale display)
+	// |||| ||+-- 1: Show background in leftmost 8 pixels of screen, 0: Hide
+	// |||| |+--- 1: Show sprites in leftmost 8 pixels of screen, 0: Hide
+	// |||| +---- 1: Show background
+	// |||+------ 1: Show sprites
+	// ||+------- Emphasize red (green on PAL/Dendy)
+	// |+-------- Emphasize green (red on PAL/Dendy)
+	// +--------- Emphasize blue
+	value: u8
+}
+
+pub const GREYSCALE            : u8 = 0b00000001;
+pub const SHOW_BACKGROUND_LEFT : u8 = 0b00000010;
+pub const SHOW_SPRITES_LEFT    : u8 = 0b00000100;
+pub const SHOW_BACKGROUND      : u8 = 0b00001000;
+pub const SHOW_SPRITES         : u8 = 0b00010000;
+pub const EMPHASIZE_RED        : u8 = 0b00100000;
+pub const EMPHASIZE_GREEN      : u8 = 0b01000000;
+pub const EMPHASIZE_BLUE       : u8 = 0b10000000;
+
+impl MaskRegister {
+	pub fn new() -> MaskRegister {
+		MaskRegister {
+			value: 0x00
+		}
+	}
+
+	pub fn contains(&self, flag: u8) -> bool {
+		(self.value & flag) != 0
+	}
+
+	pub fn is_rendering(&self) -> bool {
+		self.contains(SHOW_BACKGROUND) || self.contains(SHOW_SPRITES)
+	}
+
+	pub fn emphasis(&self) -> u8 {
+		self.value >> 5
+	}
+
+	pub fn get(&self) -> u8 {
+		self.value
+	}
+
+	pub fn write(&mut self, value: u8) {
+		self.value = value;
+	}
+}
+
+impl Default for MaskRegister {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StatusRegister {
+	// 7  bit  0
+	// ---- ----
+	// VSO. ....
+	// |||| ||||
+	// |||+-++++- PPU open bus
+	// ||+------- Sprite overflow
+	// |+-------- Sprite 0 Hit
+	// +--------- Vertical blank has started (0: not in vblank; 1: in vblank)
+	value: u8
+}
+
+pub const SPRITE_OVERFLOW : u8 = 0b00100000;
+pub const SPRITE_ZERO_HIT : u8 = 0b01000000;
+pub const VBLANK_STARTED  : u8 = 0b10000000;
+
+impl StatusRegister {
+	pub fn new() -> StatusRegister {
+		StatusRegister {
+			value: 0x00
+		}
+	}
+
+	pub fn contains(&self, flag: u8) -> bool {
+		(self.value & flag) != 0
+	}
+
+	pub fn set(&mut self, flag: u8, status: bool) {
+		if status {
+			self.value |= flag;
+		} else {
+			self.value &= !flag;
+		}
+	}
+
+	pub fn get(&self) -> u8 {
+		self.value
+	}
+
+	// Whole register, for save states
+	pub(crate) fn restore(&mut self, value: u8) {
+		self.value = value;
+	}
+}
+
+impl Default for StatusRegister {
+	fn default() -> Self {
+		Self::new()
+	}
+}
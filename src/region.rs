@@ -0,0 +1,123 @@
+use crate::frame_timer::{NTSC_FRAME_RATE, PAL_FRAME_RATE};
+use crate::rom::header::Timing;
+
+// Console model, deciding the clock speeds and the length of a frame.
+// Dendy famiclones pair a PAL master clock and scanline count with NTSC-like CPU timings
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum Region {
+	#[default]
+	Ntsc,
+	Pal,
+	Dendy
+}
+
+impl Region {
+	pub const ALL: [Region; 3] = [Region::Ntsc, Region::Pal, Region::Dendy];
+
+	// Multi-region games run as NTSC
+	pub fn from_timing(timing: Timing) -> Region {
+		match timing {
+			Timing::Ntsc | Timing::MultiRegion => Region::Ntsc,
+			Timing::Pal => Region::Pal,
+			Timing::Dendy => Region::Dendy
+		}
+	}
+
+	// Crystal frequency in Hz
+	pub fn master_clock(self) -> f64 {
+		match self {
+			Region::Ntsc => 21_477_272.0,
+			Region::Pal | Region::Dendy => 26_601_712.0
+		}
+	}
+
+	// Master clock ticks per CPU cycle
+	pub fn cpu_divider(self) -> u8 {
+		match self {
+			Region::Ntsc => 12,
+			Region::Pal => 16,
+			Region::Dendy => 15
+		}
+	}
+
+	// Master clock ticks per PPU dot
+	pub fn ppu_divider(self) -> u8 {
+		match self {
+			Region::Ntsc => 4,
+			Region::Pal | Region::Dendy => 5
+		}
+	}
+
+	pub fn cpu_frequency(self) -> f64 {
+		self.master_clock() / f64::from(self.cpu_divider())
+	}
+
+	pub fn frame_rate(self) -> f64 {
+		match self {
+			Region::Ntsc => NTSC_FRAME_RATE,
+			Region::Pal | Region::Dendy => PAL_FRAME_RATE
+		}
+	}
+
+	pub fn scanlines_per_frame(self) -> u16 {
+		match self {
+			Region::Ntsc => 262,
+			Region::Pal | Region::Dendy => 312
+		}
+	}
+
+	// The Dendy has its 50 extra lines before the vertical blank instead of in it
+	pub fn vblank_scanline(self) -> u16 {
+		match self {
+			Region::Ntsc | Region::Pal => 241,
+			Region::Dendy => 291
+		}
+	}
+
+	pub fn pre_render_scanline(self) -> u16 {
+		self.scanlines_per_frame() - 1
+	}
+
+	// Only the NTSC PPU drops a dot on odd frames
+	pub fn skips_odd_frame_dot(self) -> bool {
+		self == Region::Ntsc
+	}
+
+	// CPU cycles of the 5 APU frame counter steps
+	pub fn frame_counter_steps(self) -> [u32; 5] {
+		match self {
+			Region::Ntsc | Region::Dendy => [7457, 14913, 22371, 29829, 37281],
+			Region::Pal => [8313, 16627, 24939, 33253, 41565]
+		}
+	}
+
+	// DMC period in CPU cycles for each rate index
+	pub fn dmc_rates(self) -> &'static [u16; 16] {
+		static NTSC: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+		static PAL: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+		match self {
+			Region::Ntsc | Region::Dendy => &NTSC,
+			Region::Pal => &PAL
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn clocks() {
+		assert_eq!(Region::from_timing(Timing::MultiRegion), Region::Ntsc);
+		assert_eq!(Region::from_timing(Timing::Dendy), Region::Dendy);
+
+		for region in Region::ALL {
+			// Frame length in CPU cycles gives back the frame rate
+			let dots = f64::from(region.scanlines_per_frame()) * 341.0;
+			let cycles = dots * f64::from(region.ppu_divider()) / f64::from(region.cpu_divider());
+			assert!((region.cpu_frequency() / cycles - region.frame_rate()).abs() < 0.01, "{:?}", region);
+		}
+		assert!((Region::Pal.cpu_frequency() - 1_662_607.0).abs() < 1.0);
+	}
+}
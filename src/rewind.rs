@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+
+// Zero bytes in a row before they are worth a token of their own
+const MIN_RUN: usize = 4;
+
+// Snapshot older than the newest one, stored as the XOR with the next snapshot
+struct Delta {
+	frame: u64,
+	len: usize,
+	data: Vec<u8>
+}
+
+// Save states taken every `interval` frames, the newest kept whole and the older ones
+// as compressed XOR deltas, which are mostly zeros between close frames
+pub struct Rewind {
+	interval: u32,
+	capacity: usize,
+	countdown: u32,
+	// Oldest first
+	deltas: VecDeque<Delta>,
+	newest: Option<(u64, Vec<u8>)>
+}
+
+impl Rewind {
+	// Keeps `capacity` snapshots, so about `interval * capacity` frames of history
+	pub fn new(interval: u32, capacity: usize) -> Rewind {
+		Rewind {
+			interval: interval.max(1),
+			capacity: capacity.max(1),
+			countdown: 0,
+			deltas: VecDeque::new(),
+			newest: None
+		}
+	}
+
+	pub fn interval(&self) -> u32 {
+		self.interval
+	}
+
+	// Snapshots held, the newest included
+	pub fn len(&self) -> usize {
+		self.deltas.len() + usize::from(self.newest.is_some())
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.newest.is_none()
+	}
+
+	// Bytes used by the snapshots
+	pub fn memory(&self) -> usize {
+		self.deltas.iter().map(|delta| delta.data.len()).sum::<usize>() + self.newest.as_ref().map_or(0, |(_, state)| state.len())
+	}
+
+	pub fn clear(&mut self) {
+		self.deltas.clear();
+		self.newest = None;
+		self.countdown = 0;
+	}
+
+	// Called once per frame, true when a snapshot should be pushed
+	pub fn tick(&mut self) -> bool {
+		if self.countdown == 0 {
+			self.countdown = self.interval;
+		}
+		self.countdown -= 1;
+		self.countdown == 0
+	}
+
+	pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+		if let Some((previous_frame, previous)) = self.newest.take() {
+			self.deltas.push_back(Delta {
+				frame: previous_frame,
+				len: previous.len(),
+				data: compress(&xor(&previous, &state))
+			});
+		}
+		self.newest = Some((frame, state));
+
+		while self.len() > self.capacity {
+			self.deltas.pop_front();
+		}
+	}
+
+	// Newest snapshot taken at or before `frame`, the oldest one if none is.
+	// Newer snapshots are dropped, returns the frame number and state
+	pub fn seek(&mut self, frame: u64) -> Option<(u64, &[u8])> {
+		while self.newest.as_ref().is_some_and(|&(newest, _)| newest > frame) && !self.deltas.is_empty() {
+			let delta = self.deltas.pop_back().unwrap();
+			let (_, state) = self.newest.take().unwrap();
+			let mut previous = xor(&decompress(&delta.data), &state);
+			previous.truncate(delta.len);
+			self.newest = Some((delta.frame, previous));
+		}
+
+		// Snapshots are taken from here again
+		self.countdown = self.interval;
+		self.newest.as_ref().map(|(frame, state)| (*frame, state.as_slice()))
+	}
+}
+
+// The shorter input is padded with zeros
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+	let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+	let mut result = long.to_vec();
+	for (byte, other) in result.iter_mut().zip(short) {
+		*byte ^= other;
+	}
+	result
+}
+
+// LZ4 style tokens: literal count in the high nibble, zero run length in the low one,
+// 15 meaning more length bytes follow, then the literals
+pub fn compress(data: &[u8]) -> Vec<u8> {
+	let mut output = Vec::new();
+	let mut i = 0;
+	while i < data.len() {
+		let literals_start = i;
+		while i < data.len() && !data[i..data.len().min(i + MIN_RUN)].iter().all(|&byte| byte == 0) {
+			i += 1;
+		}
+		let literals = &data[literals_start..i];
+
+		let run_start = i;
+		while i < data.len() && data[i] == 0 {
+			i += 1;
+		}
+		let run = i - run_start;
+
+		output.push(((literals.len().min(15) as u8) << 4) | run.min(15) as u8);
+		if literals.len() >= 15 {
+			write_length(&mut output, literals.len() - 15);
+		}
+		output.extend_from_slice(literals);
+		if run >= 15 {
+			write_length(&mut output, run - 15);
+		}
+	}
+	output
+}
+
+fn write_length(output: &mut Vec<u8>, mut len: usize) {
+	while len >= 255 {
+		output.push(255);
+		len -= 255;
+	}
+	output.push(len as u8);
+}
+
+fn read_length(data: &[u8], i: &mut usize, nibble: u8) -> usize {
+	let mut len = usize::from(nibble);
+	if nibble == 15 {
+		loop {
+			let byte = data[*i];
+			*i += 1;
+			len += usize::from(byte);
+			if byte != 255 {
+				break;
+			}
+		}
+	}
+	len
+}
+
+// Panics on data that compress did not produce
+pub fn decompress(data: &[u8]) -> Vec<u8> {
+	let mut output = Vec::new();
+	let mut i = 0;
+	while i < data.len() {
+		let token = data[i];
+		i += 1;
+		let literals = read_length(data, &mut i, token >> 4);
+		output.extend_from_slice(&data[i..i + literals]);
+		i += literals;
+		let run = read_length(data, &mut i, token & 0x0F);
+		output.resize(output.len() + run, 0);
+	}
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn compression() {
+		let mut data = vec![0; 5000];
+		data[0] = 1;
+		data[3] = 2;
+		data[100..200].fill(7);
+		data[4999] = 9;
+		let compressed = compress(&data);
+		// The 100 literals and about 20 bytes of run lengths
+		assert!(compressed.len() < 140);
+		assert_eq!(decompress(&compressed), data);
+
+		for data in [vec![], vec![0], vec![1, 0, 0], vec![5; 16], vec![0; 15]] {
+			assert_eq!(decompress(&compress(&data)), data);
+		}
+	}
+
+	#[test]
+	fn seek() {
+		let mut rewind = Rewind::new(2, 3);
+		let mut pushed = 0;
+		for frame in 1..=8u64 {
+			if rewind.tick() {
+				rewind.push(frame, vec![frame as u8; frame as usize]);
+				pushed += 1;
+			}
+		}
+		assert_eq!(pushed, 4);
+		// Frames 4, 6 and 8, frame 2 fell out
+		assert_eq!(rewind.len(), 3);
+
+		assert_eq!(rewind.seek(7), Some((6, &[6u8; 6][..])));
+		assert_eq!(rewind.seek(1), Some((4, &[4u8; 4][..])));
+		assert_eq!(rewind.len(), 1);
+
+		rewind.clear();
+		assert!(rewind.is_empty());
+		assert_eq!(rewind.seek(0), None);
+	}
+}
@@ -0,0 +1,294 @@
+// Rollback netplay building blocks, for GGPO style integrations: the game runs ahead with
+// predicted buttons for the players whose inputs have not arrived, and once they do,
+// the frames since the first wrong prediction are run again from a snapshot.
+// Transport, input delay and frame pacing are left to the integration
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use crate::input::FrameInput;
+use crate::nes::Nes;
+use crate::state::StateError;
+
+// Players on the two controller ports, 0 for port 1
+pub const PLAYERS: usize = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RollbackError {
+	InvalidPlayer(usize),
+	// Buttons for a frame before the snapshots kept, it cannot be run again
+	TooOld { player: usize, frame: u64 },
+	// Confirmed buttons given again with other values
+	InputChanged { player: usize, frame: u64 },
+	// max_prediction frames ahead of the confirmed inputs, wait for the other players
+	PredictionLimit,
+	// A snapshot did not load
+	State(StateError)
+}
+
+impl fmt::Display for RollbackError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RollbackError::InvalidPlayer(player) => write!(f, "player {} is not 0 or 1", player),
+			RollbackError::TooOld { player, frame } => write!(f, "buttons of player {} for frame {} come too late to roll back", player, frame),
+			RollbackError::InputChanged { player, frame } => write!(f, "buttons of player {} for frame {} were confirmed with other values", player, frame),
+			RollbackError::PredictionLimit => write!(f, "too many frames ahead of the confirmed inputs"),
+			RollbackError::State(error) => write!(f, "{}", error)
+		}
+	}
+}
+
+impl std::error::Error for RollbackError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionConfig {
+	// Frames that can run on predictions, so also the longest rollback
+	pub max_prediction: u32
+}
+
+impl Default for SessionConfig {
+	fn default() -> Self {
+		SessionConfig { max_prediction: 8 }
+	}
+}
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+	confirmed: [Option<u8>; PLAYERS],
+	// Buttons the frame last ran with, confirmed or predicted
+	used: Option<[u8; PLAYERS]>
+}
+
+impl Slot {
+	fn is_confirmed(&self) -> bool {
+		self.confirmed.iter().all(Option::is_some)
+	}
+}
+
+pub struct Session {
+	config: SessionConfig,
+	// Next frame to run
+	frame: u64,
+	slots: BTreeMap<u64, Slot>,
+	// Save states at the start of the frames `snapshot_base` to `frame`
+	snapshots: VecDeque<Vec<u8>>,
+	snapshot_base: u64,
+	// Buttons of the last frame dropped from the slots, where predictions start
+	dropped: [u8; PLAYERS],
+	// First frame that ran with a prediction proven wrong since
+	mispredicted: Option<u64>,
+	rollbacks: u64
+}
+
+impl Session {
+	// The frame numbers of the session start at 0 with the current state of `nes`
+	pub fn new(nes: &mut Nes, config: SessionConfig) -> Session {
+		nes.power_on();
+		Session {
+			config,
+			frame: 0,
+			slots: BTreeMap::new(),
+			snapshots: VecDeque::from([nes.save_state()]),
+			snapshot_base: 0,
+			dropped: [0; PLAYERS],
+			mispredicted: None,
+			rollbacks: 0
+		}
+	}
+
+	// Next frame advance_frame runs
+	pub fn frame(&self) -> u64 {
+		self.frame
+	}
+
+	// Frames before this one ran with the confirmed buttons of every player and are final
+	pub fn confirmed_frame(&self) -> u64 {
+		let mut frame = self.snapshot_base;
+		while frame < self.frame && self.slots.get(&frame).is_some_and(Slot::is_confirmed) {
+			frame += 1;
+		}
+		frame.min(self.mispredicted.unwrap_or(u64::MAX))
+	}
+
+	// Save state at the start of confirmed_frame, for checksums against the other peers
+	pub fn confirmed_state(&self) -> (u64, &[u8]) {
+		let frame = self.confirmed_frame();
+		(frame, &self.snapshots[(frame - self.snapshot_base) as usize])
+	}
+
+	// Times advance_frame had to run frames again
+	pub fn rollbacks(&self) -> u64 {
+		self.rollbacks
+	}
+
+	// Buttons of `player` for `frame`, local ones included. A frame that already ran
+	// with other predicted buttons is run again by the next advance_frame
+	pub fn add_input(&mut self, player: usize, frame: u64, buttons: u8) -> Result<(), RollbackError> {
+		if player >= PLAYERS {
+			return Err(RollbackError::InvalidPlayer(player));
+		}
+		if frame < self.snapshot_base {
+			return Err(RollbackError::TooOld { player, frame });
+		}
+
+		let slot = self.slots.entry(frame).or_default();
+		match slot.confirmed[player] {
+			Some(confirmed) if confirmed == buttons => return Ok(()),
+			Some(_) => return Err(RollbackError::InputChanged { player, frame }),
+			None => slot.confirmed[player] = Some(buttons)
+		}
+
+		// The frames predicted from older buttons now predict these ones
+		let wrong = self.slots.range(frame..self.frame)
+			.find(|&(&frame, slot)| slot.used.is_some_and(|used| used[player] != self.buttons(player, frame)))
+			.map(|(&frame, _)| frame);
+		if let Some(wrong) = wrong {
+			self.mispredicted = Some(self.mispredicted.map_or(wrong, |frame| frame.min(wrong)));
+		}
+		Ok(())
+	}
+
+	// Run the frames since a wrong prediction again, then the next frame.
+	// Returns the frames run again, whose audio is dropped since the predicted one played
+	pub fn advance_frame(&mut self, nes: &mut Nes) -> Result<u64, RollbackError> {
+		let mut resimulated = 0;
+		if let Some(from) = self.mispredicted.take() {
+			let index = (from - self.snapshot_base) as usize;
+			nes.load_state(&self.snapshots[index]).map_err(RollbackError::State)?;
+			self.snapshots.truncate(index + 1);
+			for frame in from..self.frame {
+				self.run(nes, frame);
+				resimulated += 1;
+			}
+			nes.audio_samples();
+			self.rollbacks += 1;
+		}
+
+		if self.frame >= self.confirmed_frame() + u64::from(self.config.max_prediction) {
+			return Err(RollbackError::PredictionLimit);
+		}
+		self.run(nes, self.frame);
+		self.frame += 1;
+		self.drop_confirmed();
+		Ok(resimulated)
+	}
+
+	// Confirmed buttons, or a prediction repeating the last confirmed ones
+	fn buttons(&self, player: usize, frame: u64) -> u8 {
+		self.slots.range(..=frame).rev()
+			.find_map(|(_, slot)| slot.confirmed[player])
+			.unwrap_or(self.dropped[player])
+	}
+
+	fn run(&mut self, nes: &mut Nes, frame: u64) {
+		let buttons = [self.buttons(0, frame), self.buttons(1, frame)];
+		self.slots.entry(frame).or_default().used = Some(buttons);
+		nes.step(FrameInput::new(buttons[0], buttons[1]));
+		self.snapshots.push_back(nes.save_state());
+	}
+
+	// Snapshots and inputs of final frames are not needed anymore
+	fn drop_confirmed(&mut self) {
+		let confirmed = self.confirmed_frame();
+		while self.snapshot_base < confirmed {
+			if let Some(slot) = self.slots.remove(&self.snapshot_base) {
+				for (dropped, confirmed) in self.dropped.iter_mut().zip(slot.confirmed) {
+					*dropped = confirmed.unwrap_or(*dropped);
+				}
+			}
+			self.snapshots.pop_front();
+			self.snapshot_base += 1;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::rom::Rom;
+	use crate::rom::test::nrom;
+
+	// Adds the port 1 buttons to $10 each frame: strobe, read 8 bits into $11, add
+	fn rom() -> Rom {
+		Rom::from_ines(&nrom(&[
+			0xA9, 0x80, 0x8D, 0x00, 0x20, // LDA #$80, STA $2000: NMI on
+			0x4C, 0x05, 0x80, // JMP *
+			// NMI at $8008
+			0xA9, 0x01, 0x8D, 0x16, 0x40, // LDA #1, STA $4016
+			0xA9, 0x00, 0x8D, 0x16, 0x40, // LDA #0, STA $4016
+			0xA2, 0x08, // LDX #8
+			0xAD, 0x16, 0x40, // loop: LDA $4016
+			0x4A, // LSR A
+			0x26, 0x11, // ROL $11
+			0xCA, // DEX
+			0xD0, 0xF8, // BNE loop
+			0x18, 0xA5, 0x10, 0x65, 0x11, 0x85, 0x10, // CLC, LDA $10, ADC $11, STA $10
+			0x40 // RTI
+		], [0x8008, 0x8000, 0])).unwrap()
+	}
+
+	fn remote_buttons(frame: u64) -> u8 {
+		if frame.is_multiple_of(5) { 0x01 } else { 0x08 }
+	}
+
+	#[test]
+	fn rollback_matches_lockstep() {
+		// Reference run with every input known in time
+		let mut reference = Nes::new(rom());
+		for frame in 0..30 {
+			reference.step(FrameInput::new(remote_buttons(frame), 0x02));
+		}
+
+		let mut nes = Nes::new(rom());
+		let mut session = Session::new(&mut nes, SessionConfig { max_prediction: 8 });
+		let mut resimulated = 0;
+		for frame in 0..30 {
+			session.add_input(1, frame, 0x02).unwrap();
+			// Remote buttons arrive 3 frames late
+			if frame >= 3 {
+				session.add_input(0, frame - 3, remote_buttons(frame - 3)).unwrap();
+			}
+			resimulated += session.advance_frame(&mut nes).unwrap();
+		}
+		assert!(session.rollbacks() > 0);
+		assert!(resimulated >= session.rollbacks());
+		assert_eq!(session.confirmed_frame(), 27);
+
+		// Predicted right, nothing to run again
+		for frame in 27..30 {
+			session.add_input(0, frame, remote_buttons(frame)).unwrap();
+		}
+		assert_eq!(session.confirmed_frame(), 30);
+		session.add_input(1, 30, 0x02).unwrap();
+		session.add_input(0, 30, remote_buttons(30)).unwrap();
+		session.advance_frame(&mut nes).unwrap();
+		assert_eq!(session.confirmed_frame(), 31);
+
+		let (frame, state) = session.confirmed_state();
+		reference.step(FrameInput::new(remote_buttons(30), 0x02));
+		assert_eq!(frame, 31);
+		assert_eq!(state, reference.save_state().as_slice());
+		assert_eq!(nes.save_state(), reference.save_state());
+	}
+
+	#[test]
+	fn limits() {
+		let mut nes = Nes::new(rom());
+		let mut session = Session::new(&mut nes, SessionConfig { max_prediction: 2 });
+		session.add_input(0, 0, 0x01).unwrap();
+		assert_eq!(session.advance_frame(&mut nes), Ok(0));
+		assert_eq!(session.advance_frame(&mut nes), Ok(0));
+		assert_eq!(session.advance_frame(&mut nes), Err(RollbackError::PredictionLimit));
+
+		assert_eq!(session.add_input(0, 0, 0x02), Err(RollbackError::InputChanged { player: 0, frame: 0 }));
+		assert_eq!(session.add_input(2, 0, 0x00), Err(RollbackError::InvalidPlayer(2)));
+
+		// Frame 1 predicted A from frame 0, B was pressed
+		session.add_input(1, 0, 0x00).unwrap();
+		session.add_input(1, 1, 0x00).unwrap();
+		session.add_input(0, 1, 0x02).unwrap();
+		assert_eq!(session.advance_frame(&mut nes), Ok(1));
+		assert_eq!(session.confirmed_frame(), 2);
+		assert_eq!(session.add_input(0, 1, 0x02), Err(RollbackError::TooOld { player: 0, frame: 1 }));
+	}
+}
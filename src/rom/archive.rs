@@ -0,0 +1,98 @@
+use crate::rom::RomError;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+
+// iNES data of a file, unpacked when it is a .gz or a .zip holding a single .nes file
+pub fn unpack(buffer: Vec<u8>) -> Result<Vec<u8>, RomError> {
+	if buffer.starts_with(&GZIP_MAGIC) {
+		gunzip(&buffer)
+	} else if buffer.starts_with(&ZIP_MAGIC) {
+		unzip(buffer)
+	} else {
+		Ok(buffer)
+	}
+}
+
+#[cfg(feature = "archives")]
+fn gunzip(buffer: &[u8]) -> Result<Vec<u8>, RomError> {
+	use std::io::Read;
+
+	let mut data = Vec::new();
+	flate2::read::GzDecoder::new(buffer).read_to_end(&mut data)
+		.map_err(|error| RomError::Archive(error.to_string()))?;
+	Ok(data)
+}
+
+#[cfg(feature = "archives")]
+fn unzip(buffer: Vec<u8>) -> Result<Vec<u8>, RomError> {
+	use std::io::{Cursor, Read};
+
+	let mut archive = zip::ZipArchive::new(Cursor::new(buffer))
+		.map_err(|error| RomError::Archive(error.to_string()))?;
+
+	let roms: Vec<String> = archive.file_names()
+		.filter(|name| name.to_ascii_lowercase().ends_with(".nes"))
+		.map(String::from)
+		.collect();
+
+	let name = match roms.as_slice() {
+		[name] => name,
+		[] => return Err(RomError::Archive("no .nes file in the archive".to_string())),
+		_ => return Err(RomError::Archive(format!("{} .nes files in the archive", roms.len())))
+	};
+
+	let mut data = Vec::new();
+	archive.by_name(name)
+		.and_then(|mut file| file.read_to_end(&mut data).map_err(zip::result::ZipError::from))
+		.map_err(|error| RomError::Archive(error.to_string()))?;
+	Ok(data)
+}
+
+#[cfg(not(feature = "archives"))]
+fn gunzip(_buffer: &[u8]) -> Result<Vec<u8>, RomError> {
+	Err(RomError::Archive("compressed ROMs need the `archives` feature".to_string()))
+}
+
+#[cfg(not(feature = "archives"))]
+fn unzip(_buffer: Vec<u8>) -> Result<Vec<u8>, RomError> {
+	Err(RomError::Archive("compressed ROMs need the `archives` feature".to_string()))
+}
+
+#[cfg(all(test, feature = "archives"))]
+mod tests {
+	use super::*;
+
+	use std::io::Write;
+
+	const INES: &[u8] = &[0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0];
+
+	#[test]
+	fn gzip() {
+		let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+		encoder.write_all(INES).unwrap();
+
+		assert_eq!(unpack(encoder.finish().unwrap()).unwrap(), INES);
+	}
+
+	#[test]
+	fn zip() {
+		let archive = |names: &[&str]| {
+			let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+			for name in names {
+				writer.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+				writer.write_all(INES).unwrap();
+			}
+			writer.finish().unwrap().into_inner()
+		};
+
+		assert_eq!(unpack(archive(&["readme.txt", "Game (U).NES"])).unwrap(), INES);
+		assert!(matches!(unpack(archive(&["readme.txt"])), Err(RomError::Archive(_))));
+		assert!(matches!(unpack(archive(&["a.nes", "b.nes"])), Err(RomError::Archive(_))));
+	}
+
+	#[test]
+	fn plain() {
+		assert_eq!(unpack(INES.to_vec()).unwrap(), INES);
+	}
+}
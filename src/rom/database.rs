@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::rom::Mirroring;
+use crate::rom::header::{RomHeader, Timing};
+
+// Header fields known to be right for a dump, None keeps the file's value
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DatabaseEntry {
+	pub mapper: Option<u16>,
+	pub submapper: Option<u8>,
+	pub mirroring: Option<Mirroring>,
+	pub prg_ram_size: Option<usize>,
+	pub prg_nvram_size: Option<usize>,
+	pub chr_ram_size: Option<usize>,
+	pub chr_nvram_size: Option<usize>,
+	pub battery: Option<bool>,
+	pub timing: Option<Timing>
+}
+
+// A header field replaced by the database, values as written in the database format
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeaderOverride {
+	pub field: &'static str,
+	pub header: String,
+	pub database: String
+}
+
+impl fmt::Display for HeaderOverride {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}: {} -> {}", self.field, self.header, self.database)
+	}
+}
+
+impl DatabaseEntry {
+	// Correct `header`, return the fields that changed
+	pub fn apply(&self, header: &mut RomHeader) -> Vec<HeaderOverride> {
+		let mut overrides = Vec::new();
+
+		fn set<T: PartialEq + Copy>(overrides: &mut Vec<HeaderOverride>, field: &'static str, value: &mut T, entry: Option<T>, name: fn(T) -> String) {
+			if let Some(entry) = entry {
+				if *value != entry {
+					overrides.push(HeaderOverride { field, header: name(*value), database: name(entry) });
+					*value = entry;
+				}
+			}
+		}
+
+		set(&mut overrides, "mapper", &mut header.mapper, self.mapper, |value| value.to_string());
+		set(&mut overrides, "submapper", &mut header.submapper, self.submapper, |value| value.to_string());
+		set(&mut overrides, "mirroring", &mut header.mirroring, self.mirroring, |value| mirroring_name(value).to_string());
+		set(&mut overrides, "prg_ram", &mut header.prg_ram_size, self.prg_ram_size, |value| value.to_string());
+		set(&mut overrides, "prg_nvram", &mut header.prg_nvram_size, self.prg_nvram_size, |value| value.to_string());
+		set(&mut overrides, "chr_ram", &mut header.chr_ram_size, self.chr_ram_size, |value| value.to_string());
+		set(&mut overrides, "chr_nvram", &mut header.chr_nvram_size, self.chr_nvram_size, |value| value.to_string());
+		set(&mut overrides, "battery", &mut header.battery, self.battery, |value| value.to_string());
+		set(&mut overrides, "timing", &mut header.timing, self.timing, |value| timing_name(value).to_string());
+
+		overrides
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DatabaseError {
+	// 1-based
+	pub line: usize,
+	pub message: String
+}
+
+impl fmt::Display for DatabaseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+impl std::error::Error for DatabaseError {}
+
+// Header corrections by CRC-32 of PRG ROM + CHR ROM, one game per line:
+//
+//     # comment
+//     1A2B3C4D mapper=4 submapper=1 mirroring=vertical prg_ram=0 prg_nvram=8192 battery=true timing=pal
+pub struct GameDatabase {
+	entries: HashMap<u32, DatabaseEntry>
+}
+
+impl GameDatabase {
+	pub fn new() -> GameDatabase {
+		GameDatabase {
+			entries: HashMap::new()
+		}
+	}
+
+	pub fn parse(text: &str) -> Result<GameDatabase, DatabaseError> {
+		let mut database = GameDatabase::new();
+
+		for (index, line) in text.lines().enumerate() {
+			let error = |message: String| DatabaseError { line: index + 1, message };
+
+			let line = line.split('#').next().unwrap_or("").trim();
+			let mut fields = line.split_whitespace();
+			let Some(crc) = fields.next() else {
+				continue;
+			};
+
+			let crc = u32::from_str_radix(crc, 16).map_err(|_| error(format!("invalid CRC-32 {}", crc)))?;
+			let mut entry = DatabaseEntry::default();
+
+			for field in fields {
+				let (key, value) = field.split_once('=').ok_or_else(|| error(format!("expected key=value, got {}", field)))?;
+				let invalid = || error(format!("invalid {} {}", key, value));
+
+				match key {
+					"mapper" => entry.mapper = Some(value.parse().map_err(|_| invalid())?),
+					"submapper" => entry.submapper = Some(value.parse().map_err(|_| invalid())?),
+					"mirroring" => entry.mirroring = Some(parse_mirroring(value).ok_or_else(invalid)?),
+					"prg_ram" => entry.prg_ram_size = Some(value.parse().map_err(|_| invalid())?),
+					"prg_nvram" => entry.prg_nvram_size = Some(value.parse().map_err(|_| invalid())?),
+					"chr_ram" => entry.chr_ram_size = Some(value.parse().map_err(|_| invalid())?),
+					"chr_nvram" => entry.chr_nvram_size = Some(value.parse().map_err(|_| invalid())?),
+					"battery" => entry.battery = Some(value.parse().map_err(|_| invalid())?),
+					"timing" => entry.timing = Some(parse_timing(value).ok_or_else(invalid)?),
+					_ => return Err(error(format!("unknown field {}", key)))
+				}
+			}
+
+			database.insert(crc, entry);
+		}
+
+		Ok(database)
+	}
+
+	pub fn insert(&mut self, crc32: u32, entry: DatabaseEntry) {
+		self.entries.insert(crc32, entry);
+	}
+
+	pub fn get(&self, crc32: u32) -> Option<&DatabaseEntry> {
+		self.entries.get(&crc32)
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+}
+
+impl Default for GameDatabase {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+fn mirroring_name(mirroring: Mirroring) -> &'static str {
+	match mirroring {
+		Mirroring::Vertical => "vertical",
+		Mirroring::Horizontal => "horizontal",
+		Mirroring::FourScreen => "four_screen",
+		Mirroring::SingleScreenLower => "single_lower",
+		Mirroring::SingleScreenUpper => "single_upper"
+	}
+}
+
+fn parse_mirroring(name: &str) -> Option<Mirroring> {
+	[Mirroring::Vertical, Mirroring::Horizontal, Mirroring::FourScreen, Mirroring::SingleScreenLower, Mirroring::SingleScreenUpper]
+		.into_iter()
+		.find(|mirroring| mirroring_name(*mirroring) == name)
+}
+
+fn timing_name(timing: Timing) -> &'static str {
+	match timing {
+		Timing::Ntsc => "ntsc",
+		Timing::Pal => "pal",
+		Timing::MultiRegion => "multi",
+		Timing::Dendy => "dendy"
+	}
+}
+
+fn parse_timing(name: &str) -> Option<Timing> {
+	[Timing::Ntsc, Timing::Pal, Timing::MultiRegion, Timing::Dendy]
+		.into_iter()
+		.find(|timing| timing_name(*timing) == name)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse() {
+		let database = GameDatabase::parse("# Corrections\n\n0000ABCD mapper=4 mirroring=vertical battery=true # bad dump\n12345678 timing=pal\n").unwrap();
+
+		assert_eq!(database.len(), 2);
+		let entry = database.get(0xABCD).unwrap();
+		assert_eq!(entry.mapper, Some(4));
+		assert_eq!(entry.mirroring, Some(Mirroring::Vertical));
+		assert_eq!(entry.battery, Some(true));
+		assert_eq!(entry.prg_ram_size, None);
+		assert_eq!(database.get(0x12345678).unwrap().timing, Some(Timing::Pal));
+	}
+
+	#[test]
+	fn parse_errors() {
+		assert_eq!(GameDatabase::parse("XYZ").err().unwrap().line, 1);
+		assert_eq!(GameDatabase::parse("\n0000ABCD mapper=x").err().unwrap().line, 2);
+		assert!(GameDatabase::parse("0000ABCD colour=blue").is_err());
+		assert!(GameDatabase::parse("0000ABCD mapper").is_err());
+	}
+}
@@ -0,0 +1,96 @@
+// Checksums identifying a dump, as used by ROM databases
+
+// CRC-32 (IEEE 802.3, reflected), over several slices as if they were one
+pub fn crc32(parts: &[&[u8]]) -> u32 {
+	let mut crc = 0xFFFF_FFFFu32;
+
+	for byte in parts.iter().flat_map(|part| part.iter()) {
+		crc ^= u32::from(*byte);
+		for _ in 0..8 {
+			let mask = (crc & 0x01).wrapping_neg();
+			crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+		}
+	}
+
+	!crc
+}
+
+pub fn sha1(parts: &[&[u8]]) -> [u8; 20] {
+	let mut state: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+
+	let len: usize = parts.iter().map(|part| part.len()).sum();
+	let mut message: Vec<u8> = Vec::with_capacity(len + 72);
+	for part in parts {
+		message.extend_from_slice(part);
+	}
+
+	// Padding: a 1 bit, zeros, then the length in bits
+	message.push(0x80);
+	while message.len() % 64 != 56 {
+		message.push(0x00);
+	}
+	message.extend_from_slice(&((len as u64) * 8).to_be_bytes());
+
+	for block in message.chunks_exact(64) {
+		let mut w = [0u32; 80];
+		for (i, word) in block.chunks_exact(4).enumerate() {
+			w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+		}
+		for i in 16..80 {
+			w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+		}
+
+		let [mut a, mut b, mut c, mut d, mut e] = state;
+		for (i, word) in w.iter().enumerate() {
+			let (f, k) = match i {
+				0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+				20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+				40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+				_ => (b ^ c ^ d, 0xCA62_C1D6)
+			};
+
+			let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+			e = d;
+			d = c;
+			c = b.rotate_left(30);
+			b = a;
+			a = temp;
+		}
+
+		for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+			*value = value.wrapping_add(add);
+		}
+	}
+
+	let mut digest = [0u8; 20];
+	for (chunk, value) in digest.chunks_exact_mut(4).zip(state) {
+		chunk.copy_from_slice(&value.to_be_bytes());
+	}
+	digest
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn hex(bytes: &[u8]) -> String {
+		bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+	}
+
+	#[test]
+	fn crc32_check_value() {
+		assert_eq!(crc32(&[b"123456789"]), 0xCBF4_3926);
+		assert_eq!(crc32(&[b"1234", b"56789"]), 0xCBF4_3926);
+		assert_eq!(crc32(&[]), 0);
+	}
+
+	#[test]
+	fn sha1_vectors() {
+		assert_eq!(hex(&sha1(&[b"abc"])), "a9993e364706816aba3e25717850c26c9cd0d89d");
+		assert_eq!(hex(&sha1(&[b""])), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+		assert_eq!(
+			hex(&sha1(&[b"abcdbcdecdefdefgefghfghighij", b"hijkijkljklmklmnlmnomnopnopq"])),
+			"84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+		);
+	}
+}
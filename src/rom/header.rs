@@ -0,0 +1,240 @@
+use crate::rom::{Mirroring, RomError};
+
+pub const HEADER_SIZE: usize = 16;
+pub const TRAINER_SIZE: usize = 512;
+
+const PRG_ROM_PAGE_SIZE: usize = 16384;
+const CHR_ROM_PAGE_SIZE: usize = 8192;
+const INES_PRG_RAM_PAGE_SIZE: usize = 8192;
+
+// CPU/PPU timing the cartridge was made for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+	Ntsc,
+	Pal,
+	// Runs on both
+	MultiRegion,
+	Dendy
+}
+
+// Fields of an iNES or NES 2.0 header, sizes in bytes
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomHeader {
+	pub nes_2: bool,
+	pub mapper: u16,
+	// 0 for iNES files
+	pub submapper: u8,
+	pub prg_rom_size: usize,
+	pub chr_rom_size: usize,
+	// Volatile and battery backed RAM at $6000-$7FFF
+	pub prg_ram_size: usize,
+	pub prg_nvram_size: usize,
+	pub chr_ram_size: usize,
+	pub chr_nvram_size: usize,
+	pub mirroring: Mirroring,
+	pub battery: bool,
+	pub trainer: bool,
+	pub timing: Timing
+}
+
+impl RomHeader {
+	pub fn parse(buffer: &[u8]) -> Result<RomHeader, RomError> {
+		if buffer.len() < 4 || buffer[0..=3] != [0x4e, 0x45, 0x53, 0x1a] {
+			return Err(RomError::BadMagic);
+		}
+
+		if buffer.len() < HEADER_SIZE {
+			return Err(RomError::Truncated { expected: HEADER_SIZE, got: buffer.len() });
+		}
+
+		let flag_6 = buffer[6];
+		let flag_7 = buffer[7];
+		let nes_2 = flag_7 & 0x0C == 0x08;
+
+		let mut header = if nes_2 { RomHeader::parse_nes_2(buffer)? } else { RomHeader::parse_ines(buffer) };
+
+		let mirroring = (flag_6 & 0x01) != 0;
+		let four_screen = (flag_6 & 0x08) != 0;
+		header.mirroring = match (four_screen, mirroring) {
+			// UNROM 512 uses the four-screen bit alone for its one-screen board
+			(true, false) if header.mapper == 30 => Mirroring::SingleScreenLower,
+			(true, _) => Mirroring::FourScreen,
+			(false, true) => Mirroring::Vertical,
+			(false, false) => Mirroring::Horizontal
+		};
+		header.battery = (flag_6 & 0x02) != 0;
+		header.trainer = (flag_6 & 0x04) != 0;
+
+		// file_size then fits in a usize
+		header.prg_rom_offset().checked_add(header.prg_rom_size)
+			.and_then(|size| size.checked_add(header.chr_rom_size))
+			.ok_or(RomError::InvalidSize)?;
+
+		Ok(header)
+	}
+
+	fn parse_ines(buffer: &[u8]) -> RomHeader {
+		let flag_6 = buffer[6];
+		let flag_7 = buffer[7];
+
+		// Old dumps have garbage in bytes 7-15, the upper nibble and byte 9 are only trusted when the end is zeroed
+		let clean = buffer[12..=15] == [0x0, 0x0, 0x0, 0x0];
+		let high_mapper = if clean { flag_7 & 0xf0 } else { 0x0 };
+		let timing = if clean && buffer[9] & 0x01 != 0 { Timing::Pal } else { Timing::Ntsc };
+
+		let chr_rom_size = usize::from(buffer[5]) * CHR_ROM_PAGE_SIZE;
+
+		RomHeader {
+			nes_2: false,
+			mapper: u16::from(high_mapper | (flag_6 >> 4)),
+			submapper: 0,
+			prg_rom_size: usize::from(buffer[4]) * PRG_ROM_PAGE_SIZE,
+			chr_rom_size,
+			// 0 means 8KB, for compatibility with older dumps
+			prg_ram_size: usize::from(buffer[8].max(1)) * INES_PRG_RAM_PAGE_SIZE,
+			prg_nvram_size: 0,
+			// No CHR ROM means the board has 8KB of CHR RAM
+			chr_ram_size: if chr_rom_size == 0 { CHR_ROM_PAGE_SIZE } else { 0 },
+			chr_nvram_size: 0,
+			mirroring: Mirroring::Horizontal,
+			battery: false,
+			trainer: false,
+			timing
+		}
+	}
+
+	fn parse_nes_2(buffer: &[u8]) -> Result<RomHeader, RomError> {
+		let mapper = u16::from(buffer[6] >> 4) | u16::from(buffer[7] & 0xF0) | (u16::from(buffer[8] & 0x0F) << 8);
+
+		Ok(RomHeader {
+			nes_2: true,
+			mapper,
+			submapper: buffer[8] >> 4,
+			prg_rom_size: rom_size(buffer[4], buffer[9] & 0x0F, PRG_ROM_PAGE_SIZE).ok_or(RomError::InvalidSize)?,
+			chr_rom_size: rom_size(buffer[5], buffer[9] >> 4, CHR_ROM_PAGE_SIZE).ok_or(RomError::InvalidSize)?,
+			prg_ram_size: ram_size(buffer[10] & 0x0F),
+			prg_nvram_size: ram_size(buffer[10] >> 4),
+			chr_ram_size: ram_size(buffer[11] & 0x0F),
+			chr_nvram_size: ram_size(buffer[11] >> 4),
+			mirroring: Mirroring::Horizontal,
+			battery: false,
+			trainer: false,
+			timing: match buffer[12] & 0x03 {
+				0 => Timing::Ntsc,
+				1 => Timing::Pal,
+				2 => Timing::MultiRegion,
+				_ => Timing::Dendy
+			}
+		})
+	}
+
+	// Offset of the PRG ROM in the file
+	pub fn prg_rom_offset(&self) -> usize {
+		HEADER_SIZE + if self.trainer { TRAINER_SIZE } else { 0 }
+	}
+
+	pub fn chr_rom_offset(&self) -> usize {
+		self.prg_rom_offset() + self.prg_rom_size
+	}
+
+	// Size of a complete file, anything after the CHR ROM is ignored
+	pub fn file_size(&self) -> usize {
+		self.chr_rom_offset() + self.chr_rom_size
+	}
+}
+
+// NES 2.0 ROM size from the LSB byte and MSB nibble, an MSB of $F selects the exponent form.
+// None when the size does not fit in a usize
+fn rom_size(lsb: u8, msb: u8, page_size: usize) -> Option<usize> {
+	if msb == 0x0F {
+		let exponent = u32::from(lsb >> 2);
+		let multiplier = usize::from(lsb & 0x03) * 2 + 1;
+		1usize.checked_shl(exponent)?.checked_mul(multiplier)
+	} else {
+		((usize::from(msb) << 8) | usize::from(lsb)).checked_mul(page_size)
+	}
+}
+
+// NES 2.0 RAM shift count, 64 << shift bytes
+fn ram_size(shift: u8) -> usize {
+	if shift == 0 { 0 } else { 64 << shift }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn header(bytes: [u8; 12]) -> Vec<u8> {
+		let mut buffer = vec![0x4e, 0x45, 0x53, 0x1a];
+		buffer.extend_from_slice(&bytes);
+		buffer
+	}
+
+	#[test]
+	fn ines() {
+		let header = RomHeader::parse(&header([2, 1, 0x43, 0x10, 0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+
+		assert!(!header.nes_2);
+		assert_eq!(header.mapper, 0x14);
+		assert_eq!(header.prg_rom_size, 32768);
+		assert_eq!(header.chr_rom_size, 8192);
+		assert_eq!(header.prg_ram_size, 8192);
+		assert_eq!(header.chr_ram_size, 0);
+		assert_eq!(header.mirroring, Mirroring::Vertical);
+		assert!(header.battery);
+		assert_eq!(header.timing, Timing::Pal);
+	}
+
+	#[test]
+	fn ines_garbage() {
+		// "DiskDude!" in bytes 7-15
+		let mut buffer = header([1, 0, 0x40, 0x44, 0, 0, 0, 0, 0, 0, 0, 0]);
+		buffer[7..16].copy_from_slice(b"DiskDude!");
+		let header = RomHeader::parse(&buffer).unwrap();
+
+		assert!(!header.nes_2);
+		assert_eq!(header.mapper, 4);
+		assert_eq!(header.chr_ram_size, 8192);
+	}
+
+	#[test]
+	fn nes_2() {
+		// Mapper 0x105 submapper 2, 64 + 0 PRG pages, CHR RAM 8KB, PRG NVRAM 8KB, Dendy
+		let header = RomHeader::parse(&header([64, 0, 0x50, 0x08, 0x21, 0x00, 0x70, 0x07, 0x03, 0, 0, 0])).unwrap();
+
+		assert!(header.nes_2);
+		assert_eq!(header.mapper, 0x105);
+		assert_eq!(header.submapper, 2);
+		assert_eq!(header.prg_rom_size, 64 * 16384);
+		assert_eq!(header.chr_rom_size, 0);
+		assert_eq!(header.prg_ram_size, 0);
+		assert_eq!(header.prg_nvram_size, 8192);
+		assert_eq!(header.chr_ram_size, 8192);
+		assert_eq!(header.timing, Timing::Dendy);
+	}
+
+	#[test]
+	fn invalid_headers() {
+		assert_eq!(RomHeader::parse(b"NES"), Err(RomError::BadMagic));
+		assert_eq!(RomHeader::parse(b"UNIF\x00\x00\x00\x00"), Err(RomError::BadMagic));
+		assert_eq!(RomHeader::parse(&[0x4e, 0x45, 0x53, 0x1a, 1, 1]), Err(RomError::Truncated { expected: 16, got: 6 }));
+	}
+
+	#[test]
+	fn exponent_size() {
+		// 2^10 * 3
+		assert_eq!(rom_size(0b0010_1001, 0x0F, PRG_ROM_PAGE_SIZE), Some(3072));
+		assert_eq!(rom_size(0x02, 0x01, PRG_ROM_PAGE_SIZE), Some(0x102 * 16384));
+		// 2^63 * 7
+		assert_eq!(rom_size(0xFF, 0x0F, PRG_ROM_PAGE_SIZE), None);
+
+		let mut buffer = header([1, 1, 0x00, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0]);
+		buffer[4] = 0xFF;
+		assert_eq!(RomHeader::parse(&buffer), Err(RomError::InvalidSize));
+		// 2^62 * 3 bytes of PRG ROM and as much CHR ROM
+		buffer[4] = 0xF9;
+		buffer[5] = 0xF9;
+		buffer[9] = 0xFF;
+		assert_eq!(RomHeader::parse(&buffer), Err(RomError::InvalidSize));
+	}
+}
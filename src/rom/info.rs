@@ -0,0 +1,51 @@
+use crate::mapper::mapper_name;
+use crate::rom::Mirroring;
+use crate::rom::database::HeaderOverride;
+use crate::rom::hash::{crc32, sha1};
+use crate::rom::header::{RomHeader, Timing};
+
+// Description of a loaded dump, for title databases and save file naming
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+	pub mapper: u16,
+	pub submapper: u8,
+	// Board name, None for mappers nessy does not know
+	pub mapper_name: Option<&'static str>,
+	pub prg_rom_size: usize,
+	pub chr_rom_size: usize,
+	pub mirroring: Mirroring,
+	pub battery: bool,
+	pub timing: Timing,
+	// Of the PRG ROM followed by the CHR ROM, without header or trainer
+	pub crc32: u32,
+	pub sha1: [u8; 20],
+	// Header fields corrected by the game database
+	pub overrides: Vec<HeaderOverride>
+}
+
+impl RomInfo {
+	pub fn new(header: &RomHeader, pgr_rom: &[u8], chr_rom: &[u8]) -> RomInfo {
+		RomInfo {
+			mapper: header.mapper,
+			submapper: header.submapper,
+			mapper_name: mapper_name(header.mapper),
+			prg_rom_size: header.prg_rom_size,
+			chr_rom_size: header.chr_rom_size,
+			mirroring: header.mirroring,
+			battery: header.battery,
+			timing: header.timing,
+			crc32: crc32(&[pgr_rom, chr_rom]),
+			sha1: sha1(&[pgr_rom, chr_rom]),
+			overrides: Vec::new()
+		}
+	}
+
+	// Uppercase hex, as written in No-Intro and nes20db
+	pub fn crc32_hex(&self) -> String {
+		format!("{:08X}", self.crc32)
+	}
+
+	pub fn sha1_hex(&self) -> String {
+		self.sha1.iter().map(|byte| format!("{:02X}", byte)).collect()
+	}
+}
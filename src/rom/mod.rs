@@ -0,0 +1,248 @@
+pub mod header;
+pub mod info;
+pub mod database;
+mod archive;
+pub(crate) mod hash;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::mapper::Mapper;
+use crate::mapper::fds::{self, Fds, FdsDisk};
+use crate::mapper::registry::{Board, MapperRegistry};
+use header::RomHeader;
+use info::RomInfo;
+use database::GameDatabase;
+
+pub const PRG_RAM_PAGE_SIZE: usize = 8192;
+
+#[derive(Clone)]
+pub struct Rom {
+	pub mapper: Box<dyn Mapper>,
+	pub mirroring: Mirroring,
+	// Work RAM at $6000-$7FFF, 0 when the board has none
+	pub prg_ram_size: usize,
+	// PRG RAM keeps its content when powered off
+	pub battery: bool,
+	// None for ROMs not loaded from a file
+	pub info: Option<RomInfo>
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RomError {
+	// Not an iNES file or FDS image
+	BadMagic,
+	// The file is shorter than its header says
+	Truncated { expected: usize, got: usize },
+	// NES 2.0 ROM sizes too large to address
+	InvalidSize,
+	UnsupportedMapper(u16),
+	// Reading the file failed
+	Io(io::ErrorKind),
+	// Broken .zip or .gz, or not exactly one .nes file inside
+	Archive(String)
+}
+
+impl fmt::Display for RomError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RomError::BadMagic => write!(f, "not an iNES file"),
+			RomError::Truncated { expected, got } => write!(f, "file is truncated, {} bytes expected but {} found", expected, got),
+			RomError::InvalidSize => write!(f, "ROM size in the header is too large"),
+			RomError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+			RomError::Io(kind) => write!(f, "could not read the file: {}", kind),
+			RomError::Archive(reason) => write!(f, "could not unpack the archive: {}", reason)
+		}
+	}
+}
+
+impl std::error::Error for RomError {}
+
+impl From<RomError> for io::Error {
+	fn from(error: RomError) -> io::Error {
+		match error {
+			RomError::Io(kind) => io::Error::from(kind),
+			error => io::Error::new(io::ErrorKind::InvalidData, error)
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mirroring {
+	Vertical,
+	Horizontal,
+	FourScreen,
+	SingleScreenLower,
+	SingleScreenUpper
+}
+
+impl Rom {
+	// Load an iNES file, .gz and .zip files are unpacked with the `archives` feature
+	pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Rom, RomError> {
+		let buffer = fs::read(path).map_err(|error| RomError::Io(error.kind()))?;
+		Rom::from_ines(&archive::unpack(buffer)?)
+	}
+
+	pub fn from_ines(buffer: &[u8]) -> Result<Rom, RomError> {
+		Rom::from_ines_with_registry(buffer, &MapperRegistry::new())
+	}
+
+	// Build the mapper with `registry`, for boards not built into nessy
+	pub fn from_ines_with_registry(buffer: &[u8], registry: &MapperRegistry) -> Result<Rom, RomError> {
+		Rom::from_ines_with(buffer, registry, None)
+	}
+
+	// Headers of dumps listed in `database` are corrected before building the mapper,
+	// the changes are listed in RomInfo::overrides
+	pub fn from_ines_with(buffer: &[u8], registry: &MapperRegistry, database: Option<&GameDatabase>) -> Result<Rom, RomError> {
+		let mut header = RomHeader::parse(buffer)?;
+		if buffer.len() < header.file_size() {
+			return Err(RomError::Truncated { expected: header.file_size(), got: buffer.len() });
+		}
+
+		let pgr_rom_idx = header.prg_rom_offset();
+		let chr_rom_idx = header.chr_rom_offset();
+
+		let overrides = database
+			.and_then(|database| database.get(hash::crc32(&[&buffer[pgr_rom_idx..header.file_size()]])))
+			.map(|entry| entry.apply(&mut header))
+			.unwrap_or_default();
+
+		let chr_ram = header.chr_rom_size == 0;
+		let chr = if chr_ram {
+			// Headers declaring neither CHR ROM nor CHR RAM get the common 8KB
+			vec![0; (header.chr_ram_size + header.chr_nvram_size).max(8192)]
+		} else {
+			buffer[chr_rom_idx..(chr_rom_idx + header.chr_rom_size)].to_vec()
+		};
+
+		let pgr_rom = buffer[pgr_rom_idx..(pgr_rom_idx + header.prg_rom_size)].to_vec();
+		let mut info = RomInfo::new(&header, &pgr_rom, &buffer[chr_rom_idx..(chr_rom_idx + header.chr_rom_size)]);
+		info.overrides = overrides;
+
+		let board = Board {
+			mapper: header.mapper,
+			submapper: header.submapper,
+			pgr_rom,
+			chr_rom: chr,
+			chr_ram,
+			mirroring: header.mirroring,
+			battery: header.battery
+		};
+		let mapper = registry.create(board)
+			.ok_or(RomError::UnsupportedMapper(header.mapper))?;
+
+		Ok(Rom { 
+			mapper,
+			mirroring: header.mirroring,
+			prg_ram_size: header.prg_ram_size + header.prg_nvram_size,
+			battery: header.battery,
+			info: Some(info)
+		})
+	}
+
+	// Famicom Disk System .fds image, run by the 8KB FDS BIOS
+	pub fn from_fds(disk: &[u8], bios: &[u8]) -> Result<Rom, RomError> {
+		if bios.len() < fds::BIOS_SIZE {
+			return Err(RomError::Truncated { expected: fds::BIOS_SIZE, got: bios.len() });
+		}
+
+		let disk = FdsDisk::parse(disk)?;
+		Ok(Rom {
+			mapper: Box::new(Fds::new(bios[..fds::BIOS_SIZE].to_vec(), disk)),
+			mirroring: Mirroring::Horizontal,
+			// The RAM adapter maps its own RAM at $6000-$DFFF
+			prg_ram_size: 0,
+			battery: false,
+			info: None
+		})
+	}
+
+	pub fn info(&self) -> Option<&RomInfo> {
+		self.info.as_ref()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ines(mapper: u8, prg_pages: u8) -> Vec<u8> {
+		let mut buffer = vec![0x4e, 0x45, 0x53, 0x1a, prg_pages, 1, mapper << 4, mapper & 0xF0, 0, 0, 0, 0, 0, 0, 0, 0];
+		buffer.resize(16 + usize::from(prg_pages) * 16384 + 8192, 0);
+		buffer
+	}
+
+	#[test]
+	fn info() {
+		let rom = Rom::from_ines(&ines(4, 2)).unwrap();
+		let info = rom.info().unwrap();
+
+		assert_eq!(info.mapper, 4);
+		assert_eq!(info.mapper_name, Some("MMC3"));
+		assert_eq!(info.prg_rom_size, 32768);
+		assert_eq!(info.chr_rom_size, 8192);
+		assert!(!info.battery);
+		// 40KB of zeros
+		assert_eq!(info.crc32_hex(), "2C2BB90A");
+		assert_eq!(info.sha1_hex(), "C90116149196CBF74FFB453ECB3B12945372EBFA");
+	}
+
+	#[test]
+	fn database_override() {
+		use crate::rom::database::DatabaseEntry;
+
+		let buffer = ines(0, 1);
+		let crc = Rom::from_ines(&buffer).unwrap().info().unwrap().crc32;
+
+		let mut database = GameDatabase::new();
+		database.insert(crc, DatabaseEntry { mapper: Some(4), mirroring: Some(Mirroring::Horizontal), ..DatabaseEntry::default() });
+
+		let rom = Rom::from_ines_with(&buffer, &MapperRegistry::new(), Some(&database)).unwrap();
+		let info = rom.info().unwrap();
+		assert_eq!(info.mapper, 4);
+		// Mirroring already matched
+		assert_eq!(info.overrides.len(), 1);
+		assert_eq!(info.overrides[0].to_string(), "mapper: 0 -> 4");
+	}
+
+	#[test]
+	fn errors() {
+		assert!(Rom::from_ines(&ines(0, 1)).is_ok());
+		assert!(matches!(Rom::from_ines(&ines(0xFF, 1)), Err(RomError::UnsupportedMapper(0xFF))));
+
+		let truncated = &ines(0, 2)[..20000];
+		assert!(matches!(Rom::from_ines(truncated), Err(RomError::Truncated { expected: 40976, got: 20000 })));
+	}
+}
+
+pub mod test {
+	use super::*;
+	use crate::mapper::test;
+	
+	pub fn test_rom() -> Rom {
+		// Empty rom (Nrom)
+		Rom {
+			mapper: test::test_mapper(),
+			mirroring: Mirroring::Vertical,
+			prg_ram_size: PRG_RAM_PAGE_SIZE,
+			battery: false,
+			info: None
+		}
+	}
+
+	// iNES file of an NROM with `program` at $8000 and the NMI, reset and IRQ vectors
+	pub fn nrom(program: &[u8], vectors: [u16; 3]) -> Vec<u8> {
+		let mut ines = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+		ines.extend(program);
+		ines.resize(16 + 16384 - 6, 0);
+		for vector in vectors {
+			ines.extend(vector.to_le_bytes());
+		}
+		ines.resize(16 + 16384 + 8192, 0);
+		ines
+	}
+}
\ No newline at end of file
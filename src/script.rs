@@ -0,0 +1,476 @@
+// Rhai scripts driving the emulator, with modules named after the FCEUX Lua ones so
+// automation scripts port over line by line: memory::readbyte(0x10) for memory.readbyte(0x10).
+//
+// Scripts define the callbacks they need, all called with `this` bound to a map kept
+// between calls, since Rhai functions do not see the global variables:
+//   fn start() { this.deaths = 0; }          // once, after the top-level code
+//   fn before_frame() { joypad::set(1, #{ start: true }); }
+//   fn after_frame() { gui::box(8, 8, 40, 16, 0x000000, 0xFFFFFF); }
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Module, Scope, AST, INT};
+
+use crate::joypad::{Button, Joypad};
+use crate::nes::Nes;
+use crate::ppu::frame::{Frame, HEIGHT, WIDTH};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// Keys of joypad::get and joypad::set, in Button order
+const BUTTON_NAMES: [&str; 8] = ["A", "B", "select", "start", "up", "down", "left", "right"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScriptError {
+	Parse(String),
+	// Error thrown while running the script or one of its callbacks
+	Runtime(String)
+}
+
+impl fmt::Display for ScriptError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ScriptError::Parse(message) => write!(f, "script does not compile: {}", message),
+			ScriptError::Runtime(message) => write!(f, "script error: {}", message)
+		}
+	}
+}
+
+impl std::error::Error for ScriptError {}
+
+// Drawn over the frame, colors are 0xRRGGBB
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Shape {
+	Pixel { x: INT, y: INT, color: u32 },
+	Line { x1: INT, y1: INT, x2: INT, y2: INT, color: u32 },
+	Box { x1: INT, y1: INT, x2: INT, y2: INT, fill: Option<u32>, outline: u32 }
+}
+
+struct Context {
+	nes: Nes,
+	// Overlay of the current frame
+	shapes: Vec<Shape>,
+	// Buttons joypad::set forces (pressed mask, released mask) for the next frame only
+	input: [(u8, u8); 2],
+	messages: Vec<String>
+}
+
+// Console driven by a script, the frontend calls run_frame and draws frame() instead of the Nes ones
+pub struct Script {
+	engine: Engine,
+	ast: AST,
+	scope: Scope<'static>,
+	this: Dynamic,
+	context: Rc<RefCell<Context>>
+}
+
+impl Script {
+	// Compiles the script and runs its top-level code, then its start callback
+	pub fn new(nes: Nes, source: &str) -> Result<Script, ScriptError> {
+		let context = Rc::new(RefCell::new(Context {
+			nes,
+			shapes: Vec::new(),
+			input: [(0, 0); 2],
+			messages: Vec::new()
+		}));
+
+		let mut engine = Engine::new();
+		engine.register_static_module("memory", memory_module(&context).into());
+		engine.register_static_module("emu", emu_module(&context).into());
+		engine.register_static_module("joypad", joypad_module(&context).into());
+		engine.register_static_module("gui", gui_module(&context).into());
+		let printed = context.clone();
+		engine.on_print(move |text| printed.borrow_mut().messages.push(text.to_string()));
+
+		let ast = engine.compile(source).map_err(|error| ScriptError::Parse(error.to_string()))?;
+		let mut scope = Scope::new();
+		engine.run_ast_with_scope(&mut scope, &ast).map_err(runtime_error)?;
+
+		let mut script = Script {
+			engine,
+			ast,
+			scope,
+			this: Dynamic::from_map(Map::new()),
+			context
+		};
+		script.call("start")?;
+		Ok(script)
+	}
+
+	pub fn nes(&self) -> Ref<'_, Nes> {
+		Ref::map(self.context.borrow(), |context| &context.nes)
+	}
+
+	pub fn nes_mut(&mut self) -> RefMut<'_, Nes> {
+		RefMut::map(self.context.borrow_mut(), |context| &mut context.nes)
+	}
+
+	// Text from print and emu::message since the last call
+	pub fn messages(&mut self) -> Vec<String> {
+		mem::take(&mut self.context.borrow_mut().messages)
+	}
+
+	// before_frame, the frame with the buttons the script set, then after_frame
+	pub fn run_frame(&mut self) -> Result<(), ScriptError> {
+		self.context.borrow_mut().shapes.clear();
+		self.call("before_frame")?;
+
+		{
+			let mut context = self.context.borrow_mut();
+			let context = &mut *context;
+			let input = mem::take(&mut context.input);
+			// What the frontend set, given back once the frame is over
+			let held = [0, 1].map(|player| joypad(&mut context.nes, player).map(|joypad| joypad.buttons()));
+			for (player, (pressed, released)) in input.into_iter().enumerate() {
+				if let Some(joypad) = joypad(&mut context.nes, player) {
+					joypad.set_buttons((joypad.buttons() | pressed) & !released);
+				}
+			}
+			context.nes.run_frame();
+			for (player, buttons) in held.into_iter().enumerate() {
+				if let (Some(joypad), Some(buttons)) = (joypad(&mut context.nes, player), buttons) {
+					joypad.set_buttons(buttons);
+				}
+			}
+		}
+
+		self.call("after_frame")
+	}
+
+	// Last frame with the script overlay drawn on it
+	pub fn frame(&self) -> Frame {
+		let context = self.context.borrow();
+		let mut frame = context.nes.frame().clone();
+		for shape in &context.shapes {
+			draw(&mut frame, shape);
+		}
+		frame
+	}
+
+	// Callbacks the script does not define are skipped
+	fn call(&mut self, name: &str) -> Result<(), ScriptError> {
+		if !self.ast.iter_functions().any(|function| function.name == name && function.params.is_empty()) {
+			return Ok(());
+		}
+
+		let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.this);
+		self.engine.call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, name, ())
+			.map(|_| ())
+			.map_err(runtime_error)
+	}
+}
+
+fn runtime_error(error: Box<EvalAltResult>) -> ScriptError {
+	ScriptError::Runtime(error.to_string())
+}
+
+// Players are numbered from 1 like in FCEUX
+fn player_index(player: INT) -> ScriptResult<usize> {
+	match player {
+		1 | 2 => Ok(player as usize - 1),
+		_ => Err(format!("no player {}, 1 or 2", player).into())
+	}
+}
+
+fn joypad(nes: &mut Nes, player: usize) -> Option<&mut Joypad> {
+	if player == 0 {
+		nes.bus_mut().joypad1_mut()
+	} else {
+		nes.bus_mut().joypad2_mut()
+	}
+}
+
+fn memory_module(context: &Rc<RefCell<Context>>) -> Module {
+	let mut module = Module::new();
+
+	let shared = context.clone();
+	module.set_native_fn("readbyte", move |adress: INT| -> ScriptResult<INT> {
+		Ok(INT::from(shared.borrow().nes.bus().peek(adress as u16)))
+	});
+	let shared = context.clone();
+	module.set_native_fn("readbytesigned", move |adress: INT| -> ScriptResult<INT> {
+		Ok(INT::from(shared.borrow().nes.bus().peek(adress as u16) as i8))
+	});
+	let shared = context.clone();
+	module.set_native_fn("readword", move |adress: INT| -> ScriptResult<INT> {
+		Ok(INT::from(shared.borrow().nes.bus().peek_u16(adress as u16)))
+	});
+	let shared = context.clone();
+	module.set_native_fn("writebyte", move |adress: INT, value: INT| -> ScriptResult<()> {
+		shared.borrow_mut().nes.bus_mut().poke(adress as u16, value as u8);
+		Ok(())
+	});
+
+	let shared = context.clone();
+	module.set_native_fn("getregister", move |name: &str| -> ScriptResult<INT> {
+		let context = shared.borrow();
+		let cpu = context.nes.cpu();
+		let value = match name {
+			"a" => cpu.a().into(),
+			"x" => cpu.x().into(),
+			"y" => cpu.y().into(),
+			"s" => cpu.sp().into(),
+			"p" => cpu.status().into(),
+			"pc" => cpu.pc,
+			_ => return Err(format!("no register {}", name).into())
+		};
+		Ok(INT::from(value))
+	});
+	let shared = context.clone();
+	module.set_native_fn("setregister", move |name: &str, value: INT| -> ScriptResult<()> {
+		let mut context = shared.borrow_mut();
+		let cpu = context.nes.cpu_mut();
+		match name {
+			"a" => cpu.set_a(value as u8),
+			"x" => cpu.set_x(value as u8),
+			"y" => cpu.set_y(value as u8),
+			"s" => cpu.set_sp(value as u8),
+			"p" => cpu.set_status(value as u8),
+			"pc" => cpu.pc = value as u16,
+			_ => return Err(format!("no register {}", name).into())
+		}
+		Ok(())
+	});
+
+	module
+}
+
+fn emu_module(context: &Rc<RefCell<Context>>) -> Module {
+	let mut module = Module::new();
+
+	let shared = context.clone();
+	module.set_native_fn("framecount", move || -> ScriptResult<INT> {
+		Ok(shared.borrow().nes.bus().ppu().frame_count() as INT)
+	});
+	let shared = context.clone();
+	module.set_native_fn("softreset", move || -> ScriptResult<()> {
+		shared.borrow_mut().nes.reset();
+		Ok(())
+	});
+	// Shown on screen by FCEUX, returned by Script::messages here
+	let shared = context.clone();
+	module.set_native_fn("message", move |text: &str| -> ScriptResult<()> {
+		shared.borrow_mut().messages.push(text.to_string());
+		Ok(())
+	});
+
+	module
+}
+
+fn joypad_module(context: &Rc<RefCell<Context>>) -> Module {
+	let mut module = Module::new();
+
+	let shared = context.clone();
+	module.set_native_fn("get", move |player: INT| -> ScriptResult<Map> {
+		let player = player_index(player)?;
+		let mut context = shared.borrow_mut();
+		let buttons = joypad(&mut context.nes, player).map_or(0, |joypad| joypad.buttons());
+		Ok(Button::ALL.iter().zip(BUTTON_NAMES).map(|(button, name)| (name.into(), Dynamic::from_bool(buttons & button.mask() != 0))).collect())
+	});
+	// true presses, false releases and missing buttons keep the frontend input
+	let shared = context.clone();
+	module.set_native_fn("set", move |player: INT, buttons: Map| -> ScriptResult<()> {
+		let player = player_index(player)?;
+		let (mut pressed, mut released) = (0, 0);
+		for (name, value) in buttons {
+			let Some(index) = BUTTON_NAMES.iter().position(|button| button.eq_ignore_ascii_case(&name)) else {
+				return Err(format!("no button {}", name).into());
+			};
+			match value.as_bool() {
+				Ok(true) => pressed |= Button::ALL[index].mask(),
+				Ok(false) => released |= Button::ALL[index].mask(),
+				Err(_) => {}
+			}
+		}
+		shared.borrow_mut().input[player] = (pressed, released);
+		Ok(())
+	});
+
+	module
+}
+
+fn gui_module(context: &Rc<RefCell<Context>>) -> Module {
+	let mut module = Module::new();
+
+	let shared = context.clone();
+	module.set_native_fn("pixel", move |x: INT, y: INT, color: INT| -> ScriptResult<()> {
+		shared.borrow_mut().shapes.push(Shape::Pixel { x, y, color: color as u32 });
+		Ok(())
+	});
+	let shared = context.clone();
+	module.set_native_fn("line", move |x1: INT, y1: INT, x2: INT, y2: INT, color: INT| -> ScriptResult<()> {
+		line_end(x1, y1)?;
+		line_end(x2, y2)?;
+		shared.borrow_mut().shapes.push(Shape::Line { x1, y1, x2, y2, color: color as u32 });
+		Ok(())
+	});
+	let shared = context.clone();
+	module.set_native_fn("box", move |x1: INT, y1: INT, x2: INT, y2: INT, outline: INT| -> ScriptResult<()> {
+		shared.borrow_mut().shapes.push(Shape::Box { x1, y1, x2, y2, fill: None, outline: outline as u32 });
+		Ok(())
+	});
+	let shared = context.clone();
+	module.set_native_fn("box", move |x1: INT, y1: INT, x2: INT, y2: INT, fill: INT, outline: INT| -> ScriptResult<()> {
+		shared.borrow_mut().shapes.push(Shape::Box { x1, y1, x2, y2, fill: Some(fill as u32), outline: outline as u32 });
+		Ok(())
+	});
+
+	module
+}
+
+// Within a frame size of the screen, so lines are drawn in a few steps and do not overflow
+fn line_end(x: INT, y: INT) -> ScriptResult<()> {
+	let (width, height) = (WIDTH as INT, HEIGHT as INT);
+	if (-width..2 * width).contains(&x) && (-height..2 * height).contains(&y) {
+		Ok(())
+	} else {
+		Err(format!("line end ({}, {}) is too far off screen", x, y).into())
+	}
+}
+
+// Shapes are clipped to the frame
+fn plot(frame: &mut Frame, x: INT, y: INT, color: u32) {
+	if (0..frame.width as INT).contains(&x) && (0..frame.height as INT).contains(&y) {
+		frame.set_pixel(x as usize, y as usize, ((color >> 16) as u8, (color >> 8) as u8, color as u8));
+	}
+}
+
+fn draw(frame: &mut Frame, shape: &Shape) {
+	match *shape {
+		Shape::Pixel { x, y, color } => plot(frame, x, y, color),
+		// Bresenham
+		Shape::Line { x1, y1, x2, y2, color } => {
+			let (dx, dy) = ((x2 - x1).abs(), -(y2 - y1).abs());
+			let (step_x, step_y) = ((x2 - x1).signum(), (y2 - y1).signum());
+			let (mut x, mut y, mut error) = (x1, y1, dx + dy);
+			loop {
+				plot(frame, x, y, color);
+				if x == x2 && y == y2 {
+					break;
+				}
+				if 2 * error >= dy {
+					error += dy;
+					x += step_x;
+				}
+				if 2 * error <= dx {
+					error += dx;
+					y += step_y;
+				}
+			}
+		},
+		Shape::Box { x1, y1, x2, y2, fill, outline } => {
+			let (left, right) = (x1.min(x2), x1.max(x2));
+			let (top, bottom) = (y1.min(y2), y1.max(y2));
+			// Only the part on the frame, the edges may be off screen
+			for y in top.max(0)..=bottom.min(frame.height as INT - 1) {
+				for x in left.max(0)..=right.min(frame.width as INT - 1) {
+					let edge = x == left || x == right || y == top || y == bottom;
+					match fill {
+						_ if edge => plot(frame, x, y, outline),
+						Some(fill) => plot(frame, x, y, fill),
+						None => {}
+					}
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::rom::Rom;
+	use crate::rom::test::nrom;
+
+	// NROM adding the controller 1 buttons to $10 every frame
+	fn rom() -> Rom {
+		Rom::from_ines(&nrom(&[
+			0xA9, 0x80, 0x8D, 0x00, 0x20, // NMI on
+			0x4C, 0x05, 0x80,             // JMP *
+			// NMI
+			0xA9, 0x01, 0x8D, 0x16, 0x40, // Strobe
+			0xA9, 0x00, 0x8D, 0x16, 0x40,
+			0xAD, 0x16, 0x40,             // LDA $4016, A button
+			0x29, 0x01,                   // AND #1
+			0x18, 0x65, 0x10, 0x85, 0x10, // CLC, ADC $10, STA $10
+			0x40                          // RTI
+		], [0x8008, 0x8000, 0])).unwrap()
+	}
+
+	#[test]
+	fn callbacks() {
+		let source = r#"
+			print("loaded");
+			fn start() { this.frames = 0; }
+			fn before_frame() {
+				this.frames += 1;
+				if this.frames % 2 == 0 { joypad::set(1, #{ A: true }); }
+			}
+			fn after_frame() {
+				memory::writebyte(0x20, memory::readbyte(0x10));
+				gui::box(0, 0, 3, 3, 0x0000FF, 0xFF0000);
+				gui::line(10, 10, 13, 13, 0x00FF00);
+				if emu::framecount() == 4 { emu::message(`frame ${this.frames}`); }
+			}
+		"#;
+		let mut script = Script::new(Nes::new(rom()), source).unwrap();
+		assert_eq!(script.messages(), ["loaded"]);
+
+		for _ in 0..4 {
+			script.run_frame().unwrap();
+		}
+		// A pressed on the 2nd and 4th frame, only for that frame
+		assert_eq!(script.nes().bus().peek(0x10), 2);
+		assert_eq!(script.nes().bus().peek(0x20), 2);
+		assert_eq!(script.nes_mut().bus_mut().joypad1_mut().unwrap().buttons(), 0);
+		assert_eq!(script.messages(), ["frame 4"]);
+
+		let frame = script.frame();
+		assert_eq!(frame.pixel(0, 0), (0xFF, 0, 0));
+		assert_eq!(frame.pixel(1, 1), (0, 0, 0xFF));
+		assert_eq!(frame.pixel(12, 12), (0, 0xFF, 0));
+		assert_eq!(frame.pixel(20, 20), script.nes().frame().pixel(20, 20));
+	}
+
+	#[test]
+	fn registers_and_errors() {
+		let mut script = Script::new(Nes::new(rom()), r#"
+			fn after_frame() {
+				memory::setregister("x", 0x42);
+				memory::writebyte(0x30, memory::getregister("x"));
+				let pad = joypad::get(2);
+				if pad.start { memory::writebyte(0x31, 1); }
+			}
+		"#).unwrap();
+		script.nes_mut().set_button2(Button::Start, true);
+		script.run_frame().unwrap();
+		assert_eq!(script.nes().cpu().x(), 0x42);
+		assert_eq!(script.nes().bus().peek(0x30), 0x42);
+		assert_eq!(script.nes().bus().peek(0x31), 1);
+
+		assert!(matches!(Script::new(Nes::new(rom()), "fn (").err(), Some(ScriptError::Parse(_))));
+		let mut script = Script::new(Nes::new(rom()), "fn before_frame() { joypad::set(3, #{}); }").unwrap();
+		assert!(matches!(script.run_frame(), Err(ScriptError::Runtime(message)) if message.contains("no player 3")));
+	}
+
+	#[test]
+	fn shapes_off_screen() {
+		let mut script = Script::new(Nes::new(rom()), r#"
+			fn after_frame() {
+				gui::box(-1000000000, 2, 1000000000, 1000000000, 0x0000FF, 0xFF0000);
+				gui::line(-100, 0, 300, 0, 0x00FF00);
+			}
+		"#).unwrap();
+		script.run_frame().unwrap();
+		let frame = script.frame();
+		assert_eq!(frame.pixel(0, 2), (0xFF, 0, 0));
+		assert_eq!(frame.pixel(255, 239), (0, 0, 0xFF));
+		assert_eq!(frame.pixel(255, 0), (0, 0xFF, 0));
+
+		let mut script = Script::new(Nes::new(rom()), "fn after_frame() { gui::line(0, 0, 9223372036854775807, 0, 0); }").unwrap();
+		assert!(matches!(script.run_frame(), Err(ScriptError::Runtime(message)) if message.contains("too far off screen")));
+	}
+}
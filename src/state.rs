@@ -0,0 +1,338 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::rom::Mirroring;
+
+// Save states start with the magic, the format version and the CRC32 of the ROM,
+// followed by sections tagged with 4 letters. Readers skip the sections they do not know
+// and the fields appended to the end of a section, so the version only goes up
+// when an existing field changes and older readers have to refuse the state
+pub const MAGIC: [u8; 4] = *b"NSST";
+pub const VERSION: u16 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+	// Not a save state
+	BadMagic,
+	// Written by a newer nessy with an incompatible layout
+	UnsupportedVersion(u16),
+	// Saved with another game
+	WrongRom { expected: u32, got: u32 },
+	// A section is missing, its content is cut short or holds impossible values
+	Corrupt(String)
+}
+
+impl fmt::Display for StateError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			StateError::BadMagic => write!(f, "not a save state"),
+			StateError::UnsupportedVersion(version) => write!(f, "save state version {} is not supported, {} at most", version, VERSION),
+			StateError::WrongRom { expected, got } => write!(f, "save state is for ROM {:08X}, not {:08X}", got, expected),
+			StateError::Corrupt(message) => write!(f, "corrupt save state: {}", message)
+		}
+	}
+}
+
+impl std::error::Error for StateError {}
+
+// Corrupt with `message` unless `valid`, for values that depend on each other
+pub fn ensure(valid: bool, message: &str) -> Result<(), StateError> {
+	if valid {
+		Ok(())
+	} else {
+		Err(StateError::Corrupt(String::from(message)))
+	}
+}
+
+fn in_range<T: PartialOrd + fmt::Display>(value: T, range: RangeInclusive<T>) -> Result<T, StateError> {
+	if !range.contains(&value) {
+		return Err(StateError::Corrupt(format!("{} is not in {}..={}", value, range.start(), range.end())));
+	}
+	Ok(value)
+}
+
+// Header and sections of a save state
+pub struct StateFile<'a> {
+	pub version: u16,
+	pub rom_crc32: u32,
+	sections: Vec<([u8; 4], &'a [u8])>
+}
+
+impl<'a> StateFile<'a> {
+	pub fn write(rom_crc32: u32, sections: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+		let mut data = MAGIC.to_vec();
+		data.extend_from_slice(&VERSION.to_le_bytes());
+		data.extend_from_slice(&rom_crc32.to_le_bytes());
+		for (tag, section) in sections {
+			StateFile::append(&mut data, *tag, section);
+		}
+		data
+	}
+
+	// Add a section to a written state, for frontend data such as thumbnails
+	pub fn append(data: &mut Vec<u8>, tag: [u8; 4], section: &[u8]) {
+		data.extend_from_slice(&tag);
+		data.extend_from_slice(&(section.len() as u32).to_le_bytes());
+		data.extend_from_slice(section);
+	}
+
+	// Checks the header and the section lengths, not the section contents
+	pub fn parse(data: &'a [u8]) -> Result<StateFile<'a>, StateError> {
+		if data.len() < 10 || data[0..4] != MAGIC {
+			return Err(StateError::BadMagic);
+		}
+
+		let version = u16::from_le_bytes([data[4], data[5]]);
+		if version > VERSION {
+			return Err(StateError::UnsupportedVersion(version));
+		}
+		let rom_crc32 = u32::from_le_bytes(data[6..10].try_into().unwrap());
+
+		let mut sections = Vec::new();
+		let mut rest = &data[10..];
+		while !rest.is_empty() {
+			if rest.len() < 8 {
+				return Err(StateError::Corrupt(String::from("truncated section header")));
+			}
+			let tag: [u8; 4] = rest[0..4].try_into().unwrap();
+			let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+			if rest.len() - 8 < len {
+				return Err(StateError::Corrupt(format!("truncated {} section", String::from_utf8_lossy(&tag))));
+			}
+			sections.push((tag, &rest[8..8 + len]));
+			rest = &rest[8 + len..];
+		}
+
+		Ok(StateFile { version, rom_crc32, sections })
+	}
+
+	pub fn section(&self, tag: [u8; 4]) -> Option<&'a [u8]> {
+		self.sections.iter().find(|(name, _)| *name == tag).map(|&(_, section)| section)
+	}
+
+	pub fn required(&self, tag: [u8; 4]) -> Result<&'a [u8], StateError> {
+		self.section(tag).ok_or_else(|| StateError::Corrupt(format!("no {} section", String::from_utf8_lossy(&tag))))
+	}
+}
+
+// Little endian encoding of emulator state, read back in the order it was written
+pub struct StateWriter {
+	data: Vec<u8>
+}
+
+impl StateWriter {
+	pub fn new() -> StateWriter {
+		StateWriter {
+			data: Vec::new()
+		}
+	}
+
+	pub fn write_u8(&mut self, value: u8) {
+		self.data.push(value);
+	}
+
+	pub fn write_bool(&mut self, value: bool) {
+		self.write_u8(u8::from(value));
+	}
+
+	pub fn write_u16(&mut self, value: u16) {
+		self.data.extend_from_slice(&value.to_le_bytes());
+	}
+
+	pub fn write_u32(&mut self, value: u32) {
+		self.data.extend_from_slice(&value.to_le_bytes());
+	}
+
+	pub fn write_u64(&mut self, value: u64) {
+		self.data.extend_from_slice(&value.to_le_bytes());
+	}
+
+	// Length prefixed
+	pub fn write_bytes(&mut self, bytes: &[u8]) {
+		self.write_u32(bytes.len() as u32);
+		self.data.extend_from_slice(bytes);
+	}
+
+	pub fn write_mirroring(&mut self, mirroring: Option<Mirroring>) {
+		self.write_u8(match mirroring {
+			None => 0,
+			Some(Mirroring::Vertical) => 1,
+			Some(Mirroring::Horizontal) => 2,
+			Some(Mirroring::FourScreen) => 3,
+			Some(Mirroring::SingleScreenLower) => 4,
+			Some(Mirroring::SingleScreenUpper) => 5
+		});
+	}
+
+	pub fn finish(self) -> Vec<u8> {
+		self.data
+	}
+}
+
+impl Default for StateWriter {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+// Data that was not written by the matching StateWriter calls is a StateError::Corrupt
+pub struct StateReader<'a> {
+	data: &'a [u8],
+	position: usize
+}
+
+impl<'a> StateReader<'a> {
+	pub fn new(data: &'a [u8]) -> StateReader<'a> {
+		StateReader {
+			data,
+			position: 0
+		}
+	}
+
+	fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+		if len > self.data.len() - self.position {
+			return Err(StateError::Corrupt(format!("truncated state, {} bytes needed at offset {}", len, self.position)));
+		}
+
+		let bytes = &self.data[self.position..self.position + len];
+		self.position += len;
+		Ok(bytes)
+	}
+
+	pub fn read_u8(&mut self) -> Result<u8, StateError> {
+		Ok(self.take(1)?[0])
+	}
+
+	pub fn read_bool(&mut self) -> Result<bool, StateError> {
+		Ok(self.read_u8()? != 0)
+	}
+
+	pub fn read_u16(&mut self) -> Result<u16, StateError> {
+		Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+	}
+
+	pub fn read_u32(&mut self) -> Result<u32, StateError> {
+		Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+	}
+
+	pub fn read_u64(&mut self) -> Result<u64, StateError> {
+		Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+	}
+
+	// Values the emulation indexes tables with or counts down from a fixed span
+	pub fn read_u8_in(&mut self, range: RangeInclusive<u8>) -> Result<u8, StateError> {
+		in_range(self.read_u8()?, range)
+	}
+
+	pub fn read_u16_in(&mut self, range: RangeInclusive<u16>) -> Result<u16, StateError> {
+		in_range(self.read_u16()?, range)
+	}
+
+	pub fn read_u32_in(&mut self, range: RangeInclusive<u32>) -> Result<u32, StateError> {
+		in_range(self.read_u32()?, range)
+	}
+
+	pub fn read_bytes(&mut self) -> Result<&'a [u8], StateError> {
+		let len = self.read_u32()? as usize;
+		self.take(len)
+	}
+
+	// Bytes written with write_bytes, into a buffer of the same length
+	pub fn read_into(&mut self, destination: &mut [u8]) -> Result<(), StateError> {
+		let bytes = self.read_bytes()?;
+		if bytes.len() != destination.len() {
+			return Err(StateError::Corrupt(format!("state holds {} bytes, {} expected", bytes.len(), destination.len())));
+		}
+		destination.copy_from_slice(bytes);
+		Ok(())
+	}
+
+	pub fn read_mirroring(&mut self) -> Result<Option<Mirroring>, StateError> {
+		match self.read_u8()? {
+			0 => Ok(None),
+			1 => Ok(Some(Mirroring::Vertical)),
+			2 => Ok(Some(Mirroring::Horizontal)),
+			3 => Ok(Some(Mirroring::FourScreen)),
+			4 => Ok(Some(Mirroring::SingleScreenLower)),
+			5 => Ok(Some(Mirroring::SingleScreenUpper)),
+			value => Err(StateError::Corrupt(format!("invalid mirroring {}", value)))
+		}
+	}
+}
+
+// Serde for the byte arrays longer than the 32 elements serde handles, with `serde(with)`
+#[cfg(feature = "serde")]
+pub(crate) mod byte_array {
+	use serde::de::Error;
+	use serde::{Deserialize, Deserializer, Serializer};
+
+	pub fn serialize<S: Serializer, const N: usize>(bytes: &[u8; N], serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_bytes(bytes)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error> {
+		let bytes = Vec::<u8>::deserialize(deserializer)?;
+		let len = bytes.len();
+		bytes.try_into().map_err(|_| D::Error::invalid_length(len, &"a byte array of the field size"))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trip() {
+		let mut writer = StateWriter::new();
+		writer.write_u8(0x12);
+		writer.write_bool(true);
+		writer.write_u16(0x3456);
+		writer.write_u64(u64::MAX - 1);
+		writer.write_bytes(&[1, 2, 3]);
+		writer.write_mirroring(Some(Mirroring::SingleScreenUpper));
+		let data = writer.finish();
+
+		let mut reader = StateReader::new(&data);
+		assert_eq!(reader.read_u8(), Ok(0x12));
+		assert_eq!(reader.read_bool(), Ok(true));
+		assert_eq!(reader.read_u16(), Ok(0x3456));
+		assert_eq!(reader.read_u64(), Ok(u64::MAX - 1));
+		let mut bytes = [0; 3];
+		reader.read_into(&mut bytes).unwrap();
+		assert_eq!(bytes, [1, 2, 3]);
+		assert_eq!(reader.read_mirroring(), Ok(Some(Mirroring::SingleScreenUpper)));
+	}
+
+	#[test]
+	fn state_file() {
+		let data = StateFile::write(0x1234_5678, &[(*b"CPU ", vec![1, 2]), (*b"NEW!", vec![3])]);
+		let file = StateFile::parse(&data).unwrap();
+		assert_eq!(file.version, VERSION);
+		assert_eq!(file.rom_crc32, 0x1234_5678);
+		assert_eq!(file.section(*b"CPU "), Some(&[1, 2][..]));
+		assert_eq!(file.section(*b"NEW!"), Some(&[3][..]));
+		assert!(file.required(*b"PPU ").is_err());
+
+		assert_eq!(StateFile::parse(b"NES\x1A0000000000").err(), Some(StateError::BadMagic));
+		let mut newer = data.clone();
+		newer[4] = 0xFF;
+		assert_eq!(StateFile::parse(&newer).err(), Some(StateError::UnsupportedVersion(0x00FF)));
+		assert!(matches!(StateFile::parse(&data[..data.len() - 1]), Err(StateError::Corrupt(_))));
+	}
+
+	#[test]
+	fn truncated() {
+		assert!(matches!(StateReader::new(&[0x01]).read_u16(), Err(StateError::Corrupt(_))));
+		assert!(matches!(StateReader::new(&[3, 0, 0, 0, 1, 2]).read_into(&mut [0; 3]), Err(StateError::Corrupt(_))));
+		assert!(matches!(StateReader::new(&[1, 0, 0, 0, 1, 2]).read_into(&mut [0; 2]), Err(StateError::Corrupt(_))));
+		assert!(matches!(StateReader::new(&[9]).read_mirroring(), Err(StateError::Corrupt(_))));
+	}
+
+	#[test]
+	fn out_of_range() {
+		assert_eq!(StateReader::new(&[3]).read_u8_in(0..=3), Ok(3));
+		assert!(matches!(StateReader::new(&[4]).read_u8_in(0..=3), Err(StateError::Corrupt(_))));
+		assert!(matches!(StateReader::new(&[0, 8]).read_u16_in(0..=0x7FF), Err(StateError::Corrupt(_))));
+		assert!(matches!(ensure(false, "counters out of order"), Err(StateError::Corrupt(_))));
+	}
+}
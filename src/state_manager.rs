@@ -0,0 +1,197 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::nes::Nes;
+use crate::ppu::frame::Frame;
+use crate::state::{StateFile, StateReader, StateWriter};
+
+pub const SLOT_COUNT: u8 = 10;
+
+const THUMBNAIL_TAG: [u8; 4] = *b"THMB";
+// 128x120 thumbnails
+const THUMBNAIL_SCALE: usize = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SlotInfo {
+	pub slot: u8,
+	pub modified: SystemTime
+}
+
+// Numbered save state slots of one game, in `<dir>/<ROM CRC32>/slot<N>.state`
+pub struct StateManager {
+	dir: PathBuf
+}
+
+impl StateManager {
+	// Slots of the game inserted in `nes`
+	pub fn new<P: AsRef<Path>>(dir: P, nes: &Nes) -> StateManager {
+		let crc32 = nes.bus().rom_info().map_or(0, |info| info.crc32);
+		StateManager {
+			dir: dir.as_ref().join(format!("{:08X}", crc32))
+		}
+	}
+
+	pub fn dir(&self) -> &Path {
+		&self.dir
+	}
+
+	pub fn slot_path(&self, slot: u8) -> PathBuf {
+		self.dir.join(format!("slot{}.state", slot))
+	}
+
+	// Save state with a thumbnail of the last frame, the old slot is only replaced once written
+	pub fn save(&self, nes: &Nes, slot: u8) -> io::Result<()> {
+		check_slot(slot)?;
+		let mut data = nes.save_state();
+		StateFile::append(&mut data, THUMBNAIL_TAG, &encode_thumbnail(&nes.frame().downscale(THUMBNAIL_SCALE)));
+
+		fs::create_dir_all(&self.dir)?;
+		let path = self.slot_path(slot);
+		let temp = path.with_extension("state.tmp");
+		fs::write(&temp, &data)?;
+		fs::rename(&temp, &path)
+	}
+
+	// Invalid states are reported as InvalidData, wrapping a StateError
+	pub fn load(&self, nes: &mut Nes, slot: u8) -> io::Result<()> {
+		check_slot(slot)?;
+		let data = fs::read(self.slot_path(slot))?;
+		nes.load_state(&data).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+	}
+
+	// None for empty slots and states saved without a thumbnail
+	pub fn thumbnail(&self, slot: u8) -> io::Result<Option<Frame>> {
+		check_slot(slot)?;
+		let data = match fs::read(self.slot_path(slot)) {
+			Ok(data) => data,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(error) => return Err(error)
+		};
+
+		let file = StateFile::parse(&data).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+		Ok(file.section(THUMBNAIL_TAG).and_then(decode_thumbnail))
+	}
+
+	pub fn delete(&self, slot: u8) -> io::Result<()> {
+		check_slot(slot)?;
+		match fs::remove_file(self.slot_path(slot)) {
+			Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+			result => result
+		}
+	}
+
+	// Used slots, in slot order
+	pub fn slots(&self) -> Vec<SlotInfo> {
+		(0..SLOT_COUNT).filter_map(|slot| {
+			let metadata = fs::metadata(self.slot_path(slot)).ok()?;
+			Some(SlotInfo {
+				slot,
+				modified: metadata.modified().ok()?
+			})
+		}).collect()
+	}
+
+	// Most recently written slot, for quick load
+	pub fn latest(&self) -> Option<u8> {
+		self.slots().into_iter().max_by_key(|info| info.modified).map(|info| info.slot)
+	}
+}
+
+fn check_slot(slot: u8) -> io::Result<()> {
+	if slot < SLOT_COUNT {
+		Ok(())
+	} else {
+		Err(io::Error::new(io::ErrorKind::InvalidInput, format!("slot {} does not exist, {} slots", slot, SLOT_COUNT)))
+	}
+}
+
+fn encode_thumbnail(frame: &Frame) -> Vec<u8> {
+	let mut state = StateWriter::new();
+	state.write_u16(frame.width as u16);
+	state.write_u16(frame.height as u16);
+	state.write_bytes(&frame.data);
+	state.finish()
+}
+
+fn decode_thumbnail(data: &[u8]) -> Option<Frame> {
+	if data.len() < 8 {
+		return None;
+	}
+
+	// Sizes come from the file, check them against the pixels there before allocating
+	let mut state = StateReader::new(data);
+	let width = usize::from(state.read_u16().ok()?);
+	let height = usize::from(state.read_u16().ok()?);
+	let pixels = state.read_bytes().ok()?;
+	if pixels.len() != width * height * 3 || data.len() - 8 != pixels.len() {
+		return None;
+	}
+	let mut frame = Frame::new(width, height);
+	frame.data.copy_from_slice(pixels);
+	Some(frame)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::rom::Rom;
+	use crate::rom::test::nrom;
+
+	// NROM writing a green backdrop and counting in $10
+	fn rom() -> Rom {
+		Rom::from_ines(&nrom(&[
+			0xA9, 0x3F, 0x8D, 0x06, 0x20, // PPUADDR $3F00
+			0xA9, 0x00, 0x8D, 0x06, 0x20,
+			0xA9, 0x2A, 0x8D, 0x07, 0x20, // Green
+			0xA9, 0x0A, 0x8D, 0x01, 0x20, // Rendering on
+			0xE6, 0x10, 0x4C, 0x14, 0x80  // INC $10, JMP
+		], [0, 0x8000, 0])).unwrap()
+	}
+
+	#[test]
+	fn slots() {
+		let dir = std::env::temp_dir().join(format!("nessy-states-{}", std::process::id()));
+		let mut nes = Nes::new(rom());
+		let manager = StateManager::new(&dir, &nes);
+		assert!(manager.dir().ends_with(format!("{:08X}", nes.bus().rom_info().unwrap().crc32)));
+		assert!(manager.slots().is_empty());
+		assert!(manager.thumbnail(1).unwrap().is_none());
+
+		nes.run_frame();
+		nes.run_frame();
+		manager.save(&nes, 1).unwrap();
+		let counter = nes.bus().peek(0x0010);
+		assert!(!manager.slot_path(1).with_extension("state.tmp").exists());
+
+		let thumbnail = manager.thumbnail(1).unwrap().unwrap();
+		assert_eq!((thumbnail.width, thumbnail.height), (128, 120));
+		assert_eq!(thumbnail.pixel(10, 10), nes.frame().pixel(20, 20));
+
+		nes.run_frame();
+		assert_ne!(nes.bus().peek(0x0010), counter);
+		manager.load(&mut nes, 1).unwrap();
+		assert_eq!(nes.bus().peek(0x0010), counter);
+
+		assert_eq!(manager.slots().iter().map(|info| info.slot).collect::<Vec<_>>(), [1]);
+		assert_eq!(manager.latest(), Some(1));
+		assert_eq!(manager.load(&mut nes, 2).unwrap_err().kind(), io::ErrorKind::NotFound);
+		assert_eq!(manager.save(&nes, SLOT_COUNT).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+		manager.delete(1).unwrap();
+		assert!(manager.slots().is_empty());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn corrupt_thumbnail() {
+		let frame = Frame::new(2, 1);
+		assert_eq!(decode_thumbnail(&encode_thumbnail(&frame)).unwrap().data, frame.data);
+
+		// 65535x65535 with no pixels
+		assert!(decode_thumbnail(&[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]).is_none());
+		assert!(decode_thumbnail(&[2, 0, 1, 0, 6, 0, 0, 0, 1, 2, 3]).is_none());
+	}
+}
@@ -0,0 +1,119 @@
+// Bindings for running nessy in a browser: load a ROM from a Uint8Array, call run_frame
+// from requestAnimationFrame and put frame_rgba into an ImageData of 256x240
+use wasm_bindgen::prelude::*;
+
+use crate::joypad::Button;
+use crate::nes::Nes;
+use crate::rom::Rom;
+
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct WasmNes {
+	nes: Option<Nes>
+}
+
+#[wasm_bindgen]
+impl WasmNes {
+	#[wasm_bindgen(constructor)]
+	pub fn new() -> WasmNes {
+		WasmNes::default()
+	}
+
+	// iNES file content, replaces the running game
+	pub fn load_rom(&mut self, data: &[u8]) -> Result<(), JsError> {
+		let rom = Rom::from_ines(data).map_err(|error| JsError::new(&error.to_string()))?;
+		self.nes = Some(Nes::new(rom));
+		Ok(())
+	}
+
+	pub fn is_loaded(&self) -> bool {
+		self.nes.is_some()
+	}
+
+	pub fn reset(&mut self) {
+		if let Some(nes) = &mut self.nes {
+			nes.reset();
+		}
+	}
+
+	pub fn run_frame(&mut self) {
+		if let Some(nes) = &mut self.nes {
+			nes.run_frame();
+		}
+	}
+
+	// RGBA pixels of the last frame, black before the first one
+	pub fn frame_rgba(&self) -> Vec<u8> {
+		match &self.nes {
+			Some(nes) => nes.frame().to_rgba(),
+			None => vec![0; 256 * 240 * 4]
+		}
+	}
+
+	// `player` 0 or 1, `button` in the order A, B, Select, Start, Up, Down, Left, Right
+	pub fn set_button(&mut self, player: u8, button: u8, pressed: bool) {
+		let (Some(nes), Some(&button)) = (&mut self.nes, Button::ALL.get(usize::from(button))) else {
+			return;
+		};
+		match player {
+			0 => nes.set_button(button, pressed),
+			1 => nes.set_button2(button, pressed),
+			_ => {}
+		}
+	}
+
+	// Match the AudioContext rate
+	pub fn set_sample_rate(&mut self, sample_rate: u32) {
+		if let Some(nes) = &mut self.nes {
+			nes.bus_mut().apu_mut().set_sample_rate(sample_rate);
+		}
+	}
+
+	// Mono samples since the last call, for an AudioWorklet
+	pub fn audio_samples(&mut self) -> Vec<f32> {
+		self.nes.as_mut().map(Nes::audio_samples).unwrap_or_default()
+	}
+
+	pub fn save_state(&self) -> Vec<u8> {
+		self.nes.as_ref().map(Nes::save_state).unwrap_or_default()
+	}
+
+	pub fn load_state(&mut self, data: &[u8]) -> Result<(), JsError> {
+		let nes = self.nes.as_mut().ok_or_else(|| JsError::new("no ROM loaded"))?;
+		nes.load_state(data).map_err(|error| JsError::new(&error.to_string()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::rom::test::nrom;
+
+	#[test]
+	fn frames_and_buttons() {
+		let mut wasm = WasmNes::new();
+		assert!(!wasm.is_loaded());
+		assert_eq!(wasm.frame_rgba().len(), 256 * 240 * 4);
+		wasm.run_frame();
+		assert!(wasm.save_state().is_empty());
+
+		// JMP $8000
+		let ines = nrom(&[0x4C, 0x00, 0x80], [0, 0x8000, 0]);
+		wasm.load_rom(&ines).unwrap();
+		assert!(wasm.is_loaded());
+
+		wasm.run_frame();
+		let frame = wasm.frame_rgba();
+		assert_eq!(frame.len(), 256 * 240 * 4);
+		assert_eq!(frame[3], 0xFF);
+
+		// Out of range buttons and players are ignored
+		wasm.set_button(0, 3, true);
+		wasm.set_button(2, 0, true);
+		wasm.set_button(0, 8, true);
+		let state = wasm.save_state();
+		assert!(!state.is_empty());
+		wasm.load_state(&state).unwrap();
+	}
+}
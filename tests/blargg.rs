@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use nessy::harness::blargg;
+use nessy::rom::Rom;
+
+// Generous, the longest single tests finish in about 20 seconds
+const MAX_FRAMES: u32 = 60 * 60;
+
+// Runs the ROMs of a suite found under `directory`, missing ones are skipped
+fn run_suite(directory: &str, roms: &[&str]) {
+	let mut failures = Vec::new();
+	for name in roms {
+		let path = Path::new(directory).join(name);
+		if !path.exists() {
+			eprintln!("skipping {}", path.display());
+			continue;
+		}
+
+		let rom = Rom::from_path(&path).expect("could not load the test ROM");
+		match blargg::run(rom, MAX_FRAMES) {
+			Ok(result) if result.passed() => {},
+			Ok(result) => failures.push(format!("{}: failed #{}\n{}", name, result.code, result.message.trim_end())),
+			Err(timeout) => failures.push(format!("{}: {}", name, timeout))
+		}
+	}
+
+	assert!(failures.is_empty(), "\n{}", failures.join("\n\n"));
+}
+
+#[test]
+fn cpu_instructions() {
+	run_suite("rom/blargg/instr_test-v5/rom_singles", &[
+		"01-basics.nes",
+		"02-implied.nes",
+		"03-immediate.nes",
+		"04-zero_page.nes",
+		"05-zp_xy.nes",
+		"06-absolute.nes",
+		"07-abs_xy.nes",
+		"08-ind_x.nes",
+		"09-ind_y.nes",
+		"10-branches.nes",
+		"11-stack.nes",
+		"12-jmp_jsr.nes",
+		"13-rts.nes",
+		"14-rti.nes",
+		"15-brk.nes",
+		"16-special.nes"
+	]);
+}
+
+#[test]
+fn ppu_vbl_nmi() {
+	run_suite("rom/blargg/ppu_vbl_nmi/rom_singles", &[
+		"01-vbl_basics.nes",
+		"02-vbl_set_time.nes",
+		"03-vbl_clear_time.nes",
+		"04-nmi_control.nes",
+		"05-nmi_timing.nes",
+		"06-suppression.nes",
+		"07-nmi_on_timing.nes",
+		"08-nmi_off_timing.nes",
+		"09-even_odd_frames.nes",
+		"10-even_odd_timing.nes"
+	]);
+}
+
+#[test]
+fn apu() {
+	run_suite("rom/blargg/apu_test/rom_singles", &[
+		"1-len_ctr.nes",
+		"2-len_table.nes",
+		"3-irq_flag.nes",
+		"4-jitter.nes",
+		"5-len_timing.nes",
+		"6-irq_flag_timing.nes",
+		"7-dmc_basics.nes",
+		"8-dmc_rates.nes"
+	]);
+}
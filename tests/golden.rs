@@ -0,0 +1,58 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use nessy::harness::golden;
+use nessy::input::fm2::Fm2Movie;
+use nessy::rom::Rom;
+
+// `name.nes` with its `name.hashes`, and optionally `name.fm2` for the inputs
+const DIRECTORY: &str = "rom/golden";
+// Frames hashed when writing new goldens without a movie
+const DEFAULT_FRAMES: usize = 300;
+
+// Set NESSY_BLESS=1 to write the hashes of the current build as the new goldens
+#[test]
+fn golden_frames() {
+	let Ok(entries) = fs::read_dir(DIRECTORY) else {
+		eprintln!("skipping the golden frames, {} is missing", DIRECTORY);
+		return;
+	};
+	let bless = env::var_os("NESSY_BLESS").is_some();
+
+	let mut failures = Vec::new();
+	for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+		if path.extension().is_none_or(|extension| extension != "nes") {
+			continue;
+		}
+
+		let inputs = match fs::read_to_string(path.with_extension("fm2")) {
+			Ok(text) => Fm2Movie::parse(&text).expect("invalid movie").inputs(),
+			Err(_) => Vec::new()
+		};
+		let hashes_path = path.with_extension("hashes");
+		let rom = Rom::from_path(&path).expect("could not load the ROM");
+
+		if bless {
+			let frames = if inputs.is_empty() { DEFAULT_FRAMES } else { inputs.len() };
+			fs::write(&hashes_path, golden::format_hashes(&golden::frame_hashes(rom, &inputs, frames))).expect("could not write the hashes");
+			continue;
+		}
+
+		let Ok(text) = fs::read_to_string(&hashes_path) else {
+			eprintln!("skipping {}, no hashes", path.display());
+			continue;
+		};
+		let expected = golden::parse_hashes(&text).expect("invalid hashes");
+		let hashes = golden::frame_hashes(rom, &inputs, expected.len());
+		if let Err(error) = golden::compare(&hashes, &expected) {
+			failures.push(format!("{}: {}", display_name(&path), error));
+		}
+	}
+
+	assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+fn display_name(path: &Path) -> String {
+	path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
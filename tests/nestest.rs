@@ -0,0 +1,38 @@
+use std::fs;
+use std::path::Path;
+
+use nessy::bus::Bus;
+use nessy::cpu::{trace, Cpu};
+use nessy::rom::Rom;
+
+const ROM: &str = "rom/nestest.nes";
+// Reference log of Nintendulator, starting at $C000 with CYC:7
+const LOG: &str = "tests/fixtures/nestest.log";
+
+// Runs the automated mode of nestest and compares every trace line with the reference log.
+// Skipped when the ROM or the log are not present
+#[test]
+fn nestest_log() {
+	if !Path::new(ROM).exists() || !Path::new(LOG).exists() {
+		eprintln!("skipping nestest, {} and {} are needed", ROM, LOG);
+		return;
+	}
+
+	let rom = Rom::from_path(ROM).expect("could not load nestest");
+	let log = fs::read_to_string(LOG).expect("could not read nestest.log");
+
+	let mut bus = Bus::new(rom);
+	let mut cpu = Cpu::new();
+	cpu.reset(&mut bus);
+	cpu.pc = 0xC000;
+
+	for (number, expected) in log.lines().enumerate() {
+		let line = trace(&cpu, &bus);
+		assert_eq!(line, expected.trim_end(), "first mismatch at line {}", number + 1);
+		cpu.step(&mut bus);
+	}
+
+	// Error codes of the official and undocumented opcode tests
+	assert_eq!(bus.read(0x0002), 0x00);
+	assert_eq!(bus.read(0x0003), 0x00);
+}